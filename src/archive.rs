@@ -0,0 +1,161 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    compress_to, compressed_path, estimate, options::Options, paths, replace_original, scan,
+    FileIdentity, Log, RunPosition, SkipReason,
+};
+
+/// Compresses every video of `source` whose copy below `archive` (at the same relative path)
+/// is missing or older than the source, leaving the source tree untouched. The log is kept in
+/// `archive` and refers to the archived files, also for the skips and failures that are
+/// recorded under the source path.
+pub fn run(source: &Path, archive: &Path, options: &Options) -> Log {
+    if !source.is_dir() {
+        info!(
+            "`--source` must be a directory, got `{}`",
+            source.to_string_lossy()
+        );
+        std::process::exit(1);
+    }
+    // the archived videos would be picked up as sources by the next run
+    if let (Ok(source), Ok(archive)) = (source.canonicalize(), std::path::absolute(archive)) {
+        if archive.starts_with(paths::simplify(source)) {
+            info!("`--archive` can't be inside of `--source`");
+            std::process::exit(1);
+        }
+    }
+    if let Err(e) = std::fs::create_dir_all(archive) {
+        info!("Failed to create `{}`: {e}", archive.to_string_lossy());
+        std::process::exit(1);
+    }
+
+    let mut log = Log::new(archive.to_string_lossy().to_string());
+    let candidates = scan(&source.to_path_buf(), &mut log, options);
+    key_by_archive(&mut log, source, archive);
+    let candidate_count = candidates.len();
+    let (candidates, archived_paths): (Vec<_>, Vec<_>) = candidates
+        .into_iter()
+        .filter_map(|candidate| {
+            let archived = archive.join(candidate.path.strip_prefix(source).ok()?);
+            (!is_up_to_date(&candidate.path, &archived)).then_some((candidate, archived))
+        })
+        .unzip();
+    if candidates.len() < candidate_count {
+        info!(
+            "{} videos are already up to date in the archive",
+            candidate_count - candidates.len()
+        );
+    }
+
+    if options.estimate_time {
+        estimate::print_estimate(&candidates, options);
+    }
+
+    let positions = RunPosition::all(&candidates);
+    for ((candidate, archived), position) in
+        candidates.into_iter().zip(archived_paths).zip(positions)
+    {
+        if options.cancel.is_cancelled() || log.stopped {
+            break;
+        }
+        let path = archived.to_string_lossy().to_string();
+        if let Some(reason) = candidate.rejection(&log, options) {
+            log.mark_skipped(path, reason);
+            continue;
+        }
+
+        if let Err(e) = create_parent_dir(&archived) {
+            log.mark_skipped(path, SkipReason::DestinationNotWritable(e));
+            continue;
+        }
+
+        let previous = FileIdentity::of(&archived);
+        let dest = compressed_path(&archived);
+        let Ok(encoded) = compress_to(&candidate.path, &dest, Some(position), &mut log, options)
+        else {
+            key_by_archive(&mut log, source, archive);
+            continue;
+        };
+        if FileIdentity::of(&archived) != previous {
+            let _ = std::fs::remove_file(&encoded.dest);
+            log.mark_skipped(path, SkipReason::ChangedDuringCompression);
+            continue;
+        }
+        if let Err(e) = replace_original(&encoded.dest, &archived) {
+            log.mark_skipped(path, SkipReason::Override(e));
+            continue;
+        }
+
+        log.mark_processed(path, candidate.size, &encoded);
+        log.save();
+    }
+
+    log
+}
+
+/// Moves the skips, failures and retries recorded under paths below `source` to the same paths
+/// below `archive`, so that archiving the source into another archive doesn't find them. The
+/// input checks stay under the source path, they are about the source file.
+fn key_by_archive(log: &mut Log, source: &Path, archive: &Path) {
+    let archived = |path: &String| {
+        let relative = Path::new(path).strip_prefix(source).ok()?;
+        Some(archive.join(relative).to_string_lossy().to_string())
+    };
+    fn rekey<V>(entries: &mut HashMap<String, V>, archived: impl Fn(&String) -> Option<String>) {
+        let below_source = entries
+            .keys()
+            .filter_map(|path| Some((path.clone(), archived(path)?)))
+            .collect::<Vec<_>>();
+        for (path, archived) in below_source {
+            let entry = entries.remove(&path).unwrap();
+            entries.insert(archived, entry);
+        }
+    }
+    rekey(&mut log.skipped_files, archived);
+    rekey(&mut log.failed_files, archived);
+    rekey(&mut log.encrypted_files, archived);
+    rekey(&mut log.retry_files, archived);
+}
+
+/// Whether the archived copy exists and was written after the source was last modified
+fn is_up_to_date(source: &Path, archived: &Path) -> bool {
+    let modified = |path: &Path| path.metadata().and_then(|metadata| metadata.modified());
+    match (modified(source), modified(archived)) {
+        (Ok(source), Ok(archived)) => archived >= source,
+        _ => false,
+    }
+}
+
+fn create_parent_dir(path: &Path) -> Result<(), std::io::Error> {
+    match path.parent() {
+        Some(parent) => std::fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_and_failures_are_logged_under_the_archived_path() {
+        let mut log = Log::in_memory();
+        log.mark_skipped("/videos/a.mp4".into(), SkipReason::NoVideoStream);
+        log.mark_skipped("/videos/b/c.mp4".into(), SkipReason::ClockBeforeEpoch);
+        log.mark_skipped("/elsewhere/d.mp4".into(), SkipReason::NoVideoStream);
+        key_by_archive(&mut log, Path::new("/videos"), Path::new("/archive"));
+
+        let archived = |path: &str| {
+            Path::new("/archive")
+                .join(path)
+                .to_string_lossy()
+                .to_string()
+        };
+        let mut skipped = log.skipped_files.keys().cloned().collect::<Vec<_>>();
+        skipped.sort();
+        assert_eq!(skipped, [archived("a.mp4"), "/elsewhere/d.mp4".to_string()]);
+        let failed = archived("b/c.mp4");
+        assert_eq!(log.failed_files.keys().collect::<Vec<_>>(), [&failed]);
+        assert_eq!(log.retry_files.keys().collect::<Vec<_>>(), [&failed]);
+    }
+}
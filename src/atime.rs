@@ -0,0 +1,146 @@
+use std::{
+    fs::{File, FileTimes},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::SystemTime,
+};
+
+/// Set from `--preserve-atime` at startup
+pub static PRESERVE_ATIME: AtomicBool = AtomicBool::new(false);
+/// Restoring is only warned about once, it usually fails for every file of the run alike
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Access time of a file or directory recorded before the run reads it, restored on drop so
+/// storage that tiers by access time doesn't see it as used. Does nothing without
+/// `--preserve-atime`.
+pub struct Preserved {
+    path: PathBuf,
+    accessed: Option<SystemTime>,
+}
+
+impl Preserved {
+    pub fn new(path: &Path) -> Self {
+        Preserved::recording(path, PRESERVE_ATIME.load(Ordering::Relaxed))
+    }
+
+    fn recording(path: &Path, preserve: bool) -> Self {
+        let accessed = preserve
+            .then(|| std::fs::metadata(path).and_then(|metadata| metadata.accessed()))
+            .and_then(Result::ok);
+        Preserved {
+            path: path.to_path_buf(),
+            accessed,
+        }
+    }
+}
+
+impl Drop for Preserved {
+    fn drop(&mut self) {
+        let Some(accessed) = self.accessed else {
+            return;
+        };
+        // only the access time is set, the modification time is left alone
+        let result = open(&self.path)
+            .and_then(|file| file.set_times(FileTimes::new().set_accessed(accessed)));
+        match result {
+            Ok(()) => {}
+            // e.g. the original was moved into the trash
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                if !WARNED.swap(true, Ordering::Relaxed) {
+                    info!(
+                        "Warning: failed to restore the access time of `{}`, access times might not be preserved: {e}",
+                        self.path.to_string_lossy()
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Opens `path` for setting its times, without the write access a regular open would need
+pub fn open(path: &Path) -> Result<File, std::io::Error> {
+    imp::open(path)
+}
+
+#[cfg(not(windows))]
+mod imp {
+    use std::{fs::File, path::Path};
+
+    /// futimens only needs the file to be owned, not opened for writing, which works for
+    /// directories as well
+    pub fn open(path: &Path) -> Result<File, std::io::Error> {
+        File::open(path)
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::{
+        fs::{File, OpenOptions},
+        os::windows::fs::OpenOptionsExt,
+        path::Path,
+    };
+
+    const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+    /// Needed to open directories
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+
+    pub fn open(path: &Path) -> Result<File, std::io::Error> {
+        OpenOptions::new()
+            .access_mode(FILE_WRITE_ATTRIBUTES)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+            .open(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn the_access_time_is_restored_on_drop() {
+        let dir = TempDir::new("atime");
+        let path = dir.join("video.mp4");
+        std::fs::write(&path, "video").unwrap();
+        let accessed = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let times = FileTimes::new()
+            .set_accessed(accessed)
+            .set_modified(modified);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_times(times)
+            .unwrap();
+        let touch = || {
+            let now = FileTimes::new().set_accessed(SystemTime::now());
+            open(&path).unwrap().set_times(now).unwrap();
+        };
+        let times = || {
+            let metadata = std::fs::metadata(&path).unwrap();
+            (metadata.accessed().unwrap(), metadata.modified().unwrap())
+        };
+
+        {
+            let _accessed = Preserved::recording(&path, true);
+            std::fs::read(&path).unwrap();
+            // mounts with noatime or relatime wouldn't update it for the read alone
+            touch();
+            assert_ne!(times().0, accessed);
+        }
+        assert_eq!(times(), (accessed, modified));
+
+        // without `--preserve-atime` the access time stays as the run left it
+        {
+            let _accessed = Preserved::recording(&path, false);
+            touch();
+        }
+        assert_ne!(times().0, accessed);
+        assert_eq!(times().1, modified);
+    }
+}
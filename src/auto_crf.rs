@@ -0,0 +1,193 @@
+use std::path::{Path, PathBuf};
+
+use crate::{probe, sample, sample::Metric};
+
+const MIN_CRF: u8 = 14;
+const MAX_CRF: u8 = 40;
+/// Step used while no sample has been on the other side of the target yet
+const CRF_STEP: u8 = 4;
+/// Number of CRF values that are tried before settling on the best one found so far
+const MAX_SAMPLE_ENCODES: usize = 6;
+const SAMPLE_COUNT: usize = 3;
+const SAMPLE_SECONDS: f64 = 10.0;
+
+pub struct AutoCrf {
+    pub crf: u8,
+    pub score: f64,
+}
+
+/// Searches the highest CRF whose sample encodes still reach `target` in the given `metric`.
+/// `video_args` returns the encoder arguments for a CRF, so the samples are encoded exactly
+/// like the full video would be.
+pub fn search(
+    path: &Path,
+    metric: Metric,
+    target: f64,
+    start_crf: u8,
+    video_args: impl Fn(u8) -> Vec<String>,
+) -> Result<AutoCrf, String> {
+    let duration = probe::duration(path).ok_or("Failed to probe duration")?;
+    let dir = sample::scratch_dir()?;
+    let result = search_in(&dir, path, duration, metric, target, start_crf, video_args);
+    sample::remove_scratch_dir(&dir);
+    result
+}
+
+fn search_in(
+    dir: &Path,
+    path: &Path,
+    duration: f64,
+    metric: Metric,
+    target: f64,
+    start_crf: u8,
+    video_args: impl Fn(u8) -> Vec<String>,
+) -> Result<AutoCrf, String> {
+    let mut references = Vec::new();
+    for (i, (start, length)) in sample::windows(duration, SAMPLE_COUNT, SAMPLE_SECONDS)
+        .into_iter()
+        .enumerate()
+    {
+        let reference = dir.join(format!("sample_{i}.mkv"));
+        sample::extract(path, start, length, &reference)?;
+        references.push(reference);
+    }
+
+    converge(target, start_crf, |crf| {
+        let score = score_at(dir, &references, metric, &video_args(crf))?;
+        info!("Auto CRF: crf {crf} -> {metric} {score:.3}");
+        Ok(score)
+    })
+}
+
+/// Scores CRFs from `start_crf` on, bisecting between the highest passing and the lowest
+/// failing one, until they are adjacent or the encodes are used up
+fn converge(
+    target: f64,
+    start_crf: u8,
+    mut score_at: impl FnMut(u8) -> Result<f64, String>,
+) -> Result<AutoCrf, String> {
+    let mut results: Vec<(u8, f64)> = Vec::new();
+    let mut crf = start_crf.clamp(MIN_CRF, MAX_CRF);
+    for _ in 0..MAX_SAMPLE_ENCODES {
+        results.push((crf, score_at(crf)?));
+
+        let passing = results
+            .iter()
+            .filter(|(_, score)| *score >= target)
+            .max_by_key(|(crf, _)| *crf);
+        let failing = results
+            .iter()
+            .filter(|(_, score)| *score < target)
+            .min_by_key(|(crf, _)| *crf);
+
+        crf = match (passing, failing) {
+            (Some(&(pass_crf, pass_score)), Some(&(fail_crf, fail_score))) => {
+                // the scores aren't strictly monotonic, a lower CRF may fail where a higher one
+                // passed, which leaves nothing to bisect
+                let Some(gap) = fail_crf.checked_sub(pass_crf).filter(|&gap| gap > 1) else {
+                    break;
+                };
+
+                // the score drops roughly linearly with the crf between two close samples
+                let fraction = (pass_score - target) / (pass_score - fail_score);
+                let interpolated = f64::from(pass_crf) + fraction * f64::from(gap);
+                (interpolated.round() as u8).clamp(pass_crf + 1, fail_crf - 1)
+            }
+            (Some(&(pass_crf, _)), None) if pass_crf < MAX_CRF => {
+                (pass_crf + CRF_STEP).min(MAX_CRF)
+            }
+            (None, Some(&(fail_crf, _))) if fail_crf > MIN_CRF => {
+                fail_crf.saturating_sub(CRF_STEP).max(MIN_CRF)
+            }
+            _ => break,
+        };
+    }
+
+    // fall back to the best quality that was tried if the target was never reached
+    let (crf, score) = results
+        .iter()
+        .filter(|(_, score)| *score >= target)
+        .max_by_key(|(crf, _)| *crf)
+        .or_else(|| results.iter().min_by_key(|(crf, _)| *crf))
+        .copied()
+        .ok_or("No sample was encoded")?;
+    Ok(AutoCrf { crf, score })
+}
+
+/// Average score of all samples encoded with the given arguments
+fn score_at(
+    dir: &Path,
+    references: &[PathBuf],
+    metric: Metric,
+    video_args: &[String],
+) -> Result<f64, String> {
+    let mut total = 0.0;
+    for (i, reference) in references.iter().enumerate() {
+        let encoded = dir.join(format!("encoded_{i}.mkv"));
+        sample::encode(reference, video_args, &encoded)?;
+        total += sample::measure(metric, &encoded, reference)?;
+    }
+
+    Ok(total / references.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the search against a table of scores, returning the result and the CRFs tried
+    fn search_table(start_crf: u8, scores: impl Fn(u8) -> f64) -> (u8, Vec<u8>) {
+        let mut tried = Vec::new();
+        let result = converge(95.0, start_crf, |crf| {
+            tried.push(crf);
+            Ok(scores(crf))
+        })
+        .unwrap();
+        (result.crf, tried)
+    }
+
+    #[test]
+    fn the_search_stops_at_adjacent_crfs() {
+        let passes_up_to_25 = |crf| if crf <= 25 { 96.0 } else { 94.0 };
+        assert_eq!(
+            search_table(24, passes_up_to_25),
+            (25, vec![24, 28, 26, 25])
+        );
+        assert_eq!(
+            search_table(25, passes_up_to_25),
+            (25, vec![25, 29, 27, 26])
+        );
+        // the target sits between two neighbours right away
+        assert_eq!(
+            search_table(26, passes_up_to_25),
+            (25, vec![26, 22, 24, 25])
+        );
+    }
+
+    #[test]
+    fn non_monotonic_scores_end_the_search_without_panicking() {
+        // scores that rise and fall with the CRF like noisy metrics do
+        let tables: [fn(u8) -> f64; 3] = [
+            |crf| if crf % 3 == 0 { 96.0 } else { 94.0 },
+            |crf| 100.0 - f64::from(crf) / 4.0 + if crf % 2 == 0 { 2.0 } else { -2.0 },
+            |crf| {
+                if (20..=22).contains(&crf) || crf == 30 {
+                    94.0
+                } else {
+                    96.0
+                }
+            },
+        ];
+        for scores in tables {
+            for start_crf in 0..=u8::MAX {
+                let (crf, tried) = search_table(start_crf, scores);
+                assert!((MIN_CRF..=MAX_CRF).contains(&crf));
+                assert!(tried.len() <= MAX_SAMPLE_ENCODES);
+                // a passing CRF is picked whenever one was found
+                if tried.iter().any(|&tried| scores(tried) >= 95.0) {
+                    assert!(scores(crf) >= 95.0, "{start_crf}: {tried:?}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::{path::Path, time::Instant};
+
+use crate::{filesize, options::Options, probe, sample, EncodeSettings};
+
+/// Encodes a sample from the middle of `path` with every combination of the configured presets
+/// and CRFs and prints a row per encode. Neither the original nor the log are touched, the
+/// encodes are written to a scratch directory that is removed unless `--keep-benchmark-files`.
+pub fn bench(path: &Path, options: &Options) {
+    if !path.is_file() {
+        info!("`--bench` expects a file, got `{}`", path.to_string_lossy());
+        std::process::exit(1);
+    }
+    let Some(duration) = probe::duration(path) else {
+        info!(
+            "Failed to probe the duration of `{}`",
+            path.to_string_lossy()
+        );
+        std::process::exit(1);
+    };
+
+    let result = sample::scratch_dir().and_then(|dir| {
+        let result = bench_in(&dir, path, duration, options);
+        if options.keep_bench_files {
+            info!(
+                "Kept the sample and the encodes in `{}`",
+                dir.to_string_lossy()
+            );
+        } else {
+            sample::remove_scratch_dir(&dir);
+        }
+        result
+    });
+    if let Err(e) = result {
+        info!("{e}");
+        std::process::exit(1);
+    }
+}
+
+fn bench_in(dir: &Path, path: &Path, duration: f64, options: &Options) -> Result<(), String> {
+    let (start, length) = sample::windows(duration, 1, options.bench_seconds)[0];
+    let reference = dir.join("bench.mkv");
+    sample::extract(path, start, length, &reference)?;
+    // copying snaps the cut to keyframes, so the sample can be a bit longer than requested
+    let length = probe::duration(&reference).unwrap_or(length);
+    info!(
+        "Benchmarking a {length:.1}s sample of `{}`",
+        path.to_string_lossy()
+    );
+
+    let base = EncodeSettings {
+        deinterlace: EncodeSettings::resolve_deinterlace(path, options),
+        ..EncodeSettings::from_options(options)
+    };
+    let score_header = match options.bench_metric {
+        Some(metric) => format!("  {:>8}", metric.to_string()),
+        None => String::new(),
+    };
+    info!(
+        "{:<10} {:>3}  {:>10}  {:>8}  {:>8}  {:>12}{score_header}",
+        "preset", "crf", "size", "time", "speed", "bitrate"
+    );
+    for preset in &options.bench_presets {
+        for &crf in &options.bench_crfs {
+            let settings = EncodeSettings {
+                crf,
+                preset: Some(preset.clone()),
+                ..base.clone()
+            };
+            let encoded = dir.join(format!("bench_{preset}_{crf}.mkv"));
+            let started = Instant::now();
+            sample::encode(&reference, &settings.video_args(), &encoded)?;
+            let time = started.elapsed().as_secs_f64();
+            let speed = length / time;
+
+            let size = std::fs::metadata(&encoded)
+                .map_err(|e| format!("Failed to read `{}`: {e}", encoded.to_string_lossy()))?
+                .len();
+            let bitrate = format!("{:.0}kbps", size as f64 * 8.0 / length / 1000.0);
+            let score = match options.bench_metric {
+                Some(metric) => match sample::measure(metric, &encoded, &reference) {
+                    Ok(score) => format!("  {score:>8.3}"),
+                    Err(e) => format!("  {e}"),
+                },
+                None => String::new(),
+            };
+            info!(
+                "{preset:<10} {crf:>3}  {:>10}  {time:>7.1}s  {speed:>7.2}x  {bitrate:>12}{score}",
+                filesize::display(size)
+            );
+
+            // the encodes of long samples with slow presets add up
+            if !options.keep_bench_files {
+                let _ = std::fs::remove_file(&encoded);
+            }
+        }
+    }
+
+    Ok(())
+}
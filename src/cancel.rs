@@ -0,0 +1,112 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, OnceLock,
+};
+
+/// Shared flag that stops a run: the running ffmpeg is killed, its partial output removed
+/// and no further files are started. Clones refer to the same flag.
+#[derive(Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Cancels on the first Ctrl-C (or SIGTERM, e.g. from systemd stopping the service)
+    /// instead of terminating the process, a second one terminates it as usual
+    pub fn on_interrupt(&self) {
+        if CTRL_C.set(self.clone()).is_ok() {
+            imp::install();
+        }
+    }
+}
+
+/// Token cancelled by the handler, which can't capture anything
+static CTRL_C: OnceLock<Cancel> = OnceLock::new();
+
+#[cfg(unix)]
+mod imp {
+    use std::ffi::c_int;
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+    const SIG_DFL: usize = 0;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
+    }
+
+    extern "C" fn handle(signum: c_int) {
+        // only async-signal-safe calls here: an atomic store and `signal`
+        if let Some(cancel) = super::CTRL_C.get() {
+            cancel.cancel();
+        }
+        unsafe {
+            signal(signum, SIG_DFL);
+        }
+    }
+
+    pub fn install() {
+        unsafe {
+            for signum in [SIGINT, SIGTERM] {
+                signal(signum, handle as extern "C" fn(c_int) as usize);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    const CTRL_C_EVENT: u32 = 0;
+    const CTRL_BREAK_EVENT: u32 = 1;
+
+    extern "system" {
+        fn SetConsoleCtrlHandler(
+            handler: Option<unsafe extern "system" fn(u32) -> i32>,
+            add: i32,
+        ) -> i32;
+    }
+
+    /// Runs on its own thread. Returning 0 passes the event on to the default handler, which
+    /// terminates the process.
+    unsafe extern "system" fn handle(event: u32) -> i32 {
+        let Some(cancel) = super::CTRL_C.get() else {
+            return 0;
+        };
+        if !matches!(event, CTRL_C_EVENT | CTRL_BREAK_EVENT) || cancel.is_cancelled() {
+            return 0;
+        }
+        cancel.cancel();
+        1
+    }
+
+    pub fn install() {
+        unsafe {
+            SetConsoleCtrlHandler(Some(handle), 1);
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    pub fn install() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_the_flag() {
+        let cancel = Cancel::default();
+        let options_copy = cancel.clone();
+        assert!(!options_copy.is_cancelled());
+        cancel.cancel();
+        assert!(options_copy.is_cancelled());
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use crate::{options::Options, unix_time, Log, LOG_FILE};
+
+/// Tidies the log of the directory `path`: drops the entries of files that don't exist anymore
+/// and, with `--older-than`, retry entries older than that, stores every path in its absolute
+/// form merging entries of the same file, and writes the log compact or with `--pretty`
+/// indented. Prints every change, with `--dry-run` without saving them.
+pub fn clean_log(path: &Path, options: &Options) {
+    if !path.join(LOG_FILE).is_file() {
+        info!("No log to clean in `{}`", path.to_string_lossy());
+        std::process::exit(1);
+    }
+
+    let mut log = Log::load(path.to_string_lossy().to_string());
+    let (renamed, merged) = log.normalize_keys();
+    for (from, to) in &merged {
+        info!("Merged `{from}` into `{to}`");
+    }
+
+    let mut missing = Vec::new();
+    let mut exists = |path: &String| {
+        let exists = Path::new(path).exists();
+        if !exists {
+            missing.push(path.clone());
+        }
+        exists
+    };
+    log.shrunk_files.retain(|path, _| exists(path));
+    log.retry_files.retain(|path, _| exists(path));
+    log.skipped_files.retain(|path, _| exists(path));
+    log.failed_files.retain(|path, _| exists(path));
+    log.encrypted_files.retain(|path, _| exists(path));
+    log.input_checks.get_mut().retain(|path, _| exists(path));
+    // a file is usually listed more than once
+    missing.sort();
+    missing.dedup();
+    for path in &missing {
+        info!("Removed `{path}`, it doesn't exist anymore");
+    }
+
+    let mut old = Vec::new();
+    if let Some(days) = options.clean_older_than {
+        let cutoff = SystemTime::now()
+            .checked_sub(Duration::from_secs(days * 24 * 60 * 60))
+            .map_or(i64::MIN, unix_time);
+        old = expired(&log.retry_files, cutoff);
+        for path in &old {
+            log.retry_files.remove(path);
+            info!("Dropped the retry of `{path}`, it failed more than {days} days ago");
+        }
+    }
+
+    let changes = [
+        (renamed, "path made absolute", "paths made absolute"),
+        (merged.len(), "entry merged", "entries merged"),
+        (
+            missing.len(),
+            "missing file removed",
+            "missing files removed",
+        ),
+        (old.len(), "old retry dropped", "old retries dropped"),
+    ]
+    .into_iter()
+    .filter(|(count, _, _)| *count > 0)
+    .map(|(count, one, many)| format!("{count} {}", if count == 1 { one } else { many }))
+    .collect::<Vec<_>>();
+    let summary = if changes.is_empty() {
+        "Nothing to clean".to_string()
+    } else {
+        format!("Cleaned the log: {}", changes.join(", "))
+    };
+    if options.dry_run {
+        info!("{summary}");
+        info!("Dry run, the log is left as it was");
+        return;
+    }
+    log.pretty = options.pretty_log;
+    log.save();
+    if !log.is_saved() {
+        std::process::exit(1);
+    }
+    info!("{summary}, saved `{}`", log.save_file);
+}
+
+/// Retry entries recorded before `cutoff`, sorted. Entries without a time are kept, their age
+/// is unknown.
+fn expired(retry_files: &HashMap<String, crate::RetryEntry>, cutoff: i64) -> Vec<String> {
+    let mut expired = retry_files
+        .iter()
+        .filter(|(_, entry)| entry.recorded.is_some_and(|recorded| recorded < cutoff))
+        .map(|(path, _)| path.clone())
+        .collect::<Vec<_>>();
+    expired.sort();
+    expired
+}
@@ -0,0 +1,90 @@
+use std::process::Command;
+
+use crate::{color, doctor};
+
+/// HEVC encoders ffmpeg builds commonly offer, `true` for the one video_compressor encodes with
+const VIDEO_ENCODERS: [(&str, bool); 7] = [
+    ("libx265", true),
+    ("hevc_nvenc", false),
+    ("hevc_qsv", false),
+    ("hevc_vaapi", false),
+    ("hevc_amf", false),
+    ("hevc_videotoolbox", false),
+    ("hevc_mf", false),
+];
+
+/// Audio encoders of `--audio`
+const AUDIO_ENCODERS: [(&str, &str); 2] = [("aac", "--audio aac"), ("libopus", "--audio opus")];
+
+/// Prints which of the encoders relevant to video_compressor this ffmpeg build offers and its
+/// hardware acceleration methods
+pub fn list_codecs() {
+    let encoders = doctor::list("-encoders");
+    let available = |name: &str| {
+        if encoders.iter().any(|encoder| encoder == name) {
+            color::good("yes")
+        } else {
+            color::bad("no ")
+        }
+    };
+
+    info!("Video encoders:");
+    for (name, used) in VIDEO_ENCODERS {
+        let usage = if used {
+            "used to compress the videos"
+        } else {
+            "not used by video_compressor"
+        };
+        info!("  {} {name:<18} {usage}", available(name));
+    }
+    info!("Audio encoders:");
+    for (name, flag) in AUDIO_ENCODERS {
+        info!("  {} {name:<18} `{flag}`", available(name));
+    }
+
+    let hwaccels = hwaccels();
+    info!(
+        "Hardware acceleration methods: {}",
+        if hwaccels.is_empty() {
+            "none".to_string()
+        } else {
+            hwaccels.join(", ")
+        }
+    );
+    info!("`--hwdecode cuda` and `--hwdecode qsv` decode with the cuda and qsv methods");
+}
+
+/// Methods listed by `ffmpeg -hwaccels`
+pub fn hwaccels() -> Vec<String> {
+    match Command::new("ffmpeg")
+        .args(["-hide_banner", "-hwaccels"])
+        .output()
+    {
+        Ok(output) => parse_hwaccels(&String::from_utf8_lossy(&output.stdout)),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// One method per line after the `Hardware acceleration methods:` heading
+fn parse_hwaccels(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.ends_with(':'))
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hwaccel_list() {
+        assert_eq!(
+            parse_hwaccels("Hardware acceleration methods:\ncuda\nvaapi\n\n"),
+            ["cuda", "vaapi"]
+        );
+        assert!(parse_hwaccels("Hardware acceleration methods:\n\n").is_empty());
+    }
+}
@@ -0,0 +1,55 @@
+use std::{
+    io::IsTerminal,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use owo_colors::OwoColorize;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enables colors unless stdout isn't a terminal, `NO_COLOR` is set or `--no-color` was given
+pub fn init(no_color: bool) {
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let enabled = !no_color && !no_color_env && std::io::stdout().is_terminal();
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Savings
+pub fn good(text: &str) -> String {
+    if enabled() {
+        text.green().to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// Growth and failures
+pub fn bad(text: &str) -> String {
+    if enabled() {
+        text.red().to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// Skipped files and warnings
+pub fn skip(text: &str) -> String {
+    if enabled() {
+        text.yellow().to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// `text` in green if `delta` is a saving (negative) and in red otherwise
+pub fn by_delta(text: &str, delta: i64) -> String {
+    if delta < 0 {
+        good(text)
+    } else {
+        bad(text)
+    }
+}
@@ -0,0 +1,91 @@
+use std::path::Path;
+
+use crate::{
+    compress_to, compressed_path, display_duration, filesize, probe, sample, sample::Metric, Log,
+};
+
+/// Compresses `path` next to the original without replacing it and prints how the
+/// compressed file compares, so settings can be tuned on a single video.
+pub fn compare(path: &Path, options: &crate::Options) {
+    if !path.is_file() {
+        info!(
+            "`--compare` expects a file, got `{}`",
+            path.to_string_lossy()
+        );
+        std::process::exit(1);
+    }
+
+    let prev_size = match path.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            info!(
+                "Failed to read metadata of `{}`: {e}",
+                path.to_string_lossy()
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let mut log = Log::in_memory();
+    let Ok(encoded) = compress_to(path, &compressed_path(path), None, &mut log, options) else {
+        for reason in log
+            .skipped_files
+            .values()
+            .chain(log.encrypted_files.values())
+            .chain(log.failed_files.values())
+        {
+            info!("Failed to compress `{}`: {reason}", path.to_string_lossy());
+        }
+        std::process::exit(1);
+    };
+
+    let dest = &encoded.dest;
+    info!(" ==== ==== ==== ");
+    info!(
+        "Size: {} -> {} ({})",
+        filesize::display(prev_size),
+        filesize::display(encoded.size_post),
+        display_change(prev_size as f64, encoded.size_post as f64)
+    );
+    match (probe::video_bitrate(path), probe::video_bitrate(dest)) {
+        (Some(prev), Some(post)) => info!(
+            "Video bitrate: {prev}kbps -> {post}kbps ({})",
+            display_change(prev as f64, post as f64)
+        ),
+        _ => info!("Video bitrate: unknown"),
+    }
+    match encoded.settings.duration {
+        Some(duration) if encoded.encode_time > 0.0 => info!(
+            "Encode time: {} ({:.2}x realtime)",
+            display_duration(encoded.encode_time),
+            duration / encoded.encode_time
+        ),
+        _ => info!("Encode time: {}", display_duration(encoded.encode_time)),
+    }
+
+    if options.compare_ssim {
+        if encoded.settings.trim.is_some() {
+            info!("SSIM: skipped, the compressed file is trimmed");
+        } else {
+            match sample::measure(Metric::Ssim, dest, path) {
+                Ok(score) => info!("SSIM: {score:.4}"),
+                Err(e) => info!("SSIM: {e}"),
+            }
+        }
+    }
+
+    if options.compare_cleanup {
+        if let Err(e) = std::fs::remove_file(dest) {
+            info!("Failed to remove `{}`: {e}", dest.to_string_lossy());
+        }
+    } else {
+        info!("Compressed file: `{}`", dest.to_string_lossy());
+    }
+}
+
+fn display_change(prev: f64, post: f64) -> String {
+    if prev == 0.0 {
+        return "n/a".to_string();
+    }
+    format!("{:+.1}%", (post - prev) / prev * 100.0)
+}
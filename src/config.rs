@@ -0,0 +1,257 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use serde::Deserialize;
+
+use crate::{email::EmailConfig, sidecar::Sidecar};
+
+pub const DEFAULT_CRF: u8 = 25;
+
+/// Overrides of the config for a directory and everything below it
+pub const DIR_CONFIG_FILE: &str = ".compressor.toml";
+
+/// Settings read from the file passed with `--config`.
+///
+/// ```toml
+/// crf = 25
+///
+/// [crf_by_resolution]
+/// ">=2160p" = 27
+/// ">=1080p" = 25
+/// else = 23
+///
+/// [extensions]
+/// mov = { crf = 23, preset = "slow" }
+/// avi = { codec = "copy" }
+/// webm = { skip = true }
+///
+/// [email]
+/// host = "smtp.example.com"
+/// ```
+#[derive(Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub crf: Option<u8>,
+    crf_by_resolution: HashMap<String, u8>,
+    /// Overrides for the videos with an extension, written like a sidecar. Videos with these
+    /// extensions are compressed even if they aren't mp4 or mov files.
+    extensions: HashMap<String, Sidecar>,
+    /// SMTP server of `--email`
+    pub email: Option<EmailConfig>,
+
+    /// `(min_height, crf)` pairs parsed from `crf_by_resolution`, sorted from highest to lowest tier
+    #[serde(skip)]
+    crf_tiers: Vec<(u32, u8)>,
+    #[serde(skip)]
+    crf_else: Option<u8>,
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config `{path}`: {e}"))?;
+        Config::parse(&content, path)
+    }
+
+    /// Reads the `.compressor.toml` of `dir`, `Ok(None)` if there is none
+    pub fn load_dir(dir: &Path) -> Result<Option<Self>, String> {
+        let path = dir.join(DIR_CONFIG_FILE);
+        let path_str = path.to_string_lossy();
+        match std::fs::read_to_string(&path) {
+            Ok(content) => Config::parse(&content, &path_str).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read config `{path_str}`: {e}")),
+        }
+    }
+
+    fn parse(content: &str, path: &str) -> Result<Self, String> {
+        let mut config = toml::from_str::<Config>(content)
+            .map_err(|e| format!("Failed to parse config `{path}`: {e}"))?;
+
+        for (tier, crf) in &config.crf_by_resolution {
+            if tier == "else" {
+                config.crf_else = Some(*crf);
+                continue;
+            }
+
+            let min_height = tier
+                .trim_start_matches(">=")
+                .strip_suffix('p')
+                .and_then(|height| height.parse::<u32>().ok())
+                .ok_or(format!(
+                    "Invalid resolution tier `{tier}` in config `{path}`, expected e.g. `>=1080p` or `else`"
+                ))?;
+            config.crf_tiers.push((min_height, *crf));
+        }
+        if let Some(crf) = config
+            .crf
+            .into_iter()
+            .chain(config.crf_by_resolution.values().copied())
+            .find(|crf| *crf > 51)
+        {
+            return Err(format!(
+                "CRF must be between 0 and 51, got {crf} in config `{path}`"
+            ));
+        }
+
+        config
+            .crf_tiers
+            .sort_by_key(|(min_height, _)| std::cmp::Reverse(*min_height));
+
+        // `.MOV` and `mov` name the same extension as `mov`
+        config.extensions = std::mem::take(&mut config.extensions)
+            .into_iter()
+            .map(|(extension, rule)| {
+                let extension = extension.trim_start_matches('.').to_lowercase();
+                rule.validate(&format!("the rule for `.{extension}` in config `{path}`"))?;
+                Ok((extension, rule))
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(config)
+    }
+
+    /// CRF used when neither `--crf` nor a resolution tier applies.
+    pub fn default_crf(&self) -> u8 {
+        self.crf.unwrap_or(DEFAULT_CRF)
+    }
+
+    /// Picks the CRF of the highest resolution tier the video falls into. The tier is
+    /// compared against the shorter side so portrait videos land in the same tier as
+    /// their landscape counterparts. Returns `None` if no tier (including `else`) matches.
+    pub fn crf_for_resolution(&self, width: u32, height: u32) -> Option<(u8, String)> {
+        let size = width.min(height);
+        for (min_height, crf) in &self.crf_tiers {
+            if size >= *min_height {
+                return Some((*crf, format!(">={min_height}p")));
+            }
+        }
+
+        self.crf_else.map(|crf| (crf, "else".to_string()))
+    }
+
+    pub fn has_crf_tiers(&self) -> bool {
+        !self.crf_by_resolution.is_empty()
+    }
+
+    /// Extension of `path` and the rule configured for it
+    pub fn extension_rule(&self, path: &Path) -> Option<(String, &Sidecar)> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        let rule = self.extensions.get(&extension)?;
+        Some((extension, rule))
+    }
+
+    /// The settings of `self` where it has them and those of `parent` otherwise. The resolution
+    /// tiers are taken as a whole, a `[crf_by_resolution]` table replaces that of the parent.
+    /// Extension rules replace those of the parent for the same extension.
+    pub fn over(&self, parent: &Config) -> Config {
+        let tiers = if self.has_crf_tiers() { self } else { parent };
+        let mut extensions = parent.extensions.clone();
+        extensions.extend(self.extensions.clone());
+        Config {
+            crf: self.crf.or(parent.crf),
+            crf_by_resolution: tiers.crf_by_resolution.clone(),
+            extensions,
+            email: self.email.clone().or_else(|| parent.email.clone()),
+            crf_tiers: tiers.crf_tiers.clone(),
+            crf_else: tiers.crf_else,
+        }
+    }
+}
+
+/// Effective configs of the directories of the run that have a `.compressor.toml` or one above
+/// them, each merged with those of its parents and `--config`. Filled while scanning.
+#[derive(Default)]
+pub struct DirConfigs(RefCell<HashMap<PathBuf, Rc<Config>>>);
+
+impl DirConfigs {
+    pub fn insert(&self, dir: PathBuf, config: Rc<Config>) {
+        self.0.borrow_mut().insert(dir, config);
+    }
+
+    /// Config of the nearest directory above `path` with a `.compressor.toml`, `None` if the
+    /// config of the run applies
+    pub fn get(&self, path: &Path) -> Option<Rc<Config>> {
+        let configs = self.0.borrow();
+        path.ancestors()
+            .skip(1)
+            .find_map(|dir| configs.get(dir).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_directory_config_wins() {
+        let global =
+            Config::parse("crf = 25\n[crf_by_resolution]\n\">=1080p\" = 24", "global").unwrap();
+        let family = Config::parse("crf = 20", "family").unwrap().over(&global);
+        assert_eq!(family.default_crf(), 20);
+        assert_eq!(
+            family.crf_for_resolution(1920, 1080),
+            Some((24, ">=1080p".to_string()))
+        );
+
+        let screencasts = Config::parse("[crf_by_resolution]\nelse = 30", "screencasts")
+            .unwrap()
+            .over(&family);
+        assert_eq!(screencasts.default_crf(), 20);
+        assert_eq!(
+            screencasts.crf_for_resolution(1920, 1080),
+            Some((30, "else".to_string()))
+        );
+
+        let configs = DirConfigs::default();
+        configs.insert(PathBuf::from("videos/family"), Rc::new(family));
+        configs.insert(
+            PathBuf::from("videos/family/screencasts"),
+            Rc::new(screencasts),
+        );
+        let crf = |path: &str| {
+            configs
+                .get(Path::new(path))
+                .map(|config| config.default_crf())
+        };
+        assert_eq!(crf("videos/clip.mp4"), None);
+        assert_eq!(crf("videos/family/2019/clip.mp4"), Some(20));
+        assert_eq!(crf("videos/family/screencasts/clip.mp4"), Some(20));
+        let screencast = configs.get(Path::new("videos/family/screencasts/clip.mp4"));
+        assert_eq!(
+            screencast.unwrap().crf_for_resolution(1280, 720).unwrap().0,
+            30
+        );
+    }
+
+    #[test]
+    fn extension_rules_are_looked_up_case_insensitively() {
+        let global = Config::parse(
+            "[extensions]\n\".MOV\" = { crf = 23 }\nwebm = { skip = true }",
+            "global",
+        )
+        .unwrap();
+        let (extension, rule) = global.extension_rule(Path::new("Camera/A001.mov")).unwrap();
+        assert_eq!((extension.as_str(), rule.crf), ("mov", Some(23)));
+        assert!(global.extension_rule(Path::new("clip.mp4")).is_none());
+
+        let archive = Config::parse("[extensions]\nmov = { codec = \"copy\" }", "archive")
+            .unwrap()
+            .over(&global);
+        let (_, rule) = archive.extension_rule(Path::new("A001.MOV")).unwrap();
+        assert_eq!(rule.crf, None);
+        assert!(
+            archive
+                .extension_rule(Path::new("call.webm"))
+                .unwrap()
+                .1
+                .skip
+        );
+
+        assert!(Config::parse("[extensions]\navi = { crf = 60 }", "bad").is_err());
+    }
+}
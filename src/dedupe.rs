@@ -0,0 +1,267 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    atime, compressed_path, fingerprint,
+    options::{Dedupe, Options},
+    replace_or_trash, resolve_collision, unix_time, Candidate, Log, SkipReason,
+};
+
+/// Content of a video before it was compressed, recorded in its log entry with `--dedupe`
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// Size and hash of the first and last MiB, compared first
+    pub quick: String,
+    /// SHA-256 of the whole file, tells files apart that only differ in the middle. Computed
+    /// for every original before it's compressed, and for a later video once its quick
+    /// fingerprint matches a logged one.
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+impl Fingerprint {
+    /// Only reads both ends of the file, most videos have no duplicate
+    pub fn of(path: &Path) -> Result<Self, std::io::Error> {
+        let _accessed = atime::Preserved::new(path);
+        Ok(Fingerprint {
+            quick: fingerprint::quick(path)?,
+            sha256: None,
+        })
+    }
+
+    /// SHA-256 of the file, reading it the first time it's needed
+    pub fn sha256(&mut self, path: &Path) -> Result<&str, std::io::Error> {
+        if self.sha256.is_none() {
+            let _accessed = atime::Preserved::new(path);
+            self.sha256 = Some(fingerprint::full(path)?);
+        }
+        Ok(self.sha256.as_deref().unwrap_or_default())
+    }
+
+    /// Whether the file this was computed for has the content of the original `logged` was
+    /// recorded for, by the hashes of both whole files. An original logged without its hash
+    /// matches nothing, a video of the same size and ends may still differ in the middle and
+    /// `--dedupe copy` would replace it.
+    pub fn matches(&mut self, path: &Path, logged: &Fingerprint) -> Result<bool, std::io::Error> {
+        let Some(sha256) = logged
+            .sha256
+            .as_ref()
+            .filter(|_| self.quick == logged.quick)
+        else {
+            return Ok(false);
+        };
+        Ok(self.sha256(path)? == sha256)
+    }
+}
+
+/// Handles a candidate whose content matches the original of the compressed file `original`.
+/// Returns `false` if it has to be compressed after all because the compressed file changed.
+pub fn deduplicate(
+    candidate: &Candidate,
+    original: &str,
+    fingerprint: Fingerprint,
+    log: &mut Log,
+    options: &Options,
+) -> bool {
+    let path = candidate.path.to_string_lossy().to_string();
+    let Some(Dedupe::Copy) = options.dedupe else {
+        log.mark_skipped(path, SkipReason::Duplicate(original.to_string()));
+        return true;
+    };
+
+    // the compressed file may have been edited or replaced since it was logged
+    let unchanged = log.shrunk_files.get(original).is_some_and(|file_log| {
+        Path::new(original).metadata().is_ok_and(|metadata| {
+            metadata.len() == file_log.size_post
+                && metadata
+                    .modified()
+                    .is_ok_and(|modified| unix_time(modified) <= file_log.modified)
+        })
+    });
+    if !unchanged {
+        info!("`{original}` changed since it was compressed, compressing `{path}` itself");
+        return false;
+    }
+
+    let dest = match resolve_collision(&compressed_path(&candidate.path), options) {
+        Ok(dest) => dest,
+        Err(reason) => {
+            log.mark_skipped(path, reason);
+            return true;
+        }
+    };
+    info!("Copying the compressed `{original}` over its duplicate `{path}`");
+    let result = std::fs::copy(original, &dest)
+        .and_then(|_| replace_or_trash(&dest, &candidate.path, options));
+    let trashed = match result {
+        Ok(trashed) => trashed,
+        Err(e) => {
+            let _ = std::fs::remove_file(&dest);
+            log.mark_skipped(path, SkipReason::Override(e));
+            return true;
+        }
+    };
+
+    log.mark_deduplicated(path, candidate.size, original, fingerprint, trashed);
+    log.save();
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{temp_dir::TempDir, FileLog};
+
+    /// Log with the compressed `a.mp4` whose original was `original`
+    fn log_with_original(dir: &Path, original: &[u8], sha256: bool) -> (Log, String) {
+        let compressed = dir.join("a.mp4");
+        std::fs::write(&compressed, "compressed").unwrap();
+        std::fs::write(dir.join("original"), original).unwrap();
+        let mut fingerprint = Fingerprint::of(&dir.join("original")).unwrap();
+        if sha256 {
+            fingerprint.sha256(&dir.join("original")).unwrap();
+        }
+
+        let mut log = Log::in_memory();
+        let path = compressed.to_string_lossy().to_string();
+        let file_log = FileLog {
+            size_prev: original.len() as u64,
+            size_post: 10,
+            modified: unix_time(std::time::SystemTime::now()) + 60,
+            fingerprint: Some(fingerprint),
+            ..FileLog::default()
+        };
+        log.shrunk_files.insert(path.clone(), file_log);
+        (log, path)
+    }
+
+    fn candidate(dir: &Path, name: &str, content: &[u8]) -> Candidate {
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        Candidate {
+            path,
+            size: content.len() as u64,
+            modified: 0,
+        }
+    }
+
+    #[test]
+    fn only_quick_matches_are_hashed_in_full() {
+        let dir = TempDir::new("dedupe_hash");
+        // three MiB, so the middle MiB isn't part of the quick fingerprint
+        let mut original = vec![1; 3 * 1024 * 1024];
+        let (log, path) = log_with_original(&dir, &original, true);
+
+        let other = candidate(&dir, "other.mp4", b"another video");
+        let mut fingerprint = Fingerprint::of(&other.path).unwrap();
+        assert_eq!(log.duplicate_of(&mut fingerprint, &other.path), None);
+        assert_eq!(fingerprint.sha256, None);
+
+        let copy = candidate(&dir, "copy.mp4", &original);
+        let mut fingerprint = Fingerprint::of(&copy.path).unwrap();
+        assert_eq!(fingerprint.sha256, None);
+        assert_eq!(log.duplicate_of(&mut fingerprint, &copy.path), Some(path));
+        assert!(fingerprint.sha256.is_some());
+
+        original[3 * 1024 * 1024 / 2] = 2;
+        let edited = candidate(&dir, "edited.mp4", &original);
+        let mut fingerprint = Fingerprint::of(&edited.path).unwrap();
+        assert_eq!(log.duplicate_of(&mut fingerprint, &edited.path), None);
+    }
+
+    #[test]
+    fn originals_logged_without_a_full_hash_match_nothing() {
+        let dir = TempDir::new("dedupe_quick");
+        let (mut log, _) = log_with_original(&dir, b"original", false);
+
+        let copy = candidate(&dir, "copy.mp4", b"original");
+        let mut fingerprint = Fingerprint::of(&copy.path).unwrap();
+        assert_eq!(log.duplicate_of(&mut fingerprint, &copy.path), None);
+
+        // files compressed after the index was built are found too
+        let later = candidate(&dir, "later.mp4", b"later");
+        let later_path = later.path.to_string_lossy().to_string();
+        log.shrunk_files
+            .insert(later_path.clone(), FileLog::default());
+        let mut recorded = Fingerprint::of(&later.path).unwrap();
+        recorded.sha256(&later.path).unwrap();
+        log.record_fingerprint(&later_path, recorded);
+        let mut fingerprint = Fingerprint::of(&later.path).unwrap();
+        assert_eq!(
+            log.duplicate_of(&mut fingerprint, &later.path),
+            Some(later_path)
+        );
+    }
+
+    #[test]
+    fn copy_replaces_the_duplicate_and_counts_it_as_deduplicated() {
+        let dir = TempDir::new("dedupe_copy");
+        let (mut log, original) = log_with_original(&dir, b"original", true);
+        let options = Options {
+            dedupe: Some(Dedupe::Copy),
+            ..Options::default()
+        };
+
+        let copy = candidate(&dir, "copy.mp4", b"original");
+        let fingerprint = Fingerprint::of(&copy.path).unwrap();
+        assert!(deduplicate(
+            &copy,
+            &original,
+            fingerprint,
+            &mut log,
+            &options
+        ));
+        assert_eq!(std::fs::read_to_string(&copy.path).unwrap(), "compressed");
+        assert!(!compressed_path(&copy.path).exists());
+
+        // the totals of the overview are summed from these
+        let path = copy.path.to_string_lossy().to_string();
+        let file_log = &log.added_files[&path];
+        assert_eq!(file_log.deduplicated_from.as_deref(), Some(&*original));
+        assert_eq!((file_log.size_prev, file_log.size_post), (8, 10));
+        assert!(log.shrunk_files.get(&path).is_some());
+
+        // a compressed file that changed since is no source for copies
+        std::fs::write(&original, "edited since").unwrap();
+        let other = candidate(&dir, "other.mp4", b"original");
+        let fingerprint = Fingerprint::of(&other.path).unwrap();
+        assert!(!deduplicate(
+            &other,
+            &original,
+            fingerprint,
+            &mut log,
+            &options
+        ));
+        assert_eq!(std::fs::read_to_string(&other.path).unwrap(), "original");
+        assert!(!log.added_files.contains_key(&*other.path.to_string_lossy()));
+    }
+
+    #[test]
+    fn skip_only_logs_the_duplicate() {
+        let dir = TempDir::new("dedupe_skip");
+        let (mut log, original) = log_with_original(&dir, b"original", true);
+        let options = Options {
+            dedupe: Some(Dedupe::Skip),
+            ..Options::default()
+        };
+
+        let copy = candidate(&dir, "copy.mp4", b"original");
+        let fingerprint = Fingerprint::of(&copy.path).unwrap();
+        assert!(deduplicate(
+            &copy,
+            &original,
+            fingerprint,
+            &mut log,
+            &options
+        ));
+        assert_eq!(std::fs::read_to_string(&copy.path).unwrap(), "original");
+
+        let path = copy.path.to_string_lossy().to_string();
+        assert!(log.added_files.is_empty());
+        assert_eq!(
+            log.skipped_files[&path],
+            SkipReason::Duplicate(original).to_string()
+        );
+    }
+}
@@ -0,0 +1,30 @@
+use std::{path::Path, process::Command};
+
+/// Available space in bytes on the filesystem containing `path`, queried with `df`.
+/// Returns `None` if `df` isn't available (e.g. on windows) or its output can't be parsed.
+pub fn available_space(path: &Path) -> Option<u64> {
+    let output = Command::new("df").arg("-Pk").arg(path).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    // Filesystem 1024-blocks Used Available Capacity Mounted on
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let available = stdout.lines().nth(1)?.split_whitespace().nth(3)?;
+    available.parse::<u64>().ok().map(|kb| kb * 1024)
+}
+
+/// Available space on the filesystem `dest` is written to if it is below `floor`. `None` if
+/// there is enough, the check is disabled with a floor of 0 or the space can't be queried.
+pub fn below_floor(dest: &Path, floor: u64) -> Option<u64> {
+    if floor == 0 {
+        return None;
+    }
+    // the file itself may not exist yet
+    let dir = match dest.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    available_space(dir).filter(|&available| available < floor)
+}
@@ -0,0 +1,205 @@
+use std::{fs::File, io::BufReader, path::Path, process::Command};
+
+use crate::{
+    check_writable, codecs, color,
+    config::Config,
+    options::{AudioCodec, AudioOnlyMode, Deinterlace, Options},
+    sample::Metric,
+    version, Log, DOWNMIX_FILTER,
+};
+
+/// Checks everything a run with these options over `path` needs and prints a line per check.
+/// Returns whether all passed.
+pub fn doctor(path: &Path, options: &Options) -> bool {
+    let mut ok = true;
+    let mut report = |result: Result<String, String>| match result {
+        Ok(text) => info!("{} {text}", color::good("ok  ")),
+        Err(text) => {
+            info!("{} {text}", color::bad("FAIL"));
+            ok = false;
+        }
+    };
+
+    let ffmpeg = version::ffmpeg();
+    report(match &ffmpeg {
+        Some(version) if !options.allow_old_ffmpeg => {
+            version::check_minimum(version, options.min_ffmpeg_version)
+                .map(|_| format!("ffmpeg {version}"))
+        }
+        Some(version) => Ok(format!("ffmpeg {version}")),
+        None => Err("ffmpeg can't be run, is it installed and in PATH?".to_string()),
+    });
+    report(
+        version::ffprobe()
+            .map(|version| format!("ffprobe {version}"))
+            .ok_or("ffprobe can't be run, is it installed and in PATH?".to_string()),
+    );
+
+    // without ffmpeg there is nothing to list the encoders and filters of
+    if ffmpeg.is_some() {
+        let encoders = list("-encoders");
+        report(options.encoders.check(&encoders));
+        if let Some(result) = options.hwdecode.check(&codecs::hwaccels()) {
+            report(result);
+        }
+        for encoder in required_encoders(options) {
+            report(check_listed(&encoders, "encoder", encoder));
+        }
+        let filters = list("-filters");
+        for filter in required_filters(options) {
+            report(check_listed(&filters, "filter", filter));
+        }
+    }
+
+    if let Some(config) = &options.config_path {
+        report(
+            Config::load(config)
+                .map(|_| format!("config `{config}` is valid"))
+                .map_err(|e| e.to_string()),
+        );
+    }
+
+    report(check_log(path));
+    ok
+}
+
+/// Audio encoders used by a run with these options, the video encoder is checked with its
+/// fallbacks
+fn required_encoders(options: &Options) -> Vec<&'static str> {
+    let mut encoders = Vec::new();
+    // audio only files, downmixing and loudness normalization need an encoder even when copying
+    let audio = match options.audio {
+        AudioCodec::Copy
+            if options.audio_only_mode == AudioOnlyMode::Reencode
+                || options.downmix_stereo
+                || options.loudnorm.is_some() =>
+        {
+            AudioCodec::Aac
+        }
+        audio => audio,
+    };
+    if !options.no_audio {
+        encoders.extend(audio.encoder());
+    }
+    encoders
+}
+
+/// Filters used by a run with these options
+fn required_filters(options: &Options) -> Vec<&'static str> {
+    let mut filters = Vec::new();
+    if options.deinterlace != Deinterlace::Off {
+        filters.push("bwdif");
+    }
+    if options.max_fps.is_some() {
+        filters.push("fps");
+    }
+    if options.downmix_stereo {
+        filters.extend(DOWNMIX_FILTER.split('=').next());
+    }
+    if options.loudnorm.is_some() {
+        filters.push("loudnorm");
+    }
+    let metrics = options
+        .auto_crf
+        .map(|(metric, _)| metric)
+        .into_iter()
+        .chain(options.bench_metric);
+    for metric in metrics {
+        filters.push(match metric {
+            Metric::Vmaf => "libvmaf",
+            Metric::Ssim => "ssim",
+        });
+    }
+    if options.compare_ssim {
+        filters.push("ssim");
+    }
+    filters.dedup();
+    filters
+}
+
+/// Names listed by `ffmpeg -encoders` or `ffmpeg -filters`
+pub fn list(flag: &str) -> Vec<String> {
+    match Command::new("ffmpeg").args(["-hide_banner", flag]).output() {
+        Ok(output) => parse_list(&String::from_utf8_lossy(&output.stdout)),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// The name is the second column after the capability flags, e.g.
+/// ` V....D libx265              libx265 H.265 / HEVC (codec hevc)`. The legend lines like
+/// ` V..... = Video` never have a valid name there.
+fn parse_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .filter(|name| *name != "=")
+        .map(String::from)
+        .collect()
+}
+
+fn check_listed(list: &[String], kind: &str, name: &str) -> Result<String, String> {
+    if list.iter().any(|listed| listed == name) {
+        Ok(format!("{kind} {name} is available"))
+    } else {
+        Err(format!("{kind} {name} is missing from this ffmpeg build"))
+    }
+}
+
+/// The log has to be writable, and an existing one readable or a run would start over
+fn check_log(dir: &Path) -> Result<String, String> {
+    if !dir.is_dir() {
+        return Err(format!("`{}` isn't a directory", dir.to_string_lossy()));
+    }
+    let log_path = dir.join(crate::LOG_FILE);
+    check_writable(&log_path).map_err(|e| {
+        format!(
+            "the log `{}` can't be written: {e}",
+            log_path.to_string_lossy()
+        )
+    })?;
+
+    match File::open(&log_path) {
+        Ok(file) => match serde_json::from_reader::<_, Log>(BufReader::new(file)) {
+            Ok(log) if log.shards.is_some() => Ok(format!(
+                "log `{}` is writable and keeps the compressed files in `{}`",
+                log_path.to_string_lossy(),
+                dir.join(crate::shards::SHARD_DIR).to_string_lossy()
+            )),
+            Ok(log) => Ok(format!(
+                "log `{}` is writable and lists {} compressed files",
+                log_path.to_string_lossy(),
+                log.shrunk_files.len()
+            )),
+            Err(e) => Err(format!(
+                "the log `{}` can't be parsed and would be replaced by the next run: {e}",
+                log_path.to_string_lossy()
+            )),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(format!(
+            "log `{}` can be created",
+            log_path.to_string_lossy()
+        )),
+        Err(e) => Err(format!(
+            "the log `{}` can't be read: {e}",
+            log_path.to_string_lossy()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_encoder_and_filter_lists() {
+        let encoders = parse_list(
+            "Encoders:\n V..... = Video\n A..... = Audio\n ------\n V....D libx265              libx265 H.265 / HEVC (codec hevc)\n A....D aac                  AAC (Advanced Audio Coding)\n",
+        );
+        assert_eq!(encoders, ["libx265", "aac"]);
+
+        let filters = parse_list(
+            "Filters:\n  T.. = Timeline support\n  ..C = Command support\n TS. bwdif             V->V       Deinterlace the input image.\n",
+        );
+        assert_eq!(filters, ["bwdif"]);
+    }
+}
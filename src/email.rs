@@ -0,0 +1,366 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::SystemTime,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    filesize,
+    options::{EmailOn, Options},
+    times, Log,
+};
+
+/// SMTP server the report of `--email` is sent through, the `[email]` table of the config.
+///
+/// ```toml
+/// [email]
+/// host = "smtp.example.com"
+/// port = 587
+/// tls = "starttls"
+/// username = "nas@example.com"
+/// password_env = "SMTP_PASSWORD"
+/// from = "nas@example.com"
+/// ```
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailConfig {
+    host: String,
+    /// 465 with `tls`, 587 with `starttls` and 25 with `none` by default
+    port: Option<u16>,
+    #[serde(default)]
+    tls: Encryption,
+    username: Option<String>,
+    /// Environment variable holding the password, so it isn't written into the config
+    password_env: Option<String>,
+    /// Sender, the username by default
+    from: Option<String>,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Encryption {
+    /// Encrypted from the start (smtps)
+    Tls,
+    /// Upgraded to an encrypted connection, which is required
+    #[default]
+    Starttls,
+    /// Plain text, e.g. for a relay on the same machine
+    None,
+}
+
+impl EmailConfig {
+    /// Checks what would otherwise only fail once the run is over
+    pub fn validate(&self, to: &str) -> Result<(), String> {
+        for address in [Some(to), self.from.as_deref()].into_iter().flatten() {
+            if !address.contains('@') || address.contains(|c: char| c.is_whitespace()) {
+                return Err(format!("Invalid email address `{address}`"));
+            }
+        }
+        if self.sender().is_none() {
+            return Err(
+                "`[email]` of the config needs a `from` address or a `username`".to_string(),
+            );
+        }
+        if let Some(password_env) = &self.password_env {
+            if self.username.is_none() {
+                return Err("`password_env` of `[email]` needs a `username`".to_string());
+            }
+            if std::env::var_os(password_env).is_none() {
+                return Err(format!(
+                    "The environment variable `{password_env}` with the SMTP password isn't set"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn sender(&self) -> Option<&str> {
+        self.from.as_deref().or(self.username.as_deref())
+    }
+
+    fn url(&self) -> String {
+        let (scheme, port) = match self.tls {
+            Encryption::Tls => ("smtps", 465),
+            Encryption::Starttls => ("smtp", 587),
+            Encryption::None => ("smtp", 25),
+        };
+        format!("{scheme}://{}:{}", self.host, self.port.unwrap_or(port))
+    }
+
+    /// Settings passed to curl on stdin rather than as arguments, where other users could
+    /// read the password
+    fn curl_config(&self, to: &str) -> String {
+        let mut lines = vec![
+            format!("url = {}", quote(&self.url())),
+            format!("mail-from = {}", quote(self.sender().unwrap_or_default())),
+            format!("mail-rcpt = {}", quote(to)),
+        ];
+        if self.tls == Encryption::Starttls {
+            lines.push("ssl-reqd".to_string());
+        }
+        // without a password the server is expected to accept the mail unauthenticated
+        if let (Some(username), Some(password_env)) = (&self.username, &self.password_env) {
+            let password = std::env::var_os(password_env).unwrap_or_default();
+            let user = format!("{username}:{}", password.to_string_lossy());
+            lines.push(format!("user = {}", quote(&user)));
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+/// Quoted value of a curl config file
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Overview of the run for the report, taken before `Log::print_status` clears the lists it
+/// is made of
+pub struct Summary {
+    save_file: String,
+    processed: usize,
+    size_prev: u64,
+    size_post: u64,
+    skipped: usize,
+    /// `` `path`: reason `` of every failed file, sorted
+    failures: Vec<String>,
+}
+
+impl Summary {
+    pub fn of(log: &Log) -> Self {
+        let mut failures = log
+            .failed_files
+            .iter()
+            .map(|(path, reason)| format!("`{path}`: {reason}"))
+            .collect::<Vec<_>>();
+        failures.sort();
+        Summary {
+            save_file: log.save_file.clone(),
+            processed: log.added_files.len(),
+            size_prev: log.added_files.values().map(|file| file.size_prev).sum(),
+            size_post: log.added_files.values().map(|file| file.size_post).sum(),
+            skipped: log.skipped_files.len() + log.encrypted_files.len(),
+            failures,
+        }
+    }
+
+    /// Subject and body of the report. `failed` also covers a log that couldn't be saved.
+    fn message(&self, failed: bool, cancelled: bool) -> (String, String) {
+        let state = if cancelled {
+            "was cancelled"
+        } else if failed {
+            "completed with failures"
+        } else {
+            "completed"
+        };
+        let subject = format!(
+            "video_compressor run {state}: {} compressed, {} failed",
+            self.processed,
+            self.failures.len()
+        );
+
+        let videos = |count: usize| if count == 1 { "video" } else { "videos" };
+        let dir = Path::new(&self.save_file)
+            .parent()
+            .unwrap_or(Path::new(&self.save_file))
+            .to_string_lossy();
+        let mut body = vec![format!("The run over `{dir}` {state}."), String::new()];
+        if self.processed == 0 {
+            body.push("No video was compressed".to_string());
+        } else {
+            body.push(format!(
+                "Compressed {} {}, {} -> {} ({})",
+                self.processed,
+                videos(self.processed),
+                filesize::display(self.size_prev),
+                filesize::display(self.size_post),
+                filesize::display_delta(self.size_post as i64 - self.size_prev as i64)
+            ));
+        }
+        if self.skipped != 0 {
+            body.push(format!("Skipped {} {}", self.skipped, videos(self.skipped)));
+        }
+        if !self.failures.is_empty() {
+            body.push(format!(
+                "Failed {} {}:",
+                self.failures.len(),
+                videos(self.failures.len())
+            ));
+            body.extend(self.failures.iter().map(|failure| format!("  {failure}")));
+        } else if failed {
+            body.push(format!(
+                "The log `{}` couldn't be saved, the next run starts over",
+                self.save_file
+            ));
+        }
+        (subject, body.join("\n") + "\n")
+    }
+}
+
+/// Mails the summary to the address of `--email` if `--email-on` asks for it. Problems with
+/// the mail are only warned about, the exit code stays that of the run.
+pub fn report(summary: &Summary, failed: bool, options: &Options) {
+    let (Some(to), Some(config)) = (&options.email, &options.config.email) else {
+        return;
+    };
+    if options.email_on == EmailOn::Failure && !failed {
+        return;
+    }
+    let (subject, body) = summary.message(failed, options.cancel.is_cancelled());
+    match send(config, to, &subject, &body) {
+        Ok(()) => info!("Sent the report to {to}"),
+        Err(e) => info!("Warning: failed to send the report to {to}: {e}"),
+    }
+}
+
+/// Sends the mail with curl, which speaks SMTP and TLS
+fn send(config: &EmailConfig, to: &str, subject: &str, body: &str) -> Result<(), String> {
+    let headers = [
+        format!("From: {}", config.sender().unwrap_or_default()),
+        format!("To: {to}"),
+        format!("Subject: {subject}"),
+        format!("Date: {}", times::rfc2822_date(SystemTime::now())),
+        "MIME-Version: 1.0".to_string(),
+        "Content-Type: text/plain; charset=utf-8".to_string(),
+        "Content-Transfer-Encoding: 8bit".to_string(),
+    ];
+    let message = format!(
+        "{}\r\n\r\n{}",
+        headers.join("\r\n"),
+        body.replace('\n', "\r\n")
+    );
+    let (message_file, mut file) =
+        create_message_file().map_err(|e| format!("failed to create the message file: {e}"))?;
+    let written = file.write_all(message.as_bytes());
+    drop(file);
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&message_file);
+        return Err(format!(
+            "failed to write `{}`: {e}",
+            message_file.to_string_lossy()
+        ));
+    }
+
+    let result = (|| {
+        let mut child = Command::new("curl")
+            .args([
+                "--silent",
+                "--show-error",
+                "--max-time",
+                "60",
+                "--config",
+                "-",
+            ])
+            .arg("--upload-file")
+            .arg(&message_file)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to run curl: {e}"))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(config.curl_config(to).as_bytes())
+                .map_err(|e| format!("failed to pass the settings to curl: {e}"))?;
+        }
+        let output = child
+            .wait_with_output()
+            .map_err(|e| format!("failed to run curl: {e}"))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+        }
+    })();
+    let _ = std::fs::remove_file(&message_file);
+    result
+}
+
+/// New file in the temporary directory only the user can read, which another user can't
+/// have created in advance under a name they guessed
+fn create_message_file() -> Result<(PathBuf, File), std::io::Error> {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.subsec_nanos());
+    let mut attempt = 0;
+    loop {
+        let path = std::env::temp_dir().join(format!(
+            "video_compressor_mail_{}_{nanos:x}_{attempt}.txt",
+            std::process::id()
+        ));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempt < 100 => {
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileLog;
+
+    #[test]
+    fn the_report_lists_the_failures_and_curl_gets_the_server() {
+        let mut log = Log::in_memory();
+        for (path, size_prev, size_post) in [("a.mp4", 3000, 1000), ("b.mp4", 2000, 1000)] {
+            let file_log = FileLog {
+                size_prev,
+                size_post,
+                ..FileLog::default()
+            };
+            log.added_files.insert(path.to_string(), file_log);
+        }
+        log.failed_files
+            .insert("c.mp4".to_string(), "Encoding failed: oops".to_string());
+        let (subject, body) = Summary::of(&log).message(true, false);
+        assert_eq!(
+            subject,
+            "video_compressor run completed with failures: 2 compressed, 1 failed"
+        );
+        let body = body.lines().skip(2).collect::<Vec<_>>();
+        assert_eq!(
+            body,
+            [
+                "Compressed 2 videos, 4.88KiB -> 1.95KiB (-2.93KiB)",
+                "Failed 1 video:",
+                "  `c.mp4`: Encoding failed: oops",
+            ]
+        );
+
+        let config = toml::from_str::<EmailConfig>(
+            "host = \"mail.example.com\"\ntls = \"tls\"\nusername = \"nas@example.com\"",
+        )
+        .unwrap();
+        assert!(config.validate("me@example.com").is_ok());
+        assert!(config.validate("me").is_err());
+        assert_eq!(
+            config.curl_config("me@example.com"),
+            "url = \"smtps://mail.example.com:465\"\nmail-from = \"nas@example.com\"\nmail-rcpt = \"me@example.com\"\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_message_file_is_new_and_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (first, _) = create_message_file().unwrap();
+        let (second, _) = create_message_file().unwrap();
+        assert_ne!(first, second);
+        let mode = std::fs::metadata(&first).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        std::fs::remove_file(first).unwrap();
+        std::fs::remove_file(second).unwrap();
+    }
+}
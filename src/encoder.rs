@@ -0,0 +1,442 @@
+use std::{
+    cell::Cell,
+    sync::{Condvar, Mutex},
+};
+
+/// Video encoders tried in this order unless `--encoder-fallback` is given
+pub const DEFAULT_ENCODERS: [&str; 3] = ["libx265", "hevc_videotoolbox", "libx264"];
+
+/// Video encoders of the run in the order of preference. The run starts with the first one
+/// the ffmpeg build offers and moves on to the next if it fails to start.
+pub struct Encoders {
+    list: Vec<String>,
+    current: Cell<usize>,
+}
+
+impl Default for Encoders {
+    fn default() -> Self {
+        Encoders::new(DEFAULT_ENCODERS.map(String::from).to_vec())
+    }
+}
+
+impl Encoders {
+    pub fn new(list: Vec<String>) -> Self {
+        Encoders {
+            list,
+            current: Cell::new(0),
+        }
+    }
+
+    /// Only the preferred encoder is used with `--no-fallback`
+    pub fn without_fallback(&mut self) {
+        self.list.truncate(1);
+    }
+
+    pub fn current(&self) -> String {
+        self.list[self.current.get()].clone()
+    }
+
+    /// Index of the first encoder listed by `ffmpeg -encoders` and a description of the choice
+    fn pick(&self, available: &[String]) -> Result<(usize, String), String> {
+        let preferred = &self.list[0];
+        match self
+            .list
+            .iter()
+            .position(|encoder| available.contains(encoder))
+        {
+            Some(0) => Ok((0, format!("video encoder {preferred} is available"))),
+            Some(index) => Ok((
+                index,
+                format!(
+                    "video encoder {preferred} is missing from this ffmpeg build, falling back to {}",
+                    self.list[index]
+                ),
+            )),
+            None if self.list.len() == 1 => Err(format!(
+                "video encoder {preferred} is missing from this ffmpeg build"
+            )),
+            None => Err(format!(
+                "none of the video encoders {} is in this ffmpeg build",
+                self.list.join(", ")
+            )),
+        }
+    }
+
+    /// Line of `--doctor`
+    pub fn check(&self, available: &[String]) -> Result<String, String> {
+        self.pick(available).map(|(_, text)| text)
+    }
+
+    /// Starts the run with the first encoder of the ffmpeg build, warning if that isn't the
+    /// preferred one
+    pub fn select(&self, available: &[String]) -> Result<(), String> {
+        let (index, text) = self.pick(available)?;
+        if index > 0 {
+            info!("Warning: {text}");
+        }
+        self.current.set(index);
+        Ok(())
+    }
+
+    /// Encoder after `failed` for a single video, while the run stays with `failed`, e.g. when
+    /// the GPU has no free encode session right now
+    pub fn next_after(&self, failed: &str) -> Option<String> {
+        let index = self.list.iter().position(|encoder| encoder == failed)?;
+        self.list.get(index + 1).cloned()
+    }
+
+    /// Moves on after `failed` couldn't be used, returning the next encoder if there is one
+    pub fn fall_back(&self, failed: &str) -> Option<String> {
+        let index = self.current.get();
+        if self.list[index] != failed {
+            // an earlier file already moved on
+            return Some(self.current());
+        }
+        let next = self.list.get(index + 1)?;
+        info!("Warning: video encoder {failed} failed to start, falling back to {next}");
+        self.current.set(index + 1);
+        Some(next.clone())
+    }
+}
+
+/// Watches the output of ffmpeg for the video encoder not starting at all, e.g. because the
+/// build lacks it or there is no hardware for it, rather than something being wrong with the file
+#[derive(Default)]
+pub struct StartupFailure {
+    /// The encoder printed its own error, like hardware encoders do before failing to open
+    encoder_logged: bool,
+    unusable: bool,
+    /// The hardware has no free encode session, consumer NVIDIA GPUs only run a few at once
+    no_session: bool,
+}
+
+impl StartupFailure {
+    pub fn observe(&mut self, line: &str, encoder: &str) {
+        if line.starts_with(&format!("[{encoder} @")) {
+            self.encoder_logged = true;
+        }
+        // ffmpeg 6.1 and later prefix the line with the stream and encoder, e.g. `[vost#0:0/libx265 @`
+        let opening_failed = line.contains("Error while opening encoder")
+            && (self.encoder_logged || line.contains(&format!("/{encoder} @")));
+        if opening_failed || line.contains(&format!("Unknown encoder '{encoder}'")) {
+            self.unusable = true;
+        }
+        // the driver reports the session limit as one of these, depending on its version
+        if self.encoder_logged
+            && line.contains("OpenEncodeSessionEx failed")
+            && (line.contains("out of memory (10)")
+                || line.contains("incompatible client key (21)"))
+        {
+            self.no_session = true;
+        }
+    }
+
+    pub fn no_session(&self) -> bool {
+        self.no_session
+    }
+
+    pub fn unusable(&self) -> bool {
+        self.unusable
+    }
+}
+
+/// Encode sessions of the hardware encoders open at once unless `--gpu-sessions` is given,
+/// consumer NVIDIA GPUs long allowed three
+pub const DEFAULT_GPU_SESSIONS: usize = 3;
+
+/// Suffixes of the encoders running on a GPU or other media engine
+const HARDWARE_ENCODERS: [&str; 5] = ["_nvenc", "_qsv", "_vaapi", "_amf", "_videotoolbox"];
+
+/// Counting semaphore over the encode sessions of `--gpu-sessions`, so hardware encodes queue
+/// for a free session instead of failing to open one. Software encoders don't take any.
+pub struct GpuSessions {
+    limit: usize,
+    open: Mutex<usize>,
+    closed: Condvar,
+}
+
+impl Default for GpuSessions {
+    fn default() -> Self {
+        GpuSessions::new(DEFAULT_GPU_SESSIONS)
+    }
+}
+
+impl GpuSessions {
+    pub fn new(limit: usize) -> Self {
+        GpuSessions {
+            limit,
+            open: Mutex::new(0),
+            closed: Condvar::new(),
+        }
+    }
+
+    /// Session for `encoder`, `Ok(None)` for software encoders. With every session open it
+    /// waits for one to close if `wait` is set, and is `Err` otherwise so the video can move on
+    /// to the next encoder.
+    pub fn open(&self, encoder: &str, wait: bool) -> Result<Option<GpuSession<'_>>, ()> {
+        if !HARDWARE_ENCODERS
+            .iter()
+            .any(|suffix| encoder.ends_with(suffix))
+        {
+            return Ok(None);
+        }
+        let open = self.open.lock().unwrap();
+        if *open >= self.limit && !wait {
+            return Err(());
+        }
+        let mut open = self
+            .closed
+            .wait_while(open, |open| *open >= self.limit)
+            .unwrap();
+        *open += 1;
+        Ok(Some(GpuSession(self)))
+    }
+}
+
+/// Open encode session, closed on drop
+pub struct GpuSession<'a>(&'a GpuSessions);
+
+impl Drop for GpuSession<'_> {
+    fn drop(&mut self) {
+        *self.0.open.lock().unwrap() -= 1;
+        self.0.closed.notify_one();
+    }
+}
+
+/// Hardware decoder of `--hwdecode`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HwAccel {
+    Cuda,
+    Qsv,
+}
+
+impl HwAccel {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "cuda" => Some(HwAccel::Cuda),
+            "qsv" => Some(HwAccel::Qsv),
+            _ => None,
+        }
+    }
+
+    /// Name of the method in `-hwaccel` and `ffmpeg -hwaccels`
+    pub fn name(&self) -> &'static str {
+        match self {
+            HwAccel::Cuda => "cuda",
+            HwAccel::Qsv => "qsv",
+        }
+    }
+
+    /// Encoder taking the decoded frames without copying them to system memory and back
+    pub fn encoder(&self) -> &'static str {
+        match self {
+            HwAccel::Cuda => "hevc_nvenc",
+            HwAccel::Qsv => "hevc_qsv",
+        }
+    }
+}
+
+/// Hardware decoding of the run, which decodes on the CPU again for the rest of the run once
+/// the device turns out to be unavailable
+#[derive(Default)]
+pub struct HwDecoder {
+    method: Option<HwAccel>,
+    disabled: Cell<bool>,
+}
+
+impl HwDecoder {
+    pub fn new(method: HwAccel) -> Self {
+        HwDecoder {
+            method: Some(method),
+            disabled: Cell::new(false),
+        }
+    }
+
+    /// `None` without `--hwdecode` or after falling back to software decoding
+    pub fn current(&self) -> Option<HwAccel> {
+        self.method.filter(|_| !self.disabled.get())
+    }
+
+    /// Line of `--doctor`, `None` without `--hwdecode`
+    pub fn check(&self, available: &[String]) -> Option<Result<String, String>> {
+        let method = self.method?.name();
+        Some(if available.iter().any(|listed| listed == method) {
+            Ok(format!("hardware decoder {method} is available"))
+        } else {
+            Err(format!(
+                "hardware decoder {method} is missing from this ffmpeg build"
+            ))
+        })
+    }
+
+    /// Decodes on the CPU if the ffmpeg build lacks the method
+    pub fn select(&self, available: &[String]) {
+        if let Some(Err(text)) = self.check(available) {
+            info!("Warning: {text}, decoding on the CPU");
+            self.disabled.set(true);
+        }
+    }
+
+    /// Moves on to software decoding after the hardware decoder failed to start
+    pub fn fall_back(&self) {
+        if let Some(method) = self.current() {
+            info!(
+                "Warning: hardware decoder {} failed to start, decoding on the CPU",
+                method.name()
+            );
+            self.disabled.set(true);
+        }
+    }
+}
+
+/// Watches the output of ffmpeg for hardware decoding failing, either because the device can't
+/// be opened at all or because the frames of this video can't be decoded or kept on the GPU
+#[derive(Default)]
+pub struct HwDecodeFailure {
+    device: bool,
+    frames: bool,
+}
+
+impl HwDecodeFailure {
+    pub fn observe(&mut self, line: &str) {
+        // e.g. no GPU or no driver, ffmpeg then fails before opening the input
+        if line.contains("Device creation failed")
+            || (line.starts_with("Failed to set value") && line.contains("for option 'hwaccel'"))
+        {
+            self.device = true;
+        }
+        // a codec or pixel format the decoder doesn't support, which only fails with the frames
+        // kept on the GPU, software decoding takes over otherwise
+        if line.contains("hwaccel initialisation returned error")
+            || line.contains("Impossible to convert between the formats")
+            || line.contains("Error reinitializing filters")
+        {
+            self.frames = true;
+        }
+    }
+
+    /// `Some(true)` if no file can be decoded with the device, `Some(false)` if only this one
+    pub fn failed(&self) -> Option<bool> {
+        (self.device || self.frames).then_some(self.device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_in_order() {
+        let encoders = Encoders::default();
+        let available = ["aac", "libx264"].map(String::from);
+        encoders.select(&available).unwrap();
+        assert_eq!(encoders.current(), "libx264");
+        assert_eq!(encoders.fall_back("libx264"), None);
+
+        let encoders = Encoders::default();
+        encoders.select(&["libx265".to_string()]).unwrap();
+        assert_eq!(encoders.current(), "libx265");
+        assert_eq!(
+            encoders.fall_back("libx265").as_deref(),
+            Some("hevc_videotoolbox")
+        );
+        assert_eq!(
+            encoders.fall_back("libx265").as_deref(),
+            Some("hevc_videotoolbox")
+        );
+
+        let mut strict = Encoders::default();
+        strict.without_fallback();
+        assert!(strict.select(&available).is_err());
+    }
+
+    #[test]
+    fn busy_encoders_are_told_apart_from_missing_ones() {
+        let mut failure = StartupFailure::default();
+        for line in [
+            "[hevc_nvenc @ 0x55d] OpenEncodeSessionEx failed: out of memory (10): (no details)",
+            "[hevc_nvenc @ 0x55d] No capable devices found",
+            "Error while opening encoder for output stream #0:0 - maybe incorrect parameters",
+        ] {
+            failure.observe(line, "hevc_nvenc");
+        }
+        assert!(failure.no_session());
+        assert!(failure.unusable());
+
+        let encoders = Encoders::new(["hevc_nvenc", "libx265"].map(String::from).to_vec());
+        assert_eq!(
+            encoders.next_after("hevc_nvenc").as_deref(),
+            Some("libx265")
+        );
+        assert_eq!(encoders.current(), "hevc_nvenc");
+        assert_eq!(encoders.next_after("libx265"), None);
+    }
+
+    #[test]
+    fn hardware_encodes_queue_for_a_free_session() {
+        let sessions = GpuSessions::new(2);
+        let first = sessions.open("hevc_nvenc", false).unwrap();
+        let _second = sessions.open("hevc_qsv", false).unwrap();
+        assert!(first.is_some());
+        assert!(sessions.open("hevc_nvenc", false).is_err());
+        // software encoders run alongside without a session
+        assert!(sessions.open("libx265", false).unwrap().is_none());
+
+        let waited = std::thread::scope(|scope| {
+            let waiting = scope.spawn(|| sessions.open("hevc_nvenc", true).is_ok());
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!waiting.is_finished());
+            drop(first);
+            waiting.join().unwrap()
+        });
+        assert!(waited);
+        assert!(sessions.open("h264_nvenc", false).is_ok());
+    }
+
+    #[test]
+    fn only_failures_of_the_video_encoder_count() {
+        let unusable = |lines: &[&str]| {
+            let mut failure = StartupFailure::default();
+            for line in lines {
+                failure.observe(line, "hevc_videotoolbox");
+            }
+            failure.unusable()
+        };
+        assert!(unusable(&["Unknown encoder 'hevc_videotoolbox'"]));
+        assert!(unusable(&[
+            "[hevc_videotoolbox @ 0x7f8] Error: cannot create compression session: -12908",
+            "Error while opening encoder for output stream #0:0 - maybe incorrect parameters",
+        ]));
+        assert!(unusable(&[
+            "[vost#0:0/hevc_videotoolbox @ 0x7f8] Error while opening encoder - maybe incorrect parameters"
+        ]));
+        assert!(!unusable(&["Unknown encoder 'libopus'"]));
+        assert!(!unusable(&[
+            "Error while opening encoder for output stream #0:1 - maybe incorrect parameters"
+        ]));
+    }
+
+    #[test]
+    fn hardware_decoding_fails_for_the_run_or_the_file() {
+        let failed = |lines: &[&str]| {
+            let mut failure = HwDecodeFailure::default();
+            for line in lines {
+                failure.observe(line);
+            }
+            failure.failed()
+        };
+        assert_eq!(
+            failed(&[
+                "[AVHWDeviceContext @ 0x55d] cu->cuInit(0) failed -> CUDA_ERROR_NO_DEVICE: no CUDA-capable device is detected",
+                "Device creation failed: -542398533.",
+                "Failed to set value 'cuda' for option 'hwaccel': Generic error in an external library",
+            ]),
+            Some(true)
+        );
+        assert_eq!(
+            failed(&["[hevc @ 0x55d] hwaccel initialisation returned error."]),
+            Some(false)
+        );
+        assert_eq!(failed(&["frame=  100 fps= 50 q=28.0 size=1024kB"]), None);
+    }
+}
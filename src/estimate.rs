@@ -0,0 +1,77 @@
+use std::{path::Path, time::Instant};
+
+use crate::{display_duration, options::Options, probe, sample, Candidate, EncodeSettings};
+
+const CALIBRATION_SECONDS: f64 = 20.0;
+
+/// Prints the total duration of all candidates and the time it would take to encode them,
+/// projected from the speed of a sample encode of the longest candidate on this machine.
+/// Returns the probed duration of each candidate.
+pub fn print_estimate(candidates: &[Candidate], options: &Options) -> Vec<Option<f64>> {
+    info!("Probing the duration of {} videos...", candidates.len());
+    let probed = candidates
+        .iter()
+        .map(|candidate| probe::duration(&candidate.path))
+        .collect::<Vec<_>>();
+    let durations = candidates
+        .iter()
+        .zip(&probed)
+        .filter_map(|(candidate, duration)| Some((candidate, (*duration)?)))
+        .collect::<Vec<_>>();
+
+    let Some((longest, longest_duration)) = durations
+        .iter()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .copied()
+    else {
+        info!("No video durations could be probed, skipping the time estimate");
+        return probed;
+    };
+
+    let total = durations.iter().map(|(_, duration)| duration).sum::<f64>();
+    info!(
+        "Total duration: {} in {} videos",
+        display_duration(total),
+        durations.len()
+    );
+    if durations.len() < candidates.len() {
+        info!(
+            "{} videos couldn't be probed and are not part of the estimate",
+            candidates.len() - durations.len()
+        );
+    }
+
+    match calibrate(&longest.path, longest_duration, options) {
+        Ok(speed) => info!(
+            "Estimated encode time: {} ({speed:.2}x realtime on a sample of `{}`)",
+            display_duration(total / speed),
+            longest.path.to_string_lossy()
+        ),
+        Err(e) => info!("Failed to encode a calibration sample: {e}"),
+    }
+    probed
+}
+
+/// Realtime factor of encoding a sample of `path` with the configured settings
+fn calibrate(path: &Path, duration: f64, options: &Options) -> Result<f64, String> {
+    let dir = sample::scratch_dir()?;
+    let result = calibrate_in(&dir, path, duration, options);
+    sample::remove_scratch_dir(&dir);
+    result
+}
+
+fn calibrate_in(dir: &Path, path: &Path, duration: f64, options: &Options) -> Result<f64, String> {
+    let (start, length) = sample::windows(duration, 1, CALIBRATION_SECONDS)[0];
+    let reference = dir.join("calibration.mkv");
+    sample::extract(path, start, length, &reference)?;
+
+    let settings = EncodeSettings::base(path, options);
+    let started = Instant::now();
+    sample::encode(
+        &reference,
+        &settings.video_args(),
+        &dir.join("calibration_encoded.mkv"),
+    )?;
+
+    Ok(length / started.elapsed().as_secs_f64())
+}
@@ -0,0 +1,164 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::Serialize;
+
+use crate::{status::State, unix_time};
+
+/// How often the progress of an encode is appended at most
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Opened from `--event-log` at startup, events are dropped without it
+static EVENT_LOG: OnceLock<EventLog> = OnceLock::new();
+
+/// File the events of the run are appended to as JSON Lines, e.g. for `tail -f | jq`
+struct EventLog {
+    path: PathBuf,
+    file: Mutex<File>,
+    last_progress: Mutex<Option<Instant>>,
+    /// Writing is only warned about once, it usually fails for every event alike
+    warned: AtomicBool,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    RunStarted {
+        path: &'a str,
+    },
+    FileStarted {
+        path: &'a str,
+        size: u64,
+        /// Position of the file among the candidates of the run, if it's known
+        index: Option<usize>,
+        count: Option<usize>,
+    },
+    Progress {
+        path: &'a str,
+        percent: Option<f64>,
+        speed: Option<f64>,
+    },
+    FileDone {
+        path: &'a str,
+        size_prev: u64,
+        size_post: u64,
+        encode_time: Option<f64>,
+    },
+    Skipped {
+        path: &'a str,
+        reason: String,
+    },
+    Failed {
+        path: &'a str,
+        reason: String,
+    },
+    /// Problems of the run itself, e.g. the log that can't be saved
+    Error {
+        message: String,
+    },
+    RunFinished {
+        state: State,
+        failed: bool,
+    },
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    /// Unix timestamp of the event
+    time: i64,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+/// Opens `path` for appending, so the events of earlier runs are kept
+pub fn open(path: &Path) -> Result<(), std::io::Error> {
+    let file = File::options().create(true).append(true).open(path)?;
+    let _ = EVENT_LOG.set(EventLog {
+        path: path.to_path_buf(),
+        file: Mutex::new(file),
+        last_progress: Mutex::new(None),
+        warned: AtomicBool::new(false),
+    });
+    Ok(())
+}
+
+/// Appends `event` as one line. The file isn't buffered, so readers see it right away.
+pub fn emit(event: Event) {
+    let Some(event_log) = EVENT_LOG.get() else {
+        return;
+    };
+    if matches!(event, Event::FileStarted { .. }) {
+        *event_log.last_progress.lock().unwrap() = Some(Instant::now());
+    }
+
+    let result = line(&event, unix_time(SystemTime::now()))
+        .map_err(std::io::Error::from)
+        .and_then(|line| event_log.file.lock().unwrap().write_all(line.as_bytes()));
+    if let Err(e) = result {
+        if !event_log.warned.swap(true, Ordering::Relaxed) {
+            info!(
+                "Warning: failed to write to the event log `{}`, events might be missing: {e}",
+                event_log.path.to_string_lossy()
+            );
+        }
+    }
+}
+
+/// Progress of the current encode, appended at most every ten seconds
+pub fn progress(path: &str, percent: Option<f64>, speed: Option<f64>) {
+    let Some(event_log) = EVENT_LOG.get() else {
+        return;
+    };
+    {
+        let mut last_progress = event_log.last_progress.lock().unwrap();
+        if last_progress.is_some_and(|last_progress| last_progress.elapsed() < PROGRESS_INTERVAL) {
+            return;
+        }
+        *last_progress = Some(Instant::now());
+    }
+    emit(Event::Progress {
+        path,
+        percent,
+        speed,
+    });
+}
+
+fn line(event: &Event, time: i64) -> Result<String, serde_json::Error> {
+    let mut line = serde_json::to_string(&Line { time, event })?;
+    line.push('\n');
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_single_lines_tagged_with_their_kind() {
+        let event = Event::FileDone {
+            path: "videos/clip.mp4",
+            size_prev: 1000,
+            size_post: 400,
+            encode_time: Some(2.5),
+        };
+        assert_eq!(
+            line(&event, 1_700_000_000).unwrap(),
+            "{\"time\":1700000000,\"event\":\"file_done\",\"path\":\"videos/clip.mp4\",\
+             \"size_prev\":1000,\"size_post\":400,\"encode_time\":2.5}\n"
+        );
+
+        let event = Event::Failed {
+            path: "clip.mp4",
+            reason: "Broken\npipe".to_string(),
+        };
+        assert_eq!(line(&event, 0).unwrap().lines().count(), 1);
+    }
+}
@@ -0,0 +1,150 @@
+use std::path::Path;
+
+use crate::{
+    color,
+    filter::{self, Context, Decision, Entry},
+    ignore::IgnoreFile,
+    options::Options,
+    probe, resolve_below,
+    sidecar::SidecarCodec,
+    unix_time, Log, Trim,
+};
+
+/// Runs `file` through every check of a run over `root` in order and prints the result of
+/// each and the first one that excludes it. Nothing is compressed or logged.
+pub fn explain(file: &Path, root: &Path, options: &Options) {
+    if !file.is_file() {
+        info!(
+            "`--explain` expects a file, got `{}`",
+            file.to_string_lossy()
+        );
+        std::process::exit(1);
+    }
+    if !root.is_dir() {
+        info!(
+            "`--explain` expects a directory as <path>, got `{}`",
+            root.to_string_lossy()
+        );
+        std::process::exit(1);
+    }
+    let path = match resolve_below(root, file) {
+        Ok(path) => path,
+        Err(e) => {
+            info!("Invalid `--explain`: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let log = Log::new(root.to_string_lossy().to_string());
+    info!("Checks for `{}`:", path.to_string_lossy());
+    let mut rejection = None;
+    let mut report = |name: &str, decision: Decision, of: Option<&Path>| {
+        let of = match of {
+            Some(dir) => format!(" (directory `{}`)", dir.to_string_lossy()),
+            None => String::new(),
+        };
+        let text = decision.to_string();
+        let text = match decision {
+            Decision::Pass => color::good(&text),
+            Decision::Skipped(_) => color::bad(&text),
+            Decision::Ignored(_) | Decision::Excluded(_) => color::skip(&text),
+        };
+        info!("  {name:<24} {text}{of}");
+        if !matches!(decision, Decision::Pass) && rejection.is_none() {
+            rejection = Some(name.to_string());
+        }
+    };
+
+    // the directories above the file are walked first, each one can be excluded itself and its
+    // `.vcignore` applies to everything below it
+    let mut ignore_files = Vec::new();
+    ignore_files.extend(load_ignore_file(root));
+    let mut dir = root.to_path_buf();
+    let relative = path.strip_prefix(root).unwrap_or(&path);
+    for component in relative.parent().into_iter().flat_map(Path::components) {
+        dir.push(component);
+        let entry = Entry {
+            path: &dir,
+            is_dir: true,
+            modified: modified(&dir),
+        };
+        let context = Context::new(&log, options, &ignore_files);
+        for check in &filter::SCAN_CHECKS {
+            let decision = (check.run)(&entry, &context);
+            if !matches!(decision, Decision::Pass) {
+                report(check.name, decision, Some(&dir));
+            }
+        }
+        ignore_files.extend(load_ignore_file(&dir));
+    }
+
+    let entry = Entry {
+        path: &path,
+        is_dir: false,
+        modified: modified(&path),
+    };
+    let context = Context::new(&log, options, &ignore_files);
+    for check in filter::SCAN_CHECKS.iter().chain(&filter::CANDIDATE_CHECKS) {
+        report(check.name, (check.run)(&entry, &context), None);
+    }
+
+    // decided while preparing the encode
+    let overrides = filter::overrides(&path, options);
+    let sidecar_codec = match &overrides {
+        Ok((sidecar, _)) => sidecar.as_ref().and_then(|sidecar| sidecar.codec),
+        Err(_) => None,
+    };
+    if let Some((extension, rule)) = filter::extension_rule(&path, options) {
+        info!("  {:<24} .{extension} files: {rule}", "extension rule");
+    }
+    report("sidecar", pass_or_skip(overrides.map(|_| ())), None);
+    let audio_only = filter::audio_only(&path, options);
+    let audio_only_flag = *audio_only.as_ref().unwrap_or(&false);
+    report("video stream", pass_or_skip(audio_only.map(|_| ())), None);
+    let trim = Trim::resolve(options, probe::duration(&path));
+    report(
+        "--trim-start/--trim-end",
+        pass_or_skip(trim.map(|_| ())),
+        None,
+    );
+
+    let action = if audio_only_flag {
+        "re-encoding the audio only".to_string()
+    } else if let Some(codec) =
+        filter::efficient_codec(&path, options, audio_only_flag, sidecar_codec)
+    {
+        format!("remuxing the {codec} video")
+    } else if sidecar_codec == Some(SidecarCodec::Copy) {
+        "copying the video stream".to_string()
+    } else {
+        "compressing".to_string()
+    };
+    match rejection {
+        Some(name) => info!("Result: not compressed because of `{name}`"),
+        None => info!("Result: {action}"),
+    }
+}
+
+fn pass_or_skip(result: Result<(), crate::SkipReason>) -> Decision {
+    match result {
+        Ok(()) => Decision::Pass,
+        Err(reason) => Decision::Skipped(reason),
+    }
+}
+
+fn load_ignore_file(dir: &Path) -> Option<IgnoreFile> {
+    IgnoreFile::load(dir).unwrap_or_else(|e| {
+        info!(
+            "Warning: failed to read the `.vcignore` of `{}`: {e}",
+            dir.to_string_lossy()
+        );
+        None
+    })
+}
+
+/// Modification time in seconds since the unix epoch, 0 if it can't be read
+fn modified(path: &Path) -> i64 {
+    path.metadata()
+        .and_then(|metadata| metadata.modified())
+        .map_or(0, unix_time)
+}
@@ -0,0 +1,140 @@
+//! End-to-end tests compressing tiny videos generated with ffmpeg's `testsrc` and `sine`
+//! sources. They pass without doing anything if ffmpeg, libx265 or the sources are missing.
+
+use std::{path::Path, process::Command};
+
+use crate::{doctor, iterate_dir, probe, process_file, temp_dir::TempDir, Log, Options};
+
+/// Returns from the test if there is no ffmpeg to generate and compress the videos with
+macro_rules! require_ffmpeg {
+    () => {
+        if !ffmpeg_available() {
+            eprintln!("skipped, needs ffmpeg with libx265 and the lavfi sources in PATH");
+            return;
+        }
+    };
+}
+
+fn ffmpeg_available() -> bool {
+    let ffprobe = Command::new("ffprobe")
+        .arg("-version")
+        .output()
+        .is_ok_and(|output| output.status.success());
+    let listed = |flag, name| doctor::list(flag).iter().any(|listed| listed == name);
+    ffprobe
+        && listed("-encoders", "libx265")
+        && listed("-filters", "testsrc")
+        && listed("-filters", "sine")
+}
+
+/// Writes a two second video with a tone, encoded at a high bitrate with mpeg4 so the
+/// compressed file is clearly smaller
+fn generate(path: &Path) {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).unwrap();
+    }
+    let status = Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-f", "lavfi", "-i"])
+        .arg("testsrc=duration=2:size=320x240:rate=25")
+        .args(["-f", "lavfi", "-i", "sine=frequency=440:duration=2"])
+        .args(["-c:v", "mpeg4", "-q:v", "1", "-c:a", "aac"])
+        .arg(path)
+        .arg("-y")
+        .status()
+        .unwrap();
+    assert!(status.success(), "failed to generate `{}`", path.display());
+}
+
+fn options(path: &Path) -> Options {
+    Options::parse(
+        ["--preset", "ultrafast"]
+            .into_iter()
+            .map(String::from)
+            .chain([path.to_string_lossy().to_string()]),
+    )
+    .unwrap()
+}
+
+fn size(path: &Path) -> u64 {
+    path.metadata().unwrap().len()
+}
+
+#[test]
+fn process_file_replaces_the_original_with_a_smaller_hevc_file() {
+    require_ffmpeg!();
+    let dir = TempDir::new("process_file");
+    let video = dir.join("lecture 01.mp4");
+    generate(&video);
+    let size_prev = size(&video);
+
+    let mut log = Log::in_memory();
+    let encoded = process_file(video.clone(), None, &mut log, &options(&video)).unwrap();
+
+    assert_eq!(encoded.dest, dir.join("lecture 01.mp4_x265.mp4"));
+    assert!(!encoded.dest.exists());
+    assert_eq!(size(&video), encoded.size_post);
+    assert!(encoded.size_post < size_prev);
+    assert_eq!(probe::video_codec(&video).as_deref(), Some("hevc"));
+    assert!(log.failed_files.is_empty());
+}
+
+#[test]
+fn existing_output_is_not_overwritten() {
+    require_ffmpeg!();
+    let dir = TempDir::new("collision");
+    let video = dir.join("clip.mp4");
+    generate(&video);
+    let existing = dir.join("clip.mp4_x265.mp4");
+    std::fs::write(&existing, "copied here by hand").unwrap();
+
+    let mut log = Log::in_memory();
+    let encoded = process_file(video.clone(), None, &mut log, &options(&video)).unwrap();
+
+    assert_ne!(encoded.dest, existing);
+    assert!(!encoded.dest.exists());
+    assert_eq!(
+        std::fs::read_to_string(&existing).unwrap(),
+        "copied here by hand"
+    );
+    assert_eq!(probe::video_codec(&video).as_deref(), Some("hevc"));
+}
+
+#[test]
+fn iterate_dir_logs_the_compressed_videos_and_skips_them_next_run() {
+    require_ffmpeg!();
+    let dir = TempDir::new("iterate_dir");
+    let videos = [dir.join("a.mp4"), dir.join("course/b.mov")];
+    for video in &videos {
+        generate(video);
+    }
+    generate(&dir.join(".hidden.mp4"));
+    std::fs::write(dir.join("notes.txt"), "not a video").unwrap();
+    let options = options(&dir);
+
+    let mut log = Log::new(dir.to_string_lossy().to_string());
+    iterate_dir(&dir, &mut log, &options);
+    log.print_status(None, options.verbose);
+    log.save();
+
+    let mut log = Log::new(dir.to_string_lossy().to_string());
+    let mut logged = log
+        .shrunk_files
+        .iter()
+        .map(|(path, _)| path.clone())
+        .collect::<Vec<_>>();
+    logged.sort();
+    let expected = videos
+        .iter()
+        .map(|video| video.to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(logged, expected);
+    for video in &videos {
+        let file_log = &log.shrunk_files[&video.to_string_lossy().to_string()];
+        assert_eq!(file_log.size_post, size(video));
+        assert!(file_log.size_post < file_log.size_prev);
+    }
+    assert!(log.failed_files.is_empty());
+
+    iterate_dir(&dir, &mut log, &options);
+    assert!(log.added_files.is_empty());
+}
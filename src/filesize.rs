@@ -0,0 +1,115 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set from `--si` at startup, sizes are shown and parsed with powers of 1000 instead of 1024
+pub static SI: AtomicBool = AtomicBool::new(false);
+
+const BINARY_UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+const DECIMAL_UNITS: [&str; 6] = ["B", "kB", "MB", "GB", "TB", "PB"];
+
+fn si() -> bool {
+    SI.load(Ordering::Relaxed)
+}
+
+/// `2.30TiB`, or `2.53TB` with `--si`
+pub fn display(size: u64) -> String {
+    display_as(size, si())
+}
+
+/// `-1.20GiB` for a size that shrank, `+1.20GiB` for one that grew
+pub fn display_delta(delta: i64) -> String {
+    let sign = if delta < 0 { "-" } else { "+" };
+    format!("{sign}{}", display(delta.unsigned_abs()))
+}
+
+fn display_as(size: u64, si: bool) -> String {
+    let (base, units) = if si {
+        (1000.0, DECIMAL_UNITS)
+    } else {
+        (1024.0, BINARY_UNITS)
+    };
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= base && unit < units.len() - 1 {
+        size /= base;
+        unit += 1;
+    }
+
+    format!("{size:.2}{}", units[unit])
+}
+
+/// Parses a size in bytes with an optional unit, e.g. `500M`, `2GiB` or `1.5TB`. Units with an
+/// `i` are powers of 1024, the others follow the sizes shown by the run: powers of 1024 by
+/// default and of 1000 with `--si`.
+pub fn parse(value: &str, si: bool) -> Result<u64, String> {
+    let invalid = || format!("Invalid size `{value}`, expected e.g. `500M`, `2GiB` or `1.5TB`");
+    let number_len = value
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(number_len);
+    let unit = unit.to_ascii_uppercase();
+    let unit = unit.strip_suffix('B').unwrap_or(&unit);
+    let (prefix, binary) = match unit.strip_suffix('I') {
+        Some(prefix) if !prefix.is_empty() => (prefix, true),
+        _ => (unit, !si),
+    };
+    let power = match prefix {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        _ => return Err(invalid()),
+    };
+    let number = number.trim().parse::<f64>().map_err(|_| invalid())?;
+    if !number.is_finite() || number < 0.0 {
+        return Err(invalid());
+    }
+
+    let base: f64 = if binary { 1024.0 } else { 1000.0 };
+    Ok((number * base.powi(power)) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_switch_units_exactly_at_the_base() {
+        assert_eq!(display_as(1023, false), "1023.00B");
+        assert_eq!(display_as(1024, false), "1.00KiB");
+        assert_eq!(display_as(1024 * 1024 - 1, false), "1024.00KiB");
+        assert_eq!(display_as(2_528_876_743_884, false), "2.30TiB");
+        assert_eq!(display_as(1 << 50, false), "1.00PiB");
+        assert_eq!(display_as(1 << 60, false), "1024.00PiB");
+
+        assert_eq!(display_as(999, true), "999.00B");
+        assert_eq!(display_as(1000, true), "1.00kB");
+        assert_eq!(display_as(1024, true), "1.02kB");
+        assert_eq!(display_as(1000u64.pow(4) - 1, true), "1000.00GB");
+        assert_eq!(display_as(1000u64.pow(4), true), "1.00TB");
+        assert_eq!(display_as(1000u64.pow(5), true), "1.00PB");
+    }
+
+    #[test]
+    fn parsed_units_match_the_displayed_ones() {
+        assert_eq!(parse("500", false), Ok(500));
+        assert_eq!(parse("1K", false), Ok(1024));
+        assert_eq!(parse("1KiB", true), Ok(1024));
+        assert_eq!(parse("1kB", true), Ok(1000));
+        assert_eq!(parse("1.5M", false), Ok(1_572_864));
+        assert_eq!(parse("2gb", false), Ok(2 << 30));
+        assert_eq!(parse("1T", true), Ok(1000u64.pow(4)));
+        assert_eq!(parse("1PiB", false), Ok(1 << 50));
+        assert_eq!(parse("10B", true), Ok(10));
+        for invalid in ["", "M", "1X", "1iB", "-1G", "1 G B"] {
+            assert!(parse(invalid, false).is_err(), "{invalid}");
+        }
+        // what is shown is read back as the same size
+        assert_eq!(parse(&display_as(1 << 40, false), false), Ok(1 << 40));
+        assert_eq!(
+            parse(&display_as(1000u64.pow(4), true), true),
+            Ok(1000u64.pow(4))
+        );
+    }
+}
@@ -0,0 +1,676 @@
+use std::{fmt::Display, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ignore,
+    options::{AudioOnlyMode, Options, Since},
+    probe::{self, InputError},
+    sample, shards,
+    sidecar::Sidecar,
+    sidecar::SidecarCodec,
+    Log, SkipReason, EFFICIENT_CODECS, LOG_FILE, PARTIAL_SUFFIX, TAG_PREFIX,
+};
+
+const VIDEO_EXTENSIONS: [&str; 3] = [".mp4", ".mov", ".avi"];
+/// Files the program keeps next to the videos
+const OWN_FILES: [&str; 2] = [LOG_FILE, probe::CACHE_FILE];
+
+/// What a check decided about an entry
+pub enum Decision {
+    Pass,
+    /// Hidden or matched by a `.vcignore`, only counted with `--verbose`
+    Ignored(String),
+    /// Not a candidate, e.g. not a video or already compressed
+    Excluded(String),
+    /// Reported as skipped in the overview
+    Skipped(SkipReason),
+}
+
+impl Display for Decision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Decision::Pass => write!(f, "pass"),
+            Decision::Ignored(why) => write!(f, "ignored: {why}"),
+            Decision::Excluded(why) => write!(f, "excluded: {why}"),
+            Decision::Skipped(reason) => write!(f, "skipped: {reason}"),
+        }
+    }
+}
+
+/// File or directory found while walking the tree
+pub struct Entry<'a> {
+    pub path: &'a Path,
+    pub is_dir: bool,
+    /// Modification time in seconds since the unix epoch
+    pub modified: i64,
+}
+
+pub struct Context<'a> {
+    pub log: &'a Log,
+    pub options: &'a Options,
+    /// `.vcignore` files from the scanned directory down to the entry
+    pub ignore_files: &'a [ignore::IgnoreFile],
+    /// Files modified before this timestamp are excluded (`--since`)
+    pub since: Option<i64>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(log: &'a Log, options: &'a Options, ignore_files: &'a [ignore::IgnoreFile]) -> Self {
+        let since = options.since.and_then(|since| match since {
+            Since::LastRun => log.last_run,
+            Since::Timestamp(timestamp) => Some(timestamp),
+        });
+        Context {
+            log,
+            options,
+            ignore_files,
+            since,
+        }
+    }
+}
+
+pub struct Check {
+    pub name: &'static str,
+    pub run: fn(&Entry, &Context) -> Decision,
+}
+
+/// Checks of the directory walk, which don't need ffprobe. Checks that only apply to files
+/// pass directories.
+pub const SCAN_CHECKS: [Check; 8] = [
+    Check {
+        name: "own files",
+        run: own_file,
+    },
+    Check {
+        name: "hidden",
+        run: hidden,
+    },
+    Check {
+        name: ".vcignore",
+        run: vcignore,
+    },
+    Check {
+        name: "extension",
+        run: extension,
+    },
+    Check {
+        name: "extension rule",
+        run: skipped_extension,
+    },
+    Check {
+        name: "compressed output",
+        run: compressed_output,
+    },
+    Check {
+        name: "--since",
+        run: since,
+    },
+    Check {
+        name: "log",
+        run: already_processed,
+    },
+];
+
+/// Checks of the candidates left after the walk, which probe the file
+pub const CANDIDATE_CHECKS: [Check; 6] = [
+    Check {
+        name: "--min-size",
+        run: size,
+    },
+    Check {
+        name: "tag",
+        run: tagged,
+    },
+    Check {
+        name: "readable input",
+        run: readable_input,
+    },
+    Check {
+        name: "--skip-below-bitrate",
+        run: bitrate,
+    },
+    Check {
+        name: "--min-duration",
+        run: duration,
+    },
+    Check {
+        name: "--min/max-resolution",
+        run: resolution,
+    },
+];
+
+/// Result of the input check of a file, reused by later runs until the file is modified
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InputCheck {
+    modified: i64,
+    /// Seconds decoded by `--deep-check`, 0 if the file was only probed
+    deep_check: f64,
+    /// Why the file can't be read, `None` if it passed
+    error: Option<String>,
+    /// The error is about encryption rather than damage
+    #[serde(default)]
+    encrypted: bool,
+}
+
+impl InputCheck {
+    fn rejection(self) -> Option<SkipReason> {
+        let error = self.error?;
+        Some(if self.encrypted {
+            SkipReason::Encrypted(error)
+        } else {
+            SkipReason::CorruptInput(error)
+        })
+    }
+
+    /// Whether the check still applies to the file and covers `deep_check` seconds
+    fn covers(&self, modified: i64, deep_check: f64) -> bool {
+        self.modified == modified && (self.error.is_some() || self.deep_check >= deep_check)
+    }
+}
+
+/// First check that doesn't pass `entry` and its decision
+pub fn first_rejection<'a>(
+    checks: &'a [Check],
+    entry: &Entry,
+    context: &Context,
+) -> Option<(&'a Check, Decision)> {
+    checks
+        .iter()
+        .find_map(|check| match (check.run)(entry, context) {
+            Decision::Pass => None,
+            decision => Some((check, decision)),
+        })
+}
+
+/// The bookkeeping of the program is never compressed or walked into, not even with `--hidden`
+fn own_file(entry: &Entry, _: &Context) -> Decision {
+    let name = entry.path.file_name().unwrap_or_default().to_string_lossy();
+    let own = if entry.is_dir {
+        sample::is_scratch_dir(entry.path) || name == shards::SHARD_DIR
+    } else {
+        OWN_FILES.contains(&&*name) || name.ends_with(PARTIAL_SUFFIX)
+    };
+    if own {
+        Decision::Excluded("file of video_compressor itself".to_string())
+    } else {
+        Decision::Pass
+    }
+}
+
+fn hidden(entry: &Entry, context: &Context) -> Decision {
+    if !context.options.hidden && ignore::is_hidden(entry.path) {
+        Decision::Ignored("hidden files need `--hidden`".to_string())
+    } else {
+        Decision::Pass
+    }
+}
+
+fn vcignore(entry: &Entry, context: &Context) -> Decision {
+    if ignore::is_ignored(context.ignore_files, entry.path, entry.is_dir) {
+        Decision::Ignored(format!("matched by a `{}`", ignore::IGNORE_FILE))
+    } else {
+        Decision::Pass
+    }
+}
+
+/// mp4, mov and avi files and those with a rule for their extension in the config
+fn is_video(path: &Path, options: &Options) -> bool {
+    let name = path.to_string_lossy();
+    VIDEO_EXTENSIONS
+        .iter()
+        .any(|extension| name.ends_with(extension))
+        || extension_rule(path, options).is_some()
+}
+
+fn extension(entry: &Entry, context: &Context) -> Decision {
+    if entry.is_dir || is_video(entry.path, context.options) {
+        Decision::Pass
+    } else {
+        Decision::Excluded(format!(
+            "not a {} file or one with a rule in the config",
+            VIDEO_EXTENSIONS.join(", ")
+        ))
+    }
+}
+
+fn skipped_extension(entry: &Entry, context: &Context) -> Decision {
+    match extension_rule(entry.path, context.options) {
+        Some((extension, rule)) if !entry.is_dir && rule.skip => Decision::Excluded(format!(
+            "skipped by the rule for `.{extension}` files in the config"
+        )),
+        _ => Decision::Pass,
+    }
+}
+
+/// Rule for the extension of `path` in the config that applies to its directory
+pub fn extension_rule(path: &Path, options: &Options) -> Option<(String, Sidecar)> {
+    let dir_config = options.dir_configs.get(path);
+    let config = dir_config.as_deref().unwrap_or(&options.config);
+    config
+        .extension_rule(path)
+        .map(|(extension, rule)| (extension, rule.clone()))
+}
+
+/// `<name>_x265.mp4` and `<name>_x265.<nonce>.mp4` files are left behind by interrupted runs
+fn compressed_output(entry: &Entry, context: &Context) -> Decision {
+    let path = entry.path.to_string_lossy();
+    let path = path.strip_suffix(".mp4").unwrap_or(&path);
+    // strip the nonce of a name made unique by `--on-collision unique`
+    let path = match path.rsplit_once('.') {
+        Some((stem, nonce)) if nonce.len() == 8 && nonce.chars().all(|c| c.is_ascii_hexdigit()) => {
+            stem
+        }
+        _ => path,
+    };
+    let original = path.strip_suffix("_x265").map(Path::new);
+    if !entry.is_dir && original.is_some_and(|original| is_video(original, context.options)) {
+        Decision::Excluded("output of an unfinished compression".to_string())
+    } else {
+        Decision::Pass
+    }
+}
+
+fn since(entry: &Entry, context: &Context) -> Decision {
+    match context.since {
+        Some(since)
+            if !entry.is_dir
+                && entry.modified < since
+                && !context.log.failed_before(&entry.path.to_string_lossy()) =>
+        {
+            Decision::Excluded("not modified since `--since`".to_string())
+        }
+        _ => Decision::Pass,
+    }
+}
+
+fn already_processed(entry: &Entry, context: &Context) -> Decision {
+    let path = entry.path.to_string_lossy().to_string();
+    if !entry.is_dir
+        && context
+            .log
+            .is_already_processed(&path, entry.modified, context.options)
+    {
+        Decision::Excluded("already compressed according to the log".to_string())
+    } else {
+        Decision::Pass
+    }
+}
+
+/// Outputs carry a tag, so they are recognized without the log, e.g. after being moved to
+/// another machine. Files the log recorded with other settings than the run asks for are
+/// compressed again.
+fn tagged(entry: &Entry, context: &Context) -> Decision {
+    let path = entry.path.to_string_lossy();
+    if context.options.ignore_tag || context.log.settings_changed(&path, context.options) {
+        return Decision::Pass;
+    }
+
+    match output_tag(entry.path, context.options) {
+        Some(tag) => Decision::Skipped(SkipReason::AlreadyTagged(tag)),
+        None => Decision::Pass,
+    }
+}
+
+/// Tag a previous run wrote into the file, e.g. `video-compressor:crf=25:codec=x265`
+pub fn output_tag(path: &Path, options: &Options) -> Option<String> {
+    probe::format_tags(path)?
+        .into_iter()
+        .find(|(key, value)| {
+            key.eq_ignore_ascii_case(&options.tag_key) && value.starts_with(TAG_PREFIX)
+        })
+        .map(|(_, value)| value)
+}
+
+/// Damaged files would only fail after ffmpeg worked on them for minutes. Probing is cheap, the
+/// `--deep-check` decode a bit less so, both are cached in the log.
+fn readable_input(entry: &Entry, context: &Context) -> Decision {
+    let path = entry.path.to_string_lossy().to_string();
+    let deep_check = context.options.deep_check.unwrap_or(0.0);
+    let check = match context.log.input_check(&path) {
+        Some(check) if check.covers(entry.modified, deep_check) => check,
+        _ => {
+            let result =
+                probe::check_readable(entry.path).and_then(|_| match context.options.deep_check {
+                    Some(seconds) => probe::check_decodable(entry.path, seconds),
+                    None => Ok(()),
+                });
+            let (error, encrypted) = match result {
+                Ok(()) => (None, false),
+                Err(InputError::Corrupt(error)) => (Some(error), false),
+                Err(InputError::Encrypted(error)) => (Some(error), true),
+            };
+            let check = InputCheck {
+                modified: entry.modified,
+                deep_check,
+                error,
+                encrypted,
+            };
+            context.log.record_input_check(path, check.clone());
+            check
+        }
+    };
+
+    match check.rejection() {
+        Some(reason) => Decision::Skipped(reason),
+        None => Decision::Pass,
+    }
+}
+
+/// Rejection the readable input check cached for the file if it wasn't modified since,
+/// without probing it
+pub fn cached_input_rejection(log: &Log, path: &str, modified: i64) -> Option<SkipReason> {
+    log.input_check(path)
+        .filter(|check| check.modified == modified)?
+        .rejection()
+}
+
+/// Tiny videos aren't worth the encode, which might even come out larger
+fn size(entry: &Entry, context: &Context) -> Decision {
+    let Some(min) = context.options.min_size else {
+        return Decision::Pass;
+    };
+    match std::fs::metadata(entry.path) {
+        Ok(metadata) if metadata.len() < min => {
+            Decision::Skipped(SkipReason::BelowSize(metadata.len(), min))
+        }
+        _ => Decision::Pass,
+    }
+}
+
+/// Clips shorter than `--min-duration`, e.g. a folder of gif like snippets, aren't worth
+/// starting ffmpeg for. Videos ffprobe can't report the duration of pass the check.
+fn duration(entry: &Entry, context: &Context) -> Decision {
+    let Some(min) = context.options.min_duration else {
+        return Decision::Pass;
+    };
+    match probe::duration(entry.path) {
+        Some(duration) => duration_range(duration, min),
+        None => Decision::Pass,
+    }
+}
+
+/// Decision of `duration` for a video of `duration` seconds
+fn duration_range(duration: f64, min: f64) -> Decision {
+    if duration < min {
+        Decision::Skipped(SkipReason::BelowDuration(duration, min))
+    } else {
+        Decision::Pass
+    }
+}
+
+/// Videos below `--skip-below-bitrate` aren't worth compressing. Videos where ffprobe can't
+/// report a bitrate pass the check.
+fn bitrate(entry: &Entry, context: &Context) -> Decision {
+    let Some(threshold) = context.options.skip_below_bitrate else {
+        return Decision::Pass;
+    };
+    match probe::video_bitrate(entry.path) {
+        Some(bitrate) if bitrate < threshold => {
+            Decision::Skipped(SkipReason::BelowBitrate(bitrate, threshold))
+        }
+        _ => Decision::Pass,
+    }
+}
+
+/// Videos outside `--min-resolution` and `--max-resolution`, compared as displayed by the lines
+/// of the 16:9 resolution they count as, so portrait, letterboxed and anamorphic videos count
+/// like their landscape 16:9 version. Videos ffprobe can't report the resolution of pass the
+/// check.
+fn resolution(entry: &Entry, context: &Context) -> Decision {
+    let options = context.options;
+    if options.min_resolution.is_none() && options.max_resolution.is_none() {
+        return Decision::Pass;
+    }
+    match probe::display_lines(entry.path) {
+        Some(lines) => resolution_range(lines, options),
+        None => Decision::Pass,
+    }
+}
+
+/// Decision of `resolution` for a video displayed with `lines`
+fn resolution_range(lines: u32, options: &Options) -> Decision {
+    match (options.min_resolution, options.max_resolution) {
+        (Some(min), _) if lines < min => Decision::Skipped(SkipReason::BelowResolution(lines, min)),
+        (_, Some(max)) if lines > max => Decision::Skipped(SkipReason::AboveResolution(lines, max)),
+        _ => Decision::Pass,
+    }
+}
+
+/// Sidecar of the video, failing if it's invalid or skips the video
+pub fn sidecar(path: &Path) -> Result<Option<Sidecar>, SkipReason> {
+    let sidecar = Sidecar::load(path).map_err(SkipReason::InvalidSidecar)?;
+    if sidecar.as_ref().is_some_and(|sidecar| sidecar.skip) {
+        return Err(SkipReason::SkippedBySidecar);
+    }
+
+    Ok(sidecar)
+}
+
+/// Overrides of the file, those of its `--manifest` entry over those of its sidecar over the
+/// rule for its extension in the config, and where they come from, e.g. `rule for .mov files`
+pub fn overrides(path: &Path, options: &Options) -> Result<(Option<Sidecar>, String), SkipReason> {
+    let (overrides, source) = file_overrides(path, options)?;
+    // the manifest names the file explicitly, so its entry wins over both
+    let Some(file) = options.manifest.iter().find(|file| file.path == path) else {
+        return Ok((overrides, source));
+    };
+    Ok(match overrides {
+        Some(overrides) => (
+            Some(file.overrides.over(&overrides)),
+            format!("manifest and {source}"),
+        ),
+        None => (Some(file.overrides.clone()), "manifest".to_string()),
+    })
+}
+
+fn file_overrides(path: &Path, options: &Options) -> Result<(Option<Sidecar>, String), SkipReason> {
+    let sidecar = sidecar(path)?;
+    Ok(match (sidecar, extension_rule(path, options)) {
+        (sidecar, None) => (sidecar, "sidecar".to_string()),
+        (None, Some((extension, rule))) => (Some(rule), format!("rule for .{extension} files")),
+        (Some(sidecar), Some((extension, rule))) => (
+            Some(sidecar.over(&rule)),
+            format!("sidecar and rule for .{extension} files"),
+        ),
+    })
+}
+
+/// Whether the file has no video stream, failing unless `--audio-only-mode reencode` is set
+pub fn audio_only(path: &Path, options: &Options) -> Result<bool, SkipReason> {
+    // ffprobe failing here is not treated as audio only, compress reports the actual problem
+    let audio_only = match probe::stream_types(path) {
+        Some(types) => !types.iter().any(|codec_type| codec_type == "video"),
+        None => false,
+    };
+    if audio_only && options.audio_only_mode == AudioOnlyMode::Skip {
+        return Err(SkipReason::NoVideoStream);
+    }
+
+    Ok(audio_only)
+}
+
+/// Codec of the video stream if it is remuxed with `--remux-efficient`, which copies the codecs
+/// in `--remux-codecs`
+pub fn efficient_codec(
+    path: &Path,
+    options: &Options,
+    audio_only: bool,
+    sidecar_codec: Option<SidecarCodec>,
+) -> Option<String> {
+    // a codec set in the sidecar replaces the `--remux-efficient` decision
+    if options.remux_efficient && !audio_only && sidecar_codec.is_none() {
+        probe::video_codec(path).filter(|codec| match &options.remux_codecs {
+            Some(codecs) => codecs.contains(codec),
+            None => EFFICIENT_CODECS.contains(&codec.as_str()),
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    fn first_scan_rejection(path: &str, is_dir: bool) -> Option<&'static str> {
+        let (log, options) = (Log::in_memory(), Options::default());
+        let entry = Entry {
+            path: Path::new(path),
+            is_dir,
+            modified: 0,
+        };
+        let context = Context::new(&log, &options, &[]);
+        first_rejection(&SCAN_CHECKS, &entry, &context).map(|(check, _)| check.name)
+    }
+
+    #[test]
+    fn scan_checks_stop_at_the_first_rejection() {
+        assert_eq!(first_scan_rejection("/videos/a.mp4", false), None);
+        assert_eq!(first_scan_rejection("/videos/a.mov", false), None);
+        assert_eq!(first_scan_rejection("/videos/a.avi", false), None);
+        assert_eq!(first_scan_rejection("/videos/raw", true), None);
+        assert_eq!(
+            first_scan_rejection("/videos/a.txt", false),
+            Some("extension")
+        );
+        assert_eq!(
+            first_scan_rejection("/videos/a.mov_x265.mp4", false),
+            Some("compressed output")
+        );
+        assert_eq!(
+            first_scan_rejection("/videos/a.mp4_x265.0badf00d.mp4", false),
+            Some("compressed output")
+        );
+        assert_eq!(
+            first_scan_rejection("/videos/.a.mp4_x265.mp4", false),
+            Some("hidden")
+        );
+        assert_eq!(
+            first_scan_rejection("/videos/compression_log.json", false),
+            Some("own files")
+        );
+        assert_eq!(
+            first_scan_rejection("/videos/a.mp4.partial", false),
+            Some("own files")
+        );
+        let scratch_dir = std::env::temp_dir().join("video_compressor_4242");
+        assert_eq!(
+            first_scan_rejection(&scratch_dir.to_string_lossy(), true),
+            Some("own files")
+        );
+        assert_eq!(
+            first_scan_rejection("/videos/video_compressor_4242", true),
+            None
+        );
+    }
+
+    #[test]
+    fn videos_below_min_size_are_skipped() {
+        let dir = TempDir::new("min_size");
+        std::fs::write(dir.join("small.mp4"), [0; 100]).unwrap();
+        std::fs::write(dir.join("large.mp4"), [0; 200]).unwrap();
+        let log = Log::in_memory();
+        let decision = |name: &str, min_size| {
+            let options = Options {
+                min_size,
+                ..Options::default()
+            };
+            let path = dir.join(name);
+            let entry = Entry {
+                path: &path,
+                is_dir: false,
+                modified: 0,
+            };
+            size(&entry, &Context::new(&log, &options, &[]))
+        };
+
+        assert!(matches!(
+            decision("small.mp4", Some(200)),
+            Decision::Skipped(SkipReason::BelowSize(100, 200))
+        ));
+        assert!(matches!(decision("large.mp4", Some(200)), Decision::Pass));
+        assert!(matches!(decision("small.mp4", None), Decision::Pass));
+        // a file that vanished is left to the later checks
+        assert!(matches!(decision("missing.mp4", Some(200)), Decision::Pass));
+    }
+
+    #[test]
+    fn videos_below_min_duration_are_skipped() {
+        assert!(matches!(
+            duration_range(2.5, 5.0),
+            Decision::Skipped(SkipReason::BelowDuration(..))
+        ));
+        assert!(matches!(duration_range(5.0, 5.0), Decision::Pass));
+        assert!(matches!(duration_range(3600.0, 5.0), Decision::Pass));
+
+        // without `--min-duration` nothing is probed
+        let (log, options) = (Log::in_memory(), Options::default());
+        let entry = Entry {
+            path: Path::new("/videos/missing.mp4"),
+            is_dir: false,
+            modified: 0,
+        };
+        let context = Context::new(&log, &options, &[]);
+        assert!(matches!(duration(&entry, &context), Decision::Pass));
+    }
+
+    #[test]
+    fn resolutions_outside_the_range_are_skipped() {
+        let options = Options {
+            min_resolution: Some(720),
+            max_resolution: Some(1080),
+            ..Options::default()
+        };
+        let skipped = |lines| match resolution_range(lines, &options) {
+            Decision::Skipped(reason) => Some(reason.to_string()),
+            _ => None,
+        };
+        assert_eq!(skipped(720), None);
+        assert_eq!(skipped(1080), None);
+        assert!(skipped(576).is_some_and(|reason| reason.contains("576")));
+        assert!(skipped(2160).is_some());
+        // a 4:3 1440x1080 video counts as 1080p
+        assert_eq!(skipped(probe::lines(1440, 1080)), None);
+
+        let options = Options {
+            min_resolution: Some(720),
+            ..Options::default()
+        };
+        assert!(matches!(resolution_range(4320, &options), Decision::Pass));
+        assert!(matches!(
+            resolution_range(480, &options),
+            Decision::Skipped(SkipReason::BelowResolution(480, 720))
+        ));
+    }
+
+    #[test]
+    fn files_that_failed_before_pass_since() {
+        let mut log = Log::in_memory();
+        let options = Options {
+            since: Some(Since::Timestamp(1000)),
+            ..Options::default()
+        };
+        let entry = |path| Entry {
+            path: Path::new(path),
+            is_dir: false,
+            modified: 500,
+        };
+        let rejection = |log: &Log, path| {
+            let context = Context::new(log, &options, &[]);
+            first_rejection(&SCAN_CHECKS, &entry(path), &context).map(|(check, _)| check.name)
+        };
+        assert_eq!(rejection(&log, "/videos/a.mp4"), Some("--since"));
+
+        log.retry_files.insert(
+            "/videos/a.mp4".to_string(),
+            crate::RetryEntry {
+                reason: "Encoding failed".to_string(),
+                recorded: Some(400),
+            },
+        );
+        assert_eq!(rejection(&log, "/videos/a.mp4"), None);
+        assert_eq!(rejection(&log, "/videos/b.mp4"), Some("--since"));
+    }
+}
@@ -0,0 +1,257 @@
+use std::path::{Path, PathBuf};
+
+pub const IGNORE_FILE: &str = ".vcignore";
+
+/// Single pattern line of a `.vcignore` file
+struct Rule {
+    pattern: Vec<char>,
+    negated: bool,
+    dir_only: bool,
+    /// Matched against the path relative to the `.vcignore` instead of just the file name
+    anchored: bool,
+}
+
+/// Gitignore style patterns of a `.vcignore` file, applying to the directory it is in and
+/// everything below it.
+pub struct IgnoreFile {
+    dir: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl IgnoreFile {
+    /// Reads the `.vcignore` in `dir`, `Ok(None)` if there is none.
+    pub fn load(dir: &Path) -> Result<Option<Self>, std::io::Error> {
+        match std::fs::read_to_string(dir.join(IGNORE_FILE)) {
+            Ok(content) => Ok(Some(IgnoreFile::parse(dir, &content))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn parse(dir: &Path, content: &str) -> Self {
+        let rules = content
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim_end();
+                if line.is_empty() || line.starts_with('#') {
+                    return None;
+                }
+
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(line) => (true, line),
+                    None => (false, line.strip_prefix('\\').unwrap_or(line)),
+                };
+                let (dir_only, line) = match line.strip_suffix('/') {
+                    Some(line) => (true, line),
+                    None => (false, line),
+                };
+                // like gitignore, a slash anywhere but at the end anchors the pattern
+                let anchored = line.contains('/');
+                let line = line.strip_prefix('/').unwrap_or(line);
+                if line.is_empty() {
+                    return None;
+                }
+
+                Some(Rule {
+                    pattern: line.chars().collect(),
+                    negated,
+                    dir_only,
+                    anchored,
+                })
+            })
+            .collect();
+
+        IgnoreFile {
+            dir: dir.to_path_buf(),
+            rules,
+        }
+    }
+
+    /// `Some(true)` if the last rule matching `path` ignores it, `Some(false)` if it is
+    /// re-included with `!` and `None` if no rule matches.
+    fn matches(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.dir).ok()?;
+        let relative = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+            .chars()
+            .collect::<Vec<_>>();
+        let name = path
+            .file_name()?
+            .to_string_lossy()
+            .chars()
+            .collect::<Vec<_>>();
+
+        self.rules
+            .iter()
+            .rev()
+            .filter(|rule| is_dir || !rule.dir_only)
+            .find(|rule| {
+                let text = if rule.anchored { &relative } else { &name };
+                glob_match(&rule.pattern, text)
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+/// Whether `path` is excluded by the given ignore files, ordered from the outermost to the
+/// innermost directory. Rules of deeper files take precedence.
+pub fn is_ignored(ignore_files: &[IgnoreFile], path: &Path, is_dir: bool) -> bool {
+    ignore_files
+        .iter()
+        .rev()
+        .find_map(|ignore_file| ignore_file.matches(path, is_dir))
+        .unwrap_or(false)
+}
+
+/// Dot files and directories like `.Trash-1000` or `.snapshots`
+pub fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+/// Matches `text` against a glob supporting `*`, `**`, `?`, `[...]` classes and `\` escapes.
+/// Only `**` matches across `/`.
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // `a/**/b` also matches `a/b`
+            if let ['/', after @ ..] = rest {
+                if glob_match(after, text) {
+                    return true;
+                }
+            }
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        ['*', rest @ ..] => {
+            for i in 0..=text.len() {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        ['?', rest @ ..] => match text {
+            [c, text @ ..] => *c != '/' && glob_match(rest, text),
+            [] => false,
+        },
+        ['[', class @ ..] => match (class_match(class, text.first()), text) {
+            (Some((matched, rest)), [_, text @ ..]) => matched && glob_match(rest, text),
+            (Some(_), []) => false,
+            // an unclosed `[` is a literal
+            (None, [c, text @ ..]) => *c == '[' && glob_match(class, text),
+            (None, []) => false,
+        },
+        ['\\', c, rest @ ..] | [c, rest @ ..] => match text {
+            [t, text @ ..] => c == t && glob_match(rest, text),
+            [] => false,
+        },
+    }
+}
+
+/// Matches `c` against the class starting after `[`, returns whether it matched and the
+/// pattern after the closing `]`, or `None` if the class isn't closed.
+fn class_match<'a>(class: &'a [char], c: Option<&char>) -> Option<(bool, &'a [char])> {
+    let (negated, class) = match class {
+        ['!' | '^', class @ ..] => (true, class),
+        class => (false, class),
+    };
+    // a `]` right after the opening bracket is part of the class
+    let end = class.iter().skip(1).position(|c| *c == ']')? + 1;
+    let (members, rest) = (&class[..end], &class[end + 1..]);
+
+    let Some(&c) = c else {
+        return Some((false, rest));
+    };
+    let mut matched = false;
+    let mut i = 0;
+    while i < members.len() {
+        if i + 2 < members.len() && members[i + 1] == '-' {
+            matched |= (members[i]..=members[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            matched |= members[i] == c;
+            i += 1;
+        }
+    }
+
+    Some((matched != negated && c != '/', rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str, text: &str) -> bool {
+        glob_match(
+            &pattern.chars().collect::<Vec<_>>(),
+            &text.chars().collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob("*.mp4", "lecture 01.mp4"));
+        assert!(!glob("*.mp4", "raw/lecture 01.mp4"));
+        assert!(glob("lecture ??.mp4", "lecture 01.mp4"));
+        assert!(glob("raw/**", "raw/a/b.mp4"));
+        assert!(glob("**/thumbs", "a/b/thumbs"));
+        assert!(glob("a/**/b", "a/b"));
+        assert!(glob("a/**/b", "a/x/y/b"));
+        assert!(!glob("a/*/b", "a/x/y/b"));
+    }
+
+    #[test]
+    fn glob_classes_and_escapes() {
+        assert!(glob("take[0-9].mov", "take3.mov"));
+        assert!(!glob("take[!0-9].mov", "take3.mov"));
+        assert!(glob("take[]x].mov", "take].mov"));
+        assert!(glob("[unclosed", "[unclosed"));
+        assert!(glob("\\*.mp4", "*.mp4"));
+        assert!(!glob("\\*.mp4", "a.mp4"));
+    }
+
+    #[test]
+    fn rules_are_relative_and_last_match_wins() {
+        let dir = Path::new("/videos");
+        let ignore_file = IgnoreFile::parse(
+            dir,
+            "# synology thumbnails\n@eaDir/\n/raw\n*.mov\n!keep.mov\n",
+        );
+        let ignored = |path: &str, is_dir| ignore_file.matches(&dir.join(path), is_dir);
+
+        assert_eq!(ignored("a/@eaDir", true), Some(true));
+        assert_eq!(ignored("a/@eaDir", false), None);
+        assert_eq!(ignored("raw", true), Some(true));
+        assert_eq!(ignored("a/raw", true), None);
+        assert_eq!(ignored("a/b.mov", false), Some(true));
+        assert_eq!(ignored("a/keep.mov", false), Some(false));
+        assert_eq!(ignored("a/b.mp4", false), None);
+    }
+
+    #[test]
+    fn deeper_ignore_files_take_precedence() {
+        let ignore_files = [
+            IgnoreFile::parse(Path::new("/videos"), "*.mov\n"),
+            IgnoreFile::parse(Path::new("/videos/keep"), "!*.mov\n"),
+        ];
+
+        assert!(is_ignored(&ignore_files, Path::new("/videos/a.mov"), false));
+        assert!(!is_ignored(
+            &ignore_files,
+            Path::new("/videos/keep/a.mov"),
+            false
+        ));
+        assert!(!is_ignored(
+            &ignore_files,
+            Path::new("/videos/a.mp4"),
+            false
+        ));
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    cell::Cell,
+    io::{BufRead, Write},
+    path::Path,
+};
+
+use crate::{display_duration, filesize, probe, Encoded};
+
+/// Compressed files whose length is off by more than this many seconds are pointed out
+const DURATION_TOLERANCE: f64 = 1.0;
+
+/// How the question before replacing an original was answered
+#[derive(Debug, PartialEq)]
+pub enum Answer {
+    Yes,
+    No,
+    /// Replace this and all further originals without asking
+    Always,
+    /// Keep the compressed file next to the original and end the run
+    Quit,
+}
+
+/// Asks before every original is replaced with `--interactive`, until one is answered with
+/// "always"
+#[derive(Default)]
+pub struct Interactive(Cell<bool>);
+
+impl Interactive {
+    pub fn enabled() -> Self {
+        Interactive(Cell::new(true))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.get()
+    }
+
+    /// Shows the sizes of `original` and its compressed file and how their lengths compare,
+    /// then asks whether to replace it. `Yes` without asking once it's disabled.
+    pub fn confirm(&self, original: &Path, size_prev: u64, encoded: &Encoded) -> Answer {
+        if !self.is_enabled() {
+            return Answer::Yes;
+        }
+        let delta = encoded.size_post as i64 - size_prev as i64;
+        info!(
+            "`{}`: {} -> {} ({})",
+            original.to_string_lossy(),
+            filesize::display(size_prev),
+            filesize::display(encoded.size_post),
+            filesize::display_delta(delta)
+        );
+        let compressed_duration = probe::duration(&encoded.dest);
+        // the compressed file is renamed over the original, its entry would only linger
+        probe::forget(&encoded.dest);
+        info!(
+            "Length: {}",
+            duration_check(encoded.settings.duration, compressed_duration)
+        );
+        if !encoded.lost_streams.is_empty() {
+            info!("Lacks {} of the original", encoded.lost_streams.join(", "));
+        }
+
+        let answer = ask();
+        if answer == Answer::Always {
+            info!("Replacing this and the remaining originals without asking");
+            self.0.set(false);
+        }
+        answer
+    }
+}
+
+/// Asks until the answer is understood, the end of stdin counts as quitting
+fn ask() -> Answer {
+    let mut stdin = std::io::stdin().lock();
+    loop {
+        print!("Replace the original? [y/N/a(lways)/q(uit)] ");
+        let _ = std::io::stdout().flush();
+        let mut line = String::new();
+        match stdin.read_line(&mut line) {
+            Ok(0) | Err(_) => {
+                info!("");
+                return Answer::Quit;
+            }
+            Ok(_) => {}
+        }
+        match parse(&line) {
+            Some(answer) => return answer,
+            None => info!("Please answer y, n, a or q"),
+        }
+    }
+}
+
+fn parse(line: &str) -> Option<Answer> {
+    match line.trim().to_lowercase().as_str() {
+        "y" | "yes" => Some(Answer::Yes),
+        "" | "n" | "no" => Some(Answer::No),
+        "a" | "always" => Some(Answer::Always),
+        "q" | "quit" => Some(Answer::Quit),
+        _ => None,
+    }
+}
+
+/// Length the compressed file should have after the trim and the length it has
+fn duration_check(expected: Option<f64>, actual: Option<f64>) -> String {
+    match (expected, actual) {
+        (Some(expected), Some(actual)) if (expected - actual).abs() <= DURATION_TOLERANCE => {
+            format!("{} (matches the original)", display_duration(actual))
+        }
+        (Some(expected), Some(actual)) => format!(
+            "{} -> {}, differs by {:.1} seconds, check the compressed file before replacing",
+            display_duration(expected),
+            display_duration(actual),
+            (expected - actual).abs()
+        ),
+        (None, Some(actual)) => format!("{}, the original's is unknown", display_duration(actual)),
+        (_, None) => "unknown, the compressed file couldn't be probed".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_default_to_no_and_lengths_are_compared() {
+        assert_eq!(parse("y\n"), Some(Answer::Yes));
+        assert_eq!(parse(" Always\n"), Some(Answer::Always));
+        assert_eq!(parse("\n"), Some(Answer::No));
+        assert_eq!(parse("q"), Some(Answer::Quit));
+        assert_eq!(parse("maybe"), None);
+
+        assert_eq!(
+            duration_check(Some(60.4), Some(60.0)),
+            "00:01:00 (matches the original)"
+        );
+        assert!(duration_check(Some(60.0), Some(55.0)).contains("differs by 5.0 seconds"));
+        assert!(duration_check(Some(60.0), None).starts_with("unknown"));
+    }
+}
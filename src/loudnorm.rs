@@ -0,0 +1,107 @@
+use std::{path::Path, process::Command};
+
+use serde::Deserialize;
+
+use crate::paths;
+
+/// EBU R128 target of `--loudnorm`: integrated loudness, loudness range and true peak
+const TARGET: &str = "I=-23:LRA=7:TP=-2";
+
+/// Values printed by the first pass with `print_format=json`
+#[derive(Deserialize)]
+struct Measurement {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    target_offset: String,
+}
+
+/// Filter of a single pass, which adjusts the gain dynamically as it goes
+pub fn single_pass() -> String {
+    format!("loudnorm={TARGET}")
+}
+
+/// Measures the loudness of the audio and returns the filter of the second pass, which
+/// normalizes linearly with the measured values. `input_args` trims the measured part like
+/// the encode.
+pub fn two_pass(path: &Path, input_args: &[String]) -> Result<String, String> {
+    let output = Command::new("ffmpeg")
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .args(input_args)
+        .arg("-i")
+        .arg(paths::for_ffmpeg(path))
+        .arg("-vn")
+        .arg("-af")
+        .arg(format!("loudnorm={TARGET}:print_format=json"))
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .output()
+        .map_err(|e| format!("Failed to run ffmpeg: {e}"))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        let last_line = stderr.lines().last().unwrap_or_default();
+        return Err(format!("ffmpeg failed: {last_line}"));
+    }
+
+    let measurement = parse_measurement(&stderr)?;
+    Ok(format!(
+        "loudnorm={TARGET}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+        measurement.input_i,
+        measurement.input_tp,
+        measurement.input_lra,
+        measurement.input_thresh,
+        measurement.target_offset
+    ))
+}
+
+/// The json object is the last thing loudnorm prints, after its `[Parsed_loudnorm_0 @ ...]`
+/// line
+fn parse_measurement(stderr: &str) -> Result<Measurement, String> {
+    let json = stderr
+        .rfind('{')
+        .zip(stderr.rfind('}'))
+        .and_then(|(start, end)| stderr.get(start..=end))
+        .ok_or("loudnorm printed no measurement")?;
+    let measurement = serde_json::from_str::<Measurement>(json)
+        .map_err(|e| format!("Failed to parse the loudnorm measurement: {e}"))?;
+
+    // silent audio measures `-inf`, which the second pass doesn't accept
+    let values = [
+        &measurement.input_i,
+        &measurement.input_tp,
+        &measurement.input_lra,
+        &measurement.input_thresh,
+        &measurement.target_offset,
+    ];
+    if values
+        .iter()
+        .any(|value| !value.parse::<f64>().is_ok_and(f64::is_finite))
+    {
+        return Err(format!(
+            "Measured loudness of {} LUFS can't be normalized linearly",
+            measurement.input_i
+        ));
+    }
+
+    Ok(measurement)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_loudnorm_json() {
+        let stderr = "Output #0, null, to 'pipe:':\n[Parsed_loudnorm_0 @ 0x55d1c8]\n{\n\t\"input_i\" : \"-27.61\",\n\t\"input_tp\" : \"-4.47\",\n\t\"input_lra\" : \"18.06\",\n\t\"input_thresh\" : \"-39.20\",\n\t\"output_i\" : \"-23.00\",\n\t\"output_tp\" : \"-2.00\",\n\t\"output_lra\" : \"7.00\",\n\t\"output_thresh\" : \"-34.32\",\n\t\"normalization_type\" : \"dynamic\",\n\t\"target_offset\" : \"0.03\"\n}\n";
+        let measurement = parse_measurement(stderr).unwrap();
+        assert_eq!(measurement.input_i, "-27.61");
+        assert_eq!(measurement.target_offset, "0.03");
+
+        let silent = stderr.replace("\"-27.61\"", "\"-inf\"");
+        assert!(parse_measurement(&silent).is_err());
+        assert!(parse_measurement("Conversion failed!").is_err());
+    }
+}
@@ -1,27 +1,222 @@
 use std::{
+    cell::{Cell, RefCell},
     collections::HashMap,
+    ffi::OsString,
     fmt::Display,
     fs::File,
-    io::{BufRead, BufReader, Error, Read, Write},
-    path::PathBuf,
+    io::{BufReader, Error, Read, Write},
+    path::{Path, PathBuf},
     process::{Command, Stdio},
-    time::SystemTime,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        OnceLock,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
-use regex::Regex;
+use config::Config;
+use events::Event;
+use interactive::Answer;
+use options::{
+    AudioCodec, Command as Mode, Deinterlace, Loudnorm, Metadata, OnCollision, OnStreamLoss,
+    Options, Profile, Since, USAGE,
+};
+use progress::{ProgressReader, RecordSplitter};
 use serde::{Deserialize, Serialize};
+use shards::Sharded;
+use sidecar::{Sidecar, SidecarCodec};
 
-macro_rules! filetype_check {
-    ($path:ident, $($type:literal),*) => {
-        ($($path.ends_with($type)) ||*) && !($($path.ends_with(&($type.to_string() + "_x265.mp4"))) ||*)
-    };
+/// Whether messages go to stderr because stdout carries the video (`--stdout`)
+static MESSAGES_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Set by the first call to `run_id`
+static RUN_ID: OnceLock<String> = OnceLock::new();
+
+/// `println!` for messages, which go to stderr with `--stdout`. Unlike `println!` it ignores a
+/// closed or full stdout, e.g. of a dropped ssh session, which mustn't end a long run.
+macro_rules! info {
+    ($($arg:tt)*) => {{
+        use std::io::Write as _;
+        let _ = if $crate::MESSAGES_TO_STDERR.load(std::sync::atomic::Ordering::Relaxed) {
+            writeln!(std::io::stderr(), $($arg)*)
+        } else {
+            writeln!(std::io::stdout(), $($arg)*)
+        };
+    }};
 }
 
+mod archive;
+mod atime;
+mod auto_crf;
+mod bench;
+mod cancel;
+mod clean;
+mod codecs;
+mod color;
+mod compare;
+mod config;
+mod dedupe;
+mod disk;
+mod doctor;
+mod email;
+mod encoder;
+mod estimate;
+mod events;
+mod explain;
+#[cfg(test)]
+mod ffmpeg_tests;
+mod filesize;
+mod filter;
+mod fingerprint;
+mod ignore;
+mod interactive;
+mod loudnorm;
+mod manifest;
+mod mounts;
+mod options;
+mod paths;
+mod prefetch;
+mod probe;
+mod processed;
+mod progress;
+mod rebuild;
+mod remote;
+mod retry;
+mod run_progress;
+mod sample;
+mod savings;
+mod shards;
+mod sidecar;
+mod status;
+mod stream;
+mod stream_loss;
+mod subtitles;
+mod systemd;
+#[cfg(test)]
+mod temp_dir;
+mod throttle;
+mod times;
+mod trash;
+mod version;
+mod versions;
+mod walk;
+
 enum SkipReason {
     Metadata(Error),
     ReadDir(Error),
     Override(Error),
+    /// The original couldn't be replaced for lack of permissions, e.g. owned by another user
+    PermissionDenied(String),
     OpeningCompressedFile(Error),
+    DestinationNotWritable(Error),
+    InvalidSidecar(String),
+    /// Error of the `.compressor.toml` of a directory, which is left out with everything below it
+    InvalidDirConfig(String),
+    /// Directory reached again, with the path it was walked as first
+    AlreadyWalked(PathBuf),
+    /// Original kept because `--interactive` was answered with no, or with quit, which kept
+    /// the compressed file at this path
+    Declined(Option<PathBuf>),
+    /// Directory on a network filesystem or removable drive with `--local-only`
+    NotLocal(String),
+    SkippedBySidecar,
+    EncodeFailed(String),
+    DestinationExists(PathBuf),
+    ChangedDuringCompression,
+    Remote(String),
+    CorruptInput(String),
+    Encrypted(String),
+    /// Available and required free space in bytes at the destination
+    OutOfSpace(u64, u64),
+    NotEnoughSpace(u64, u64),
+    /// Video encoder that failed to start, after trying the fallbacks
+    EncoderUnavailable(String),
+    /// Hardware encoder without a free session, which only this video falls back from
+    EncoderBusy(String),
+    /// Hardware decoder of `--hwdecode` that failed, `true` if its device couldn't be opened at
+    /// all. The video is compressed again with software decoding.
+    HwDecodeFailed(&'static str, bool),
+    /// ffmpeg itself couldn't be started, which stops the run
+    FfmpegNotStarted(Error),
+    /// ffmpeg was stopped for taking too long or hanging
+    Timeout(String),
+    /// The system clock is set before 1970, the log couldn't tell when the file was compressed
+    ClockBeforeEpoch,
+    Duplicate(String),
+    Cancelled,
+    AlreadyTagged(String),
+    BelowBitrate(u64, u64),
+    /// Bytes of the file and `--min-size`
+    BelowSize(u64, u64),
+    /// Seconds of the video and `--min-duration`
+    BelowDuration(f64, f64),
+    /// Lines of the video and the bound of the range
+    BelowResolution(u32, u32),
+    AboveResolution(u32, u32),
+    NoVideoStream,
+    TrimExceedsDuration(f64, f64, f64),
+    UnknownDuration,
+    UnknownFrameRate,
+    /// Streams of the original missing from the compressed file with `--on-stream-loss skip`
+    StreamsLost(Vec<String>),
+}
+
+impl SkipReason {
+    /// Whether the file couldn't be processed because something went wrong, rather than
+    /// being skipped on purpose
+    fn is_failure(&self) -> bool {
+        use SkipReason::*;
+        match self {
+            Metadata(_)
+            | ReadDir(_)
+            | Override(_)
+            | PermissionDenied(_)
+            | OpeningCompressedFile(_)
+            | DestinationNotWritable(_)
+            | InvalidSidecar(_)
+            | InvalidDirConfig(_)
+            | UnknownDuration
+            | UnknownFrameRate
+            | Remote(_)
+            | CorruptInput(_)
+            | OutOfSpace(..)
+            | Timeout(_)
+            | ClockBeforeEpoch
+            | EncoderUnavailable(_)
+            | EncoderBusy(_)
+            | HwDecodeFailed(..)
+            | FfmpegNotStarted(_)
+            | EncodeFailed(_) => true,
+            // the next run picks up the changed file
+            ChangedDuringCompression
+            | Duplicate(_)
+            | Cancelled
+            | Encrypted(_)
+            | AlreadyWalked(_)
+            | NotLocal(_)
+            | Declined(_)
+            | NotEnoughSpace(..)
+            | AlreadyTagged(_)
+            | DestinationExists(_)
+            | BelowBitrate(..)
+            | BelowSize(..)
+            | BelowDuration(..)
+            | BelowResolution(..)
+            | AboveResolution(..)
+            | NoVideoStream
+            | TrimExceedsDuration(..)
+            | StreamsLost(_)
+            | SkippedBySidecar => false,
+        }
+    }
+
+    /// Whether `--retry-failed` tries the file again, which is worth it for failures and
+    /// missing space, which the user can fix, but not for the skips that are on purpose
+    fn is_retryable(&self) -> bool {
+        self.is_failure() || matches!(self, SkipReason::NotEnoughSpace(..))
+    }
 }
 
 impl Display for SkipReason {
@@ -31,380 +226,3390 @@ impl Display for SkipReason {
             Metadata(e) => write!(f, "Failed to read metadata: {e}"),
             ReadDir(e) => write!(f, "Failed to read directory: {e}"),
             Override(e) => write!(f, "Failed to override file: {e}"),
+            PermissionDenied(e) => write!(f, "Not allowed to replace the original: {e}"),
             OpeningCompressedFile(e) => {
                 write!(f, "Failed to open compressed file to read size: {e}")
             }
+            DestinationNotWritable(e) => write!(f, "Destination not writable: {e}"),
+            InvalidSidecar(e) => write!(f, "{e}"),
+            InvalidDirConfig(e) => write!(f, "{e}"),
+            AlreadyWalked(first) => write!(
+                f,
+                "Same directory as `{}`, which is already walked",
+                first.to_string_lossy()
+            ),
+            Declined(None) => write!(f, "Not replaced, declined with `--interactive`"),
+            Declined(Some(kept)) => write!(
+                f,
+                "Not replaced, the run was quit with `--interactive` and the compressed file kept at `{}`",
+                kept.to_string_lossy()
+            ),
+            NotLocal(kind) => write!(f, "On a {kind}, not walked (`--local-only`)"),
+            SkippedBySidecar => write!(f, "Skipped by `skip = true` in the sidecar"),
+            EncodeFailed(e) => write!(f, "Encoding failed: {e}"),
+            DestinationExists(dest) => write!(
+                f,
+                "`{}` already exists (`--on-collision skip`)",
+                dest.to_string_lossy()
+            ),
+            Remote(e) => write!(f, "{e}"),
+            CorruptInput(e) => write!(f, "Input is corrupt or unreadable: {e}"),
+            Encrypted(e) => write!(f, "Input is encrypted or DRM protected: {e}"),
+            Timeout(reason) => write!(f, "Timed out, {reason}"),
+            EncoderUnavailable(encoder) => {
+                write!(f, "Video encoder {encoder} failed to start")
+            }
+            EncoderBusy(encoder) => write!(
+                f,
+                "Video encoder {encoder} has no free session, other programs might use the GPU"
+            ),
+            HwDecodeFailed(method, _) => write!(f, "Hardware decoder {method} failed"),
+            FfmpegNotStarted(e) => write!(f, "Failed to run ffmpeg: {e}"),
+            ClockBeforeEpoch => write!(
+                f,
+                "The system clock is set before 1970, set it to compress the file"
+            ),
+            OutOfSpace(available, floor) => write!(
+                f,
+                "Out of space, only {} left at the destination (`--min-free-space` {})",
+                filesize::display(*available),
+                filesize::display(*floor)
+            ),
+            NotEnoughSpace(available, required) => write!(
+                f,
+                "Only {} free at the destination, {} are needed for the output and `--min-free-space`",
+                filesize::display(*available),
+                filesize::display(*required)
+            ),
+            Duplicate(original) => write!(
+                f,
+                "Duplicate of `{original}`, which is already compressed (`--dedupe skip`)"
+            ),
+            Cancelled => write!(f, "Cancelled, the partial output was removed"),
+            AlreadyTagged(tag) => write!(
+                f,
+                "Already compressed by video_compressor according to its tag `{tag}` (use `--ignore-tag` to compress it again)"
+            ),
+            ChangedDuringCompression => write!(
+                f,
+                "File was changed or replaced during the compression, keeping it"
+            ),
+            BelowBitrate(bitrate, threshold) => write!(
+                f,
+                "Video bitrate of {bitrate}kbps is already below {threshold}kbps"
+            ),
+            BelowSize(size, min) => write!(
+                f,
+                "Size of {} is below --min-size {}",
+                filesize::display(*size),
+                filesize::display(*min)
+            ),
+            BelowDuration(duration, min) => write!(
+                f,
+                "Duration of {duration:.1}s is below --min-duration {min}s"
+            ),
+            BelowResolution(lines, min) => {
+                write!(f, "Resolution of {lines}p is below --min-resolution {min}p")
+            }
+            AboveResolution(lines, max) => {
+                write!(f, "Resolution of {lines}p is above --max-resolution {max}p")
+            }
+            TrimExceedsDuration(start, end, duration) => write!(
+                f,
+                "Trimming {start}s from the start and {end}s from the end leaves nothing of the {duration:.1}s video"
+            ),
+            StreamsLost(streams) => write!(
+                f,
+                "The compressed file lacks {} of the original (--on-stream-loss skip)",
+                streams.join(", ")
+            ),
+            UnknownDuration => write!(f, "Failed to probe the duration needed for `--trim-end`"),
+            UnknownFrameRate => write!(
+                f,
+                "Failed to probe the frame rate needed for `--keyframe-seconds`"
+            ),
+            NoVideoStream => write!(
+                f,
+                "File has no video stream (use `--audio-only-mode reencode` to compress its audio)"
+            ),
         }
     }
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 struct FileLog {
     pub size_prev: u64,
     pub size_post: u64,
-    pub modified: u64,
+    pub modified: i64,
+    #[serde(default)]
+    pub crf: Option<u8>,
+    /// Sample score measured by `--auto-crf` for the chosen CRF
+    #[serde(default)]
+    pub auto_crf_score: Option<f64>,
+    /// VBV settings in kbit/s and kbit, set with `--maxrate`/`--bufsize`
+    #[serde(default)]
+    pub maxrate: Option<u64>,
+    #[serde(default)]
+    pub bufsize: Option<u64>,
+    /// File had no video stream and only its audio was re-encoded
+    #[serde(default)]
+    pub audio_only: bool,
+    /// Duration in seconds of the output if it was trimmed with `--trim-start`/`--trim-end`
+    #[serde(default)]
+    pub trimmed_duration: Option<f64>,
+    /// Video stream was copied into a new container instead of being re-encoded
+    #[serde(default)]
+    pub remuxed: bool,
+    #[serde(default)]
+    pub deinterlaced: bool,
+    /// Audio streams were dropped with `--no-audio`
+    #[serde(default)]
+    pub no_audio: bool,
+    /// Audio was downmixed to stereo with `--downmix-stereo`
+    #[serde(default)]
+    pub downmixed: bool,
+    /// Index into `Log::runs` of the run that compressed the file
+    #[serde(default)]
+    pub run: Option<usize>,
+    /// `run_id` of the run that added the entry, see `--list-processed`
+    #[serde(default)]
+    pub run_id: Option<String>,
+    /// Overrides applied from the `<video>.vc.toml` sidecar and the rule for the extension in
+    /// the config
+    #[serde(default)]
+    pub sidecar: Option<Sidecar>,
+    /// GOP limits in frames passed to x265, set with `--keyint`/`--keyframe-seconds` and
+    /// `--min-keyint`
+    #[serde(default)]
+    pub keyint: Option<u32>,
+    #[serde(default)]
+    pub min_keyint: Option<u32>,
+    /// Duration in seconds of the encoded video as probed before the encode
+    #[serde(default)]
+    pub duration: Option<f64>,
+    /// Wall-clock seconds spent running ffmpeg
+    #[serde(default)]
+    pub encode_time: Option<f64>,
+    /// Content of the original, recorded with `--dedupe`
+    #[serde(default)]
+    pub fingerprint: Option<dedupe::Fingerprint>,
+    /// Compressed file that was copied over this duplicate instead of encoding it again
+    #[serde(default)]
+    pub deduplicated_from: Option<String>,
+    /// loudnorm filter applied with `--loudnorm`, including the values measured by the first
+    /// pass of `--loudnorm-two-pass`
+    #[serde(default)]
+    pub loudnorm: Option<String>,
+    /// Where `--trash` moved the original
+    #[serde(default)]
+    pub trashed: Option<String>,
+    /// Video encoder if it wasn't libx265, after falling back to another one
+    #[serde(default)]
+    pub encoder: Option<String>,
+    /// Recorded by `--rebuild-log` from a file that was already compressed, `size_prev` is
+    /// unknown and set to `size_post`
+    #[serde(default)]
+    pub imported: bool,
+    /// `--profile` the file was encoded with
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Encoder preset if one was given, ffmpeg's default otherwise
+    #[serde(default)]
+    pub preset: Option<String>,
+    /// Frame rates of the original and the output, probed for `--max-fps` and
+    /// `--keyframe-seconds`
+    #[serde(default)]
+    pub source_fps: Option<f64>,
+    #[serde(default)]
+    pub output_fps: Option<f64>,
+    /// Subtitle files embedded with `--embed-subtitles`
+    #[serde(default)]
+    pub subtitles: Vec<String>,
+    /// Streams of the original the compressed file doesn't have, e.g. `audio #2 (ac3)`
+    #[serde(default)]
+    pub lost_streams: Vec<String>,
+    /// The index is at the start of the file (`--faststart`), so it plays while downloading
+    #[serde(default)]
+    pub faststart: bool,
+    /// ffmpeg arguments of the encoders as they were used, e.g. `-c:v libx265 -preset slow
+    /// -x265-params crf=23:...`, with the filters and the audio encoder
+    #[serde(default)]
+    pub codec_args: Vec<String>,
+}
+
+impl FileLog {
+    /// Realtime factor of the encode, e.g. 2.0 for an hour long video encoded in 30 minutes
+    fn speed(&self) -> Option<f64> {
+        match (self.duration, self.encode_time) {
+            (Some(duration), Some(encode_time)) if encode_time > 0.0 => {
+                Some(duration / encode_time)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the run asks for another CRF, preset or encoder than the file was encoded with.
+    /// Only settings that are known without looking at the video are compared, and a sidecar
+    /// decides the settings of its video itself.
+    fn settings_differ(&self, path: &Path, options: &Options) -> bool {
+        if self.sidecar.is_some() {
+            return false;
+        }
+        // only re-encoded files have a CRF, remuxed and audio only ones have no encode settings
+        let reencoded = self.crf.is_some();
+        let crf_differs = matches!(
+            (self.crf, requested_crf(path, options)),
+            (Some(crf), Some(requested)) if crf != requested
+        );
+        // a file encoded without `--preset` isn't known to match the preset the run asks for
+        let preset_differs = reencoded
+            && options
+                .preset
+                .as_ref()
+                .is_some_and(|requested| self.preset.as_ref() != Some(requested));
+        // the encoder the run uses rather than the preferred one, or a build without it would
+        // compress its fallback's files again on every run
+        let encoder_differs = reencoded
+            && self
+                .encoder
+                .as_deref()
+                .unwrap_or(encoder::DEFAULT_ENCODERS[0])
+                != options.encoders.current();
+        crf_differs || preset_differs || encoder_differs
+    }
+}
+
+/// CRF the run encodes `path` with if it was asked for with `--crf` or a config and doesn't
+/// depend on the video, like a resolution tier or `--auto-crf` would
+fn requested_crf(path: &Path, options: &Options) -> Option<u8> {
+    if options.auto_crf.is_some() {
+        return None;
+    }
+    if options.crf.is_some() {
+        return options.crf;
+    }
+    let dir_config = options.dir_configs.get(path);
+    let config = dir_config.as_deref().unwrap_or(&options.config);
+    if config.has_crf_tiers() {
+        None
+    } else {
+        config.crf
+    }
+}
+
+/// Why a file or directory is listed for `--retry-failed`
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "StoredRetry")]
+struct RetryEntry {
+    reason: String,
+    /// Unix time of the failure, `None` for entries of logs from before it was recorded
+    recorded: Option<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredRetry {
+    Entry {
+        reason: String,
+        recorded: Option<i64>,
+    },
+    /// Older logs only have the reason
+    Reason(String),
+}
+
+impl From<StoredRetry> for RetryEntry {
+    fn from(stored: StoredRetry) -> Self {
+        match stored {
+            StoredRetry::Entry { reason, recorded } => RetryEntry { reason, recorded },
+            StoredRetry::Reason(reason) => RetryEntry {
+                reason,
+                recorded: None,
+            },
+        }
+    }
+}
+
+/// Encoder builds used by one run, recorded once the run compresses its first file
+#[derive(Serialize, Deserialize)]
+struct Run {
+    /// Time the first file of the run was compressed
+    recorded: i64,
+    ffmpeg: Option<String>,
+    /// Where the ffmpeg in PATH was found
+    #[serde(default)]
+    ffmpeg_path: Option<String>,
+    x265: Option<String>,
+    /// The ffprobe the files were checked and probed with
+    #[serde(default)]
+    ffprobe: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 struct Log {
-    shrunk_files: HashMap<String, FileLog>,
+    shrunk_files: Sharded<FileLog>,
     added_files: HashMap<String, FileLog>,
     skipped_files: HashMap<String, String>,
+    #[serde(default)]
+    failed_files: HashMap<String, String>,
+    /// Encrypted files, listed apart from other skips since they can't be compressed at all
+    #[serde(default)]
+    encrypted_files: HashMap<String, String>,
+    /// Files and directories that failed or lacked the space in an earlier run and haven't
+    /// been processed since, with the reason (`--retry-failed`). Unlike the lists above, these
+    /// survive the overview.
+    #[serde(default)]
+    retry_files: HashMap<String, RetryEntry>,
+    /// Start of the last run that iterated over the whole directory
+    #[serde(default)]
+    last_run: Option<i64>,
+    #[serde(default)]
+    runs: Vec<Run>,
+    /// Cached results of the readable input check, behind a `RefCell` so the checks can
+    /// fill it through the shared log
+    #[serde(default)]
+    input_checks: RefCell<Sharded<filter::InputCheck>>,
+    /// Number of shards of `shards::SHARD_DIR` the compressed files and input checks are kept
+    /// in once the log grew past `shards::COMPACT_SIZE`, `None` while they are in this file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shards: Option<usize>,
+    /// Compressed files by the quick fingerprint of their original for `--dedupe`, built by
+    /// the first lookup
+    #[serde(skip)]
+    fingerprints: RefCell<Option<HashMap<String, Vec<String>>>>,
 
     #[serde(skip)]
     save_file: String,
+    /// Index into `runs` for this run, once it compressed a file
+    #[serde(skip)]
+    current_run: Option<usize>,
+    /// Set by `--stop-on-low-space`, ffmpeg not starting or quitting `--interactive`, no further
+    /// files are started
+    #[serde(skip)]
+    stopped: bool,
+    /// The last save failed, which is only warned about once until a save works again
+    #[serde(skip)]
+    save_failed: Cell<bool>,
+    /// Indent the JSON, set by `--clean-log --pretty`
+    #[serde(skip)]
+    pretty: bool,
 }
 
 impl Log {
+    /// Log of the directory `path`, with its paths in the absolute form of `paths::absolute`
     pub fn new(path: String) -> Self {
-        let path = path + "/compression_log.json";
+        let mut log = Log::load(path);
+        log.normalize_keys();
+        log.compact_if_large();
+        log
+    }
+
+    /// Log of the directory `path` with its paths as they were stored, for logs of remote runs
+    /// that refer to paths on the server
+    pub fn load(path: String) -> Self {
+        probe::open_cache(Path::new(&path));
+        let path = Path::new(&path)
+            .join(LOG_FILE)
+            .to_string_lossy()
+            .to_string();
         if let Ok(log_file) = File::open(path.clone()) {
-            match serde_json::from_reader::<BufReader<File>, Log>(BufReader::new(log_file)) {
-                Ok(mut cache) => {
-                    cache.save_file = path;
-                    return cache;
-                }
-                Err(_) => {}
+            if let Ok(mut cache) =
+                serde_json::from_reader::<BufReader<File>, Log>(BufReader::new(log_file))
+            {
+                cache.save_file = path;
+                cache.open_shards();
+                return cache;
             };
         };
 
         // if file doesn't exist or problems while opening just create a new log and ignore it
         Log {
-            shrunk_files: HashMap::new(),
+            shrunk_files: Sharded::default(),
             added_files: HashMap::new(),
             skipped_files: HashMap::new(),
+            failed_files: HashMap::new(),
+            encrypted_files: HashMap::new(),
+            retry_files: HashMap::new(),
+            last_run: None,
+            runs: Vec::new(),
+            input_checks: RefCell::default(),
+            shards: None,
+            fingerprints: RefCell::default(),
             save_file: path,
+            current_run: None,
+            stopped: false,
+            save_failed: Cell::new(false),
+            pretty: false,
+        }
+    }
+
+    /// Log that is never written to disk, for runs that must not affect the real log
+    pub fn in_memory() -> Self {
+        Log {
+            shrunk_files: Sharded::default(),
+            added_files: HashMap::new(),
+            skipped_files: HashMap::new(),
+            failed_files: HashMap::new(),
+            encrypted_files: HashMap::new(),
+            retry_files: HashMap::new(),
+            last_run: None,
+            runs: Vec::new(),
+            input_checks: RefCell::default(),
+            shards: None,
+            fingerprints: RefCell::default(),
+            save_file: String::new(),
+            current_run: None,
+            stopped: false,
+            save_failed: Cell::new(false),
+            pretty: false,
         }
     }
 
-    pub fn is_already_processed(&self, path: &String, modified_time: u64) -> bool {
-        self.shrunk_files.contains_key(path)
-            && self.shrunk_files.get(path).unwrap().modified >= modified_time
+    /// Whether the file was compressed after it was last modified, with the CRF and preset
+    /// the run asks for
+    pub fn is_already_processed(
+        &self,
+        path: &String,
+        modified_time: i64,
+        options: &Options,
+    ) -> bool {
+        self.shrunk_files.get(path).is_some_and(|file_log| {
+            file_log.modified >= modified_time
+                && !file_log.settings_differ(Path::new(path), options)
+        })
     }
 
-    pub fn mark_processed(&mut self, path: String, prev: u64, post: u64) {
-        let modified = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-            Ok(d) => d.as_secs(),
-            Err(e) => {
-                self.save();
-                panic!("Unable to retrieve system time!\n{e}");
+    /// The file was compressed with another CRF, preset or encoder than the run asks for, so
+    /// it's compressed again even though it's tagged
+    pub fn settings_changed(&self, path: &str, options: &Options) -> bool {
+        self.shrunk_files
+            .get(path)
+            .is_some_and(|file_log| file_log.settings_differ(Path::new(path), options))
+    }
+
+    pub fn mark_processed(&mut self, path: String, prev: u64, encoded: &Encoded) {
+        let settings = &encoded.settings;
+        let reencoded = !settings.audio_only && !settings.remux;
+        let modified = unix_time(SystemTime::now());
+
+        let file_log = FileLog {
+            size_prev: prev,
+            size_post: encoded.size_post,
+            modified,
+            crf: reencoded.then_some(settings.crf),
+            auto_crf_score: settings.auto_crf_score,
+            maxrate: settings.maxrate,
+            bufsize: settings.bufsize,
+            audio_only: settings.audio_only,
+            trimmed_duration: settings.trim.and(settings.duration),
+            remuxed: settings.remux,
+            deinterlaced: settings.deinterlace,
+            no_audio: settings.no_audio,
+            downmixed: settings.downmix_stereo,
+            run: Some(self.current_run(modified)),
+            run_id: Some(run_id().to_string()),
+            sidecar: settings.sidecar.clone(),
+            keyint: settings.keyint.filter(|_| reencoded),
+            min_keyint: settings.min_keyint.filter(|_| reencoded),
+            duration: settings.duration,
+            encode_time: Some(encoded.encode_time),
+            fingerprint: None,
+            deduplicated_from: None,
+            loudnorm: settings.loudnorm.clone(),
+            trashed: encoded
+                .trashed
+                .as_ref()
+                .map(|trashed| trashed.to_string_lossy().to_string()),
+            encoder: Some(settings.encoder.clone())
+                .filter(|encoder| reencoded && encoder != "libx265"),
+            imported: false,
+            profile: settings
+                .profile
+                .filter(|_| reencoded)
+                .map(|profile| profile.name().to_string()),
+            preset: settings.preset.clone().filter(|_| reencoded),
+            source_fps: settings.source_fps,
+            output_fps: settings.output_fps,
+            subtitles: settings
+                .subtitles
+                .iter()
+                .map(|subtitle| subtitle.path.to_string_lossy().to_string())
+                .collect(),
+            lost_streams: encoded.lost_streams.clone(),
+            faststart: settings.faststart(),
+            codec_args: settings.codec_args(),
+        };
+
+        events::emit(Event::FileDone {
+            path: &path,
+            size_prev: prev,
+            size_post: encoded.size_post,
+            encode_time: Some(encoded.encode_time),
+        });
+        self.retry_files.remove(&path);
+        self.shrunk_files.insert(path.clone(), file_log.clone());
+        self.added_files.insert(path, file_log);
+    }
+
+    /// Records a file compressed before the log was lost, with the settings its tag names
+    pub fn mark_imported(&mut self, path: String, size: u64, modified: i64, tag: Option<&str>) {
+        let settings = tag
+            .and_then(|tag| tag.strip_prefix(TAG_PREFIX))
+            .unwrap_or_default();
+        let crf = settings
+            .split(':')
+            .find_map(|setting| setting.strip_prefix("crf=")?.parse().ok());
+        let file_log = FileLog {
+            size_prev: size,
+            size_post: size,
+            modified,
+            crf,
+            audio_only: settings == "audio-only",
+            remuxed: settings == "codec=copy",
+            run_id: Some(run_id().to_string()),
+            imported: true,
+            ..FileLog::default()
+        };
+        self.retry_files.remove(&path);
+        self.shrunk_files.insert(path, file_log);
+    }
+
+    /// Compressed file whose original had the same content as the candidate at `path`, which
+    /// is hashed in full if its quick fingerprint matches a logged one and compared by that hash
+    pub fn duplicate_of(
+        &self,
+        fingerprint: &mut dedupe::Fingerprint,
+        path: &Path,
+    ) -> Option<String> {
+        let logged_paths = self
+            .fingerprints
+            .borrow_mut()
+            .get_or_insert_with(|| {
+                let mut index = HashMap::<String, Vec<String>>::new();
+                for (path, file_log) in self.shrunk_files.iter() {
+                    if let Some(fingerprint) = &file_log.fingerprint {
+                        let paths = index.entry(fingerprint.quick.clone()).or_default();
+                        paths.push(path.clone());
+                    }
+                }
+                index
+            })
+            .get(&fingerprint.quick)
+            .cloned()?;
+
+        for logged_path in logged_paths {
+            // the entry may have been removed or compressed again since it was indexed
+            let Some(logged) = self
+                .shrunk_files
+                .get(&logged_path)
+                .and_then(|file_log| file_log.fingerprint.as_ref())
+            else {
+                continue;
+            };
+            match fingerprint.matches(path, logged) {
+                Ok(true) => return Some(logged_path),
+                Ok(false) => {}
+                Err(e) => {
+                    info!(
+                        "Failed to hash `{}`, compressing it anyway: {e}",
+                        path.display()
+                    );
+                    return None;
+                }
+            }
+        }
+        None
+    }
+
+    /// Adds a fingerprint to the index of `duplicate_of` once it's built
+    fn index_fingerprint(&self, path: &str, fingerprint: &dedupe::Fingerprint) {
+        if let Some(index) = self.fingerprints.borrow_mut().as_mut() {
+            let paths = index.entry(fingerprint.quick.clone()).or_default();
+            if !paths.iter().any(|indexed| indexed == path) {
+                paths.push(path.to_string());
             }
+        }
+    }
+
+    /// Adds the fingerprint of the original to the entry of a file that was just compressed
+    pub fn record_fingerprint(&mut self, path: &str, fingerprint: dedupe::Fingerprint) {
+        self.index_fingerprint(path, &fingerprint);
+        if let Some(file_log) = self.shrunk_files.get_mut(path) {
+            file_log.fingerprint = Some(fingerprint.clone());
+        }
+        if let Some(file_log) = self.added_files.get_mut(path) {
+            file_log.fingerprint = Some(fingerprint);
+        }
+    }
+
+    /// Logs a duplicate that got a copy of the compressed `original`, with its settings
+    pub fn mark_deduplicated(
+        &mut self,
+        path: String,
+        prev: u64,
+        original: &str,
+        fingerprint: dedupe::Fingerprint,
+        trashed: Option<PathBuf>,
+    ) {
+        let Some(original_log) = self.shrunk_files.get(original) else {
+            return;
         };
+        self.index_fingerprint(&path, &fingerprint);
+        let modified = unix_time(SystemTime::now());
 
         let file_log = FileLog {
             size_prev: prev,
-            size_post: post,
             modified,
+            // nothing was encoded
+            encode_time: None,
+            run: None,
+            run_id: Some(run_id().to_string()),
+            fingerprint: Some(fingerprint),
+            deduplicated_from: Some(original.to_string()),
+            trashed: trashed.map(|trashed| trashed.to_string_lossy().to_string()),
+            imported: false,
+            ..original_log.clone()
         };
-
-        self.shrunk_files.insert(path.clone(), file_log);
+        events::emit(Event::FileDone {
+            path: &path,
+            size_prev: prev,
+            size_post: file_log.size_post,
+            encode_time: None,
+        });
+        self.retry_files.remove(&path);
+        self.shrunk_files.insert(path.clone(), file_log.clone());
         self.added_files.insert(path, file_log);
     }
 
+    /// Index of this run in `runs`, probing the encoder versions the first time it's needed
+    /// so runs that don't compress anything don't pay for it
+    fn current_run(&mut self, now: i64) -> usize {
+        if let Some(run) = self.current_run {
+            return run;
+        }
+
+        self.runs.push(Run {
+            recorded: now,
+            ffmpeg: version::ffmpeg(),
+            ffmpeg_path: version::program_path("ffmpeg")
+                .map(|path| path.to_string_lossy().to_string()),
+            x265: version::x265(),
+            ffprobe: version::ffprobe(),
+        });
+        let run = self.runs.len() - 1;
+        self.current_run = Some(run);
+        run
+    }
+
+    /// The file failed in an earlier run and is looked at again even if it didn't change,
+    /// e.g. by `--since` and `--since-last-run`
+    pub fn failed_before(&self, path: &str) -> bool {
+        self.retry_files.contains_key(path)
+    }
+
+    /// Records why the file wasn't processed, failures are kept apart from intentional skips
     pub fn mark_skipped(&mut self, path: String, reason: SkipReason) {
-        self.skipped_files.insert(path, reason.to_string());
+        if reason.is_retryable() {
+            let entry = RetryEntry {
+                reason: reason.to_string(),
+                recorded: Some(unix_time(SystemTime::now())),
+            };
+            self.retry_files.insert(path.clone(), entry);
+        } else {
+            self.retry_files.remove(&path);
+        }
+        if reason.is_failure() {
+            events::emit(Event::Failed {
+                path: &path,
+                reason: reason.to_string(),
+            });
+            self.failed_files.insert(path, reason.to_string());
+            return;
+        }
+        events::emit(Event::Skipped {
+            path: &path,
+            reason: reason.to_string(),
+        });
+        if let SkipReason::Encrypted(_) = reason {
+            self.encrypted_files.insert(path, reason.to_string());
+        } else {
+            self.skipped_files.insert(path, reason.to_string());
+        }
+    }
+
+    /// Moves the skip or failure and the input check recorded for `from` to `to`
+    pub fn rename_entry(&mut self, from: &str, to: String) {
+        let input_checks = self.input_checks.get_mut();
+        if let Some(check) = input_checks.remove(from) {
+            input_checks.insert(to.clone(), check);
+        }
+        if let Some(entry) = self.retry_files.remove(from) {
+            self.retry_files.insert(to.clone(), entry);
+        }
+        if let Some(reason) = self.skipped_files.remove(from) {
+            self.skipped_files.insert(to, reason);
+        } else if let Some(reason) = self.encrypted_files.remove(from) {
+            self.encrypted_files.insert(to, reason);
+        } else if let Some(reason) = self.failed_files.remove(from) {
+            self.failed_files.insert(to, reason);
+        }
     }
 
-    fn display_filesize(size: u64) -> String {
-        let mut size = size as f64;
-        let mut unit = "B";
-        if size > 1024.0 {
-            size /= 1024.0;
-            unit = "KB";
+    /// Stores every path in its absolute form, merging entries that turn out to be the same
+    /// file, e.g. `./clip.mp4` and `clip.mp4` from runs over `.` and over the directory's name.
+    /// Relative paths of older logs are taken as relative to the current directory, like
+    /// `--retry-failed` opens them. Returns the renamed and the merged paths with their new
+    /// name.
+    fn normalize_keys(&mut self) -> (usize, Vec<(String, String)>) {
+        let mut renamed = 0;
+        let mut merged = Vec::new();
+        let mut normalize = |path: &String| {
+            let absolute = paths::absolute(Path::new(path))
+                .to_string_lossy()
+                .to_string();
+            if &absolute != path {
+                renamed += 1;
+            }
+            absolute
+        };
+        // compacted logs only ever stored absolute paths
+        if let Some(shrunk_files) = self.shrunk_files.inline_mut() {
+            *shrunk_files = normalized(
+                std::mem::take(shrunk_files),
+                &mut normalize,
+                &mut merged,
+                |kept, other| other.modified > kept.modified,
+            );
+            for file_log in shrunk_files.values_mut() {
+                file_log.deduplicated_from =
+                    file_log.deduplicated_from.as_ref().map(&mut normalize);
+            }
         }
-        if size > 1024.0 {
-            size /= 1024.0;
-            unit = "MB";
+        self.retry_files = normalized(
+            std::mem::take(&mut self.retry_files),
+            &mut normalize,
+            &mut merged,
+            |kept, other| other.recorded > kept.recorded,
+        );
+        for files in [
+            &mut self.skipped_files,
+            &mut self.failed_files,
+            &mut self.encrypted_files,
+        ] {
+            *files = normalized(
+                std::mem::take(files),
+                &mut normalize,
+                &mut merged,
+                |_, _| false,
+            );
         }
-        if size > 1024.0 {
-            size /= 1024.0;
-            unit = "GB";
+        if let Some(input_checks) = self.input_checks.get_mut().inline_mut() {
+            *input_checks = normalized(
+                std::mem::take(input_checks),
+                &mut normalize,
+                &mut merged,
+                |_, _| false,
+            );
         }
+        (renamed, merged)
+    }
 
-        format!("{size:.2}{unit}")
+    /// A directory of a retried file was walked without an error
+    fn mark_walked(&mut self, dir: &str) {
+        self.retry_files.remove(dir);
     }
 
-    pub fn print_status(&mut self) {
+    pub fn input_check(&self, path: &str) -> Option<filter::InputCheck> {
+        self.input_checks.borrow().get(path).cloned()
+    }
+
+    pub fn record_input_check(&self, path: String, check: filter::InputCheck) {
+        self.input_checks.borrow_mut().insert(path, check);
+    }
+
+    pub fn forget_input_check(&self, path: &str) {
+        self.input_checks.borrow_mut().remove(path);
+    }
+
+    pub fn has_failures(&self) -> bool {
+        !self.failed_files.is_empty()
+    }
+
+    /// Prints the summary of the run, listing every compressed file with `verbose`.
+    /// `free_space` is the available space on the target filesystem before and after the run.
+    pub fn print_status(&mut self, free_space: Option<(u64, u64)>, verbose: bool) {
         let mut total_prev = 0;
         let mut total_post = 0;
+        let mut remux_prev = 0;
+        let mut remux_post = 0;
+        let mut dedupe_prev = 0;
+        let mut dedupe_post = 0;
+        // originals in the trash still take up their space
+        let mut trashed = 0;
+        let run_encode_time = Log::encode_time(self.added_files.values());
         if !self.added_files.is_empty() {
-            println!(" ==== ==== ==== ");
-            for (path, file_log) in &self.added_files {
+            info!(" ==== ==== ==== ");
+            let mut added_files = self.added_files.iter().collect::<Vec<_>>();
+            added_files.sort_by_key(|(path, _)| *path);
+            let report = savings::report(&added_files);
+            let path_width = added_files
+                .iter()
+                .map(|(path, _)| path.chars().count())
+                .max()
+                .unwrap_or_default();
+            for (path, file_log) in added_files {
                 total_prev += file_log.size_prev;
                 total_post += file_log.size_post;
-                println!(
-                    "Compressed `{path}`: {} -> {}",
-                    Log::display_filesize(file_log.size_prev),
-                    Log::display_filesize(file_log.size_post),
-                );
+                if file_log.trashed.is_some() {
+                    trashed += file_log.size_prev;
+                }
+                let action = if file_log.deduplicated_from.is_some() {
+                    dedupe_prev += file_log.size_prev;
+                    dedupe_post += file_log.size_post;
+                    "Copied"
+                } else if file_log.remuxed {
+                    remux_prev += file_log.size_prev;
+                    remux_post += file_log.size_post;
+                    "Remuxed"
+                } else {
+                    "Compressed"
+                };
+                let audio = match (file_log.no_audio, file_log.downmixed, &file_log.loudnorm) {
+                    (true, _, _) => " (audio dropped)",
+                    (_, true, Some(_)) => " (audio downmixed to stereo and normalized)",
+                    (_, true, None) => " (audio downmixed to stereo)",
+                    (_, false, Some(_)) => " (audio normalized)",
+                    _ => "",
+                };
+                // padded before coloring, the escape codes would count towards the width
+                let delta = file_log.size_post as i64 - file_log.size_prev as i64;
+                let delta_text = format!("{:>10}", filesize::display_delta(delta));
+                let time = match (file_log.encode_time, file_log.speed()) {
+                    (Some(encode_time), Some(speed)) => {
+                        format!("  {} {speed:>6.2}x", display_duration(encode_time))
+                    }
+                    (Some(encode_time), None) => format!("  {}", display_duration(encode_time)),
+                    _ => String::new(),
+                };
+                if verbose {
+                    info!(
+                        "{action:<10} {path:<path_width$}  {:>10} -> {:>10}  {}{time}{audio}",
+                        filesize::display(file_log.size_prev),
+                        filesize::display(file_log.size_post),
+                        color::by_delta(&delta_text, delta),
+                    );
+                }
+            }
+            if verbose && !report.is_empty() {
+                info!("");
+            }
+            for line in report {
+                info!("{line}");
             }
             self.added_files.clear();
-            println!(" ==== ==== ==== \n");
+            info!(" ==== ==== ==== \n");
         }
 
         if !self.skipped_files.is_empty() {
-            println!(" ==== ==== ==== ");
-            for (path, reason) in &self.skipped_files {
-                println!("Skipped `{path}`: {}", reason);
+            info!(" ==== ==== ==== ");
+            let mut skipped_files = self.skipped_files.iter().collect::<Vec<_>>();
+            skipped_files.sort();
+            for (path, reason) in skipped_files {
+                info!("{} `{path}`: {}", color::skip("Skipped"), reason);
             }
             self.skipped_files.clear();
-            println!(" ==== ==== ==== \n");
+            info!(" ==== ==== ==== \n");
         }
 
-        if total_prev != 0 {
-            println!(
-                "Total compression: {} -> {}",
-                Log::display_filesize(total_prev),
-                Log::display_filesize(total_post),
-            );
+        if !self.encrypted_files.is_empty() {
+            info!(" ==== ==== ==== ");
+            let mut encrypted_files = self.encrypted_files.iter().collect::<Vec<_>>();
+            encrypted_files.sort();
+            for (path, reason) in encrypted_files {
+                info!("{} `{path}`: {}", color::skip("Encrypted"), reason);
+            }
+            self.encrypted_files.clear();
+            info!(" ==== ==== ==== \n");
         }
-    }
 
-    pub fn save(&self) {
-        if let Ok(mut log_file) = File::create(self.save_file.clone()) {
-            if let Err(e) = log_file.write(serde_json::to_string(self).unwrap().as_bytes()) {
-                panic!("Failed to save cache to {}: {e}", self.save_file);
+        if !self.failed_files.is_empty() {
+            info!(" ==== ==== ==== ");
+            let mut failed_files = self.failed_files.iter().collect::<Vec<_>>();
+            failed_files.sort();
+            for (path, reason) in failed_files {
+                info!("{} `{path}`: {}", color::bad("Failed"), reason);
             }
-        };
-    }
-}
-
-fn iterate_dir(path: &PathBuf, log: &mut Log) {
-    let read_dir = match std::fs::read_dir(path) {
-        Ok(read_dir) => read_dir,
-        Err(e) => {
-            log.mark_skipped(path.to_string_lossy().to_string(), SkipReason::ReadDir(e));
-            return;
+            self.failed_files.clear();
+            info!(" ==== ==== ==== \n");
         }
-    };
 
-    for dir_entry in read_dir {
-        if let Ok(dir_entry) = dir_entry {
-            let path = dir_entry.path().to_string_lossy().to_string();
-            let metadata = match dir_entry.metadata() {
-                Ok(metadata) => metadata,
-                Err(e) => {
-                    log.mark_skipped(path, SkipReason::Metadata(e));
-                    continue;
-                }
-            };
+        if total_prev != remux_prev + dedupe_prev {
+            Log::print_total(
+                "Total compression",
+                total_prev - remux_prev - dedupe_prev,
+                total_post - remux_post - dedupe_post,
+            );
+        }
+        if remux_prev != 0 {
+            Log::print_total("Total remux", remux_prev, remux_post);
+        }
+        if dedupe_prev != 0 {
+            Log::print_total("Total deduplication", dedupe_prev, dedupe_post);
+        }
+        if let Some((duration, encode_time)) = run_encode_time {
+            Log::print_encode_time("Encode time", duration, encode_time);
+        }
+        if trashed != 0 {
+            info!(
+                "Moved {} of originals to the trash, empty it to free their space",
+                filesize::display(trashed)
+            );
+        }
+        // only worth a line once earlier runs contribute to it, and not worth reading every
+        // shard of a compacted log
+        let lifetime_encode_time = (!self.shrunk_files.is_sharded())
+            .then(|| Log::encode_time(self.shrunk_files.values()))
+            .flatten();
+        if let Some((duration, encode_time)) = lifetime_encode_time {
+            if lifetime_encode_time != run_encode_time {
+                Log::print_encode_time("Lifetime encode time", duration, encode_time);
+            }
+        }
 
-            let modified = match metadata.modified() {
-                Ok(system_time) => match system_time.duration_since(SystemTime::UNIX_EPOCH) {
-                    Ok(d) => d.as_secs(),
-                    Err(e) => {
-                        log.save();
-                        panic!("Unable to retrieve system time!\n{e}");
-                    }
-                },
-                Err(e) => {
-                    log.mark_skipped(path, SkipReason::Metadata(e));
-                    continue;
-                }
-            };
+        if let Some((before, after)) = free_space {
+            let actual = after as i64 - before as i64;
+            // more free space is the saving here
+            let delta = filesize::display_delta(actual);
+            info!(
+                "Free space: {} -> {} ({})",
+                filesize::display(before),
+                filesize::display(after),
+                color::by_delta(&delta, -actual),
+            );
 
-            if !metadata.is_dir() {
-                if !log.is_already_processed(&path, modified) {
-                    let path = dir_entry.path().to_string_lossy().to_string();
-                    if filetype_check!(path, ".mp4", ".mov") {
-                        let prev_size = metadata.len();
-                        if let Ok(post_size) = process_file(dir_entry.path(), log) {
-                            log.mark_processed(path, prev_size, post_size);
-                            log.save();
-                        }
-                    }
-                }
-            } else {
-                iterate_dir(&dir_entry.path(), log);
+            // other programs may write to the same filesystem, so only point out larger gaps
+            let logical = total_prev as i64 - total_post as i64 - trashed as i64;
+            if logical > 0 && actual < logical - logical / 10 {
+                info!(
+                    "{}",
+                    color::skip(&format!(
+                        "Warning: the free space only changed by {} although {} were saved, there might be leftover temporary files",
+                        filesize::display_delta(actual),
+                        filesize::display(logical as u64),
+                    ))
+                );
             }
         }
+        info!("Run ID: {}", run_id());
     }
-}
 
-fn print_video_length(path_buf: PathBuf) {
-    let stdout = match Command::new("ffprobe")
-        .arg("-loglevel")
-        .arg("fatal")
-        .arg("-i")
-        .arg(path_buf)
-        .arg("-show_entries")
-        .arg("format=duration")
-        .arg("-of")
-        .arg("csv=p=0")
-        .arg("-sexagesimal")
-        .stdout(Stdio::piped())
-        .spawn()
-    {
-        Ok(child) => match child.stdout {
-            Some(stdout) => stdout,
-            None => return,
-        },
-        Err(_) => return,
-    };
+    fn print_total(label: &str, prev: u64, post: u64) {
+        let delta = post as i64 - prev as i64;
+        info!(
+            "{label}: {} -> {} ({})",
+            filesize::display(prev),
+            filesize::display(post),
+            color::by_delta(&filesize::display_delta(delta), delta),
+        );
+    }
 
-    let reader = BufReader::new(stdout);
-    reader
-        .lines()
-        .filter_map(|line| line.ok())
-        .for_each(|line| {
-            println!(
-                "Video length: {}",
-                line.split(".").collect::<Vec<&str>>()[0]
+    /// Total video duration and encode time of the re-encoded files that recorded both, remuxes
+    /// would inflate the speed
+    fn encode_time<'a>(files: impl Iterator<Item = &'a FileLog>) -> Option<(f64, f64)> {
+        files
+            .filter(|file_log| !file_log.remuxed)
+            .filter_map(|file_log| Some((file_log.duration?, file_log.encode_time?)))
+            .reduce(
+                |(duration, encode_time), (file_duration, file_encode_time)| {
+                    (duration + file_duration, encode_time + file_encode_time)
+                },
             )
-        });
-}
+    }
 
-fn compress(path_buf: PathBuf, dest_path_buf: PathBuf, log: &mut Log) {
-    let stderr = match Command::new("ffmpeg")
-        .arg("-loglevel")
-        .arg("fatal")
-        .arg("-stats")
-        .arg("-i")
-        .arg(path_buf)
-        .arg("-c:v")
-        .arg("libx265")
-        .arg("-c:a")
-        .arg("copy")
-        .arg("-x265-params")
-        .arg("crf=25")
-        .arg("-x265-params")
-        .arg("log-level=fatal")
-        .arg(dest_path_buf)
-        .arg("-y")
-        .stderr(Stdio::piped())
-        .spawn()
-    {
-        Ok(child) => match child.stderr {
-            Some(stderr) => stderr,
-            None => {
-                log.save();
-                panic!("Failed to get ffmpeg stderr");
-            }
-        },
-        Err(e) => {
-            log.save();
-            panic!("Failed to run ffmpeg: {e}");
-        }
-    };
+    fn print_encode_time(label: &str, duration: f64, encode_time: f64) {
+        let speed = if encode_time > 0.0 {
+            format!(" ({:.1}x realtime)", duration / encode_time)
+        } else {
+            String::new()
+        };
+        info!(
+            "{label}: encoded {} of video in {}{speed}",
+            display_time_span(duration),
+            display_time_span(encode_time),
+        );
+    }
 
-    eprint!("Progress: 00:00:00");
-    let time_regex = Regex::new(r"time=(\d+):(\d+):(\d+).*speed=(\d+).(\d+)").unwrap();
-    let mut buffer = String::new();
-    for byte in stderr.bytes() {
-        if let Ok(byte) = byte {
-            buffer.push(byte as char);
-
-            if time_regex.is_match(&buffer) {
-                if let Some(captures) = time_regex.captures(&buffer) {
-                    let speed_minor = captures[5].parse::<u64>().unwrap();
-                    let speed_major = captures[4].parse::<u64>().unwrap();
-                    let second = captures[3].parse::<u64>().unwrap();
-                    let minute = captures[2].parse::<u64>().unwrap();
-                    let hour = captures[1].parse::<u64>().unwrap();
-                    eprint!("\rProgress: {hour:0>2}:{minute:0>2}:{second:0>2} Speed: {speed_major:0>2}.{speed_minor:0<2}x");
-                    buffer.clear();
+    /// Writes the log to its file. A log that can't be written, e.g. on a read-only share,
+    /// doesn't stop the run, which goes on with the log in memory after a warning.
+    pub fn save(&self) {
+        match self.try_save() {
+            Ok(()) => {
+                if self.save_failed.replace(false) {
+                    info!("Saved the log to `{}` again", self.save_file);
+                }
+            }
+            Err(e) => {
+                events::emit(Event::Error {
+                    message: e.to_string(),
+                });
+                if !self.save_failed.replace(true) {
+                    info!(
+                        "{}: {e}, the run goes on but what it does is only kept in memory until the log can be written",
+                        color::bad("Warning")
+                    );
                 }
             }
         }
+        probe::save_cache();
     }
-    eprintln!();
-}
 
-fn process_file(path_buf: PathBuf, log: &mut Log) -> Result<u64, ()> {
-    let path = path_buf.to_string_lossy().to_string();
-    let mut dest_path_buf = path_buf.clone();
-    dest_path_buf.set_file_name(
-        dest_path_buf
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_string()
-            + "_x265.mp4",
-    );
+    fn try_save(&self) -> Result<(), RunError> {
+        // the log of `in_memory`
+        if self.save_file.is_empty() {
+            return Ok(());
+        }
+        let error = |e| RunError::SaveLog(self.save_file.clone(), e);
+        let json = if self.pretty {
+            serde_json::to_string_pretty(self)
+        } else {
+            serde_json::to_string(self)
+        }
+        .map_err(|e| error(e.into()))?;
+        // the shards first, a log pointing at shards that weren't written would lose entries
+        self.shrunk_files.save(self.pretty).map_err(error)?;
+        self.input_checks
+            .borrow()
+            .save(self.pretty)
+            .map_err(error)?;
+        std::fs::write(&self.save_file, json).map_err(error)
+    }
 
-    println!("Compressing {}...", path_buf.to_string_lossy());
-    print_video_length(path_buf.clone());
-    compress(path_buf.clone(), dest_path_buf.clone(), log);
+    /// `shards::SHARD_DIR` next to the log file
+    fn shard_dir(&self) -> PathBuf {
+        Path::new(&self.save_file).with_file_name(shards::SHARD_DIR)
+    }
 
-    let post_size = match File::open(dest_path_buf.clone()) {
-        Ok(file) => match file.metadata() {
-            Ok(metadata) => metadata.len(),
-            Err(e) => {
-                log.mark_skipped(path, SkipReason::Metadata(e));
-                return Err(());
-            }
-        },
-        Err(e) => {
-            log.mark_skipped(path, SkipReason::OpeningCompressedFile(e));
-            return Err(());
+    /// Moves the compressed files and input checks into shards once the log file grew past
+    /// `shards::COMPACT_SIZE`, the next save writes them
+    fn compact_if_large(&mut self) {
+        let size = std::fs::metadata(&self.save_file).map_or(0, |metadata| metadata.len());
+        if self.shards.is_some() || size <= shards::COMPACT_SIZE as u64 {
+            return;
         }
-    };
+        let dir = self.shard_dir();
+        info!(
+            "The log has grown to {}, moving the compressed files and input checks into `{}`",
+            filesize::display(size),
+            dir.to_string_lossy()
+        );
+        self.shrunk_files
+            .compact(dir.clone(), "files", shards::SHARD_COUNT);
+        self.input_checks
+            .get_mut()
+            .compact(dir, "input_checks", shards::SHARD_COUNT);
+        self.shards = Some(shards::SHARD_COUNT);
+    }
 
-    if cfg!(unix) {
-        if let Err(e) = Command::new("mv").arg(dest_path_buf).arg(path_buf).spawn() {
-            log.mark_skipped(path.clone(), SkipReason::Override(e));
-            return Err(());
-        }
-    } else if cfg!(windows) {
-        if let Err(e) = Command::new("move")
-            .arg("/y")
-            .arg(path_buf)
-            .arg(dest_path_buf)
-            .spawn()
-        {
-            log.mark_skipped(path.clone(), SkipReason::Override(e));
-            return Err(());
-        }
+    /// Reads the compressed files and input checks of a compacted log from its shards when
+    /// they are looked up
+    fn open_shards(&mut self) {
+        let Some(count) = self.shards else {
+            return;
+        };
+        let dir = self.shard_dir();
+        self.shrunk_files = Sharded::open(dir.clone(), "files", count);
+        self.input_checks = RefCell::new(Sharded::open(dir, "input_checks", count));
     }
 
-    return Ok(post_size);
+    /// Whether the last save wrote the log to its file
+    pub fn is_saved(&self) -> bool {
+        !self.save_failed.get()
+    }
 }
 
-fn main() {
-    let path: Vec<String> = std::env::args().collect();
-    if path.len() != 2 {
-        println!("Usage: {} <path>", path[0]);
-        std::process::exit(1);
+/// `entries` with their paths passed through `normalize`. Of the entries that end up with the
+/// same path the one that `replaces` all others is kept, the others are listed in `merged`.
+fn normalized<V>(
+    entries: HashMap<String, V>,
+    normalize: &mut impl FnMut(&String) -> String,
+    merged: &mut Vec<(String, String)>,
+    replaces: impl Fn(&V, &V) -> bool,
+) -> HashMap<String, V> {
+    let mut entries = entries
+        .into_iter()
+        .map(|(path, entry)| (normalize(&path), path, entry))
+        .collect::<Vec<_>>();
+    // the entry stored in the absolute form comes first, the others are merged into it
+    entries.sort_by(|(a, a_path, _), (b, b_path, _)| {
+        (a, a_path != a, a_path).cmp(&(b, b_path != b, b_path))
+    });
+    let mut normalized = HashMap::<String, V>::with_capacity(entries.len());
+    for (absolute, path, entry) in entries {
+        match normalized.get_mut(&absolute) {
+            Some(kept) => {
+                if replaces(kept, &entry) {
+                    *kept = entry;
+                }
+                merged.push((path, absolute));
+            }
+            None => {
+                normalized.insert(absolute, entry);
+            }
+        }
     }
+    normalized
+}
 
-    let path = path[1].clone();
-    let path_buf = PathBuf::from(path.clone());
-    let mut log = if path_buf.is_dir() {
-        let mut log = Log::new(path.clone());
-        iterate_dir(&path_buf, &mut log);
+/// Errors of the run rather than of a single file, `main` decides which of them end it
+#[derive(Debug)]
+enum RunError {
+    /// Path of the log and why it couldn't be written
+    SaveLog(String, Error),
+}
+
+impl Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::SaveLog(path, e) => write!(f, "Failed to save the log to `{path}`: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for RunError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RunError::SaveLog(_, e) => Some(e),
+        }
+    }
+}
+
+/// Seconds since the unix epoch, negative for times before it, e.g. files restored from
+/// backups that lost their timestamps or a system clock that was never set
+fn unix_time(time: SystemTime) -> i64 {
+    match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(after) => after.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+/// ID of this run stamped into the entries it adds to the log, the UTC time it started and
+/// the process ID, e.g. `20240312T031500-4711`. It sorts by time and tells apart runs started
+/// in the same second, e.g. by timers over several directories.
+fn run_id() -> &'static str {
+    RUN_ID.get_or_init(|| {
+        let started = times::utc_date_time(SystemTime::now()).replace(['-', ':'], "");
+        format!("{started}-{}", std::process::id())
+    })
+}
+
+/// Video found while scanning that still has to be compressed
+struct Candidate {
+    path: PathBuf,
+    size: u64,
+    modified: i64,
+}
+
+impl Candidate {
+    /// Reason to skip the candidate found by the checks that probe it
+    fn rejection(&self, log: &Log, options: &Options) -> Option<SkipReason> {
+        let entry = filter::Entry {
+            path: &self.path,
+            is_dir: false,
+            modified: self.modified,
+        };
+        let context = filter::Context::new(log, options, &[]);
+        // the candidate checks only ever skip, the walk already excluded everything else
+        match filter::first_rejection(&filter::CANDIDATE_CHECKS, &entry, &context)? {
+            (_, filter::Decision::Skipped(reason)) => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+/// Position of a candidate within the run, shown in front of its "Compressing" line
+#[derive(Clone, Copy)]
+struct RunPosition {
+    /// 1-based
+    index: usize,
+    count: usize,
+    /// Size of the candidates before this one
+    done_size: u64,
+    total_size: u64,
+}
+
+impl RunPosition {
+    fn all(candidates: &[Candidate]) -> Vec<Self> {
+        let total_size = candidates.iter().map(|candidate| candidate.size).sum();
+        let mut done_size = 0;
+        candidates
+            .iter()
+            .enumerate()
+            .map(|(i, candidate)| {
+                let position = RunPosition {
+                    index: i + 1,
+                    count: candidates.len(),
+                    done_size,
+                    total_size,
+                };
+                done_size += candidate.size;
+                position
+            })
+            .collect()
+    }
+}
+
+impl Display for RunPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}/{}, {}/{}]",
+            self.index,
+            self.count,
+            filesize::display(self.done_size),
+            filesize::display(self.total_size)
+        )
+    }
+}
+
+#[derive(Default)]
+struct Scan {
+    candidates: Vec<Candidate>,
+    /// Hidden entries and entries matched by a `.vcignore`, which aren't logged as skipped
+    ignored: usize,
+    /// `.vcignore` files of the directories from the scanned path down to the current one
+    ignore_files: Vec<ignore::IgnoreFile>,
+    /// Entries walked before this path are skipped (`--start-from`)
+    start_from: Option<PathBuf>,
+    visited: walk::Visited,
+    /// Storage of the directories, looked up with `--local-only`
+    mounts: mounts::Mounts,
+    /// A file modified before 1970 was warned about
+    warned_before_epoch: bool,
+    /// Start of the last completed run with `--since-last-run`. The files of directories
+    /// modified before it are left out without looking at them, new and removed files change
+    /// the modification time of their directory.
+    prune_before: Option<i64>,
+    /// Directories whose files were left out for `prune_before`
+    pruned: usize,
+}
+
+/// Directory of the scan being walked, with the entries left to look at
+struct Frame {
+    entries: std::vec::IntoIter<std::fs::DirEntry>,
+    has_ignore_file: bool,
+    /// Not modified since `Scan::prune_before`, only its subdirectories are walked
+    pruned: bool,
+    /// Restored once everything below the directory is walked
+    _accessed: atime::Preserved,
+}
+
+/// Reads the directory and the files configuring it, `None` if it's left out
+fn enter_dir(path: &PathBuf, log: &mut Log, options: &Options, scan: &mut Scan) -> Option<Frame> {
+    // before anything touches a directory that may be on a dead network share
+    if options.local_only {
+        if let Some(kind) = scan.mounts.not_local(path) {
+            log.mark_skipped(
+                path.to_string_lossy().to_string(),
+                SkipReason::NotLocal(kind),
+            );
+            return None;
+        }
+    }
+    if let Some(first) = scan.visited.enter(path) {
+        log.mark_skipped(
+            path.to_string_lossy().to_string(),
+            SkipReason::AlreadyWalked(first),
+        );
+        return None;
+    }
+
+    let accessed = atime::Preserved::new(path);
+    let read_dir = match std::fs::read_dir(path) {
+        Ok(read_dir) => read_dir,
+        Err(e) => {
+            log.mark_skipped(path.to_string_lossy().to_string(), SkipReason::ReadDir(e));
+            return None;
+        }
+    };
+
+    let ignore_file = match ignore::IgnoreFile::load(path) {
+        Ok(ignore_file) => ignore_file,
+        Err(e) => {
+            info!(
+                "Warning: failed to read `{}`: {e}",
+                path.join(ignore::IGNORE_FILE).to_string_lossy()
+            );
+            None
+        }
+    };
+
+    match Config::load_dir(path) {
+        Ok(Some(dir_config)) => {
+            let parent = options.dir_configs.get(path);
+            let config = dir_config.over(parent.as_deref().unwrap_or(&options.config));
+            if options.verbose {
+                info!(
+                    "Using `{}` for `{}`",
+                    path.join(config::DIR_CONFIG_FILE).to_string_lossy(),
+                    path.to_string_lossy()
+                );
+            }
+            options.dir_configs.insert(path.clone(), Rc::new(config));
+        }
+        Ok(None) => {}
+        Err(e) => {
+            // compressing with the wrong settings is worse than not compressing
+            log.mark_skipped(
+                path.to_string_lossy().to_string(),
+                SkipReason::InvalidDirConfig(e),
+            );
+            return None;
+        }
+    }
+
+    log.mark_walked(&path.to_string_lossy());
+    let has_ignore_file = ignore_file.is_some();
+    scan.ignore_files.extend(ignore_file);
+    // sorted so every run walks the tree in the same order and `--start-from` can resume one
+    let mut entries = read_dir.flatten().collect::<Vec<_>>();
+    entries.sort_by_key(|dir_entry| dir_entry.file_name());
+    let pruned = scan.prune_before.is_some_and(|prune_before| {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| unix_time(modified) < prune_before)
+    });
+    scan.pruned += usize::from(pruned);
+    Some(Frame {
+        entries: entries.into_iter(),
+        has_ignore_file,
+        pruned,
+        _accessed: accessed,
+    })
+}
+
+/// Collects all videos below `root` that aren't excluded by the checks that don't need
+/// ffprobe. The directories being walked are kept on a stack rather than recursed into, so
+/// deep trees don't overflow the stack.
+fn scan_dir(root: &PathBuf, log: &mut Log, options: &Options, scan: &mut Scan) {
+    let mut stack = Vec::from_iter(enter_dir(root, log, options, scan));
+    while let Some(frame) = stack.last_mut() {
+        let Some(dir_entry) = frame.entries.next() else {
+            if frame.has_ignore_file {
+                scan.ignore_files.pop();
+            }
+            stack.pop();
+            continue;
+        };
+
+        if let Some(start_from) = &scan.start_from {
+            if is_before_start(&dir_entry.path(), start_from) {
+                continue;
+            }
+        }
+        // the type comes with the entry on most platforms, unlike the metadata. Links can
+        // point at directories, so they are looked at.
+        if frame.pruned
+            && dir_entry
+                .file_type()
+                .is_ok_and(|file_type| file_type.is_file())
+            && !log.failed_before(&dir_entry.path().to_string_lossy())
+        {
+            continue;
+        }
+
+        let path = dir_entry.path().to_string_lossy().to_string();
+        // the metadata of a mount point is that of the mounted filesystem's root
+        let may_be_dir = dir_entry
+            .file_type()
+            .map_or(true, |file_type| !file_type.is_file());
+        if options.local_only && may_be_dir {
+            if let Some(kind) = scan.mounts.not_local(&dir_entry.path()) {
+                log.mark_skipped(path, SkipReason::NotLocal(kind));
+                continue;
+            }
+        }
+        let metadata = match dir_entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                log.mark_skipped(path, SkipReason::Metadata(e));
+                continue;
+            }
+        };
+
+        let modified = match metadata.modified() {
+            Ok(system_time) => unix_time(system_time),
+            Err(e) => {
+                log.mark_skipped(path, SkipReason::Metadata(e));
+                continue;
+            }
+        };
+        if modified < 0 && !scan.warned_before_epoch {
+            // usually restored from a backup that didn't keep the timestamps
+            info!("Warning: `{path}` and possibly more files were modified before 1970, check the timestamps of the backup they came from");
+            scan.warned_before_epoch = true;
+        }
+
+        let entry_path = dir_entry.path();
+        let entry = filter::Entry {
+            path: &entry_path,
+            is_dir: metadata.is_dir(),
+            modified,
+        };
+        let context = filter::Context::new(log, options, &scan.ignore_files);
+        match filter::first_rejection(&filter::SCAN_CHECKS, &entry, &context) {
+            None if metadata.is_dir() => {
+                if let Some(frame) = enter_dir(&entry_path, log, options, scan) {
+                    stack.push(frame);
+                }
+            }
+            None => scan.candidates.push(Candidate {
+                path: entry_path,
+                size: metadata.len(),
+                modified,
+            }),
+            Some((_, filter::Decision::Ignored(_))) => scan.ignored += 1,
+            Some((_, filter::Decision::Skipped(reason))) => log.mark_skipped(path, reason),
+            Some((_, filter::Decision::Pass | filter::Decision::Excluded(_))) => {}
+        }
+    }
+}
+
+/// Whether `path` is walked before `start` and isn't one of its parent directories. Paths
+/// compare by their components, which is the order the sorted entries are walked in.
+fn is_before_start(path: &Path, start: &Path) -> bool {
+    path < start && !start.starts_with(path)
+}
+
+/// `path` in the form of the paths produced by the scan of `root`, so it can be compared
+/// with them. Relative paths are also looked up below `root`.
+fn resolve_below(root: &Path, path: &Path) -> Result<PathBuf, String> {
+    let canonical_root = root
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve `{}`: {e}", root.to_string_lossy()))?;
+    let canonical_path = path
+        .canonicalize()
+        .or_else(|_| root.join(path).canonicalize())
+        .map_err(|e| format!("`{}` doesn't exist: {e}", path.to_string_lossy()))?;
+    let relative = canonical_path.strip_prefix(&canonical_root).map_err(|_| {
+        format!(
+            "`{}` isn't inside of `{}`",
+            path.to_string_lossy(),
+            root.to_string_lossy()
+        )
+    })?;
+
+    Ok(root.join(relative))
+}
+
+/// Collects the videos below `path` that have to be compressed
+fn scan(path: &PathBuf, log: &mut Log, options: &Options) -> Vec<Candidate> {
+    let mut scan = Scan::default();
+    if let Some(start) = &options.start_from {
+        match resolve_below(path, Path::new(start)) {
+            Ok(start_from) => {
+                info!("Starting from `{}`", start_from.to_string_lossy());
+                scan.start_from = Some(start_from);
+            }
+            Err(e) => {
+                info!("Invalid `--start-from`: {e}");
+                log.save();
+                std::process::exit(1);
+            }
+        }
+    }
+    if options.since_last_run && !options.full_scan {
+        scan.prune_before = log.last_run;
+        if log.last_run.is_none() {
+            info!("No completed run recorded yet, looking at all directories");
+        }
+    }
+    scan_dir(path, log, options, &mut scan);
+    if options.verbose && scan.pruned > 0 {
+        info!(
+            "Left out the files of {} directories not modified since the last run, a file changed in place is only noticed with `--full-scan`",
+            scan.pruned
+        );
+    }
+    if options.verbose && scan.ignored > 0 {
+        info!(
+            "Ignored {} hidden files or directories and entries matched by `{}`",
+            scan.ignored,
+            ignore::IGNORE_FILE
+        );
+    }
+    options.systemd.ready();
+
+    scan.candidates
+}
+
+fn iterate_dir(path: &PathBuf, log: &mut Log, options: &Options) {
+    let candidates = scan(path, log, options);
+    compress_all(candidates, log, options);
+}
+
+/// Compresses the candidates in order, saving the log after each one
+fn compress_all(candidates: Vec<Candidate>, log: &mut Log, options: &Options) {
+    let prefetch = prefetch::Prefetch::start(
+        candidates
+            .iter()
+            .map(|candidate| candidate.path.clone())
+            .collect(),
+        options.probe_jobs,
+    );
+    let durations = options
+        .estimate_time
+        .then(|| estimate::print_estimate(&candidates, options));
+    let mut run_progress = run_progress::RunProgress::new(durations);
+
+    let positions = RunPosition::all(&candidates);
+    for (candidate, position) in candidates.into_iter().zip(positions) {
+        if options.cancel.is_cancelled() || log.stopped {
+            break;
+        }
+        let path = candidate.path.to_string_lossy().to_string();
+        if let Some(reason) = candidate.rejection(log, options) {
+            log.mark_skipped(path, reason);
+            continue;
+        }
+
+        if options.dry_run {
+            dry_run(&candidate.path, Some(position), log, options);
+            continue;
+        }
+
+        let mut fingerprint = match options.dedupe {
+            Some(_) => match dedupe::Fingerprint::of(&candidate.path) {
+                Ok(fingerprint) => Some(fingerprint),
+                Err(e) => {
+                    info!("Failed to fingerprint `{path}`, compressing it anyway: {e}");
+                    None
+                }
+            },
+            None => None,
+        };
+        if let Some(fingerprint) = &mut fingerprint {
+            if let Some(original) = log.duplicate_of(fingerprint, &candidate.path) {
+                if dedupe::deduplicate(&candidate, &original, fingerprint.clone(), log, options) {
+                    continue;
+                }
+            }
+        }
+        // later copies are only recognized by the hash of the whole original
+        if let Some(Err(e)) = fingerprint
+            .as_mut()
+            .map(|fingerprint| fingerprint.sha256(&candidate.path).map(|_| ()))
+        {
+            info!("Failed to hash `{path}`, its copies won't be recognized: {e}");
+            fingerprint = None;
+        }
+
+        if let Some(status) = prefetch.as_ref().and_then(|prefetch| prefetch.status()) {
+            info!("{status}");
+        }
+        if let Some(line) = run_progress.line(&position) {
+            info!("{line}");
+        }
+        if let Ok(encoded) = process_file(candidate.path, Some(position), log, options) {
+            run_progress.compressed(&position, candidate.size, encoded.size_post);
+            log.mark_processed(path.clone(), candidate.size, &encoded);
+            if let Some(fingerprint) = fingerprint {
+                log.record_fingerprint(&path, fingerprint);
+            }
+            log.save();
+        }
+    }
+}
+
+fn print_video_length(path: &Path) {
+    if let Some(duration) = probe::duration(path) {
+        info!("Video length: {}", display_duration(duration));
+    }
+}
+
+fn display_duration(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    format!(
+        "{:0>2}:{:0>2}:{:0>2}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Rough duration for summaries, e.g. `41 minutes` or `2.3 hours`
+fn display_time_span(seconds: f64) -> String {
+    if seconds < 60.0 {
+        format!("{seconds:.0} seconds")
+    } else if seconds < 3600.0 {
+        format!("{:.0} minutes", seconds / 60.0)
+    } else {
+        format!("{:.1} hours", seconds / 3600.0)
+    }
+}
+
+/// Part of the input that is encoded, `end` is a timestamp rather than the trimmed amount
+#[derive(Clone, Copy)]
+struct Trim {
+    start: f64,
+    end: Option<f64>,
+}
+
+impl Trim {
+    fn resolve(options: &Options, duration: Option<f64>) -> Result<Option<Self>, SkipReason> {
+        let start = options.trim_start.unwrap_or(0.0);
+        let trim_end = match options.trim_end {
+            Some(trim_end) if trim_end > 0.0 => trim_end,
+            _ if start > 0.0 => {
+                return match duration {
+                    Some(duration) if start >= duration => {
+                        Err(SkipReason::TrimExceedsDuration(start, 0.0, duration))
+                    }
+                    _ => Ok(Some(Trim { start, end: None })),
+                };
+            }
+            _ => return Ok(None),
+        };
+
+        let duration = duration.ok_or(SkipReason::UnknownDuration)?;
+        if start + trim_end >= duration {
+            return Err(SkipReason::TrimExceedsDuration(start, trim_end, duration));
+        }
+
+        Ok(Some(Trim {
+            start,
+            end: Some(duration - trim_end),
+        }))
+    }
+}
+
+/// Encoder settings resolved for a single file
+#[derive(Clone)]
+struct EncodeSettings {
+    crf: u8,
+    auto_crf_score: Option<f64>,
+    maxrate: Option<u64>,
+    bufsize: Option<u64>,
+    audio_only: bool,
+    /// Video stream is copied instead of re-encoded
+    remux: bool,
+    audio: AudioCodec,
+    no_audio: bool,
+    downmix_stereo: bool,
+    /// loudnorm filter, resolved by the first pass with `--loudnorm-two-pass`
+    loudnorm: Option<String>,
+    /// Sample rate of the original audio, restored after loudnorm upsampled it
+    sample_rate: Option<u32>,
+    metadata: Option<Metadata>,
+    /// Container tag marking the output as compressed by video_compressor
+    tag_key: String,
+    /// `creation_time` of the original, copied explicitly with `--metadata preserve`
+    creation_time: Option<String>,
+    /// Output goes to a pipe, which the mp4 muxer can't seek in
+    stream: bool,
+    /// Move the index to the start once the output is written, see `faststart`
+    faststart: bool,
+    deinterlace: bool,
+    /// Probed frame rates of the original and the output, after deinterlacing and `--max-fps`
+    source_fps: Option<f64>,
+    output_fps: Option<f64>,
+    /// Frame rate of the `fps` filter if the video exceeds `--max-fps`
+    fps_cap: Option<f64>,
+    /// Video encoder, `libx265` unless the run fell back to another one
+    encoder: String,
+    /// Hardware decoder of `--hwdecode`, unless the run fell back to decoding on the CPU
+    hwdecode: Option<encoder::HwAccel>,
+    tune: Option<String>,
+    preset: Option<String>,
+    ten_bit: bool,
+    profile: Option<Profile>,
+    /// Additional x265 params, replacing built-in ones with the same key
+    x265_params: Vec<(String, String)>,
+    keyint: Option<u32>,
+    min_keyint: Option<u32>,
+    trim: Option<Trim>,
+    /// Duration in seconds of the encoded output, used to show the progress
+    duration: Option<f64>,
+    sidecar: Option<Sidecar>,
+    /// Subtitle files embedded with `--embed-subtitles`
+    subtitles: Vec<subtitles::Subtitle>,
+    /// Indices of the text subtitle streams of the original, kept when embedding subtitles
+    kept_subtitles: Vec<usize>,
+}
+
+impl EncodeSettings {
+    /// Settings taken from the options alone, before looking at the file
+    fn from_options(options: &Options) -> Self {
+        EncodeSettings {
+            crf: options.config.default_crf(),
+            auto_crf_score: None,
+            maxrate: options.maxrate,
+            bufsize: options.bufsize,
+            audio_only: false,
+            remux: false,
+            audio: options.audio,
+            no_audio: options.no_audio,
+            downmix_stereo: options.downmix_stereo,
+            loudnorm: None,
+            sample_rate: None,
+            metadata: options.metadata,
+            tag_key: options.tag_key.clone(),
+            creation_time: None,
+            stream: false,
+            faststart: options.faststart,
+            deinterlace: options.deinterlace == Deinterlace::On,
+            source_fps: None,
+            output_fps: None,
+            fps_cap: None,
+            encoder: options.encoders.current(),
+            hwdecode: options.hwdecode.current(),
+            tune: options.tune.clone(),
+            preset: options.preset.clone(),
+            ten_bit: options.ten_bit,
+            profile: options.profile,
+            x265_params: options.x265_params.clone(),
+            keyint: options.keyint,
+            min_keyint: options.min_keyint,
+            trim: None,
+            duration: None,
+            sidecar: None,
+            subtitles: Vec::new(),
+            kept_subtitles: Vec::new(),
+        }
+    }
+
+    /// Settings for files without a video stream, which only get their audio re-encoded
+    fn audio_only(options: &Options) -> Self {
+        info!("No video stream found, re-encoding audio only");
+        EncodeSettings {
+            audio_only: true,
+            ..EncodeSettings::from_options(options)
+        }
+    }
+
+    /// Settings for files whose video stream is copied into a new container
+    fn remux(codec: &str, options: &Options) -> Self {
+        info!("Video is already encoded with {codec}, remuxing without re-encoding");
+        EncodeSettings {
+            remux: true,
+            ..EncodeSettings::from_options(options)
+        }
+    }
+
+    /// Settings from the options and config, without running the `--auto-crf` search
+    fn base(path: &Path, options: &Options) -> Self {
+        EncodeSettings {
+            crf: EncodeSettings::resolve_crf(path, options),
+            deinterlace: EncodeSettings::resolve_deinterlace(path, options),
+            ..EncodeSettings::from_options(options)
+        }
+    }
+
+    fn resolve_deinterlace(path: &Path, options: &Options) -> bool {
+        match options.deinterlace {
+            Deinterlace::On => {
+                info!("Deinterlacing (--deinterlace on)");
+                true
+            }
+            Deinterlace::Off => false,
+            Deinterlace::Auto => match probe::field_order(path) {
+                Some(field_order) if INTERLACED_FIELD_ORDERS.contains(&field_order.as_str()) => {
+                    info!("Deinterlacing (field order {field_order})");
+                    true
+                }
+                Some(_) => false,
+                None => {
+                    info!("Warning: failed to probe field order, not deinterlacing");
+                    false
+                }
+            },
+        }
+    }
+
+    fn resolve(path: &Path, options: &Options) -> Self {
+        let mut settings = EncodeSettings::base(path, options);
+        if let Some((metric, _)) = options.auto_crf.filter(|_| options.dry_run) {
+            info!(
+                "Not searching the CRF with {metric} (--dry-run), using CRF {}",
+                settings.crf
+            );
+        } else if let Some((metric, target)) = options.auto_crf {
+            let base = settings.clone();
+            let video_args = |crf| {
+                EncodeSettings {
+                    crf,
+                    ..base.clone()
+                }
+                .video_args()
+            };
+
+            match auto_crf::search(path, metric, target, settings.crf, video_args) {
+                Ok(result) => {
+                    info!(
+                        "Using CRF {} ({metric} {:.3} on samples, target {target})",
+                        result.crf, result.score
+                    );
+                    settings.crf = result.crf;
+                    settings.auto_crf_score = Some(result.score);
+                }
+                Err(e) => info!(
+                    "Warning: automatic CRF search failed, using CRF {}: {e}",
+                    settings.crf
+                ),
+            }
+        }
+
+        settings
+    }
+
+    fn resolve_crf(path: &Path, options: &Options) -> u8 {
+        let dir_config = options.dir_configs.get(path);
+        let config = dir_config.as_deref().unwrap_or(&options.config);
+        if let Some(crf) = options.crf {
+            info!("Using CRF {crf} (--crf)");
+            crf
+        } else if config.has_crf_tiers() {
+            match probe::video_resolution(path) {
+                Some((width, height)) => match config.crf_for_resolution(width, height) {
+                    Some((crf, tier)) => {
+                        info!("Using CRF {crf} ({width}x{height}, tier {tier})");
+                        crf
+                    }
+                    None => {
+                        let crf = config.default_crf();
+                        info!("Using CRF {crf} ({width}x{height}, no matching tier)");
+                        crf
+                    }
+                },
+                None => {
+                    let crf = config.default_crf();
+                    info!(
+                        "Warning: failed to probe resolution of `{}`, using default CRF {crf}",
+                        path.to_string_lossy()
+                    );
+                    crf
+                }
+            }
+        } else {
+            config.default_crf()
+        }
+    }
+
+    /// Probes the frame rate of the original and caps the output at `max_fps`. bwdif outputs a
+    /// frame for every field, so deinterlaced videos start out at twice the frame rate.
+    fn resolve_frame_rate(&mut self, path: &Path, max_fps: Option<f64>) {
+        let Some(source_fps) = probe::frame_rate(path) else {
+            if max_fps.is_some() {
+                info!("Warning: failed to probe frame rate, not capping it");
+            }
+            return;
+        };
+        let frame_rate = if self.deinterlace {
+            source_fps * 2.0
+        } else {
+            source_fps
+        };
+        self.source_fps = Some(source_fps);
+        self.output_fps = Some(match max_fps {
+            Some(max_fps) if frame_rate > max_fps => {
+                info!("Capping frame rate at {max_fps}fps ({frame_rate:.3}fps, --max-fps)");
+                self.fps_cap = Some(max_fps);
+                max_fps
+            }
+            _ => frame_rate,
+        });
+    }
+
+    /// Video filters in the order they have to be applied. Deinterlacing has to come first as
+    /// any filter that scales or drops frames would otherwise mix up the two fields.
+    fn filters(&self) -> Vec<String> {
+        let mut filters = Vec::new();
+        if self.deinterlace {
+            filters.push("bwdif".to_string());
+        }
+        if let Some(fps) = self.fps_cap {
+            filters.push(format!("fps={fps}"));
+        }
+
+        filters
+    }
+
+    /// ffmpeg arguments selecting and configuring the video encoder
+    fn video_args(&self) -> Vec<String> {
+        if self.encoder != "libx265" {
+            return self.fallback_video_args();
+        }
+        let mut params = vec![("crf".to_string(), self.crf.to_string())];
+        if let (Some(maxrate), Some(bufsize)) = (self.maxrate, self.bufsize) {
+            params.push(("vbv-maxrate".to_string(), maxrate.to_string()));
+            params.push(("vbv-bufsize".to_string(), bufsize.to_string()));
+        }
+        if let Some(keyint) = self.keyint {
+            params.push(("keyint".to_string(), keyint.to_string()));
+        }
+        if let Some(min_keyint) = self.min_keyint {
+            params.push(("min-keyint".to_string(), min_keyint.to_string()));
+        }
+        params.push(("log-level".to_string(), "fatal".to_string()));
+
+        for (key, value) in &self.x265_params {
+            match params.iter_mut().find(|(existing, _)| existing == key) {
+                Some(param) => param.1 = value.clone(),
+                None => params.push((key.clone(), value.clone())),
+            }
+        }
+
+        let mut args = Vec::new();
+        let filters = self.filters();
+        if !filters.is_empty() {
+            args.extend(["-vf".to_string(), filters.join(",")]);
+        }
+
+        args.extend(["-c:v".to_string(), "libx265".to_string()]);
+        if self.ten_bit {
+            args.extend(["-pix_fmt".to_string(), "yuv420p10le".to_string()]);
+        }
+        if let Some(preset) = &self.preset {
+            args.extend(["-preset".to_string(), preset.clone()]);
+        }
+        if let Some(tune) = &self.tune {
+            args.extend(["-tune".to_string(), tune.clone()]);
+        }
+
+        // ffmpeg only keeps the last `-x265-params`, so everything has to go into a single one
+        let params = params
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>()
+            .join(":");
+        args.extend(["-x265-params".to_string(), params]);
+        args
+    }
+
+    /// Arguments of the other encoders, which take the quality, GOP and VBV settings as
+    /// generic ffmpeg options. The CRF is mapped to each encoder's own quality scale, which
+    /// only roughly matches x265.
+    fn fallback_video_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let filters = self.filters();
+        if !filters.is_empty() {
+            args.extend(["-vf".to_string(), filters.join(",")]);
+        }
+
+        args.extend(["-c:v".to_string(), self.encoder.clone()]);
+        let crf = self.crf.to_string();
+        match self.encoder.as_str() {
+            // 0-100, higher is better
+            "hevc_videotoolbox" => {
+                let quality = 100u8.saturating_sub(self.crf * 2).max(1);
+                args.extend(["-q:v".to_string(), quality.to_string()]);
+            }
+            "hevc_nvenc" => args.extend(["-rc", "vbr", "-cq", &crf].map(String::from)),
+            "hevc_qsv" => args.extend(["-global_quality".to_string(), crf]),
+            encoder => {
+                args.extend(["-crf".to_string(), crf]);
+                // x264 shares the preset and tune names of x265
+                if encoder == "libx264" {
+                    if let Some(preset) = &self.preset {
+                        args.extend(["-preset".to_string(), preset.clone()]);
+                    }
+                    if let Some(tune) = &self.tune {
+                        args.extend(["-tune".to_string(), tune.clone()]);
+                    }
+                }
+            }
+        }
+        if let Some(keyint) = self.keyint {
+            args.extend(["-g".to_string(), keyint.to_string()]);
+        }
+        if let Some(min_keyint) = self.min_keyint {
+            args.extend(["-keyint_min".to_string(), min_keyint.to_string()]);
+        }
+        if let (Some(maxrate), Some(bufsize)) = (self.maxrate, self.bufsize) {
+            args.extend(["-maxrate".to_string(), format!("{maxrate}k")]);
+            args.extend(["-bufsize".to_string(), format!("{bufsize}k")]);
+        }
+        args
+    }
+
+    /// ffmpeg arguments that have to go before `-i`
+    fn input_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        // remuxing and audio only files don't decode the video
+        if let Some(method) = self.hwdecode.filter(|_| !self.remux && !self.audio_only) {
+            args.extend(["-hwaccel", method.name()].map(String::from));
+            // the software filters and encoders need the frames in system memory, which
+            // ffmpeg copies them to without an output format
+            if self.encoder == method.encoder() && self.filters().is_empty() {
+                args.extend(["-hwaccel_output_format", method.name()].map(String::from));
+            }
+        }
+        args.extend(self.trim_args());
+        args
+    }
+
+    /// `-ss` and `-to` of the trim, which apply to the embedded subtitle files as well
+    fn trim_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(trim) = self.trim {
+            args.extend(["-ss".to_string(), format!("{:.3}", trim.start)]);
+            if let Some(end) = trim.end {
+                args.extend(["-to".to_string(), format!("{end:.3}")]);
+            }
+        }
+
+        args
+    }
+
+    /// ffmpeg arguments opening the subtitle files to embed, after the video itself
+    fn subtitle_inputs(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+        for subtitle in &self.subtitles {
+            args.extend(self.trim_args().into_iter().map(OsString::from));
+            args.extend(["-i".into(), paths::for_ffmpeg(&subtitle.path)]);
+        }
+        args
+    }
+
+    /// ffmpeg arguments mapping the embedded subtitles. With more than one input ffmpeg would
+    /// pick the streams from any of them, so the first video stream, every audio stream and the
+    /// text subtitles of the original are mapped explicitly.
+    fn subtitle_args(&self) -> Vec<String> {
+        if self.subtitles.is_empty() {
+            return Vec::new();
+        }
+        let mut args = Vec::new();
+        let mut map = |stream: String| args.extend(["-map".to_string(), stream]);
+        map("0:V:0?".to_string());
+        if !self.no_audio {
+            map("0:a?".to_string());
+        }
+        for index in &self.kept_subtitles {
+            map(format!("0:{index}"));
+        }
+        for input in 1..=self.subtitles.len() {
+            map(format!("{input}:s:0"));
+        }
+        // the only text subtitle codec of mp4
+        args.extend(["-c:s", "mov_text"].map(String::from));
+        for (index, subtitle) in self.subtitles.iter().enumerate() {
+            if let Some(language) = &subtitle.language {
+                args.extend([
+                    format!("-metadata:s:s:{}", self.kept_subtitles.len() + index),
+                    format!("language={language}"),
+                ]);
+            }
+        }
+        args
+    }
+
+    /// ffmpeg arguments selecting the encoders of all output streams
+    fn codec_args(&self) -> Vec<String> {
+        let mut args = if self.audio_only {
+            vec!["-vn".to_string()]
+        } else if self.remux {
+            vec!["-c:v".to_string(), "copy".to_string()]
+        } else {
+            self.video_args()
+        };
+
+        if self.no_audio {
+            args.push("-an".to_string());
+            return args;
+        }
+
+        // copying the audio when it is the only thing in the file would make the encode pointless
+        let audio = match self.audio {
+            AudioCodec::Copy if self.audio_only => AudioCodec::Aac,
+            audio => audio,
+        };
+        args.extend(audio.args().into_iter().map(String::from));
+        // ffmpeg only keeps the last `-af`, so the filters are chained into one
+        let mut filters = Vec::new();
+        if self.downmix_stereo {
+            args.extend(["-ac", "2"].map(String::from));
+            filters.push(DOWNMIX_FILTER);
+        }
+        if let Some(loudnorm) = &self.loudnorm {
+            filters.push(loudnorm);
+            // loudnorm outputs 192kHz to measure true peaks
+            let sample_rate = self.sample_rate.unwrap_or(48000);
+            args.extend(["-ar".to_string(), sample_rate.to_string()]);
+        }
+        if !filters.is_empty() {
+            args.extend(["-af".to_string(), filters.join(",")]);
+        }
+        args
+    }
+
+    /// ffmpeg arguments deciding which container metadata ends up in the output
+    fn metadata_args(&self) -> Vec<String> {
+        let mut args = match self.metadata {
+            Some(Metadata::Preserve) => {
+                let mut args = ["-map_metadata", "0"].map(String::from).to_vec();
+                if let Some(creation_time) = &self.creation_time {
+                    args.extend([
+                        "-metadata".to_string(),
+                        format!("creation_time={creation_time}"),
+                    ]);
+                }
+                args
+            }
+            Some(Metadata::Strip) => ["-map_metadata", "-1"].map(String::from).to_vec(),
+            None => Vec::new(),
+        };
+        // set after `-map_metadata`, so it also survives `--metadata strip`
+        args.extend([
+            "-metadata".to_string(),
+            format!("{}={}", self.tag_key, self.tag()),
+        ]);
+        args
+    }
+
+    /// Value of the tag, e.g. `video-compressor:crf=25:codec=x265`
+    fn tag(&self) -> String {
+        let settings = if self.audio_only {
+            "audio-only".to_string()
+        } else if self.remux {
+            "codec=copy".to_string()
+        } else {
+            let codec = match self.encoder.as_str() {
+                "libx265" => "x265",
+                encoder => encoder,
+            };
+            format!("crf={}:codec={codec}", self.crf)
+        };
+        format!("{TAG_PREFIX}{settings}")
+    }
+
+    /// With `--faststart`, unless the output is a stream whose fragments play as they arrive
+    /// anyway. ffmpeg then rewrites the whole file once more after the encode to shift the
+    /// index in front of the media data.
+    fn faststart(&self) -> bool {
+        self.faststart && !self.stream
+    }
+
+    /// ffmpeg arguments of the mp4 muxer. ffmpeg only keeps the last `-movflags`, so all flags
+    /// go into a single one.
+    fn container_args(&self) -> Vec<String> {
+        let mut movflags = Vec::new();
+        // without use_metadata_tags the mp4 muxer drops tags it doesn't know, like some
+        // camera and location tags or a custom `--tag-key`
+        if self.metadata == Some(Metadata::Preserve) || self.tag_key != options::DEFAULT_TAG_KEY {
+            movflags.push("+use_metadata_tags");
+        }
+        // a regular mp4 needs to seek back to the start to write the index once it's done
+        if self.stream {
+            movflags.push("+frag_keyframe+empty_moov+default_base_moof");
+        }
+        if self.faststart() {
+            movflags.push("+faststart");
+        }
+
+        let mut args = Vec::new();
+        if !movflags.is_empty() {
+            args.extend(["-movflags".to_string(), movflags.concat()]);
+        }
+        if self.stream {
+            args.extend(["-f".to_string(), "mp4".to_string()]);
+        }
+        args
+    }
+}
+
+fn ffmpeg_args(
+    path_buf: &Path,
+    dest_path_buf: &Path,
+    settings: &EncodeSettings,
+    options: &Options,
+) -> Vec<OsString> {
+    // the mp4 muxer only announces the second pass of `--faststart` at the info level, which
+    // tells `compress` to stop expecting progress
+    let loglevel = if settings.faststart() {
+        "info"
+    } else {
+        "fatal"
+    };
+    // `-progress` goes to stderr as well, `--stdout` needs stdout for the video
+    let mut args: Vec<OsString> = ["-loglevel", loglevel, "-nostats", "-progress", "pipe:2"]
+        .map(OsString::from)
+        .to_vec();
+    args.extend(settings.input_args().into_iter().map(OsString::from));
+    args.extend(options.ffmpeg_input_args.iter().map(OsString::from));
+    args.extend(["-i".into(), paths::for_ffmpeg(path_buf)]);
+    args.extend(settings.subtitle_inputs());
+    args.extend(settings.codec_args().into_iter().map(OsString::from));
+    args.extend(settings.subtitle_args().into_iter().map(OsString::from));
+    args.extend(settings.metadata_args().into_iter().map(OsString::from));
+    args.extend(settings.container_args().into_iter().map(OsString::from));
+    args.extend(options.ffmpeg_output_args.iter().map(OsString::from));
+    args.extend([paths::for_ffmpeg(dest_path_buf), "-y".into()]);
+    args
+}
+
+/// Prints the ffmpeg command quoted for a shell, so it can be copied to reproduce the encode
+fn print_command(args: &[OsString]) {
+    let command = std::iter::once("ffmpeg".into())
+        .chain(args.iter().map(|arg| arg.to_string_lossy()))
+        .collect::<Vec<_>>();
+    info!("{}", shell_words::join(command));
+}
+
+/// Prints the command `compress` would run for the video with `--dry-run`. The settings are
+/// resolved like for the encode, except for the steps that run ffmpeg themselves.
+fn dry_run(path_buf: &Path, position: Option<RunPosition>, log: &mut Log, options: &Options) {
+    if let Ok(settings) = prepare(path_buf, position, log, options) {
+        print_command(&ffmpeg_args(
+            path_buf,
+            &compressed_path(path_buf),
+            &settings,
+            options,
+        ));
+    }
+}
+
+fn compress(
+    path_buf: PathBuf,
+    dest_path_buf: PathBuf,
+    settings: &EncodeSettings,
+    options: &Options,
+) -> Result<(), SkipReason> {
+    // a hardware encode waits for a session of `--gpu-sessions` if there is no other encoder
+    // to compress the video with
+    let _session = if settings.remux || settings.audio_only {
+        None
+    } else {
+        let wait = options.encoders.next_after(&settings.encoder).is_none();
+        match options.gpu_sessions.open(&settings.encoder, wait) {
+            Ok(session) => session,
+            Err(()) => return Err(SkipReason::EncoderBusy(settings.encoder.clone())),
+        }
+    };
+    let args = ffmpeg_args(&path_buf, &dest_path_buf, settings, options);
+    if options.print_command {
+        print_command(&args);
+    }
+
+    let mut child = match Command::new("ffmpeg")
+        .args(args)
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => return Err(SkipReason::FfmpegNotStarted(e)),
+    };
+    let Some(mut stderr) = child.stderr.take() else {
+        let _ = child.kill();
+        return Err(SkipReason::FfmpegNotStarted(Error::other(
+            "no stderr to read",
+        )));
+    };
+    let mut throttle = throttle::Throttle::start(&child, options.cpu_limit);
+    let cpu_limit = throttle.as_ref().map_or(String::new(), |throttle| {
+        format!(" CPU limit: {}%", throttle.percent())
+    });
+
+    // the line is redrawn with `\r`, which works in every terminal including cmd and powershell,
+    // so shorter lines have to be padded to overwrite the previous one
+    let mut line = format!("Progress: 00:00:00{cpu_limit}");
+    let _ = write!(std::io::stderr(), "{line}");
+    let last_progress = Cell::new(Instant::now());
+    // the moov atom of `--faststart` is moved after the encode without any progress records
+    let second_pass = Cell::new(false);
+    let mut startup_failure = encoder::StartupFailure::default();
+    let mut hwdecode_failure = encoder::HwDecodeFailure::default();
+    let mut progress_reader = ProgressReader::default();
+    let mut show_progress = |record: &str| {
+        startup_failure.observe(record, &settings.encoder);
+        if settings.hwdecode.is_some() {
+            hwdecode_failure.observe(record);
+        }
+        if record.contains(FASTSTART_PASS) {
+            second_pass.set(true);
+        }
+        let Some(progress) = progress_reader.push(record) else {
+            return;
+        };
+        last_progress.set(Instant::now());
+
+        let percent = match settings.duration {
+            Some(duration) if duration > 0.0 => Some((progress.time / duration * 100.0).min(100.0)),
+            _ => None,
+        };
+        if let Some(status) = &options.status_file {
+            status.progress(percent, progress.speed);
+        }
+        events::progress(&path_buf.to_string_lossy(), percent, progress.speed);
+        options.systemd.progress(&format!(
+            "Compressing `{}`: {}{}",
+            path_buf.to_string_lossy(),
+            percent.map_or(display_duration(progress.time), |percent| format!(
+                "{percent:.0}%"
+            )),
+            progress
+                .speed
+                .map_or(String::new(), |speed| format!(" at {speed:.2}x"))
+        ));
+        let percent = match percent {
+            Some(percent) => format!(" ({percent:.0}%)"),
+            None => String::new(),
+        };
+        let speed = match progress.speed {
+            Some(speed) => format!(" Speed: {speed:.2}x"),
+            None => String::new(),
+        };
+        let previous_len = line.len();
+        line = format!(
+            "Progress: {}{percent}{speed}{cpu_limit}",
+            display_duration(progress.time)
+        );
+        let _ = write!(std::io::stderr(), "\r{line:<previous_len$}");
+    };
+
+    // stderr is read on its own thread, so the cancellation is noticed while ffmpeg is quiet
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut chunk = [0; 4096];
+        loop {
+            match stderr.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => {
+                    if sender.send(chunk[..read].to_vec()).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+    });
+
+    let mut splitter = RecordSplitter::default();
+    let started = Instant::now();
+    let time_limit = time_limit(settings, options);
+    let mut space_checked = Instant::now();
+    let mut out_of_space = None;
+    let mut timed_out = None;
+    loop {
+        let wait = throttle
+            .as_ref()
+            .and_then(throttle::Throttle::next_switch)
+            .map_or(POLL_INTERVAL, |next| next.min(POLL_INTERVAL));
+        match receiver.recv_timeout(wait) {
+            Ok(chunk) => splitter.push(&chunk, &mut show_progress),
+            Err(RecvTimeoutError::Timeout) if options.cancel.is_cancelled() => {
+                let _ = child.kill();
+                break;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        // the time ffmpeg was paused for `--cpu-limit` doesn't count as a stall
+        if let Some(paused) = throttle.as_mut().and_then(throttle::Throttle::tick) {
+            last_progress.set(last_progress.get() + paused);
+        }
+        // a full disk would only show up as an obscure ffmpeg error
+        if !settings.stream && space_checked.elapsed() >= SPACE_CHECK_INTERVAL {
+            space_checked = Instant::now();
+            out_of_space = disk::below_floor(&dest_path_buf, options.min_free_space);
+            if out_of_space.is_some() {
+                let _ = child.kill();
+                break;
+            }
+        }
+        if let Some(limit) = time_limit.filter(|&limit| started.elapsed().as_secs_f64() > limit) {
+            timed_out = Some(format!(
+                "ffmpeg took longer than the limit of {}",
+                display_duration(limit)
+            ));
+        } else if !settings.stream
+            && !second_pass.get()
+            && options.stall_timeout > 0.0
+            && stalled_for(last_progress.get(), throttle.as_ref()) > options.stall_timeout
+        {
+            // `--stdout` can wait on whatever reads the stream, e.g. a paused player
+            timed_out = Some(format!(
+                "ffmpeg reported no progress for {}",
+                display_duration(options.stall_timeout)
+            ));
+        }
+        if timed_out.is_some() {
+            let _ = child.kill();
+            break;
+        }
+    }
+    splitter.finish(&mut show_progress);
+    let _ = writeln!(std::io::stderr());
+
+    // a paused ffmpeg can't exit
+    drop(throttle);
+    let status = child.wait();
+    // with Ctrl-C ffmpeg receives the signal as well and exits on its own
+    if options.cancel.is_cancelled() {
+        return Err(SkipReason::Cancelled);
+    }
+    if let Some(available) = out_of_space {
+        return Err(SkipReason::OutOfSpace(available, options.min_free_space));
+    }
+    if let Some(timed_out) = timed_out {
+        return Err(SkipReason::Timeout(timed_out));
+    }
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(match (settings.hwdecode, hwdecode_failure.failed()) {
+            (Some(method), Some(device)) => SkipReason::HwDecodeFailed(method.name(), device),
+            _ if startup_failure.no_session() && !settings.remux && !settings.audio_only => {
+                SkipReason::EncoderBusy(settings.encoder.clone())
+            }
+            _ if startup_failure.unusable() && !settings.remux && !settings.audio_only => {
+                SkipReason::EncoderUnavailable(settings.encoder.clone())
+            }
+            _ => SkipReason::EncodeFailed(format!("ffmpeg exited with {status}")),
+        }),
+        Err(e) => Err(SkipReason::EncodeFailed(format!(
+            "Failed to wait for ffmpeg: {e}"
+        ))),
+    }
+}
+
+/// Seconds the encode may take: `--timeout-factor` times the duration of the video, or
+/// `--file-timeout` if there is no factor or the duration is unknown
+fn time_limit(settings: &EncodeSettings, options: &Options) -> Option<f64> {
+    match (options.timeout_factor, settings.duration) {
+        (Some(factor), Some(duration)) if duration > 0.0 => Some(factor * duration),
+        _ => options.file_timeout,
+    }
+}
+
+/// Seconds since ffmpeg last reported progress, without the time it is paused for
+/// `--cpu-limit`
+fn stalled_for(last_progress: Instant, throttle: Option<&throttle::Throttle>) -> f64 {
+    let paused = throttle.map_or(Duration::ZERO, throttle::Throttle::paused_for);
+    last_progress.elapsed().saturating_sub(paused).as_secs_f64()
+}
+
+/// How often the encode loop wakes up to check for cancellation, timeouts and the free space
+/// while ffmpeg is quiet
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What the mp4 muxer logs when `--faststart` starts moving the index to the front of the file
+const FASTSTART_PASS: &str = "Starting second pass: moving the moov atom";
+
+/// How often the free space at the destination is checked during an encode
+const SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// `field_order` values of interlaced videos, everything else is progressive or unknown
+const INTERLACED_FIELD_ORDERS: [&str; 4] = ["tt", "bb", "tb", "bt"];
+
+/// Mixes the center channel in at full level instead of ffmpeg's default -3dB, which
+/// makes dialogue in downmixed surround tracks noticeably quieter
+const DOWNMIX_FILTER: &str = "aresample=clev=1:slev=0.707:lfe_mix_level=0.5";
+
+/// Audio codecs mp4 can store, others are re-encoded even with `--audio copy`
+const MP4_AUDIO_CODECS: [&str; 8] = ["aac", "mp3", "mp2", "ac3", "eac3", "opus", "flac", "alac"];
+
+/// Video codecs that gain little from being re-encoded with x265
+const EFFICIENT_CODECS: [&str; 3] = ["hevc", "av1", "vp9"];
+
+/// Name of the log in the scanned directory
+const LOG_FILE: &str = "compression_log.json";
+
+/// Start of the tag written into every output, files carrying it are skipped even without a
+/// log entry, e.g. after moving the library to another machine
+const TAG_PREFIX: &str = "video-compressor:";
+
+/// Creates and removes an empty file next to `dest_path_buf` to check that the directory
+/// accepts new files, e.g. that it isn't on a read-only mount
+fn check_writable(dest_path_buf: &Path) -> Result<(), Error> {
+    let mut file_name = OsString::from(".");
+    file_name.push(dest_path_buf.file_name().unwrap_or_default());
+    file_name.push(".writable");
+    let probe_path = dest_path_buf.with_file_name(file_name);
+
+    File::create(&probe_path)?;
+    std::fs::remove_file(&probe_path)
+}
+
+/// Prints which container tags ended up in the compressed file, to check `--metadata`
+fn print_metadata_keys(path: &Path) {
+    match probe::format_tags(path) {
+        Some(tags) if tags.is_empty() => info!("Metadata kept: none"),
+        Some(tags) => {
+            let keys = tags.into_iter().map(|(key, _)| key).collect::<Vec<_>>();
+            info!("Metadata kept: {}", keys.join(", "));
+        }
+        None => info!("Failed to probe the metadata of the compressed file"),
+    }
+}
+
+/// `<name>_x265.mp4` next to the original
+fn compressed_path(path_buf: &Path) -> PathBuf {
+    let mut file_name = path_buf.file_name().unwrap_or_default().to_os_string();
+    file_name.push("_x265.mp4");
+    path_buf.with_file_name(file_name)
+}
+
+fn process_file(
+    path_buf: PathBuf,
+    position: Option<RunPosition>,
+    log: &mut Log,
+    options: &Options,
+) -> Result<Encoded, ()> {
+    let path = path_buf.to_string_lossy().to_string();
+    // restored onto the compressed file once it replaced the original
+    let _accessed = atime::Preserved::new(&path_buf);
+    // windows refuses to replace it after the encode
+    if cfg!(windows) && !options.force_readonly && is_read_only(&path_buf) {
+        log.mark_skipped(
+            path,
+            SkipReason::PermissionDenied(
+                "the file is read-only (use `--force-readonly` to replace it anyway)".to_string(),
+            ),
+        );
+        return Err(());
+    }
+    let original = FileIdentity::of(&path_buf);
+    let times = times::Times::of(&path_buf);
+    let mut encoded = compress_to(
+        &path_buf,
+        &compressed_path(&path_buf),
+        position,
+        log,
+        options,
+    )?;
+
+    // don't clobber a file that another program replaced or wrote to in the meantime
+    if FileIdentity::of(&path_buf) != original {
+        let _ = std::fs::remove_file(&encoded.dest);
+        log.mark_skipped(path, SkipReason::ChangedDuringCompression);
+        return Err(());
+    }
+    let size_prev = original.as_ref().map_or(0, |original| original.len);
+    let answer = options.interactive.confirm(&path_buf, size_prev, &encoded);
+    let Some(path) = follow_answer(answer, path, &encoded.dest, log) else {
+        return Err(());
+    };
+    match replace_or_trash(&encoded.dest, &path_buf, options) {
+        Ok(trashed) => {
+            encoded.trashed = trashed;
+            if let Some(times) = times {
+                if let Err(e) = times.restore(&path_buf) {
+                    info!("Warning: failed to restore the times of `{path}`: {e}");
+                }
+                probe::forget(&path_buf);
+            }
+        }
+        Err(e) => {
+            if options.keep_output_on_failure {
+                info!(
+                    "Kept the compressed file `{}`",
+                    encoded.dest.to_string_lossy()
+                );
+            } else {
+                let _ = std::fs::remove_file(&encoded.dest);
+            }
+            let reason = if e.kind() == std::io::ErrorKind::PermissionDenied {
+                SkipReason::PermissionDenied(e.to_string())
+            } else {
+                SkipReason::Override(e)
+            };
+            log.mark_skipped(path, reason);
+            return Err(());
+        }
+    }
+
+    Ok(encoded)
+}
+
+/// Gives the path back if the original is to be replaced, else keeps or removes the compressed
+/// file. Quitting ends the run and keeps it next to the original.
+fn follow_answer(answer: Answer, path: String, dest: &Path, log: &mut Log) -> Option<String> {
+    match answer {
+        Answer::Yes | Answer::Always => Some(path),
+        Answer::No => {
+            let _ = std::fs::remove_file(dest);
+            log.mark_skipped(path, SkipReason::Declined(None));
+            None
+        }
+        Answer::Quit => {
+            info!("Stopping the run, the original of `{path}` is left as it was");
+            log.stopped = true;
+            log.mark_skipped(path, SkipReason::Declined(Some(dest.to_path_buf())));
+            None
+        }
+    }
+}
+
+/// `replace_original`, moving the original into the trash first with `--trash`. Returns where
+/// it went, `None` without `--trash`. Fails without touching the original if there is no
+/// trash to move it into, `--trash` asked for it to be recoverable.
+fn replace_or_trash(
+    compressed: &Path,
+    original: &Path,
+    options: &Options,
+) -> Result<Option<PathBuf>, Error> {
+    let trashed = if options.trash {
+        let trashed = trash::trash(original)
+            .map_err(|e| Error::other(format!("failed to move the original to the trash: {e}")))?;
+        Some(trashed)
+    } else {
+        None
+    };
+
+    if let Err(e) = replace_writable(compressed, original, options) {
+        if let Some(trashed) = trashed {
+            let file = trashed.file.clone();
+            if let Err(restore_error) = trashed.restore(original) {
+                info!(
+                    "Failed to move the original back from `{}`: {restore_error}",
+                    file.to_string_lossy()
+                );
+            }
+        }
+        return Err(e);
+    }
+
+    Ok(trashed.map(|trashed| trashed.file))
+}
+
+fn is_read_only(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| metadata.permissions().readonly())
+}
+
+/// `replace_original`, with `--force-readonly` clearing the read-only attribute that keeps
+/// windows from replacing the original and setting it on the compressed file in its place
+fn replace_writable(compressed: &Path, original: &Path, options: &Options) -> Result<(), Error> {
+    if !(cfg!(windows) && options.force_readonly && is_read_only(original)) {
+        return replace_original(compressed, original);
+    }
+    set_read_only(original, false)?;
+    if let Err(e) = replace_original(compressed, original) {
+        let _ = set_read_only(original, true);
+        return Err(e);
+    }
+    set_read_only(original, true)
+}
+
+fn set_read_only(path: &Path, read_only: bool) -> Result<(), Error> {
+    let mut permissions = path.metadata()?.permissions();
+    // only used on windows, where this clears the attribute rather than making the file
+    // writable for everyone
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(read_only);
+    std::fs::set_permissions(path, permissions)
+}
+
+/// What identifies a file well enough to notice that it was replaced or modified
+#[derive(PartialEq)]
+struct FileIdentity {
+    modified: Option<SystemTime>,
+    len: u64,
+    /// std has no stable equivalent on Windows, the other fields have to do there
+    #[cfg(unix)]
+    inode: u64,
+}
+
+impl FileIdentity {
+    /// `None` if there is no file at `path`
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = path.metadata().ok()?;
+        Some(FileIdentity {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            #[cfg(unix)]
+            inode: std::os::unix::fs::MetadataExt::ino(&metadata),
+        })
+    }
+}
+
+/// Path to write the compressed file to, which is `dest` unless it already exists. The path
+/// is reserved by creating it empty, so a file that appears after the check, e.g. from another
+/// run on the same directory, is never overwritten by ffmpeg.
+fn resolve_collision(dest: &Path, options: &Options) -> Result<PathBuf, SkipReason> {
+    if reserve(dest)? {
+        return Ok(dest.to_path_buf());
+    }
+
+    match options.on_collision {
+        OnCollision::Overwrite => Ok(dest.to_path_buf()),
+        OnCollision::Skip => Err(SkipReason::DestinationExists(dest.to_path_buf())),
+        OnCollision::Unique => {
+            // `<name>_x265.<nonce>.mp4`, which the scan still excludes as a compressed output
+            let dest = dest.to_string_lossy();
+            let stem = dest.strip_suffix(".mp4").unwrap_or(&dest);
+            let mut nonce = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.subsec_nanos())
+                ^ std::process::id().rotate_left(16);
+            loop {
+                let unique = PathBuf::from(format!("{stem}.{nonce:08x}.mp4"));
+                if reserve(&unique)? {
+                    return Ok(unique);
+                }
+                nonce = nonce.wrapping_add(1);
+            }
+        }
+    }
+}
+
+/// Creates `path` empty if no file has that name yet, `false` if one does
+fn reserve(path: &Path) -> Result<bool, SkipReason> {
+    match File::options().write(true).create_new(true).open(path) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(SkipReason::DestinationNotWritable(e)),
+    }
+}
+
+/// Result of compressing a single file
+struct Encoded {
+    /// Path of the compressed file, differs from the requested one after a collision
+    dest: PathBuf,
+    size_post: u64,
+    /// Wall-clock seconds spent in `compress`
+    encode_time: f64,
+    settings: EncodeSettings,
+    /// Where `--trash` moved the original once it was replaced
+    trashed: Option<PathBuf>,
+    /// Streams of the original that didn't make it into the compressed file
+    lost_streams: Vec<String>,
+}
+
+/// Records the run as the last one unless it was resumed, cancelled or stopped early
+fn record_last_run(log: &mut Log, run_start: i64, options: &Options) {
+    if options.start_from.is_none() && !options.cancel.is_cancelled() && !log.stopped {
+        log.last_run = Some(run_start);
+    }
+}
+
+/// A clock before 1970 would log the compressed file as older than any later change to it
+fn check_clock(now: SystemTime) -> Result<(), SkipReason> {
+    if unix_time(now) < 0 {
+        return Err(SkipReason::ClockBeforeEpoch);
+    }
+    Ok(())
+}
+
+/// Resolves the settings for the file and compresses it into `dest_path_buf`, or a unique
+/// name next to it if that exists, leaving the original untouched.
+fn compress_to(
+    path_buf: &Path,
+    dest_path_buf: &Path,
+    position: Option<RunPosition>,
+    log: &mut Log,
+    options: &Options,
+) -> Result<Encoded, ()> {
+    let path = path_buf.to_string_lossy().to_string();
+    let _accessed = atime::Preserved::new(path_buf);
+
+    if let Err(reason) = check_clock(SystemTime::now()) {
+        log.mark_skipped(path, reason);
+        return Err(());
+    }
+    // fail before the expensive part instead of when the compressed file is written or renamed
+    if let Err(e) = check_writable(dest_path_buf) {
+        log.mark_skipped(path, SkipReason::DestinationNotWritable(e));
+        return Err(());
+    }
+    // the original stays until the output replaces it, which is rarely larger than the source
+    let size = path_buf.metadata().map_or(0, |metadata| metadata.len());
+    let required = options.min_free_space + size;
+    if options.min_free_space > 0 {
+        if let Some(available) = disk::below_floor(dest_path_buf, required) {
+            if options.stop_on_low_space {
+                info!("Stopping the run, not enough free space for `{path}` (--stop-on-low-space)");
+                log.stopped = true;
+            }
+            log.mark_skipped(path, SkipReason::NotEnoughSpace(available, required));
+            return Err(());
+        }
+    }
+
+    let dest_path_buf = &match resolve_collision(dest_path_buf, options) {
+        Ok(dest) => dest,
+        Err(reason) => {
+            log.mark_skipped(path, reason);
+            return Err(());
+        }
+    };
+    let Ok(mut settings) = prepare(path_buf, position, log, options) else {
+        let _ = std::fs::remove_file(dest_path_buf);
+        return Err(());
+    };
+    if let Some(status) = &options.status_file {
+        status.start_file(path_buf, size, position);
+    }
+    events::emit(Event::FileStarted {
+        path: &path,
+        size,
+        index: position.map(|position| position.index),
+        count: position.map(|position| position.count),
+    });
+    options.systemd.status(&match position {
+        Some(position) => format!("Compressing `{path}` {position}"),
+        None => format!("Compressing `{path}`"),
+    });
+    let started = Instant::now();
+    let result = loop {
+        let result = compress(
+            path_buf.to_path_buf(),
+            dest_path_buf.to_path_buf(),
+            &settings,
+            options,
+        );
+        match &result {
+            Err(SkipReason::EncoderUnavailable(encoder)) => {
+                match options.encoders.fall_back(encoder) {
+                    Some(next) => settings.encoder = next,
+                    None => break result,
+                }
+            }
+            Err(SkipReason::EncoderBusy(encoder)) => match options.encoders.next_after(encoder) {
+                Some(next) => {
+                    info!("Warning: {encoder} has no free encode session, compressing this video with {next}");
+                    settings.encoder = next;
+                }
+                None => break result,
+            },
+            Err(SkipReason::HwDecodeFailed(_, true)) => {
+                options.hwdecode.fall_back();
+                settings.hwdecode = None;
+            }
+            Err(SkipReason::HwDecodeFailed(method, false)) => {
+                info!("Note: {method} can't decode `{path}`, decoding it on the CPU");
+                settings.hwdecode = None;
+            }
+            _ => break result,
+        }
+    };
+    if let Err(reason) = result {
+        // a partial output would be mistaken for a finished one by the next run
+        let _ = std::fs::remove_file(dest_path_buf);
+        if let SkipReason::FfmpegNotStarted(e) = &reason {
+            // every other file would fail the same way with a missing or unrunnable ffmpeg,
+            // others like running out of processes only fail this file
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied
+            ) {
+                info!("Stopping the run, ffmpeg can't be started: {e}");
+                log.stopped = true;
+            }
+        }
+        log.mark_skipped(path, reason);
+        return Err(());
+    }
+    let encode_time = started.elapsed().as_secs_f64();
+
+    let post_size = match File::open(dest_path_buf) {
+        Ok(file) => match file.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                log.mark_skipped(path, SkipReason::Metadata(e));
+                return Err(());
+            }
+        },
+        Err(e) => {
+            log.mark_skipped(path, SkipReason::OpeningCompressedFile(e));
+            return Err(());
+        }
+    };
+
+    let lost_streams = stream_loss::lost(path_buf, dest_path_buf, &settings);
+    if !lost_streams.is_empty() {
+        match options.on_stream_loss {
+            OnStreamLoss::Skip => {
+                let _ = std::fs::remove_file(dest_path_buf);
+                log.mark_skipped(path, SkipReason::StreamsLost(lost_streams));
+                return Err(());
+            }
+            OnStreamLoss::Warn => info!(
+                "Warning: the compressed `{path}` lacks {} of the original",
+                lost_streams.join(", ")
+            ),
+            OnStreamLoss::Allow => {}
+        }
+    }
+
+    if let Some(status) = &options.status_file {
+        status.finish_file(size as i64 - post_size as i64);
+    }
+    if options.verbose {
+        print_metadata_keys(dest_path_buf);
+    }
+
+    Ok(Encoded {
+        dest: dest_path_buf.clone(),
+        size_post: post_size,
+        encode_time,
+        settings,
+        trashed: None,
+        lost_streams,
+    })
+}
+
+/// Checks the sidecar, extension rule and skip conditions and resolves the encoder settings for
+/// the file, printing the "Compressing" header and where each override comes from
+fn prepare(
+    path_buf: &Path,
+    position: Option<RunPosition>,
+    log: &mut Log,
+    options: &Options,
+) -> Result<EncodeSettings, ()> {
+    let path = path_buf.to_string_lossy().to_string();
+
+    let (sidecar, source) = match filter::overrides(path_buf, options) {
+        Ok(overrides) => overrides,
+        Err(reason) => {
+            log.mark_skipped(path, reason);
+            return Err(());
+        }
+    };
+    let sidecar_codec = sidecar.as_ref().and_then(|sidecar| sidecar.codec);
+    let sidecar_crf = sidecar.as_ref().and_then(|sidecar| sidecar.crf);
+
+    let audio_only = match filter::audio_only(path_buf, options) {
+        Ok(audio_only) => audio_only,
+        Err(reason) => {
+            log.mark_skipped(path, reason);
+            return Err(());
+        }
+    };
+
+    let duration = probe::duration(path_buf);
+    let trim = match Trim::resolve(options, duration) {
+        Ok(trim) => trim,
+        Err(reason) => {
+            log.mark_skipped(path, reason);
+            return Err(());
+        }
+    };
+
+    let efficient_codec = filter::efficient_codec(path_buf, options, audio_only, sidecar_codec);
+
+    match position {
+        Some(position) => info!("{position} Compressing {}...", path_buf.to_string_lossy()),
+        None => info!("Compressing {}...", path_buf.to_string_lossy()),
+    }
+    print_video_length(path_buf);
+    let mut settings = if audio_only {
+        EncodeSettings::audio_only(options)
+    } else if let Some(codec) = efficient_codec {
+        EncodeSettings::remux(&codec, options)
+    } else if sidecar_codec == Some(SidecarCodec::Copy) {
+        info!("Copying the video stream ({source})");
+        EncodeSettings {
+            remux: true,
+            ..EncodeSettings::from_options(options)
+        }
+    } else if let Some(crf) = sidecar_crf {
+        info!("Using CRF {crf} ({source})");
+        EncodeSettings {
+            crf,
+            deinterlace: EncodeSettings::resolve_deinterlace(path_buf, options),
+            ..EncodeSettings::from_options(options)
+        }
+    } else {
+        if sidecar_codec == Some(SidecarCodec::X265) {
+            info!("Re-encoding the video stream ({source})");
+        }
+        EncodeSettings::resolve(path_buf, options)
+    };
+    if let Some(preset) = sidecar.as_ref().and_then(|sidecar| sidecar.preset.clone()) {
+        info!("Using preset {preset} ({source})");
+        settings.preset = Some(preset);
+    }
+    settings.sidecar = sidecar;
+
+    let reencoded = !settings.audio_only && !settings.remux;
+    if reencoded && (options.max_fps.is_some() || options.keyframe_seconds.is_some()) {
+        settings.resolve_frame_rate(path_buf, options.max_fps);
+    }
+
+    if let Some(seconds) = options.keyframe_seconds {
+        if reencoded {
+            let Some(frame_rate) = settings.output_fps else {
+                log.mark_skipped(path, SkipReason::UnknownFrameRate);
+                return Err(());
+            };
+            let keyint = ((seconds * frame_rate).round() as u32).max(1);
+            info!("Keyframe interval: {keyint} frames ({seconds}s at {frame_rate:.3}fps)");
+            settings.keyint = Some(keyint);
+            settings.min_keyint = settings.min_keyint.map(|min_keyint| min_keyint.min(keyint));
+        }
+    }
+
+    settings.trim = trim;
+    settings.duration = match trim {
+        Some(Trim {
+            start,
+            end: Some(end),
+        }) => Some(end - start),
+        Some(Trim { start, end: None }) => duration.map(|duration| duration - start),
+        None => duration,
+    };
+    if let (Some(_), Some(duration)) = (trim, settings.duration) {
+        info!("Trimmed length: {}", display_duration(duration));
+    }
+    if let Some(mode) = options.loudnorm {
+        settings.loudnorm = Some(match mode {
+            Loudnorm::SinglePass => loudnorm::single_pass(),
+            Loudnorm::TwoPass if options.dry_run => {
+                info!("Not measuring the loudness (--dry-run), normalizing in a single pass");
+                loudnorm::single_pass()
+            }
+            Loudnorm::TwoPass => {
+                info!("Measuring the loudness for --loudnorm-two-pass...");
+                loudnorm::two_pass(path_buf, &settings.input_args()).unwrap_or_else(|e| {
+                    info!("Warning: {e}, normalizing in a single pass");
+                    loudnorm::single_pass()
+                })
+            }
+        });
+        settings.sample_rate = probe::audio_sample_rate(path_buf);
+    }
+    // e.g. the PCM audio of avi and mov files, ffmpeg refuses to copy it into mp4
+    if settings.audio == AudioCodec::Copy && !settings.no_audio && !settings.audio_only {
+        let audio_codecs = probe::audio_codecs(path_buf);
+        if let Some(codec) = audio_codecs
+            .iter()
+            .find(|codec| !MP4_AUDIO_CODECS.contains(&codec.as_str()))
+        {
+            info!("The {codec} audio can't be copied into mp4, re-encoding it with aac");
+            settings.audio = AudioCodec::Aac;
+        }
+    }
+    if settings.metadata == Some(Metadata::Preserve) {
+        settings.creation_time = probe::creation_time(path_buf);
+    }
+    if options.embed_subtitles && !settings.audio_only {
+        settings.subtitles = subtitles::find(path_buf);
+        if !settings.subtitles.is_empty() {
+            let names = settings
+                .subtitles
+                .iter()
+                .map(|subtitle| {
+                    subtitle
+                        .path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                })
+                .collect::<Vec<_>>();
+            info!("Embedding subtitles: {}", names.join(", "));
+            settings.kept_subtitles = subtitles::text_streams(path_buf);
+        }
+    }
+
+    Ok(settings)
+}
+
+/// Moves the compressed file over the original. `fs::rename` replaces existing files on both
+/// unix and windows, unlike shelling out to `mv`/`move` (a cmd builtin that can't be spawned).
+fn replace_original(compressed: &Path, original: &Path) -> Result<(), Error> {
+    match std::fs::rename(compressed, original) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_into_place(compressed, original)?;
+            std::fs::remove_file(compressed)
+        }
+        result => result,
+    }
+}
+
+/// Appended to the name of the copy on the other filesystem until it replaces the original
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Copies larger than this show their progress
+const COPY_PROGRESS_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Copies `source` over `dest` on another filesystem. The copy is written to a `.partial` file
+/// next to `dest` and only renamed once it is synced, so an interruption never leaves a
+/// half-written file under the final name.
+fn copy_into_place(source: &Path, dest: &Path) -> Result<(), Error> {
+    let mut partial = dest.as_os_str().to_os_string();
+    partial.push(PARTIAL_SUFFIX);
+    let partial = PathBuf::from(partial);
+
+    let result = copy_synced(source, &partial).and_then(|_| std::fs::rename(&partial, dest));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+    result
+}
+
+fn copy_synced(source: &Path, dest: &Path) -> Result<(), Error> {
+    let mut reader = File::open(source)?;
+    let mut writer = File::create(dest)?;
+    let total = reader.metadata()?.len();
+    let show_progress = total >= COPY_PROGRESS_SIZE;
+
+    let mut buffer = vec![0; 8 * 1024 * 1024];
+    let mut copied = 0;
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buffer[..read])?;
+        copied += read as u64;
+        if show_progress {
+            let _ = write!(
+                std::io::stderr(),
+                "\rCopying: {} of {} ({:.0}%)",
+                filesize::display(copied),
+                filesize::display(total),
+                copied as f64 / total as f64 * 100.0
+            );
+        }
+    }
+    if show_progress {
+        let _ = writeln!(std::io::stderr());
+    }
+
+    writer.sync_all()
+}
+
+/// Checks that ffmpeg and ffprobe can be found in `PATH` (`ffmpeg.exe` on windows)
+fn check_ffmpeg() -> Result<(), String> {
+    for program in ["ffmpeg", "ffprobe"] {
+        match Command::new(program)
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+        {
+            Ok(status) if status.success() => {}
+            Ok(status) => return Err(format!("`{program} -version` failed with {status}")),
+            Err(e) => {
+                return Err(format!(
+                    "Failed to run `{program}`, is it installed and in PATH? {e}"
+                ))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Exit code if any file failed, skipped files don't affect it
+const EXIT_FAILED: i32 = 2;
+
+/// Exit code after Ctrl-C, like a shell reports a process terminated by SIGINT
+const EXIT_CANCELLED: i32 = 130;
+
+fn main() {
+    let mut args = std::env::args();
+    let program = args.next().unwrap_or_default();
+    let options = match Options::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{e}\n\nUsage: {program} [options] <path>\n\n{USAGE}");
+            std::process::exit(1);
+        }
+    };
+
+    MESSAGES_TO_STDERR.store(options.command == Mode::Stream, Ordering::Relaxed);
+    run_id();
+    let run_start = unix_time(SystemTime::now());
+    if run_start < 0 {
+        info!("Warning: the system clock is set before 1970, files are skipped until it is set");
+    }
+    atime::PRESERVE_ATIME.store(options.preserve_atime, Ordering::Relaxed);
+    times::PRESERVE_TIMES.store(options.preserve_times, Ordering::Relaxed);
+    filesize::SI.store(options.si, Ordering::Relaxed);
+    options.warn_managed_args();
+    color::init(options.no_color);
+    // the doctor reports a missing ffmpeg itself
+    if options.command == Mode::Doctor {
+        if !doctor::doctor(Path::new(&options.path), &options) {
+            std::process::exit(1);
+        }
+        return;
+    }
+    // only read and write the log
+    if options.command == Mode::CleanLog {
+        clean::clean_log(Path::new(&options.path), &options);
+        return;
+    }
+    if options.command == Mode::LogVersions {
+        versions::log_versions(Path::new(&options.path));
+        return;
+    }
+    if options.command == Mode::ListProcessed {
+        processed::list_processed(Path::new(&options.path), options.run_id.as_deref());
+        return;
+    }
+    if let Err(e) = check_ffmpeg() {
+        info!("{e}");
+        std::process::exit(1);
+    }
+    if !options.allow_old_ffmpeg {
+        if let Some(version) = version::ffmpeg() {
+            if let Err(e) = version::check_minimum(&version, options.min_ffmpeg_version) {
+                info!("{e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    let encodes = !matches!(
+        options.command,
+        Mode::ListCodecs | Mode::Explain(_) | Mode::RebuildLog
+    );
+    if encodes {
+        let available = doctor::list("-encoders");
+        // a build that can't list its encoders is left to fail on the first encode
+        if !available.is_empty() {
+            if let Err(e) = options.encoders.select(&available) {
+                info!("Can't compress: {e}");
+                std::process::exit(1);
+            }
+        }
+        if options.hwdecode.current().is_some() {
+            let hwaccels = codecs::hwaccels();
+            if !hwaccels.is_empty() {
+                options.hwdecode.select(&hwaccels);
+            }
+        }
+    }
+
+    if let Some(event_log) = &options.event_log {
+        if let Err(e) = events::open(event_log) {
+            info!(
+                "Can't open the event log `{}`: {e}",
+                event_log.to_string_lossy()
+            );
+            std::process::exit(1);
+        }
+    }
+
+    let path = options.path.clone();
+    let path_buf = PathBuf::from(path.clone());
+    events::emit(Event::RunStarted { path: &path });
+    match &options.command {
+        Mode::Compress => {}
+        Mode::Compare => {
+            compare::compare(&path_buf, &options);
+            return;
+        }
+        Mode::Doctor | Mode::CleanLog | Mode::LogVersions | Mode::ListProcessed => {
+            unreachable!("handled before checking ffmpeg")
+        }
+        Mode::ListCodecs => {
+            codecs::list_codecs();
+            return;
+        }
+        Mode::RebuildLog => {
+            options.cancel.on_interrupt();
+            let mut log = rebuild::rebuild_log(&path_buf, &options);
+            log.print_status(None, options.verbose);
+            log.save();
+            return;
+        }
+        Mode::Explain(file) => {
+            explain::explain(Path::new(file), &path_buf, &options);
+            return;
+        }
+        Mode::Bench => {
+            bench::bench(&path_buf, &options);
+            return;
+        }
+        Mode::Stream => {
+            if stream::run(&path_buf, &options).is_err() {
+                std::process::exit(EXIT_FAILED);
+            }
+            return;
+        }
+        Mode::Remote(remote) => {
+            options.cancel.on_interrupt();
+            let (failed, summary) = remote::run(remote, &options);
+            finish_run(failed, &summary, &options);
+            return;
+        }
+        Mode::Manifest => {
+            options.cancel.on_interrupt();
+            let free_space_before = disk::available_space(&path_buf);
+            let mut log = manifest::run(&options);
+            if options.dry_run {
+                info!("Dry run, no video was compressed and the log is left as it was");
+                return;
+            }
+            let free_space = free_space_before.zip(disk::available_space(&path_buf));
+            let failed = log.has_failures();
+            let summary = email::Summary::of(&log);
+            log.print_status(free_space, options.verbose);
+            log.save();
+            finish_run(failed || !log.is_saved(), &summary, &options);
+            return;
+        }
+        Mode::RetryFailed => {
+            options.cancel.on_interrupt();
+            let free_space_before = disk::available_space(&path_buf);
+            let mut log = retry::retry_failed(&path_buf, &options);
+            let free_space = free_space_before.zip(disk::available_space(&path_buf));
+            let failed = log.has_failures();
+            let summary = email::Summary::of(&log);
+            log.print_status(free_space, options.verbose);
+            log.save();
+            finish_run(failed || !log.is_saved(), &summary, &options);
+            return;
+        }
+        Mode::Archive(archive) => {
+            let archive = PathBuf::from(archive);
+            options.cancel.on_interrupt();
+            let free_space_before = disk::available_space(&archive);
+            let mut log = archive::run(&path_buf, &archive, &options);
+            let free_space = free_space_before.zip(disk::available_space(&archive));
+            let failed = log.has_failures();
+            let summary = email::Summary::of(&log);
+            log.print_status(free_space, options.verbose);
+            log.save();
+            finish_run(failed || !log.is_saved(), &summary, &options);
+            return;
+        }
+    }
+
+    options.cancel.on_interrupt();
+    let free_space_before = disk::available_space(&path_buf);
+    let free_space = || Some((free_space_before?, disk::available_space(&path_buf)?));
+    let mut log = if path_buf.is_dir() {
+        let mut log = Log::new(path.clone());
+        if matches!(options.since, Some(Since::LastRun)) && log.last_run.is_none() {
+            info!("No completed run recorded yet, considering all files");
+        }
+
+        iterate_dir(&path_buf, &mut log, &options);
+        record_last_run(&mut log, run_start, &options);
         log
     } else {
-        let mut log = Log::new(
-            path_buf
-                .parent()
-                .expect(format!("Failed to get parent of `{path}`").as_str())
-                .to_string_lossy()
-                .to_string(),
-        );
+        // only a missing directory or drive like `D:\` has no parent
+        let Some(dir) = path_buf.parent() else {
+            info!("`{path}` doesn't exist");
+            std::process::exit(1);
+        };
+        let mut log = Log::new(dir.to_string_lossy().to_string());
 
+        options.systemd.ready();
         let metadata = path_buf.metadata();
         if let Ok(metadata) = metadata {
             let modified = match metadata.modified() {
-                Ok(system_time) => match system_time.duration_since(SystemTime::UNIX_EPOCH) {
-                    Ok(d) => d.as_secs(),
-                    Err(e) => {
-                        log.save();
-                        panic!("Unable to retrieve system time!\n{e}");
-                    }
-                },
+                Ok(system_time) => unix_time(system_time),
                 Err(e) => {
                     log.mark_skipped(path, SkipReason::Metadata(e));
                     log.save();
-                    log.print_status();
-                    return;
+                    log.print_status(free_space(), options.verbose);
+                    std::process::exit(EXIT_FAILED);
                 }
             };
 
-            if !log.is_already_processed(&path, modified) {
+            if log.is_already_processed(&path, modified, &options) {
+                info!("`{path}` is already compressed");
+            } else if options.dry_run {
+                dry_run(&path_buf, None, &mut log, &options);
+            } else {
                 let prev_size = metadata.len();
-                if let Ok(post_size) = process_file(path_buf, &mut log) {
-                    log.mark_processed(path, prev_size, post_size);
+                if let Ok(encoded) = process_file(path_buf.clone(), None, &mut log, &options) {
+                    log.mark_processed(path, prev_size, &encoded);
                     log.save();
                 }
             }
@@ -415,6 +3620,749 @@ fn main() {
 
         log
     };
-    log.print_status();
+    if options.dry_run {
+        info!("Dry run, no video was compressed and the log is left as it was");
+        return;
+    }
+    let failed = log.has_failures();
+    let summary = email::Summary::of(&log);
+    log.print_status(free_space(), options.verbose);
     log.save();
+    // the next run would start over without the log
+    finish_run(failed || !log.is_saved(), &summary, &options);
+}
+
+/// Marks the status file as finished, mails the summary with `--email` and exits with the code
+/// of a cancelled run or of one where files failed
+fn finish_run(failed: bool, summary: &email::Summary, options: &Options) {
+    options.systemd.stopping(if options.cancel.is_cancelled() {
+        "Cancelled"
+    } else if failed {
+        "Completed with failures"
+    } else {
+        "Completed"
+    });
+    let state = if options.cancel.is_cancelled() {
+        status::State::Cancelled
+    } else {
+        status::State::Completed
+    };
+    if let Some(status) = &options.status_file {
+        status.finish_run(state);
+    }
+    events::emit(Event::RunFinished { state, failed });
+    email::report(summary, failed, options);
+    if options.cancel.is_cancelled() {
+        info!("Cancelled, the remaining videos are compressed by the next run");
+        std::process::exit(EXIT_CANCELLED);
+    }
+    if failed {
+        std::process::exit(EXIT_FAILED);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn entries_before_the_start_path_are_skipped() {
+        let start = Path::new("/videos/2019/b/clip.mp4");
+        assert!(is_before_start(Path::new("/videos/2018"), start));
+        assert!(is_before_start(Path::new("/videos/2019/a"), start));
+        assert!(is_before_start(Path::new("/videos/2019/b/a.mp4"), start));
+        assert!(!is_before_start(Path::new("/videos/2019"), start));
+        assert!(!is_before_start(Path::new("/videos/2019/b"), start));
+        assert!(!is_before_start(start, start));
+        assert!(!is_before_start(Path::new("/videos/2019/b/d.mp4"), start));
+        assert!(!is_before_start(Path::new("/videos/2020"), start));
+    }
+
+    #[test]
+    fn deep_trees_are_walked_in_order() {
+        let dir = TempDir::new("walk");
+        // 10 chains of nested directories with a video at the bottom of each, the paths of
+        // macOS and windows end at 1024 and 260 characters
+        let depth = if cfg!(target_os = "linux") { 1000 } else { 80 };
+        for chain in (0..10).rev() {
+            let mut path = dir.join(chain.to_string());
+            for _ in 1..depth {
+                path.push("d");
+            }
+            std::fs::create_dir_all(&path).unwrap();
+            std::fs::write(path.join("clip.mp4"), "").unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&dir, dir.join("0/loop")).unwrap();
+        std::fs::write(dir.join("0/clip.mp4"), "").unwrap();
+
+        let mut log = Log::in_memory();
+        let candidates = scan(&dir, &mut log, &Options::default());
+        let chains = candidates
+            .iter()
+            .map(|candidate| {
+                let relative = candidate.path.strip_prefix(&dir).unwrap();
+                (
+                    relative.components().count(),
+                    relative.iter().next().unwrap().to_owned(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(chains.len(), 11);
+        assert_eq!(chains[0], (2, "0".into()));
+        for (i, chain) in chains[1..].iter().enumerate() {
+            assert_eq!(*chain, (depth + 1, i.to_string().into()));
+        }
+        assert!(log.skipped_files.is_empty());
+    }
+
+    #[test]
+    fn runs_that_stopped_early_are_no_last_run() {
+        let options = Options::default();
+        let mut log = Log::in_memory();
+        record_last_run(&mut log, 1000, &options);
+        assert_eq!(log.last_run, Some(1000));
+
+        // e.g. quitting `--interactive` or `--stop-on-low-space` left files unvisited
+        log.stopped = true;
+        record_last_run(&mut log, 2000, &options);
+        assert_eq!(log.last_run, Some(1000));
+
+        let mut log = Log::in_memory();
+        let dest = Path::new("a.mp4_x265.mp4");
+        assert_eq!(
+            follow_answer(Answer::Yes, "a.mp4".to_string(), dest, &mut log),
+            Some("a.mp4".to_string())
+        );
+        assert_eq!(
+            follow_answer(Answer::Quit, "b.mp4".to_string(), dest, &mut log),
+            None
+        );
+        assert_eq!(
+            log.skipped_files["b.mp4"],
+            SkipReason::Declined(Some(dest.to_path_buf())).to_string()
+        );
+        assert!(log.stopped);
+        record_last_run(&mut log, 1000, &options);
+        assert_eq!(log.last_run, None);
+
+        let mut log = Log::in_memory();
+        let resumed = Options {
+            start_from: Some("b.mp4".to_string()),
+            ..Options::default()
+        };
+        record_last_run(&mut log, 1000, &resumed);
+        assert_eq!(log.last_run, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unmodified_directories_are_pruned_since_the_last_run() {
+        let dir = TempDir::new("prune");
+        for sub in ["old", "new"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+            std::fs::write(dir.join(sub).join("clip.mp4"), "").unwrap();
+        }
+        std::fs::write(dir.join("clip.mp4"), "").unwrap();
+        let long_ago = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        for old in [dir.join("old"), dir.clone()] {
+            std::fs::File::open(old)
+                .unwrap()
+                .set_modified(long_ago)
+                .unwrap();
+        }
+
+        let mut log = Log::in_memory();
+        log.last_run = Some(unix_time(SystemTime::now()) - 60);
+        let mut options = Options {
+            since_last_run: true,
+            ..Options::default()
+        };
+        let candidates = scan(&dir, &mut log, &options);
+        assert_eq!(
+            candidates
+                .iter()
+                .map(|candidate| candidate.path.strip_prefix(&dir).unwrap())
+                .collect::<Vec<_>>(),
+            [Path::new("new/clip.mp4")]
+        );
+
+        options.full_scan = true;
+        assert_eq!(scan(&dir, &mut log, &options).len(), 3);
+    }
+
+    #[test]
+    fn time_spans_use_the_largest_fitting_unit() {
+        assert_eq!(display_time_span(42.4), "42 seconds");
+        assert_eq!(display_time_span(41.0 * 60.0 + 20.0), "41 minutes");
+        assert_eq!(display_time_span(2.3 * 3600.0), "2.3 hours");
+    }
+
+    fn x265_params(args: &[String]) -> Vec<&String> {
+        args.iter()
+            .zip(args.iter().skip(1))
+            .filter(|(arg, _)| *arg == "-x265-params")
+            .map(|(_, value)| value)
+            .collect()
+    }
+
+    #[test]
+    fn video_args_merge_x265_params_into_one_argument() {
+        let options = Options::parse(
+            [
+                "--crf",
+                "18",
+                "--x265-params",
+                "aq-mode=3:bframes=8",
+                "test.mp4",
+            ]
+            .into_iter()
+            .map(String::from),
+        )
+        .unwrap();
+        let settings = EncodeSettings {
+            crf: 18,
+            ..EncodeSettings::from_options(&options)
+        };
+
+        assert_eq!(
+            x265_params(&settings.video_args()),
+            ["crf=18:log-level=fatal:aq-mode=3:bframes=8"]
+        );
+    }
+
+    #[test]
+    fn video_args_user_x265_params_win() {
+        let options = Options::parse(
+            [
+                "--maxrate",
+                "4000",
+                "--x265-params",
+                "crf=20:vbv-bufsize=6000",
+                "--x265-extra",
+                "log-level=info",
+                "test.mp4",
+            ]
+            .into_iter()
+            .map(String::from),
+        )
+        .unwrap();
+        let settings = EncodeSettings::from_options(&options);
+
+        assert_eq!(
+            x265_params(&settings.video_args()),
+            ["crf=20:vbv-maxrate=4000:vbv-bufsize=6000:log-level=info"]
+        );
+    }
+
+    #[test]
+    fn ffmpeg_args_keep_input_options_before_the_input() {
+        let options = Options::parse(
+            [
+                "--ffmpeg-input-args",
+                "-threads 2",
+                "--hwdecode",
+                "qsv",
+                "test.mp4",
+            ]
+            .into_iter()
+            .map(String::from),
+        )
+        .unwrap();
+        let settings = EncodeSettings::from_options(&options);
+        let args = ffmpeg_args(
+            Path::new("in.mp4"),
+            Path::new("out.mp4"),
+            &settings,
+            &options,
+        );
+        let args = args
+            .iter()
+            .map(|arg| arg.to_str().unwrap())
+            .collect::<Vec<_>>();
+        let input = args.iter().position(|arg| *arg == "-i").unwrap();
+        assert_eq!(
+            args[input - 4..=input + 1],
+            ["-hwaccel", "qsv", "-threads", "2", "-i", "in.mp4"]
+        );
+        assert_eq!(args[args.len() - 2..], ["out.mp4", "-y"]);
+    }
+
+    #[test]
+    fn embedded_subtitles_are_mapped_after_the_streams_of_the_original() {
+        let options = Options::parse(["test.mp4".to_string()].into_iter()).unwrap();
+        let settings = EncodeSettings {
+            subtitles: vec![
+                subtitles::Subtitle {
+                    path: PathBuf::from("clip.srt"),
+                    language: None,
+                },
+                subtitles::Subtitle {
+                    path: PathBuf::from("clip.en.srt"),
+                    language: Some("en".to_string()),
+                },
+            ],
+            kept_subtitles: vec![2],
+            trim: Some(Trim {
+                start: 10.0,
+                end: None,
+            }),
+            ..EncodeSettings::from_options(&options)
+        };
+        let args = ffmpeg_args(
+            Path::new("clip.mkv"),
+            Path::new("out.mp4"),
+            &settings,
+            &options,
+        );
+        let args = args
+            .iter()
+            .map(|arg| arg.to_str().unwrap())
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert!(args
+            .contains("-ss 10.000 -i clip.mkv -ss 10.000 -i clip.srt -ss 10.000 -i clip.en.srt"));
+        assert!(args.contains(
+            "-map 0:V:0? -map 0:a? -map 0:2 -map 1:s:0 -map 2:s:0 -c:s mov_text \
+             -metadata:s:s:2 language=en"
+        ));
+    }
+
+    #[test]
+    fn frame_rate_cap_comes_after_deinterlacing() {
+        let options = Options::parse(["test.mp4".to_string()].into_iter()).unwrap();
+        let settings = EncodeSettings {
+            deinterlace: true,
+            fps_cap: Some(29.97),
+            ..EncodeSettings::from_options(&options)
+        };
+
+        let args = settings.video_args();
+        let vf = args.iter().position(|arg| arg == "-vf").unwrap();
+        assert_eq!(args[vf + 1], "bwdif,fps=29.97");
+    }
+
+    #[test]
+    fn decoded_frames_stay_on_the_gpu_only_for_the_matching_encoder() {
+        let options = Options::parse(
+            ["--hwdecode", "cuda", "test.mp4"]
+                .into_iter()
+                .map(String::from),
+        )
+        .unwrap();
+        let settings = EncodeSettings {
+            encoder: "hevc_nvenc".to_string(),
+            ..EncodeSettings::from_options(&options)
+        };
+        assert_eq!(
+            settings.input_args(),
+            ["-hwaccel", "cuda", "-hwaccel_output_format", "cuda"]
+        );
+
+        let filtered = EncodeSettings {
+            deinterlace: true,
+            ..settings
+        };
+        assert_eq!(filtered.input_args(), ["-hwaccel", "cuda"]);
+        let software = EncodeSettings::from_options(&options);
+        assert_eq!(software.input_args(), ["-hwaccel", "cuda"]);
+        let remux = EncodeSettings {
+            remux: true,
+            ..EncodeSettings::from_options(&options)
+        };
+        assert!(remux.input_args().is_empty());
+    }
+
+    #[test]
+    fn tag_survives_stripped_metadata() {
+        let options = Options::parse(
+            ["--metadata", "strip", "--tag-key", "vc", "test.mp4"]
+                .into_iter()
+                .map(String::from),
+        )
+        .unwrap();
+        let settings = EncodeSettings {
+            crf: 25,
+            ..EncodeSettings::from_options(&options)
+        };
+
+        assert_eq!(
+            settings.metadata_args(),
+            [
+                "-map_metadata",
+                "-1",
+                "-metadata",
+                "vc=video-compressor:crf=25:codec=x265"
+            ]
+        );
+        assert_eq!(
+            settings.container_args(),
+            ["-movflags", "+use_metadata_tags"]
+        );
+    }
+
+    #[test]
+    fn faststart_shares_the_movflags_and_is_left_out_for_streams() {
+        let options = Options::parse(
+            ["--faststart", "--tag-key", "vc", "test.mp4"]
+                .into_iter()
+                .map(String::from),
+        )
+        .unwrap();
+        let mut settings = EncodeSettings::from_options(&options);
+        assert_eq!(
+            settings.container_args(),
+            ["-movflags", "+use_metadata_tags+faststart"]
+        );
+        let args = ffmpeg_args(Path::new("a.mp4"), Path::new("b.mp4"), &settings, &options);
+        assert_eq!(args[..2], ["-loglevel", "info"]);
+
+        settings.stream = true;
+        assert!(!settings.faststart());
+        assert!(!settings.container_args().concat().contains("faststart"));
+        let args = ffmpeg_args(Path::new("a.mp4"), Path::new("b.mp4"), &settings, &options);
+        assert_eq!(args[..2], ["-loglevel", "fatal"]);
+    }
+
+    #[test]
+    fn copy_into_place_leaves_no_partial_file() {
+        let dir = TempDir::new("copy");
+        let original = dir.join("lecture 01.mp4");
+        let compressed = dir.join("lecture 01.mp4_x265.mp4");
+        std::fs::write(&original, "original").unwrap();
+        std::fs::write(&compressed, "compressed").unwrap();
+
+        copy_into_place(&compressed, &original).unwrap();
+        assert_eq!(std::fs::read_to_string(&original).unwrap(), "compressed");
+        assert!(!dir.join("lecture 01.mp4.partial").exists());
+
+        // a failed copy keeps the destination as it was
+        assert!(copy_into_place(&dir.join("missing.mp4"), &original).is_err());
+        assert_eq!(std::fs::read_to_string(&original).unwrap(), "compressed");
+        assert!(!dir.join("lecture 01.mp4.partial").exists());
+    }
+
+    #[test]
+    fn imported_files_keep_the_settings_of_their_tag() {
+        let mut log = Log::in_memory();
+        log.mark_imported(
+            "a.mp4".into(),
+            100,
+            7,
+            Some("video-compressor:crf=23:codec=x265"),
+        );
+        log.mark_imported("b.mp4".into(), 100, 7, Some("video-compressor:codec=copy"));
+        log.mark_imported("c.mp4".into(), 100, 7, None);
+
+        let a = &log.shrunk_files["a.mp4"];
+        assert_eq!((a.crf, a.remuxed, a.imported), (Some(23), false, true));
+        assert_eq!(a.size_prev, a.size_post);
+        assert!(log.shrunk_files["b.mp4"].remuxed);
+        assert_eq!(log.shrunk_files["c.mp4"].crf, None);
+        assert!(log.is_already_processed(&"c.mp4".to_string(), 7, &Options::default()));
+        assert!(log.added_files.is_empty());
+    }
+
+    #[test]
+    fn changed_settings_compress_files_again() {
+        let mut log = Log::in_memory();
+        let file_log = FileLog {
+            modified: 7,
+            crf: Some(28),
+            preset: Some("medium".to_string()),
+            ..FileLog::default()
+        };
+        log.shrunk_files.insert("a.mp4".into(), file_log.clone());
+        log.shrunk_files.insert(
+            "b.mp4".into(),
+            FileLog {
+                sidecar: Some(Sidecar::default()),
+                ..file_log
+            },
+        );
+        let processed = |log: &Log, path: &str, args: &[&str]| {
+            let args = args.iter().chain(&["test.mp4"]).map(|arg| arg.to_string());
+            log.is_already_processed(&path.to_string(), 7, &Options::parse(args).unwrap())
+        };
+
+        assert!(processed(&log, "a.mp4", &[]));
+        assert!(processed(
+            &log,
+            "a.mp4",
+            &["--crf", "28", "--preset", "medium"]
+        ));
+        assert!(!processed(&log, "a.mp4", &["--crf", "22"]));
+        assert!(!processed(&log, "a.mp4", &["--preset", "slow"]));
+        assert!(!processed(
+            &log,
+            "a.mp4",
+            &["--encoder-fallback", "libx264"]
+        ));
+        assert!(processed(&log, "b.mp4", &["--crf", "22"]));
+
+        // files compressed without `--preset` are compressed again with one
+        let default_preset = FileLog {
+            modified: 7,
+            crf: Some(28),
+            ..FileLog::default()
+        };
+        log.shrunk_files.insert("c.mp4".into(), default_preset);
+        assert!(processed(&log, "c.mp4", &[]));
+        assert!(!processed(&log, "c.mp4", &["--preset", "medium"]));
+        assert!(!processed(&log, "c.mp4", &["--preset", "slow"]));
+
+        // remuxed files have no encode settings to compare
+        let remuxed = FileLog {
+            modified: 7,
+            remuxed: true,
+            ..FileLog::default()
+        };
+        log.shrunk_files.insert("d.mp4".into(), remuxed);
+        assert!(processed(&log, "d.mp4", &["--preset", "slow"]));
+        assert!(processed(&log, "d.mp4", &["--encoder-fallback", "libx264"]));
+
+        let fallback = FileLog {
+            modified: 7,
+            crf: Some(28),
+            encoder: Some("libx264".to_string()),
+            ..FileLog::default()
+        };
+        log.shrunk_files.insert("e.mp4".into(), fallback);
+        assert!(!processed(&log, "e.mp4", &[]));
+        assert!(processed(
+            &log,
+            "e.mp4",
+            &["--encoder-fallback", "libx264,libx265"]
+        ));
+    }
+
+    #[test]
+    fn log_pointing_at_a_directory_is_kept_in_memory() {
+        let dir = TempDir::new("log_dir");
+        std::fs::create_dir_all(dir.join(LOG_FILE)).unwrap();
+
+        let mut log = Log::new(dir.to_string_lossy().to_string());
+        log.mark_skipped("a.mp4".into(), SkipReason::NoVideoStream);
+        assert!(matches!(log.try_save(), Err(RunError::SaveLog(..))));
+        log.save();
+        assert!(!log.is_saved());
+        assert_eq!(log.skipped_files.len(), 1);
+
+        // a log that can be written again is saved by the next save
+        std::fs::remove_dir(dir.join(LOG_FILE)).unwrap();
+        log.save();
+        assert!(log.is_saved());
+        assert!(dir.join(LOG_FILE).is_file());
+    }
+
+    #[test]
+    fn large_logs_are_compacted_into_shards_that_are_read_back() {
+        let dir = TempDir::new("compact");
+        let clip = dir.join("clip.mp4").to_string_lossy().to_string();
+        let mut log = Log::in_memory();
+        let file_log = FileLog {
+            size_post: 1000,
+            ..FileLog::default()
+        };
+        log.shrunk_files.insert(clip.clone(), file_log);
+        let check = serde_json::from_str(r#"{"modified":5,"deep_check":0.0,"error":null}"#);
+        log.record_input_check(clip.clone(), check.unwrap());
+        let json = serde_json::to_string(&log).unwrap() + &" ".repeat(shards::COMPACT_SIZE);
+        std::fs::write(dir.join(LOG_FILE), json).unwrap();
+
+        let log = Log::new(dir.to_string_lossy().to_string());
+        assert_eq!(log.shards, Some(shards::SHARD_COUNT));
+        assert!(log.shrunk_files.is_sharded());
+
+        // the log isn't pointed at shards that couldn't be written
+        std::fs::write(dir.join(shards::SHARD_DIR), "").unwrap();
+        assert!(log.try_save().is_err());
+        let unchanged = Log::load(dir.to_string_lossy().to_string());
+        assert_eq!(unchanged.shards, None);
+        assert_eq!(unchanged.shrunk_files[&clip].size_post, 1000);
+
+        std::fs::remove_file(dir.join(shards::SHARD_DIR)).unwrap();
+        log.try_save().unwrap();
+        let written = std::fs::read_dir(dir.join(shards::SHARD_DIR))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(written.len(), 2, "{written:?}");
+        assert!(written.iter().all(|name| name.ends_with(".json")));
+        let size = std::fs::metadata(dir.join(LOG_FILE)).unwrap().len();
+        assert!(size < shards::COMPACT_SIZE as u64, "{size}");
+
+        let log = Log::new(dir.to_string_lossy().to_string());
+        assert_eq!(log.shards, Some(shards::SHARD_COUNT));
+        assert_eq!(log.shrunk_files[&clip].size_post, 1000);
+        assert!(log.input_check(&clip).is_some());
+    }
+
+    #[test]
+    fn unwritable_log_directory_doesnt_stop_the_run() {
+        // a log below a regular file can't be written even by root
+        let dir = TempDir::new("read_only");
+        let file = dir.join("file");
+        std::fs::write(&file, "").unwrap();
+
+        let mut log = Log::new(file.to_string_lossy().to_string());
+        log.save();
+        assert!(!log.is_saved());
+        // the run goes on with the log in memory
+        log.mark_skipped("a.mp4".into(), SkipReason::NoVideoStream);
+        log.save();
+        assert!(!log.is_saved());
+        assert_eq!(log.skipped_files.len(), 1);
+    }
+
+    #[test]
+    fn paths_of_the_same_file_are_merged_into_one_absolute_entry() {
+        let dir = std::env::current_dir().unwrap();
+        let absolute = dir.join("clip.mp4").to_string_lossy().to_string();
+        let mut log = Log::in_memory();
+        for (path, modified) in [("./clip.mp4", 2), (absolute.as_str(), 1), ("clip.mp4", 3)] {
+            let file_log = FileLog {
+                modified,
+                ..FileLog::default()
+            };
+            log.shrunk_files.insert(path.to_string(), file_log);
+        }
+        log.mark_skipped(
+            format!("{}/", dir.to_string_lossy()),
+            SkipReason::EncodeFailed("boom".into()),
+        );
+
+        let (renamed, merged) = log.normalize_keys();
+        assert_eq!(renamed, 4);
+        assert_eq!(
+            merged,
+            [
+                ("./clip.mp4".to_string(), absolute.clone()),
+                ("clip.mp4".to_string(), absolute.clone())
+            ]
+        );
+        assert_eq!(log.shrunk_files.len(), 1);
+        assert_eq!(log.shrunk_files[&absolute].modified, 3);
+        let dir = dir.to_string_lossy().to_string();
+        assert!(log.retry_files[&dir].recorded.is_some());
+        assert!(log.failed_files.contains_key(&dir));
+        assert_eq!(log.normalize_keys(), (0, Vec::new()));
+    }
+
+    #[test]
+    fn retry_entries_of_older_logs_have_no_time() {
+        let log = serde_json::from_str::<HashMap<String, RetryEntry>>(
+            r#"{"a.mp4": "Encoding failed", "b.mp4": {"reason": "Timeout", "recorded": 17}}"#,
+        )
+        .unwrap();
+        assert_eq!(log["a.mp4"].reason, "Encoding failed");
+        assert_eq!(log["a.mp4"].recorded, None);
+        assert_eq!(log["b.mp4"].recorded, Some(17));
+    }
+
+    #[test]
+    fn failures_stay_listed_for_a_retry_until_processed() {
+        let mut log = Log::in_memory();
+        log.mark_skipped("a.mp4".into(), SkipReason::EncodeFailed("boom".into()));
+        log.mark_skipped("b.mp4".into(), SkipReason::BelowBitrate(500, 1000));
+        log.mark_skipped("c.mp4".into(), SkipReason::NotEnoughSpace(1, 2));
+        let mut retry = log.retry_files.keys().collect::<Vec<_>>();
+        retry.sort();
+        assert_eq!(retry, ["a.mp4", "c.mp4"]);
+
+        log.mark_imported("a.mp4".into(), 100, 7, None);
+        // skipped on purpose this time
+        log.mark_skipped("c.mp4".into(), SkipReason::BelowBitrate(500, 1000));
+        assert!(log.retry_files.is_empty());
+    }
+
+    #[test]
+    fn files_modified_before_1970_round_trip_through_the_log() {
+        let dir = TempDir::new("epoch");
+        let path = dir.join("restored.mp4");
+        let before_epoch = SystemTime::UNIX_EPOCH - Duration::from_secs(10 * 365 * 24 * 3600);
+        File::create(&path)
+            .unwrap()
+            .set_modified(before_epoch)
+            .unwrap();
+        assert_eq!(unix_time(before_epoch), -315360000);
+
+        let mut log = Log::in_memory();
+        let candidates = scan(&dir, &mut log, &Options::default());
+        assert_eq!(candidates[0].modified, -315360000);
+        let key = path.to_string_lossy().to_string();
+        log.mark_imported(key.clone(), 0, candidates[0].modified, None);
+        let log: Log = serde_json::from_str(&serde_json::to_string(&log).unwrap()).unwrap();
+        assert_eq!(log.shrunk_files[&key].modified, -315360000);
+        assert!(check_clock(before_epoch).is_err());
+        assert!(check_clock(SystemTime::now()).is_ok());
+        assert!(SkipReason::ClockBeforeEpoch.is_retryable());
+        assert!(log.is_already_processed(&key, -315360000, &Options::default()));
+    }
+
+    #[test]
+    fn compressed_paths_are_reserved() {
+        let dir = TempDir::new("reserve");
+        let dest = dir.join("clip.mp4_x265.mp4");
+        let options = Options::default();
+
+        assert_eq!(resolve_collision(&dest, &options).ok(), Some(dest.clone()));
+        assert!(dest.exists());
+        // a second run on the directory gets its own name instead of sharing the reserved one
+        let unique = resolve_collision(&dest, &options).ok().unwrap();
+        assert_ne!(unique, dest);
+        assert!(unique.exists());
+        assert_ne!(resolve_collision(&dest, &options).ok(), Some(unique));
+    }
+}
+
+#[cfg(all(test, windows))]
+mod windows_tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn replace_original_overwrites_existing_file() {
+        let dir = TempDir::new("with spaces ä");
+        let original = dir.join("lecture 01.mp4");
+        let compressed = dir.join("lecture 01.mp4_x265.mp4");
+        std::fs::write(&original, "original").unwrap();
+        std::fs::write(&compressed, "compressed").unwrap();
+
+        replace_original(&compressed, &original).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&original).unwrap(), "compressed");
+        assert!(!compressed.exists());
+    }
+
+    #[test]
+    fn read_only_originals_are_only_replaced_when_forced() {
+        let dir = TempDir::new("ro");
+        let original = dir.join("lecture 01.mp4");
+        let compressed = dir.join("lecture 01.mp4_x265.mp4");
+        std::fs::write(&original, "original").unwrap();
+        std::fs::write(&compressed, "compressed").unwrap();
+        set_read_only(&original, true).unwrap();
+
+        let mut options = Options::default();
+        let e = replace_writable(&compressed, &original, &options).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+        options.force_readonly = true;
+        replace_writable(&compressed, &original, &options).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&original).unwrap(), "compressed");
+        assert!(is_read_only(&original));
+        set_read_only(&original, false).unwrap();
+    }
+
+    #[test]
+    fn log_is_created_next_to_backslash_paths() {
+        let dir = TempDir::new("log");
+
+        let log = Log::new(dir.to_string_lossy().to_string());
+        log.save();
+
+        assert!(dir.join("compression_log.json").is_file());
+    }
 }
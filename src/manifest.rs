@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::{
+    compress_all,
+    options::Options,
+    paths,
+    sidecar::{Sidecar, SidecarCodec},
+    unix_time, Candidate, Log, SkipReason,
+};
+
+/// List of files for `--manifest`, each with its own overrides.
+///
+/// ```toml
+/// [[file]]
+/// path = "2019/wedding.mov"
+/// crf = 18
+/// preset = "slow"
+///
+/// [[file]]
+/// path = "2019/drone.mp4"
+/// codec = "copy"
+/// ```
+///
+/// JSON manifests have the same structure, `{"file": [{"path": "...", "crf": 18}]}`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    file: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    /// Relative to the directory of the manifest
+    path: String,
+    crf: Option<u8>,
+    codec: Option<SidecarCodec>,
+    preset: Option<String>,
+}
+
+/// File listed in the manifest with its overrides, which take precedence over its sidecar
+#[derive(Clone)]
+pub struct ManifestFile {
+    pub path: PathBuf,
+    pub overrides: Sidecar,
+}
+
+/// Reads the manifest at `path`, as JSON for a `.json` file and as TOML otherwise
+pub fn load(path: &Path) -> Result<Vec<ManifestFile>, String> {
+    let name = path.to_string_lossy();
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read manifest `{name}`: {e}"))?;
+    let is_json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    let manifest = if is_json {
+        serde_json::from_str::<Manifest>(&content).map_err(|e| e.to_string())
+    } else {
+        toml::from_str::<Manifest>(&content).map_err(|e| e.to_string())
+    }
+    .map_err(|e| format!("Failed to parse manifest `{name}`: {e}"))?;
+
+    let dir = directory(path);
+    let mut files = Vec::<ManifestFile>::new();
+    for entry in manifest.file {
+        let overrides = Sidecar {
+            crf: entry.crf,
+            codec: entry.codec,
+            preset: entry.preset,
+            skip: false,
+        };
+        overrides.validate(&format!(
+            "the entry of `{}` in manifest `{name}`",
+            entry.path
+        ))?;
+        let file = paths::absolute(&dir.join(&entry.path));
+        if files.iter().any(|listed| listed.path == file) {
+            return Err(format!(
+                "`{}` is listed twice in manifest `{name}`",
+                entry.path
+            ));
+        }
+        files.push(ManifestFile {
+            path: file,
+            overrides,
+        });
+    }
+    if files.is_empty() {
+        return Err(format!("Manifest `{name}` lists no files"));
+    }
+    Ok(files)
+}
+
+/// Directory the paths of the manifest are relative to, `.` for a bare file name whose parent
+/// is empty
+pub fn directory(manifest: &Path) -> &Path {
+    manifest
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+/// Compresses the files of `--manifest` in the order they are listed, sharing the log of the
+/// directory of the manifest (`options.path`). Nothing is compressed if a listed file is
+/// missing.
+pub fn run(options: &Options) -> Log {
+    let missing = options
+        .manifest
+        .iter()
+        .filter(|file| !file.path.is_file())
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        for file in &missing {
+            info!(
+                "`{}` is listed in the manifest but isn't a file",
+                file.path.to_string_lossy()
+            );
+        }
+        info!(
+            "{} of the {} files of the manifest are missing, nothing was compressed",
+            missing.len(),
+            options.manifest.len()
+        );
+        std::process::exit(1);
+    }
+
+    let mut log = Log::new(options.path.clone());
+    let mut candidates = Vec::new();
+    for file in &options.manifest {
+        let path = file.path.to_string_lossy().to_string();
+        let metadata = match file.path.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                log.mark_skipped(path, SkipReason::Metadata(e));
+                continue;
+            }
+        };
+        let modified = metadata.modified().map_or(0, unix_time);
+        if log.is_already_processed(&path, modified, options) {
+            info!("`{path}` is already compressed");
+            continue;
+        }
+        candidates.push(Candidate {
+            path: file.path.clone(),
+            size: metadata.len(),
+            modified,
+        });
+    }
+    options.systemd.ready();
+
+    compress_all(candidates, &mut log, options);
+    log
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn bare_manifest_names_are_in_the_current_directory() {
+        assert_eq!(directory(Path::new("job.toml")), Path::new("."));
+        assert_eq!(directory(Path::new("jobs/job.toml")), Path::new("jobs"));
+        assert_eq!(directory(Path::new("/job.toml")), Path::new("/"));
+    }
+
+    #[test]
+    fn manifests_list_files_relative_to_their_directory_in_order() {
+        let dir = TempDir::new("manifest");
+        let toml = dir.join("job.toml");
+        std::fs::write(
+            &toml,
+            "[[file]]\npath = \"b.mp4\"\ncrf = 18\n\n[[file]]\npath = \"./a.mp4\"\ncodec = \"copy\"\n",
+        )
+        .unwrap();
+        let files = load(&toml).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, paths::absolute(&dir.join("b.mp4")));
+        assert_eq!(files[0].overrides.crf, Some(18));
+        assert_eq!(files[1].path, paths::absolute(&dir.join("a.mp4")));
+        assert!(files[1].overrides.codec == Some(SidecarCodec::Copy));
+
+        let json = dir.join("job.json");
+        std::fs::write(&json, r#"{"file": [{"path": "a.mp4", "preset": "slow"}]}"#).unwrap();
+        assert_eq!(
+            load(&json).unwrap()[0].overrides.preset.as_deref(),
+            Some("slow")
+        );
+
+        for invalid in [
+            "[[file]]\npath = \"a.mp4\"\ncrf = 60\n",
+            "[[file]]\npath = \"a.mp4\"\n[[file]]\npath = \"./a.mp4\"\n",
+            "[[file]]\npath = \"a.mp4\"\nskip = true\n",
+            "",
+        ] {
+            std::fs::write(&toml, invalid).unwrap();
+            assert!(load(&toml).is_err(), "{invalid}");
+        }
+    }
+}
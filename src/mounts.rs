@@ -0,0 +1,401 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::paths;
+
+/// Symlinks followed from a directory to the one it stands for
+const MAX_LINKS: usize = 8;
+
+/// Kind of storage of the directories walked with `--local-only`, looked up once per mount
+/// (or drive on windows) since most trees live on a single one. The mounts are read once per
+/// run and a directory is matched to its mount by its path without touching it, a stat of a
+/// directory on a dead hard-mounted NFS share would block.
+#[derive(Default)]
+pub struct Mounts {
+    table: Option<imp::Table>,
+    kinds: HashMap<imp::Key, Option<String>>,
+}
+
+impl Mounts {
+    /// Why `dir` isn't stored locally, e.g. `network filesystem (nfs4)`. `None` for local
+    /// drives and for directories whose storage can't be told, which are walked as usual.
+    pub fn not_local(&mut self, dir: &Path) -> Option<String> {
+        let table = &*self.table.get_or_insert_with(imp::Table::read);
+        let mut dir = lexical(&paths::absolute(dir));
+        for _ in 0..MAX_LINKS {
+            let key = table.key(&dir)?;
+            let kind = self
+                .kinds
+                .entry(key)
+                .or_insert_with_key(|key| table.not_local(key));
+            if kind.is_some() {
+                return kind.clone();
+            }
+            // a link on a local filesystem may lead elsewhere, reading it doesn't follow it
+            let Ok(target) = std::fs::read_link(&dir) else {
+                return None;
+            };
+            dir = lexical(&dir.parent().unwrap_or(&dir).join(target));
+        }
+        None
+    }
+}
+
+/// `path` with `.` and `..` resolved by its components alone
+fn lexical(path: &Path) -> PathBuf {
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            component => resolved.push(component),
+        }
+    }
+    resolved
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::{
+        ffi::OsString,
+        os::unix::ffi::OsStringExt,
+        path::{Path, PathBuf},
+    };
+
+    /// Types of `/proc/self/mountinfo` whose files are read over the network
+    const NETWORK_FILESYSTEMS: [&str; 16] = [
+        "nfs",
+        "nfs4",
+        "cifs",
+        "smb3",
+        "smbfs",
+        "ncpfs",
+        "afs",
+        "ceph",
+        "glusterfs",
+        "lustre",
+        "davfs",
+        "fuse.sshfs",
+        "fuse.rclone",
+        "fuse.s3fs",
+        "fuse.gcsfuse",
+        "fuse.glusterfs",
+    ];
+
+    /// Index of the mount in the table
+    pub type Key = usize;
+
+    /// Entries of `/proc/self/mountinfo`
+    pub struct Table(pub Vec<Mount>);
+
+    /// Entry of `/proc/self/mountinfo`
+    #[derive(Debug, PartialEq)]
+    pub struct Mount {
+        pub point: PathBuf,
+        /// `major:minor` of the device
+        pub device: String,
+        pub fs_type: String,
+        pub source: String,
+    }
+
+    impl Table {
+        /// No mounts if the table can't be read, every directory is local then
+        pub fn read() -> Self {
+            let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+            Table(parse(&mountinfo))
+        }
+
+        /// Mount `dir` is on, the one with the longest mount point containing it. Of several
+        /// mounts on the same point the last one hides the others.
+        pub fn key(&self, dir: &Path) -> Option<Key> {
+            self.0
+                .iter()
+                .enumerate()
+                .filter(|(_, mount)| dir.starts_with(&mount.point))
+                .max_by_key(|(_, mount)| mount.point.components().count())
+                .map(|(index, _)| index)
+        }
+
+        pub fn not_local(&self, key: &Key) -> Option<String> {
+            let mount = &self.0[*key];
+            if NETWORK_FILESYSTEMS.contains(&mount.fs_type.as_str()) {
+                Some(format!("network filesystem ({})", mount.fs_type))
+            } else if is_removable(&mount.device) {
+                Some(format!("removable drive ({})", mount.source))
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Mounts in the order they were mounted, lines that can't be read are left out
+    pub fn parse(mountinfo: &str) -> Vec<Mount> {
+        mountinfo
+            .lines()
+            .filter_map(|line| {
+                // 36 35 98:0 /mnt1 /mnt/parent rw,noatime master:1 - ext3 /dev/root rw
+                let fields = line.split(' ').collect::<Vec<_>>();
+                let separator = fields.iter().skip(6).position(|field| *field == "-")? + 6;
+                Some(Mount {
+                    point: unescape(fields.get(4)?),
+                    device: fields.get(2)?.to_string(),
+                    fs_type: fields.get(separator + 1)?.to_string(),
+                    source: unescape(fields.get(separator + 2)?)
+                        .to_string_lossy()
+                        .to_string(),
+                })
+            })
+            .collect()
+    }
+
+    /// Spaces, tabs, newlines and backslashes of the fields are written as octal escapes
+    fn unescape(field: &str) -> PathBuf {
+        let raw = field.as_bytes();
+        let mut bytes = Vec::with_capacity(raw.len());
+        let mut index = 0;
+        while index < raw.len() {
+            let code = field
+                .get(index + 1..index + 4)
+                .filter(|_| raw[index] == b'\\')
+                .and_then(|octal| u8::from_str_radix(octal, 8).ok());
+            match code {
+                Some(code) => {
+                    bytes.push(code);
+                    index += 4;
+                }
+                None => {
+                    bytes.push(raw[index]);
+                    index += 1;
+                }
+            }
+        }
+        PathBuf::from(OsString::from_vec(bytes))
+    }
+
+    /// Whether the block device is flagged as removable or attached over USB, which USB disks
+    /// usually aren't flagged as. Partitions share the flag of their disk.
+    fn is_removable(device: &str) -> bool {
+        let Ok(sys) = Path::new("/sys/dev/block").join(device).canonicalize() else {
+            return false;
+        };
+        let flagged = [Some(sys.as_path()), sys.parent()]
+            .into_iter()
+            .flatten()
+            .any(|dir| {
+                std::fs::read_to_string(dir.join("removable"))
+                    .is_ok_and(|removable| removable.trim() == "1")
+            });
+        flagged
+            || sys
+                .components()
+                .any(|component| component.as_os_str().to_string_lossy().starts_with("usb"))
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use std::{
+        path::{Path, PathBuf},
+        process::Command,
+    };
+
+    /// Index of the mount in the table
+    pub type Key = usize;
+
+    /// Source, mount point and flags of the mounts listed by `mount`, e.g.
+    /// `//user@nas/share on /Volumes/share (smbfs, nodev, nosuid, mounted by user)`
+    pub struct Table(Vec<(String, PathBuf, String)>);
+
+    impl Table {
+        pub fn read() -> Self {
+            let Ok(output) = Command::new("mount").output() else {
+                return Table(Vec::new());
+            };
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let mounts = stdout.lines().filter_map(|line| {
+                let (source, rest) = line.split_once(" on ")?;
+                let (point, flags) = rest.rsplit_once(" (")?;
+                let flags = flags.trim_end_matches(')');
+                Some((source.to_string(), PathBuf::from(point), flags.to_string()))
+            });
+            Table(mounts.collect())
+        }
+
+        /// Mount `dir` is on, the one with the longest mount point containing it
+        pub fn key(&self, dir: &Path) -> Option<Key> {
+            self.0
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, point, _))| dir.starts_with(point))
+                .max_by_key(|(_, (_, point, _))| point.components().count())
+                .map(|(index, _)| index)
+        }
+
+        pub fn not_local(&self, key: &Key) -> Option<String> {
+            let (source, point, flags) = &self.0[*key];
+            not_local(source, point, flags)
+        }
+    }
+
+    /// Local filesystems are flagged `local`, whether they are removable is up to `diskutil`
+    fn not_local(source: &str, point: &Path, flags: &str) -> Option<String> {
+        let mut flags = flags.split(", ");
+        let fs_type = flags.next().unwrap_or("unknown");
+        if !flags.any(|flag| flag == "local") {
+            return Some(format!("network filesystem ({fs_type})"));
+        }
+
+        let output = Command::new("diskutil")
+            .arg("info")
+            .arg(point)
+            .output()
+            .ok()?;
+        let removable = String::from_utf8_lossy(&output.stdout).lines().any(|line| {
+            let Some((key, value)) = line.split_once(':') else {
+                return false;
+            };
+            matches!(
+                (key.trim(), value.trim()),
+                ("Removable Media", "Removable") | ("Device Location", "External")
+            )
+        });
+        removable.then(|| format!("removable drive ({source})"))
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::{
+        os::windows::ffi::OsStrExt,
+        path::{Path, PathBuf},
+    };
+
+    const DRIVE_REMOVABLE: u32 = 2;
+    const DRIVE_REMOTE: u32 = 4;
+    const DRIVE_CDROM: u32 = 5;
+
+    extern "system" {
+        fn GetDriveTypeW(root: *const u16) -> u32;
+    }
+
+    /// Root of the drive or share, e.g. `C:\` or `\\nas\share\`
+    pub type Key = PathBuf;
+
+    /// The drive of a directory is told by its path alone
+    pub struct Table;
+
+    impl Table {
+        pub fn read() -> Self {
+            Table
+        }
+
+        /// The prefix and the root directory of the absolute `dir`
+        pub fn key(&self, dir: &Path) -> Option<Key> {
+            Some(dir.components().take(2).collect())
+        }
+
+        pub fn not_local(&self, root: &Key) -> Option<String> {
+            not_local(root)
+        }
+    }
+
+    /// Mapped network drives and shares are remote. USB disks usually count as fixed drives,
+    /// only sticks and card readers as removable ones.
+    fn not_local(root: &Path) -> Option<String> {
+        let wide = root
+            .as_os_str()
+            .encode_wide()
+            .chain(Some(0))
+            .collect::<Vec<_>>();
+        match unsafe { GetDriveTypeW(wide.as_ptr()) } {
+            DRIVE_REMOTE => Some(format!("network drive ({})", root.to_string_lossy())),
+            DRIVE_REMOVABLE | DRIVE_CDROM => {
+                Some(format!("removable drive ({})", root.to_string_lossy()))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod imp {
+    use std::path::Path;
+
+    pub type Key = ();
+
+    /// Every directory counts as local where the storage can't be told
+    pub struct Table;
+
+    impl Table {
+        pub fn read() -> Self {
+            Table
+        }
+
+        pub fn key(&self, _dir: &Path) -> Option<Key> {
+            Some(())
+        }
+
+        pub fn not_local(&self, _key: &Key) -> Option<String> {
+            None
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{imp::*, Mounts};
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn directories_are_on_the_last_mount_of_the_longest_mount_point() {
+        let mounts = parse(
+            "22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw\n\
+             40 22 0:45 / /mnt/nas rw,relatime shared:20 - nfs4 nas:/video rw,vers=4.2\n\
+             41 22 8:17 / /media/usb\\040stick rw,nosuid shared:21 - vfat /dev/sdb1 rw\n\
+             42 40 0:46 / /mnt/nas rw,relatime - cifs //nas/video rw\n\
+             garbage line\n",
+        );
+        assert_eq!(mounts.len(), 4);
+        assert_eq!(mounts[2].point, PathBuf::from("/media/usb stick"));
+        assert_eq!(mounts[2].device, "8:17");
+        assert_eq!(mounts[2].source, "/dev/sdb1");
+
+        let table = Table(mounts);
+        let fs_type = |dir: &str| {
+            let key = table.key(Path::new(dir))?;
+            Some(table.0[key].fs_type.as_str())
+        };
+        assert_eq!(fs_type("/home/videos"), Some("ext4"));
+        assert_eq!(fs_type("/mnt/nas/2019"), Some("cifs"));
+        assert_eq!(fs_type("/mnt/nasty"), Some("ext4"));
+        assert_eq!(fs_type("/media/usb stick/clips"), Some("vfat"));
+    }
+
+    #[test]
+    fn directories_are_matched_by_their_path_without_touching_them() {
+        let dir = TempDir::new("mounts");
+        let nas = dir.join("nas");
+        std::os::unix::fs::symlink(&nas, dir.join("link")).unwrap();
+        let mountinfo = format!(
+            "22 1 0:999 / / rw - ext4 /dev/root rw\n40 22 0:45 / {} rw - nfs4 nas:/video rw\n",
+            nas.to_string_lossy()
+        );
+        let mut mounts = Mounts {
+            table: Some(Table(parse(&mountinfo))),
+            ..Mounts::default()
+        };
+
+        // the share isn't mounted, a stat would fail rather than block
+        let network = Some("network filesystem (nfs4)".to_string());
+        assert_eq!(mounts.not_local(&nas.join("2019")), network);
+        assert_eq!(mounts.not_local(&dir.join("nas/a/../b")), network);
+        assert_eq!(mounts.not_local(&dir.join("link")), network);
+        assert_eq!(mounts.not_local(&dir), None);
+    }
+}
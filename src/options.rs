@@ -0,0 +1,1597 @@
+use std::{
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    cancel::Cancel,
+    config::{Config, DirConfigs, DEFAULT_CRF},
+    encoder::{Encoders, GpuSessions, HwAccel, HwDecoder},
+    filesize,
+    interactive::Interactive,
+    manifest::{self, ManifestFile},
+    paths, probe,
+    remote::Remote,
+    sample::Metric,
+    status::StatusFile,
+    systemd::Systemd,
+    version,
+};
+
+pub const USAGE: &str = "Options:
+    --auto-crf <metric>=<score>   search the highest CRF reaching e.g. vmaf=93 or ssim=0.98 on samples
+    --audio <codec>               `copy` (default) the audio or re-encode it with `aac` or `opus`
+    --audio-only-mode <mode>      `skip` (default) files without a video stream or `reencode` their audio
+    --no-audio                    drop all audio streams
+    --embed-subtitles             mux `<video>.srt`/`.vtt` and `<video>.<lang>.srt`/`.vtt` next to a
+                                  video into the compressed file
+    --faststart                   put the index at the start of the compressed file, so it plays
+                                  while it is downloaded (rewrites the file once more)
+    --downmix-stereo              re-encode the audio as stereo (with aac unless `--audio opus` is given)
+    --loudnorm                    normalize the audio loudness to EBU R128 (re-encodes the audio like
+                                  --downmix-stereo)
+    --loudnorm-two-pass           measure the loudness first and normalize linearly, more accurate
+                                  than --loudnorm
+    --metadata <mode>             `preserve` all container tags including creation_time or `strip` them
+    --verbose                     print details like the metadata that survived the encode and
+                                  every compressed file in the overview
+    --hidden                      also compress videos in hidden (dot) files and directories
+    --local-only                  skip the directories on network filesystems and removable drives
+    --preserve-atime              restore the access times of the files and directories the run reads
+    --preserve-times              give compressed files the modification time of their original, and
+                                  on macOS and windows its creation time
+    --no-color                    disable colored output (also disabled by NO_COLOR or without a tty)
+    --si                          show and read sizes in powers of 1000 (kB, MB, GB) instead of 1024
+                                  (KiB, MiB, GiB)
+    --compare <file>              compress <file> next to the original without replacing it and
+                                  print the size and bitrate difference
+    --source <dir>                compress the videos of <dir> into the tree given with --archive
+    --archive <dir>               instead of replacing them, skipping videos whose archived copy
+                                  is newer than the source
+    --stdout                      write the compressed video (fragmented mp4) of a single file to
+                                  stdout, all messages go to stderr
+    --compare-ssim                also print the SSIM of the compressed file with --compare
+    --compare-cleanup             remove the compressed file again with --compare
+    --bench <file>                encode a sample of <file> with every preset and CRF combination
+                                  and print the size, time, speed and bitrate of each, the
+                                  original isn't touched (alias --benchmark)
+    --bench-presets <presets>     comma separated presets for --bench, default fast,medium,slow
+    --bench-crfs <crfs>           comma separated CRFs for --bench, default 22,25,28
+    --bench-seconds <secs>        length of the --bench sample from the middle, default 60
+    --bench-metric <metric>       also score every --bench encode with `ssim` or `vmaf`
+    --keep-benchmark-files        keep the sample and the encodes of --bench and print where
+    --config <file>               read settings (e.g. resolution dependent CRFs) from a toml file
+    --crf <crf>                   CRF used for every video, overrides the config
+    --maxrate <kbps>              cap the bitrate of CRF encodes (x265 vbv-maxrate)
+    --bufsize <kbits>             VBV buffer size used with --maxrate, defaults to twice the maxrate
+    --trim-start <secs>           drop the first <secs> seconds of every video
+    --trim-end <secs>             drop the last <secs> seconds of every video
+    --tune <tune>                 x265 tune: psnr, ssim, grain, animation, fastdecode or zerolatency
+    --preset <preset>             x265 preset from ultrafast to placebo, ffmpeg defaults to medium
+    --profile <profile>           `fast`, `balanced` or `archive` bundle of CRF, preset and bit depth,
+                                  --crf, --preset and the config take precedence
+    --keyint <frames>             maximum distance between keyframes (x265 keyint)
+    --min-keyint <frames>         minimum distance between keyframes (x265 min-keyint)
+    --keyframe-seconds <secs>     keyframe interval in seconds, converted with the frame rate
+    --x265-params <params>        additional x265 params, e.g. \"aq-mode=3:bframes=8\", replacing
+                                  built-in ones with the same key
+    --encoder-fallback <list>     video encoders tried in order if the previous one is missing or
+                                  fails (default libx265,hevc_videotoolbox,libx264)
+    --no-fallback                 only encode with the first video encoder
+    --hwdecode <method>           decode the videos on the GPU with `cuda` or `qsv`
+    --gpu-sessions <n>            hardware encodes open at once, others wait or use the next
+                                  encoder (default 3)
+    --deinterlace <mode>          `off` (default), `on` or `auto` to deinterlace interlaced videos
+    --max-fps <fps>               drop frames of videos with a higher frame rate, e.g. 30
+    --estimate-time               estimate the total encode time from a sample before compressing
+    --ffmpeg-input-args <args>    additional ffmpeg arguments inserted before the input
+    --ffmpeg-output-args <args>   additional ffmpeg arguments inserted before the output
+    --print-command               print the ffmpeg command before running it
+    --dry-run                     print the ffmpeg command of every video without compressing
+                                  anything
+    --remux-efficient             copy videos already encoded with hevc, av1 or vp9 into a new
+                                  container instead of re-encoding them
+    --remux-codecs <list>         codecs copied by --remux-efficient (default hevc,av1,vp9), implies
+                                  --remux-efficient
+    --min-ffmpeg-version <ver>    refuse to run with an ffmpeg older than <ver> (default 4.0)
+    --allow-old-ffmpeg            run with an ffmpeg older than --min-ffmpeg-version anyway
+    --doctor                      check ffmpeg, the needed encoders, the config and the log of <path>
+    --rebuild-log                 record the already compressed videos of <path> in a new log
+    --retry-failed                only compress the videos of <path> that failed in earlier runs
+                                  (default: the current directory), exits with 1 on problems
+    --clean-log                   remove the entries of missing files from the log of <path>, merge
+                                  entries of the same file and print what changed (with --dry-run
+                                  only print it)
+    --older-than <days>           with --clean-log, also drop retry entries older than <days>
+    --pretty                      with --clean-log, write the log indented
+    --manifest <file>             compress the files listed in a TOML or JSON manifest in their order,
+                                  each with its own `crf`, `codec` and `preset`, instead of <path>
+    --log-versions                list the compressed files of the log of <path> grouped by the
+                                  ffmpeg and x265 builds that compressed them
+    --list-processed              list the compressed files of the log of <path> with the ID of the
+                                  run that compressed them, oldest runs first
+    --run-id <id>                 with --list-processed, only list the files of runs whose ID starts
+                                  with <id>, e.g. `20240312` for the runs of that day
+    --list-codecs                 list the encoders and hardware acceleration methods of ffmpeg
+    --explain <file>              print every check deciding whether <file> is compressed in a run
+                                  over <path>, which defaults to the directory of <file>
+    --start-from <path>           skip everything that is walked before <path> below the directory,
+                                  to resume an interrupted run
+    --on-collision <mode>         if <file>_x265.mp4 already exists: write a `unique` (default) name
+                                  next to it, `skip` the video or `overwrite` it
+    --on-stream-loss <mode>       if the compressed file lacks streams of the original, like a second
+                                  audio track: `warn` (default), `skip` the video or `allow` it
+    --dedupe <copy|skip>          recognize copies of already compressed videos by their content and
+                                  copy the compressed file over them or skip them
+    --since <last-run|date>       only consider files modified after the last completed run or a
+                                  date (YYYY-MM-DD or unix timestamp)
+    --since-last-run              skip the files of directories not modified since the last
+                                  completed run without looking at them (alias
+                                  --skip-unchanged-dirs)
+    --full-scan                   look at every file, overriding --since-last-run
+    --file-timeout <duration>     stop ffmpeg after <duration>, e.g. 90m or 2h, and move on
+                                  (0 disables the timeout)
+    --timeout-factor <factor>     scale the timeout with the video instead, e.g. 5x-realtime
+    --stall-timeout <duration>    stop ffmpeg if it reports no progress for <duration> (default 10m)
+    --cpu-limit <percent>         keep ffmpeg to <percent> of the CPU by pausing it in a duty cycle
+                                  (a job object CPU rate limit on windows), e.g. 50
+    --probe-jobs <n>              ffprobe processes probing the videos in the background (default 4)
+    --skip-below-bitrate <kbps>   skip videos whose video bitrate is already below <kbps>
+    --min-size <size>             skip videos smaller than <size>, e.g. 20M
+    --min-duration <seconds>      skip videos shorter than <seconds>
+    --min-resolution <res>        skip videos below <res>, e.g. 720p, 1080p, 4k or 1280x720
+    --max-resolution <res>        skip videos above <res>
+    --deep-check <seconds>        decode the first <seconds> of every video to skip damaged ones
+    --tag-key <key>               container tag marking compressed videos, default `comment`
+    --ignore-tag                  also compress videos whose tag says they are already compressed
+    --min-free-space <size>       abort the encode if the free space at the destination drops below
+                                  <size>, e.g. 500M or 2G (default 100M, 0 disables the check)
+    --stop-on-low-space           end the run instead of skipping a video whose output may not fit
+    --trash                       move the originals to the trash instead of overwriting them
+                                  (linux, the BSDs and macOS), keeping the original if it can't
+                                  be moved
+    --interactive                 show the sizes and length of every compressed file and ask
+                                  y/N/a(lways)/q(uit) before replacing its original
+    --force-readonly              replace read-only originals on windows, keeping them read-only
+    --keep-output-on-failure      keep the compressed file if it can't replace the original
+    --status-file <path>          keep the progress of the run as json in <path>
+    --event-log <path>            append the events of the run to <path> as JSON Lines
+    --systemd                     notify systemd of readiness and progress and ping its watchdog
+    --email <address>             mail the summary of the run to <address> through the SMTP server
+                                  of the `[email]` table of the config
+    --email-on <when>             send the mail `always` (default) or only on `failure`";
+
+/// Tag the outputs are marked with unless `--tag-key` is given
+pub const DEFAULT_TAG_KEY: &str = "comment";
+
+/// Free space floor in bytes unless `--min-free-space` is given
+const DEFAULT_MIN_FREE_SPACE: u64 = 100 * 1024 * 1024;
+
+/// Oldest ffmpeg release a run accepts unless `--min-ffmpeg-version` is given, older ones
+/// lack muxer flags like `use_metadata_tags`
+const DEFAULT_MIN_FFMPEG_VERSION: [u32; 3] = [4, 0, 0];
+
+/// ffprobe processes probing the candidates in the background unless `--probe-jobs` is given
+const DEFAULT_PROBE_JOBS: usize = 4;
+/// Seconds without progress until ffmpeg is stopped unless `--stall-timeout` is given
+const DEFAULT_STALL_TIMEOUT: f64 = 600.0;
+
+const TUNES: [&str; 6] = [
+    "psnr",
+    "ssim",
+    "grain",
+    "animation",
+    "fastdecode",
+    "zerolatency",
+];
+
+pub const PRESETS: [&str; 10] = [
+    "ultrafast",
+    "superfast",
+    "veryfast",
+    "faster",
+    "fast",
+    "medium",
+    "slow",
+    "slower",
+    "veryslow",
+    "placebo",
+];
+
+/// ffmpeg arguments that `compress` sets itself
+const MANAGED_FFMPEG_ARGS: [&str; 33] = [
+    "-i",
+    "-y",
+    "-loglevel",
+    "-stats",
+    "-nostats",
+    "-progress",
+    "-ss",
+    "-to",
+    "-t",
+    "-c",
+    "-codec",
+    "-c:v",
+    "-codec:v",
+    "-vcodec",
+    "-c:a",
+    "-codec:a",
+    "-acodec",
+    "-b:a",
+    "-vn",
+    "-an",
+    "-ac",
+    "-af",
+    "-filter:a",
+    "-tune",
+    "-preset",
+    "-x265-params",
+    "-crf",
+    "-vf",
+    "-filter:v",
+    "-filter_complex",
+    "-map_metadata",
+    "-metadata",
+    "-movflags",
+];
+
+/// loudnorm mode of `--loudnorm` and `--loudnorm-two-pass`
+#[derive(Clone, Copy, PartialEq)]
+pub enum Loudnorm {
+    SinglePass,
+    TwoPass,
+}
+
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum AudioCodec {
+    #[default]
+    Copy,
+    Aac,
+    Opus,
+}
+
+impl AudioCodec {
+    /// Name of the ffmpeg encoder, `None` when copying
+    pub fn encoder(&self) -> Option<&'static str> {
+        match self {
+            AudioCodec::Copy => None,
+            AudioCodec::Aac => Some("aac"),
+            AudioCodec::Opus => Some("libopus"),
+        }
+    }
+
+    /// ffmpeg arguments selecting the audio encoder
+    pub fn args(&self) -> Vec<&'static str> {
+        match self {
+            AudioCodec::Copy => vec!["-c:a", "copy"],
+            AudioCodec::Aac => vec!["-c:a", "aac", "-b:a", "128k"],
+            AudioCodec::Opus => vec!["-c:a", "libopus", "-b:a", "96k"],
+        }
+    }
+}
+
+/// What happens when the path of the compressed file already exists, e.g. left behind by a
+/// crash or copied there by the user
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum OnCollision {
+    /// Write to a new unique name next to it
+    #[default]
+    Unique,
+    /// Skip the video
+    Skip,
+    /// Overwrite the existing file
+    Overwrite,
+}
+
+/// What `--on-stream-loss` does with a compressed file that has fewer streams than the
+/// original, e.g. a second audio track or bitmap subtitles that mp4 can't hold
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum OnStreamLoss {
+    /// Replace the original and warn about the dropped streams
+    #[default]
+    Warn,
+    /// Keep the original and remove the compressed file
+    Skip,
+    /// Replace the original without a warning, the log still lists the dropped streams
+    Allow,
+}
+
+/// When `--email` sends the summary of the run
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum EmailOn {
+    #[default]
+    Always,
+    /// Only if files failed or the log couldn't be saved
+    Failure,
+}
+
+/// What `--dedupe` does with a copy of a video that is already compressed
+#[derive(Clone, Copy, PartialEq)]
+pub enum Dedupe {
+    /// Copy the compressed file over it
+    Copy,
+    /// Skip it
+    Skip,
+}
+
+/// Bundle of encoder settings picked with `--profile`, for users who don't know which CRF and
+/// preset to choose. Explicit options and the config take precedence over its values.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Profile {
+    /// Faster preset and a higher CRF, for quickly reclaiming space
+    Fast,
+    /// The defaults
+    Balanced,
+    /// Slow preset, a lower CRF and 10-bit, for keeping videos in the best quality
+    Archive,
+}
+
+impl Profile {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "fast" => Some(Profile::Fast),
+            "balanced" => Some(Profile::Balanced),
+            "archive" => Some(Profile::Archive),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Fast => "fast",
+            Profile::Balanced => "balanced",
+            Profile::Archive => "archive",
+        }
+    }
+
+    fn crf(self) -> u8 {
+        match self {
+            Profile::Fast => 28,
+            Profile::Balanced => DEFAULT_CRF,
+            Profile::Archive => 20,
+        }
+    }
+
+    fn preset(self) -> &'static str {
+        match self {
+            Profile::Fast => "faster",
+            Profile::Balanced => "medium",
+            Profile::Archive => "slow",
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum Deinterlace {
+    Auto,
+    On,
+    #[default]
+    Off,
+}
+
+/// What happens to the container metadata (e.g. GPS position or creation time)
+#[derive(Clone, Copy, PartialEq)]
+pub enum Metadata {
+    /// Keep all tags, including `creation_time`
+    Preserve,
+    /// Drop all tags
+    Strip,
+}
+
+/// What to do with files that don't contain a video stream
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum AudioOnlyMode {
+    #[default]
+    Skip,
+    Reencode,
+}
+
+/// Files last modified before this point are ignored without further checks
+#[derive(Clone, Copy)]
+pub enum Since {
+    LastRun,
+    /// Seconds since the unix epoch
+    Timestamp(i64),
+}
+
+/// What the program does with `path`
+#[derive(Default, PartialEq)]
+pub enum Command {
+    /// Compress and replace all videos
+    #[default]
+    Compress,
+    /// Compress a single video next to the original and compare the two
+    Compare,
+    /// Compress the videos of the source tree (`path`) into this archive tree, only if they
+    /// are missing or outdated there
+    Archive(String),
+    /// Compress a single video to stdout
+    Stream,
+    /// Encode a sample of a single video with several settings
+    Bench,
+    /// Print the checks deciding whether this file below `path` is compressed
+    Explain(String),
+    /// Check ffmpeg, the encoders, the config and the log of `path`
+    Doctor,
+    /// List the encoders of ffmpeg relevant to video_compressor
+    ListCodecs,
+    /// Record the videos below `path` that are already compressed in its log
+    RebuildLog,
+    /// Compress the files of the log of `path` that failed in earlier runs, without a scan
+    RetryFailed,
+    /// Remove stale entries from the log of `path` and store its paths in one form
+    CleanLog,
+    /// List the compressed files of the log of `path` by the ffmpeg build that compressed them
+    LogVersions,
+    /// List the compressed files of the log of `path` with the runs that compressed them
+    ListProcessed,
+    /// Compress the files of `--manifest` in their order, with the log in its directory
+    Manifest,
+    /// Compress the videos of a `user@host:/path` argument through a local staging directory
+    Remote(Remote),
+}
+
+#[derive(Default)]
+pub struct Options {
+    pub command: Command,
+    pub path: String,
+    pub config: Config,
+    /// File `config` was loaded from, only loaded by the doctor itself with `--doctor`
+    pub config_path: Option<String>,
+    /// Configs of the directories with a `.compressor.toml`, found by the scan
+    pub dir_configs: DirConfigs,
+    pub crf: Option<u8>,
+    pub auto_crf: Option<(Metric, f64)>,
+    pub maxrate: Option<u64>,
+    pub bufsize: Option<u64>,
+    pub audio: AudioCodec,
+    pub audio_only_mode: AudioOnlyMode,
+    pub no_audio: bool,
+    pub downmix_stereo: bool,
+    pub loudnorm: Option<Loudnorm>,
+    pub metadata: Option<Metadata>,
+    pub verbose: bool,
+    pub hidden: bool,
+    /// Leave out the directories on network filesystems and removable drives
+    pub local_only: bool,
+    pub preserve_atime: bool,
+    /// Give compressed files the modification and creation time of their original
+    pub preserve_times: bool,
+    pub no_color: bool,
+    /// Sizes in powers of 1000 instead of 1024
+    pub si: bool,
+    pub remux_efficient: bool,
+    /// Codecs of `--remux-codecs`, hevc, av1 and vp9 without it
+    pub remux_codecs: Option<Vec<String>>,
+    pub on_collision: OnCollision,
+    pub on_stream_loss: OnStreamLoss,
+    /// Address the summary of the run is mailed to
+    pub email: Option<String>,
+    pub email_on: EmailOn,
+    /// Ask before replacing each original
+    pub interactive: Interactive,
+    pub dedupe: Option<Dedupe>,
+    pub deinterlace: Deinterlace,
+    pub since: Option<Since>,
+    /// Files of directories whose modification time is before the last completed run aren't
+    /// looked at, unless `full_scan` is set
+    pub since_last_run: bool,
+    pub full_scan: bool,
+    pub start_from: Option<String>,
+    pub tune: Option<String>,
+    pub preset: Option<String>,
+    pub profile: Option<Profile>,
+    /// Encode with 10 bits per channel, set by `--profile archive`
+    pub ten_bit: bool,
+    pub trim_start: Option<f64>,
+    pub trim_end: Option<f64>,
+    pub x265_params: Vec<(String, String)>,
+    pub encoders: Encoders,
+    pub hwdecode: HwDecoder,
+    pub gpu_sessions: GpuSessions,
+    pub min_ffmpeg_version: [u32; 3],
+    pub allow_old_ffmpeg: bool,
+    pub keyint: Option<u32>,
+    pub min_keyint: Option<u32>,
+    /// Keyframe interval converted to `keyint` with the probed frame rate of each video
+    pub keyframe_seconds: Option<f64>,
+    /// Frame rate the output is capped at, sources with a lower one are left alone
+    pub max_fps: Option<f64>,
+    pub ffmpeg_input_args: Vec<String>,
+    pub ffmpeg_output_args: Vec<String>,
+    pub print_command: bool,
+    /// Print the ffmpeg commands of the run instead of running them
+    pub dry_run: bool,
+    /// Retry entries older than this many days are dropped by `--clean-log`
+    pub clean_older_than: Option<u64>,
+    /// `--clean-log` writes the log indented
+    pub pretty_log: bool,
+    /// `--list-processed` only lists the files of runs whose ID starts with this
+    pub run_id: Option<String>,
+    /// Mux subtitle files named like the video into the output
+    pub embed_subtitles: bool,
+    /// Put the index of the output at its start for playback while downloading
+    pub faststart: bool,
+    /// Files of `--manifest` with their overrides, in the order they are compressed
+    pub manifest: Vec<ManifestFile>,
+    pub estimate_time: bool,
+    pub compare_ssim: bool,
+    pub compare_cleanup: bool,
+    pub bench_presets: Vec<String>,
+    pub bench_crfs: Vec<u8>,
+    pub bench_seconds: f64,
+    pub bench_metric: Option<Metric>,
+    pub keep_bench_files: bool,
+    pub skip_below_bitrate: Option<u64>,
+    /// Bytes
+    pub min_size: Option<u64>,
+    /// Seconds
+    pub min_duration: Option<f64>,
+    /// Seconds an encode may take at most
+    pub file_timeout: Option<f64>,
+    /// Multiple of the video duration an encode may take at most
+    pub timeout_factor: Option<f64>,
+    /// Seconds without progress from ffmpeg until it's considered hung, 0 if not checked
+    pub stall_timeout: f64,
+    /// Percent of the CPU ffmpeg may use, below 100
+    pub cpu_limit: Option<u8>,
+    /// ffprobe processes probing ahead of the encodes, 0 probes each file right before it
+    pub probe_jobs: usize,
+    /// Lines of the shorter side, e.g. 1080 for `1080p`
+    pub min_resolution: Option<u32>,
+    pub max_resolution: Option<u32>,
+    /// Seconds decoded at the start of every candidate to catch damaged files
+    pub deep_check: Option<f64>,
+    pub tag_key: String,
+    pub ignore_tag: bool,
+    /// Bytes that have to stay free at the destination, 0 if not checked
+    pub min_free_space: u64,
+    pub stop_on_low_space: bool,
+    pub trash: bool,
+    pub force_readonly: bool,
+    pub keep_output_on_failure: bool,
+    pub status_file: Option<StatusFile>,
+    /// JSON Lines file the events of the run are appended to as they happen
+    pub event_log: Option<PathBuf>,
+    pub systemd: Systemd,
+    /// Set to stop the run, e.g. by Ctrl-C
+    pub cancel: Cancel,
+}
+
+impl Options {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            bench_presets: ["fast", "medium", "slow"].map(String::from).to_vec(),
+            bench_crfs: vec![22, 25, 28],
+            bench_seconds: 60.0,
+            tag_key: DEFAULT_TAG_KEY.to_string(),
+            min_free_space: DEFAULT_MIN_FREE_SPACE,
+            stall_timeout: DEFAULT_STALL_TIMEOUT,
+            probe_jobs: DEFAULT_PROBE_JOBS,
+            min_ffmpeg_version: DEFAULT_MIN_FFMPEG_VERSION,
+            ..Options::default()
+        };
+        let mut path = None;
+        let mut config = None;
+        let mut audio_explicit = false;
+        let mut source = None;
+        let mut stream = false;
+        let mut explain = None;
+        let mut doctor = false;
+        let mut list_codecs = false;
+        let mut rebuild_log = false;
+        let mut retry_failed = false;
+        let mut clean_log = false;
+        let mut log_versions = false;
+        let mut list_processed = false;
+        let mut manifest = None;
+        let mut email_on = None;
+        let mut no_fallback = false;
+        let mut min_free_space = None;
+        let mut min_size = None;
+        let mut archive = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => config = Some(parse_value::<String>(&arg, args.next())?),
+                "--crf" => options.crf = Some(parse_value(&arg, args.next())?),
+                "--maxrate" => options.maxrate = Some(parse_value(&arg, args.next())?),
+                "--bufsize" => options.bufsize = Some(parse_value(&arg, args.next())?),
+                "--audio" => {
+                    options.audio = match parse_value::<String>(&arg, args.next())?.as_str() {
+                        "copy" => AudioCodec::Copy,
+                        "aac" => AudioCodec::Aac,
+                        "opus" => AudioCodec::Opus,
+                        codec => {
+                            return Err(format!(
+                                "Invalid audio codec `{codec}`, expected `copy`, `aac` or `opus`"
+                            ))
+                        }
+                    };
+                    audio_explicit = true;
+                }
+                "--no-audio" => options.no_audio = true,
+                "--downmix-stereo" => options.downmix_stereo = true,
+                "--loudnorm" => options.loudnorm = Some(Loudnorm::SinglePass),
+                "--loudnorm-two-pass" => options.loudnorm = Some(Loudnorm::TwoPass),
+                "--metadata" => {
+                    options.metadata = match parse_value::<String>(&arg, args.next())?.as_str() {
+                        "preserve" => Some(Metadata::Preserve),
+                        "strip" => Some(Metadata::Strip),
+                        mode => {
+                            return Err(format!(
+                                "Invalid metadata mode `{mode}`, expected `preserve` or `strip`"
+                            ))
+                        }
+                    }
+                }
+                "--verbose" => options.verbose = true,
+                "--hidden" => options.hidden = true,
+                "--local-only" => options.local_only = true,
+                "--preserve-atime" => options.preserve_atime = true,
+                "--preserve-times" => options.preserve_times = true,
+                "--no-color" => options.no_color = true,
+                "--si" => options.si = true,
+                "--remux-efficient" => options.remux_efficient = true,
+                "--remux-codecs" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    let codecs = parse_list::<String>(&arg, &value)?;
+                    if codecs.iter().any(String::is_empty) {
+                        return Err(format!("Invalid codec list `{value}`"));
+                    }
+                    options.remux_codecs = Some(codecs);
+                    options.remux_efficient = true;
+                }
+                "--on-collision" => {
+                    options.on_collision = match parse_value::<String>(&arg, args.next())?.as_str()
+                    {
+                        "unique" => OnCollision::Unique,
+                        "skip" => OnCollision::Skip,
+                        "overwrite" => OnCollision::Overwrite,
+                        mode => {
+                            return Err(format!(
+                        "Invalid collision mode `{mode}`, expected `unique`, `skip` or `overwrite`"
+                    ))
+                        }
+                    }
+                }
+                "--on-stream-loss" => {
+                    options.on_stream_loss =
+                        match parse_value::<String>(&arg, args.next())?.as_str() {
+                            "warn" => OnStreamLoss::Warn,
+                            "skip" => OnStreamLoss::Skip,
+                            "allow" => OnStreamLoss::Allow,
+                            mode => {
+                                return Err(format!(
+                        "Invalid stream loss mode `{mode}`, expected `warn`, `skip` or `allow`"
+                    ))
+                            }
+                        }
+                }
+                "--interactive" => options.interactive = Interactive::enabled(),
+                "--email" => options.email = Some(parse_value(&arg, args.next())?),
+                "--email-on" => {
+                    email_on = match parse_value::<String>(&arg, args.next())?.as_str() {
+                        "always" => Some(EmailOn::Always),
+                        "failure" => Some(EmailOn::Failure),
+                        when => {
+                            return Err(format!(
+                            "Invalid `--email-on` value `{when}`, expected `always` or `failure`"
+                        ))
+                        }
+                    }
+                }
+                "--dedupe" => {
+                    options.dedupe = match parse_value::<String>(&arg, args.next())?.as_str() {
+                        "copy" => Some(Dedupe::Copy),
+                        "skip" => Some(Dedupe::Skip),
+                        mode => {
+                            return Err(format!(
+                                "Invalid dedupe mode `{mode}`, expected `copy` or `skip`"
+                            ))
+                        }
+                    }
+                }
+                "--deinterlace" => {
+                    options.deinterlace = match parse_value::<String>(&arg, args.next())?.as_str() {
+                        "auto" => Deinterlace::Auto,
+                        "on" => Deinterlace::On,
+                        "off" => Deinterlace::Off,
+                        mode => {
+                            return Err(format!(
+                                "Invalid deinterlace mode `{mode}`, expected `auto`, `on` or `off`"
+                            ))
+                        }
+                    }
+                }
+                "--audio-only-mode" => {
+                    options.audio_only_mode =
+                        match parse_value::<String>(&arg, args.next())?.as_str() {
+                            "skip" => AudioOnlyMode::Skip,
+                            "reencode" => AudioOnlyMode::Reencode,
+                            mode => {
+                                return Err(format!(
+                                "Invalid audio only mode `{mode}`, expected `skip` or `reencode`"
+                            ))
+                            }
+                        }
+                }
+                "--trim-start" => options.trim_start = Some(parse_seconds(&arg, args.next())?),
+                "--trim-end" => options.trim_end = Some(parse_seconds(&arg, args.next())?),
+                "--tune" => {
+                    let tune = parse_value::<String>(&arg, args.next())?;
+                    if !TUNES.contains(&tune.as_str()) {
+                        return Err(format!(
+                            "Invalid tune `{tune}`, expected one of {}",
+                            TUNES.join(", ")
+                        ));
+                    }
+                    options.tune = Some(tune);
+                }
+                "--profile" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    options.profile = Some(Profile::parse(&value).ok_or(format!(
+                        "Invalid profile `{value}`, expected `fast`, `balanced` or `archive`"
+                    ))?);
+                }
+                "--preset" => {
+                    let preset = parse_value::<String>(&arg, args.next())?;
+                    if !PRESETS.contains(&preset.as_str()) {
+                        return Err(format!(
+                            "Invalid preset `{preset}`, expected one of {}",
+                            PRESETS.join(", ")
+                        ));
+                    }
+                    options.preset = Some(preset);
+                }
+                // `--x265-extra` is the old name of the option
+                "--x265-params" | "--x265-extra" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    options.x265_params.extend(parse_x265_params(&value)?);
+                }
+                "--encoder-fallback" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    let list = parse_list::<String>(&arg, &value)?;
+                    if list.iter().any(String::is_empty) {
+                        return Err(format!("Invalid video encoder list `{value}`"));
+                    }
+                    options.encoders = Encoders::new(list);
+                }
+                "--no-fallback" => no_fallback = true,
+                "--hwdecode" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    let method = HwAccel::parse(&value).ok_or(format!(
+                        "Invalid hardware decoder `{value}`, expected `cuda` or `qsv`"
+                    ))?;
+                    options.hwdecode = HwDecoder::new(method);
+                }
+                "--gpu-sessions" => {
+                    let limit = parse_value(&arg, args.next())?;
+                    if limit == 0 {
+                        return Err("`--gpu-sessions` must be greater than 0".to_string());
+                    }
+                    options.gpu_sessions = GpuSessions::new(limit);
+                }
+                "--min-ffmpeg-version" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    options.min_ffmpeg_version = version::release(&value).ok_or(format!(
+                        "Invalid ffmpeg version `{value}`, expected e.g. `4.0`"
+                    ))?;
+                }
+                "--allow-old-ffmpeg" => options.allow_old_ffmpeg = true,
+                "--keyint" => options.keyint = Some(parse_value(&arg, args.next())?),
+                "--min-keyint" => options.min_keyint = Some(parse_value(&arg, args.next())?),
+                "--keyframe-seconds" => {
+                    options.keyframe_seconds = Some(parse_seconds(&arg, args.next())?)
+                }
+                "--max-fps" => {
+                    let fps = parse_value::<f64>(&arg, args.next())?;
+                    if !fps.is_finite() || fps <= 0.0 {
+                        return Err(format!("`{arg}` must be a positive frame rate, got {fps}"));
+                    }
+                    options.max_fps = Some(fps);
+                }
+                "--ffmpeg-input-args" => {
+                    options.ffmpeg_input_args = parse_shell_words(&arg, args.next())?
+                }
+                "--ffmpeg-output-args" => {
+                    options.ffmpeg_output_args = parse_shell_words(&arg, args.next())?
+                }
+                "--print-command" => options.print_command = true,
+                "--dry-run" => options.dry_run = true,
+                "--clean-log" => clean_log = true,
+                "--log-versions" => log_versions = true,
+                "--manifest" => manifest = Some(parse_value::<PathBuf>(&arg, args.next())?),
+                "--list-processed" => list_processed = true,
+                "--run-id" => options.run_id = Some(parse_value(&arg, args.next())?),
+                "--older-than" => options.clean_older_than = Some(parse_value(&arg, args.next())?),
+                "--pretty" => options.pretty_log = true,
+                "--embed-subtitles" => options.embed_subtitles = true,
+                "--faststart" => options.faststart = true,
+                "--estimate-time" => options.estimate_time = true,
+                "--compare" => {
+                    if let Some(path) = &path {
+                        return Err(format!(
+                            "`--compare` can't be combined with the path `{path}`"
+                        ));
+                    }
+                    options.command = Command::Compare;
+                    path = Some(parse_value::<String>(&arg, args.next())?);
+                }
+                "--source" => source = Some(parse_value::<String>(&arg, args.next())?),
+                "--archive" => archive = Some(parse_value::<String>(&arg, args.next())?),
+                "--stdout" => stream = true,
+                "--doctor" => doctor = true,
+                "--rebuild-log" => rebuild_log = true,
+                "--retry-failed" => retry_failed = true,
+                "--list-codecs" => list_codecs = true,
+                "--explain" => explain = Some(parse_value::<String>(&arg, args.next())?),
+                "--bench" | "--benchmark" => {
+                    if let Some(path) = &path {
+                        return Err(format!("`{arg}` can't be combined with the path `{path}`"));
+                    }
+                    options.command = Command::Bench;
+                    path = Some(parse_value::<String>(&arg, args.next())?);
+                }
+                "--bench-presets" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    options.bench_presets = parse_list(&arg, &value)?;
+                    if let Some(preset) = options
+                        .bench_presets
+                        .iter()
+                        .find(|preset| !PRESETS.contains(&preset.as_str()))
+                    {
+                        return Err(format!(
+                            "Invalid preset `{preset}`, expected one of {}",
+                            PRESETS.join(", ")
+                        ));
+                    }
+                }
+                "--bench-crfs" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    options.bench_crfs = parse_list(&arg, &value)?;
+                    if let Some(crf) = options.bench_crfs.iter().find(|crf| **crf > 51) {
+                        return Err(format!("CRF must be between 0 and 51, got {crf}"));
+                    }
+                }
+                "--bench-seconds" => {
+                    options.bench_seconds = parse_seconds(&arg, args.next())?;
+                    if options.bench_seconds == 0.0 {
+                        return Err("`--bench-seconds` must be greater than 0".to_string());
+                    }
+                }
+                "--bench-metric" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    options.bench_metric = Some(Metric::parse(&value).ok_or(format!(
+                        "Invalid metric `{value}`, expected `ssim` or `vmaf`"
+                    ))?);
+                }
+                "--keep-benchmark-files" => options.keep_bench_files = true,
+                "--compare-ssim" => options.compare_ssim = true,
+                "--compare-cleanup" => options.compare_cleanup = true,
+                "--since" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    options.since = Some(parse_since(&value)?);
+                }
+                "--since-last-run" | "--skip-unchanged-dirs" => options.since_last_run = true,
+                "--full-scan" => options.full_scan = true,
+                "--start-from" => options.start_from = Some(parse_value(&arg, args.next())?),
+                "--auto-crf" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    options.auto_crf = Some(parse_quality_target(&value)?);
+                }
+                "--deep-check" => options.deep_check = Some(parse_seconds(&arg, args.next())?),
+                "--tag-key" => {
+                    let key = parse_value::<String>(&arg, args.next())?;
+                    if key.is_empty() || key.contains('=') {
+                        return Err(format!("Invalid tag key `{key}`"));
+                    }
+                    options.tag_key = key;
+                }
+                "--ignore-tag" => options.ignore_tag = true,
+                "--stop-on-low-space" => options.stop_on_low_space = true,
+                "--trash" => {
+                    if !cfg!(any(unix, windows)) {
+                        return Err("`--trash` isn't supported on this platform".to_string());
+                    }
+                    options.trash = true;
+                }
+                "--force-readonly" => options.force_readonly = true,
+                "--keep-output-on-failure" => options.keep_output_on_failure = true,
+                "--systemd" => options.systemd = Systemd::from_env(),
+                "--status-file" => {
+                    let path = parse_value::<PathBuf>(&arg, args.next())?;
+                    options.status_file = Some(StatusFile::new(path));
+                }
+                "--event-log" => options.event_log = Some(parse_value(&arg, args.next())?),
+                "--min-free-space" => {
+                    min_free_space = Some(parse_value::<String>(&arg, args.next())?)
+                }
+                "--skip-below-bitrate" => {
+                    options.skip_below_bitrate = Some(parse_value(&arg, args.next())?)
+                }
+                "--min-size" => min_size = Some(parse_value::<String>(&arg, args.next())?),
+                "--min-duration" => options.min_duration = Some(parse_seconds(&arg, args.next())?),
+                "--file-timeout" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    // like `--stall-timeout 0`, rather than timing out every encode at once
+                    options.file_timeout = Some(parse_duration(&value)?).filter(|&t| t > 0.0);
+                }
+                "--timeout-factor" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    options.timeout_factor = Some(parse_timeout_factor(&value)?);
+                }
+                "--stall-timeout" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    options.stall_timeout = parse_duration(&value)?;
+                }
+                "--cpu-limit" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    options.cpu_limit = parse_cpu_limit(&value)?;
+                }
+                "--probe-jobs" => options.probe_jobs = parse_value(&arg, args.next())?,
+                "--min-resolution" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    options.min_resolution = Some(parse_resolution(&value)?);
+                }
+                "--max-resolution" => {
+                    let value = parse_value::<String>(&arg, args.next())?;
+                    options.max_resolution = Some(parse_resolution(&value)?);
+                }
+                _ if arg.starts_with("--") => return Err(format!("Unknown option `{arg}`")),
+                _ if path.is_none() => path = Some(arg),
+                _ if options.command == Command::Compare => {
+                    return Err(format!(
+                        "`--compare` takes the file to compress, got `{arg}`"
+                    ))
+                }
+                _ if options.command == Command::Bench => {
+                    return Err(format!(
+                        "`--bench` takes the file to benchmark, got `{arg}`"
+                    ))
+                }
+                _ => return Err(format!("Unexpected argument `{arg}`")),
+            }
+        }
+
+        match (source, archive) {
+            (Some(source), Some(archive)) => {
+                if let Some(path) = path {
+                    return Err(format!(
+                        "`--source` can't be combined with the path `{path}`"
+                    ));
+                }
+                if options.command == Command::Compare {
+                    return Err("`--compare` can't be combined with `--source`".to_string());
+                }
+                if options.command == Command::Bench {
+                    return Err("`--bench` can't be combined with `--source`".to_string());
+                }
+                path = Some(source);
+                options.command = Command::Archive(archive);
+            }
+            (Some(_), None) => return Err("`--source` requires `--archive`".to_string()),
+            (None, Some(_)) => return Err("`--archive` requires `--source`".to_string()),
+            (None, None) => {}
+        }
+        if stream {
+            if options.command != Command::Compress {
+                return Err(
+                    "`--stdout` can't be combined with `--compare`, `--bench` or `--source`"
+                        .to_string(),
+                );
+            }
+            options.command = Command::Stream;
+        }
+        if let Some(explain) = explain {
+            if options.command != Command::Compress {
+                return Err(
+                    "`--explain` can't be combined with `--compare`, `--bench`, `--stdout` or `--source`"
+                        .to_string(),
+                );
+            }
+            // the log of a single file is kept in its directory
+            path = path.or_else(|| {
+                let parent = Path::new(&explain).parent()?.to_string_lossy().to_string();
+                Some(if parent.is_empty() {
+                    ".".to_string()
+                } else {
+                    parent
+                })
+            });
+            options.command = Command::Explain(explain);
+        }
+        if doctor {
+            if options.command != Command::Compress {
+                return Err(
+                    "`--doctor` can't be combined with `--compare`, `--bench`, `--stdout`, `--explain` or `--source`"
+                        .to_string(),
+                );
+            }
+            path = path.or(Some(".".to_string()));
+            options.command = Command::Doctor;
+        }
+        if list_codecs {
+            if options.command != Command::Compress {
+                return Err(
+                    "`--list-codecs` can't be combined with `--compare`, `--bench`, `--stdout`, `--explain`, `--doctor` or `--source`"
+                        .to_string(),
+                );
+            }
+            if let Some(path) = path {
+                return Err(format!("`--list-codecs` doesn't take a path, got `{path}`"));
+            }
+            path = Some(".".to_string());
+            options.command = Command::ListCodecs;
+        }
+        if rebuild_log {
+            if options.command != Command::Compress {
+                return Err(
+                    "`--rebuild-log` can't be combined with `--compare`, `--bench`, `--stdout`, `--explain`, `--doctor`, `--list-codecs` or `--source`"
+                        .to_string(),
+                );
+            }
+            options.command = Command::RebuildLog;
+        }
+        if retry_failed {
+            if options.command != Command::Compress {
+                return Err(
+                    "`--retry-failed` can't be combined with `--compare`, `--bench`, `--stdout`, `--explain`, `--doctor`, `--list-codecs`, `--rebuild-log` or `--source`"
+                        .to_string(),
+                );
+            }
+            options.command = Command::RetryFailed;
+        }
+        if clean_log {
+            if options.command != Command::Compress {
+                return Err(
+                    "`--clean-log` can't be combined with `--compare`, `--bench`, `--stdout`, `--explain`, `--doctor`, `--list-codecs`, `--rebuild-log`, `--retry-failed` or `--source`"
+                        .to_string(),
+                );
+            }
+            options.command = Command::CleanLog;
+        } else if options.clean_older_than.is_some() || options.pretty_log {
+            return Err("`--older-than` and `--pretty` only work with `--clean-log`".to_string());
+        }
+        if log_versions {
+            if options.command != Command::Compress {
+                return Err(
+                    "`--log-versions` can't be combined with `--compare`, `--bench`, `--stdout`, `--explain`, `--doctor`, `--list-codecs`, `--rebuild-log`, `--retry-failed`, `--clean-log` or `--source`"
+                        .to_string(),
+                );
+            }
+            options.command = Command::LogVersions;
+        }
+        if list_processed {
+            if options.command != Command::Compress {
+                return Err(
+                    "`--list-processed` can't be combined with `--compare`, `--bench`, `--stdout`, `--explain`, `--doctor`, `--list-codecs`, `--rebuild-log`, `--retry-failed`, `--clean-log`, `--log-versions` or `--source`"
+                        .to_string(),
+                );
+            }
+            options.command = Command::ListProcessed;
+        } else if options.run_id.is_some() {
+            return Err("`--run-id` only works with `--list-processed`".to_string());
+        }
+        if let Some(manifest) = manifest {
+            if options.command != Command::Compress {
+                return Err(
+                    "`--manifest` can't be combined with `--compare`, `--bench`, `--stdout`, `--explain`, `--doctor`, `--list-codecs`, `--rebuild-log`, `--retry-failed`, `--clean-log`, `--log-versions`, `--list-processed` or `--source`"
+                        .to_string(),
+                );
+            }
+            if let Some(path) = path {
+                return Err(format!(
+                    "`--manifest` lists the files itself and doesn't take a path, got `{path}`"
+                ));
+            }
+            options.manifest = manifest::load(&manifest)?;
+            path = Some(manifest::directory(&manifest).to_string_lossy().to_string());
+            options.command = Command::Manifest;
+        }
+        if options.start_from.is_some()
+            && !matches!(options.command, Command::Compress | Command::Archive(_))
+        {
+            return Err(
+                "`--start-from` can't be combined with `--compare`, `--bench`, `--stdout` or `--explain`"
+                    .to_string(),
+            );
+        }
+        options.path = path.ok_or("Missing <path> argument")?;
+        // a local file named like `host:path` wins
+        if options.command == Command::Compress && !Path::new(&options.path).exists() {
+            if let Some(remote) = Remote::parse(&options.path) {
+                if options.start_from.is_some() {
+                    return Err("`--start-from` isn't supported for remote paths".to_string());
+                }
+                options.command = Command::Remote(remote);
+            }
+        }
+        if options.dry_run
+            && !matches!(
+                options.command,
+                Command::Compress | Command::Manifest | Command::CleanLog
+            )
+        {
+            return Err(
+                "`--dry-run` only works when compressing a local directory, video or `--manifest` or with `--clean-log`"
+                    .to_string(),
+            );
+        }
+        // the log stores absolute paths, so runs over `.` and over the name of the directory
+        // share its entries
+        match &mut options.command {
+            Command::Remote(_) | Command::ListCodecs => {}
+            command => {
+                if let Command::Archive(archive) = command {
+                    *archive = paths::absolute(Path::new(archive))
+                        .to_string_lossy()
+                        .to_string();
+                }
+                options.path = paths::absolute(Path::new(&options.path))
+                    .to_string_lossy()
+                    .to_string();
+            }
+        }
+        // the doctor reports an invalid config in its checklist instead of failing here
+        if let Some(config) = &config {
+            if options.command != Command::Doctor {
+                options.config = Config::load(config)?;
+            }
+        }
+        options.config_path = config;
+        match (&options.email, email_on) {
+            (Some(to), _) => {
+                if !matches!(
+                    options.command,
+                    Command::Compress
+                        | Command::Manifest
+                        | Command::RetryFailed
+                        | Command::Archive(_)
+                        | Command::Remote(_)
+                ) || options.dry_run
+                {
+                    return Err(
+                        "`--email` only reports runs that compress videos, not `--dry-run` or runs of a command like `--compare`"
+                            .to_string(),
+                    );
+                }
+                let Some(email) = &options.config.email else {
+                    return Err(
+                        "`--email` needs the SMTP server in an `[email]` table of `--config`"
+                            .to_string(),
+                    );
+                };
+                email.validate(to)?;
+            }
+            (None, Some(_)) => return Err("`--email-on` requires `--email`".to_string()),
+            (None, None) => {}
+        }
+        options.email_on = email_on.unwrap_or_default();
+        if options.interactive.is_enabled() {
+            if !matches!(
+                options.command,
+                Command::Compress | Command::Manifest | Command::RetryFailed
+            ) || options.dry_run
+            {
+                return Err(
+                    "`--interactive` asks before replacing local originals and can't be combined with `--dry-run`, `--archive`, remote paths or commands like `--compare`"
+                        .to_string(),
+                );
+            }
+            // nobody would answer, the run would wait forever
+            if !std::io::stdin().is_terminal() {
+                return Err(
+                    "`--interactive` needs a terminal to ask on, stdin isn't one (e.g. under cron or with piped input)"
+                        .to_string(),
+                );
+            }
+        }
+        if let Some(profile) = options.profile {
+            // the profile only fills in what neither the options nor the config set
+            if options.config.crf.is_none() {
+                options.config.crf = Some(profile.crf());
+            }
+            if options.preset.is_none() {
+                options.preset = Some(profile.preset().to_string());
+            }
+            options.ten_bit = profile == Profile::Archive;
+        }
+
+        if let Some(crf) = options.crf {
+            if crf > 51 {
+                return Err(format!("CRF must be between 0 and 51, got {crf}"));
+            }
+            if options.auto_crf.is_some() {
+                return Err("`--crf` and `--auto-crf` can't be combined".to_string());
+            }
+        }
+
+        if options.no_audio {
+            if audio_explicit {
+                return Err("`--no-audio` can't be combined with `--audio`".to_string());
+            }
+            if options.downmix_stereo {
+                return Err("`--no-audio` can't be combined with `--downmix-stereo`".to_string());
+            }
+            if options.loudnorm.is_some() {
+                return Err("`--no-audio` can't be combined with `--loudnorm`".to_string());
+            }
+            if options.audio_only_mode == AudioOnlyMode::Reencode {
+                return Err(
+                    "`--no-audio` can't be combined with `--audio-only-mode reencode`".to_string(),
+                );
+            }
+        }
+        if options.downmix_stereo && options.audio == AudioCodec::Copy {
+            if audio_explicit {
+                return Err(
+                    "`--downmix-stereo` re-encodes the audio and can't be combined with `--audio copy`"
+                        .to_string(),
+                );
+            }
+            options.audio = AudioCodec::Aac;
+        }
+        if options.loudnorm.is_some() && options.audio == AudioCodec::Copy {
+            if audio_explicit {
+                return Err(
+                    "`--loudnorm` re-encodes the audio and can't be combined with `--audio copy`"
+                        .to_string(),
+                );
+            }
+            options.audio = AudioCodec::Aac;
+        }
+
+        // vbv only caps the rate of crf encodes here, there is no bitrate targeting mode that
+        // would give the two options a different meaning
+        match (options.maxrate, options.bufsize) {
+            (Some(0), _) => return Err("`--maxrate` must be greater than 0".to_string()),
+            (_, Some(0)) => return Err("`--bufsize` must be greater than 0".to_string()),
+            (None, Some(_)) => return Err("`--bufsize` requires `--maxrate`".to_string()),
+            (Some(maxrate), None) => options.bufsize = Some(maxrate * 2),
+            _ => {}
+        }
+
+        if no_fallback {
+            options.encoders.without_fallback();
+        }
+        // the units of the sizes depend on `--si`, which may come after them
+        if let Some(value) = min_free_space {
+            options.min_free_space = filesize::parse(&value, options.si)?;
+        }
+        if let Some(value) = min_size {
+            options.min_size = Some(filesize::parse(&value, options.si)?);
+        }
+
+        if let (Some(min), Some(max)) = (options.min_resolution, options.max_resolution) {
+            if min > max {
+                return Err(format!(
+                    "`--min-resolution` {min}p can't be larger than `--max-resolution` {max}p"
+                ));
+            }
+        }
+
+        match (options.keyint, options.min_keyint, options.keyframe_seconds) {
+            (Some(_), _, Some(_)) => {
+                return Err("`--keyint` can't be combined with `--keyframe-seconds`".to_string())
+            }
+            (Some(0), _, _) => return Err("`--keyint` must be greater than 0".to_string()),
+            (_, Some(0), _) => return Err("`--min-keyint` must be greater than 0".to_string()),
+            (_, _, Some(0.0)) => {
+                return Err("`--keyframe-seconds` must be greater than 0".to_string())
+            }
+            (Some(keyint), Some(min_keyint), _) if min_keyint > keyint => {
+                return Err(format!(
+                    "`--min-keyint` {min_keyint} can't be larger than `--keyint` {keyint}"
+                ))
+            }
+            _ => {}
+        }
+        if options.stop_on_low_space && options.min_free_space == 0 {
+            return Err(
+                "`--stop-on-low-space` can't be combined with `--min-free-space 0`".to_string(),
+            );
+        }
+        if options.deep_check == Some(0.0) {
+            return Err("`--deep-check` must be greater than 0".to_string());
+        }
+
+        Ok(options)
+    }
+
+    /// Warns about passthrough arguments that are also set by `compress`
+    pub fn warn_managed_args(&self) {
+        for arg in self
+            .ffmpeg_input_args
+            .iter()
+            .chain(&self.ffmpeg_output_args)
+        {
+            if MANAGED_FFMPEG_ARGS.contains(&arg.as_str()) {
+                info!("Warning: `{arg}` is also set by video_compressor, passing it again may override or break the encode");
+            }
+        }
+    }
+}
+
+fn parse_seconds(flag: &str, value: Option<String>) -> Result<f64, String> {
+    let seconds = parse_value::<f64>(flag, value)?;
+    if !seconds.is_finite() || seconds < 0.0 {
+        return Err(format!(
+            "`{flag}` must be a positive number of seconds, got {seconds}"
+        ));
+    }
+
+    Ok(seconds)
+}
+
+/// Parses a comma separated list like `22,25,28`
+fn parse_list<T: FromStr>(flag: &str, value: &str) -> Result<Vec<T>, String> {
+    value
+        .split(',')
+        .map(|item| {
+            item.trim()
+                .parse::<T>()
+                .map_err(|_| format!("Invalid value `{item}` in `{flag}`"))
+        })
+        .collect()
+}
+
+/// Splits the value with shell like quoting, e.g. `-vf "scale=1280:-2"`
+fn parse_shell_words(flag: &str, value: Option<String>) -> Result<Vec<String>, String> {
+    let value = parse_value::<String>(flag, value)?;
+    shell_words::split(&value).map_err(|e| format!("Invalid value `{value}` for `{flag}`: {e}"))
+}
+
+/// Percentage like `50` or `50%`, `None` for 100 which doesn't limit anything
+fn parse_cpu_limit(value: &str) -> Result<Option<u8>, String> {
+    match value.strip_suffix('%').unwrap_or(value).parse::<u8>() {
+        Ok(percent @ 1..=100) => Ok((percent < 100).then_some(percent)),
+        _ => Err(format!(
+            "`--cpu-limit` expects a percentage from 1 to 100, got `{value}`"
+        )),
+    }
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or(format!("Missing value for `{flag}`"))?;
+    value
+        .parse::<T>()
+        .map_err(|_| format!("Invalid value `{value}` for `{flag}`"))
+}
+
+/// Parses `<metric>=<score>`, e.g. `vmaf=93`
+fn parse_quality_target(value: &str) -> Result<(Metric, f64), String> {
+    let invalid =
+        || format!("Invalid quality target `{value}`, expected e.g. `vmaf=93` or `ssim=0.98`");
+    let (metric, score) = value.split_once('=').ok_or_else(invalid)?;
+    let metric = Metric::parse(metric).ok_or_else(invalid)?;
+    let score = score.parse::<f64>().map_err(|_| invalid())?;
+
+    let max = match metric {
+        Metric::Vmaf => 100.0,
+        Metric::Ssim => 1.0,
+    };
+    if !(0.0..=max).contains(&score) {
+        return Err(format!(
+            "{metric} score must be between 0 and {max}, got {score}"
+        ));
+    }
+
+    Ok((metric, score))
+}
+
+/// Parses a duration in seconds with an optional `s`, `m` or `h` suffix, e.g. `90m`
+fn parse_duration(value: &str) -> Result<f64, String> {
+    let invalid = || format!("Invalid duration `{value}`, expected e.g. `90s`, `30m` or `2h`");
+    let (number, unit) = match value.char_indices().last() {
+        Some((index, unit)) if unit.is_ascii_alphabetic() => (&value[..index], unit),
+        _ => (value, 's'),
+    };
+    let factor = match unit.to_ascii_lowercase() {
+        's' => 1.0,
+        'm' => 60.0,
+        'h' => 3600.0,
+        _ => return Err(invalid()),
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number.is_finite() && number >= 0.0 => Ok(number * factor),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parses `5x-realtime`, `5x` or `5` as a multiple of the video duration
+fn parse_timeout_factor(value: &str) -> Result<f64, String> {
+    let number = value.strip_suffix("-realtime").unwrap_or(value);
+    let number = number.strip_suffix('x').unwrap_or(number);
+    match number.parse::<f64>() {
+        Ok(factor) if factor.is_finite() && factor > 0.0 => Ok(factor),
+        _ => Err(format!(
+            "Invalid timeout factor `{value}`, expected e.g. `5x-realtime`"
+        )),
+    }
+}
+
+/// Parses a resolution as the lines of its 16:9 equivalent: `1080p`, `1080`, `4k`, `8k` or
+/// `1920x1080`
+fn parse_resolution(value: &str) -> Result<u32, String> {
+    let lines = match value.to_ascii_lowercase().as_str() {
+        "4k" => Some(2160),
+        "8k" => Some(4320),
+        lowercase => match lowercase.split_once('x') {
+            Some((width, height)) => width
+                .parse::<u32>()
+                .ok()
+                .zip(height.parse::<u32>().ok())
+                .map(|(width, height)| probe::lines(width, height)),
+            None => lowercase
+                .strip_suffix('p')
+                .unwrap_or(lowercase)
+                .parse::<u32>()
+                .ok(),
+        },
+    };
+    match lines {
+        Some(lines) if lines > 0 => Ok(lines),
+        _ => Err(format!(
+            "Invalid resolution `{value}`, expected e.g. `720p`, `1080p`, `4k` or `1280x720`"
+        )),
+    }
+}
+
+/// Parses `last-run`, a `YYYY-MM-DD` date (midnight UTC) or a unix timestamp
+fn parse_since(value: &str) -> Result<Since, String> {
+    if value == "last-run" {
+        return Ok(Since::LastRun);
+    }
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return Ok(Since::Timestamp(timestamp));
+    }
+
+    let invalid = || {
+        format!("Invalid value `{value}` for `--since`, expected `last-run`, YYYY-MM-DD or a unix timestamp")
+    };
+    let mut parts = value.splitn(3, '-').map(|part| part.parse::<i64>());
+    let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || year < 1970 {
+        return Err(invalid());
+    }
+
+    // days since the epoch in the proleptic gregorian calendar, with years starting in march
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    Ok(Since::Timestamp(days * 24 * 60 * 60))
+}
+
+/// Parses colon separated `key=value` pairs as accepted by `-x265-params`
+fn parse_x265_params(value: &str) -> Result<Vec<(String, String)>, String> {
+    value
+        .split(':')
+        .map(|param| {
+            let (key, value) = param.split_once('=').ok_or(format!(
+                "Invalid x265 param `{param}`, expected `key=value`"
+            ))?;
+
+            let valid_key = !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            let valid_value = !value.is_empty() && !value.contains(['=', ' ']);
+            if !valid_key || !valid_value {
+                return Err(format!(
+                    "Invalid x265 param `{param}`, expected `key=value`"
+                ));
+            }
+
+            Ok((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn since_last_run_is_also_known_as_skip_unchanged_dirs() {
+        for flag in ["--since-last-run", "--skip-unchanged-dirs"] {
+            assert!(parse(&[flag, "a"]).unwrap().since_last_run, "{flag}");
+        }
+        assert!(!parse(&["a"]).unwrap().since_last_run);
+    }
+
+    #[test]
+    fn cpu_limits_are_percentages_below_100() {
+        assert_eq!(parse_cpu_limit("50"), Ok(Some(50)));
+        assert_eq!(parse_cpu_limit("50%"), Ok(Some(50)));
+        assert_eq!(parse_cpu_limit("1"), Ok(Some(1)));
+        // all of the CPU is no limit
+        assert_eq!(parse_cpu_limit("100%"), Ok(None));
+        for invalid in ["0", "101", "-5", "50%%", "half", ""] {
+            assert!(parse_cpu_limit(invalid).is_err(), "{invalid}");
+        }
+        assert_eq!(
+            parse(&["--cpu-limit", "25%", "a"]).unwrap().cpu_limit,
+            Some(25)
+        );
+    }
+
+    #[test]
+    fn durations_default_to_seconds() {
+        assert_eq!(parse_duration("90"), Ok(90.0));
+        assert_eq!(parse_duration("90s"), Ok(90.0));
+        assert_eq!(parse_duration("1.5m"), Ok(90.0));
+        assert_eq!(parse_duration("2H"), Ok(7200.0));
+        assert_eq!(parse_duration("0"), Ok(0.0));
+        for invalid in ["", "m", "10d", "-5m", "inf", "NaN", "1h30m"] {
+            assert!(parse_duration(invalid).is_err(), "{invalid}");
+        }
+
+        let options = parse(&["--file-timeout", "2h", "--stall-timeout", "0", "a"]).unwrap();
+        assert_eq!(options.file_timeout, Some(7200.0));
+        assert_eq!(options.stall_timeout, 0.0);
+        // no timeout rather than one that stops every encode right away
+        assert_eq!(
+            parse(&["--file-timeout", "0", "a"]).unwrap().file_timeout,
+            None
+        );
+    }
+
+    #[test]
+    fn timeout_factors_are_positive_multiples() {
+        assert_eq!(parse_timeout_factor("5x-realtime"), Ok(5.0));
+        assert_eq!(parse_timeout_factor("2.5x"), Ok(2.5));
+        assert_eq!(parse_timeout_factor("3"), Ok(3.0));
+        for invalid in ["", "0", "0x", "-1x", "x", "5-realtime-x", "inf"] {
+            assert!(parse_timeout_factor(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn gpu_sessions_are_limited_to_at_least_one() {
+        let options = parse(&["--gpu-sessions", "1", "a"]).unwrap();
+        let _session = options.gpu_sessions.open("hevc_nvenc", false).unwrap();
+        assert!(options.gpu_sessions.open("hevc_nvenc", false).is_err());
+        assert!(parse(&["--gpu-sessions", "0", "a"]).is_err());
+        assert!(parse(&["--gpu-sessions", "many", "a"]).is_err());
+    }
+
+    #[test]
+    fn resolutions_are_counted_in_lines() {
+        assert_eq!(parse_resolution("720p"), Ok(720));
+        assert_eq!(parse_resolution("1080"), Ok(1080));
+        assert_eq!(parse_resolution("4K"), Ok(2160));
+        assert_eq!(parse_resolution("8k"), Ok(4320));
+        assert_eq!(parse_resolution("1280x720"), Ok(720));
+        // portrait and 4:3 sizes count like their 16:9 version
+        assert_eq!(parse_resolution("1080X1920"), Ok(1080));
+        assert_eq!(parse_resolution("1440x1080"), Ok(1080));
+        assert!(parse_resolution("4294967295x1").is_ok());
+        for invalid in ["", "0p", "p", "hd", "1280x", "x720", "-720p", "0x0"] {
+            assert!(parse_resolution(invalid).is_err(), "{invalid}");
+        }
+
+        let options = parse(&["--min-resolution", "720p", "--max-resolution", "4k", "a"]).unwrap();
+        assert_eq!(
+            (options.min_resolution, options.max_resolution),
+            (Some(720), Some(2160))
+        );
+        assert!(
+            parse(&["--min-resolution", "1080p", "--max-resolution", "720p", "a"])
+                .is_err_and(|e| e.contains("--min-resolution"))
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::{ffi::OsString, path::Path, path::PathBuf};
+
+/// `path` in a form ffmpeg and ffprobe can open. The standard library handles paths longer
+/// than `MAX_PATH` on its own, but they are passed on to ffmpeg as they are, so on windows
+/// long paths are handed over in their `\\?\` form.
+pub fn for_ffmpeg(path: &Path) -> OsString {
+    imp::for_ffmpeg(path)
+}
+
+/// `path` as returned by `canonicalize` in the form of the paths given on the command line, so
+/// they can be compared and printed alike. On windows this is `C:\...` and `\\server\share\...`
+/// instead of `\\?\C:\...` and `\\?\UNC\server\share\...` wherever the short form means the
+/// same path.
+pub fn simplify(path: PathBuf) -> PathBuf {
+    imp::simplify(path)
+}
+
+/// Absolute form of `path` the log stores paths in, so `./videos/clip.mp4`, `videos//clip.mp4`
+/// and `/home/me/videos/clip.mp4` are one entry. `.` and trailing separators are dropped
+/// without touching the filesystem, symlinks and `..` are kept like they were named.
+pub fn absolute(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    simplify(absolute.components().collect())
+}
+
+#[cfg(not(windows))]
+mod imp {
+    use std::{
+        ffi::OsString,
+        path::{Path, PathBuf},
+    };
+
+    pub fn for_ffmpeg(path: &Path) -> OsString {
+        path.as_os_str().to_owned()
+    }
+
+    pub fn simplify(path: PathBuf) -> PathBuf {
+        path
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::{
+        ffi::OsString,
+        os::windows::ffi::{OsStrExt, OsStringExt},
+        path::{Path, PathBuf},
+    };
+
+    /// Longest path windows opens without the `\\?\` prefix, including the terminating null
+    const MAX_PATH: usize = 260;
+    const VERBATIM: &str = r"\\?\";
+    const VERBATIM_UNC: &str = r"\\?\UNC\";
+    /// Names of the devices, which only refer to a file within a verbatim path
+    const DEVICE_NAMES: [&str; 22] = [
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().collect()
+    }
+
+    fn len(path: &Path) -> usize {
+        path.as_os_str().encode_wide().count()
+    }
+
+    pub fn for_ffmpeg(path: &Path) -> OsString {
+        if len(path) < MAX_PATH {
+            return path.as_os_str().to_owned();
+        }
+        // verbatim paths aren't normalized, so the path has to be absolute with backslashes
+        match std::path::absolute(path) {
+            Ok(absolute) => verbatim(&absolute),
+            Err(_) => path.as_os_str().to_owned(),
+        }
+    }
+
+    /// `\\?\C:\...` for `C:\...` and `\\?\UNC\server\share\...` for `\\server\share\...`
+    fn verbatim(absolute: &Path) -> OsString {
+        let path = absolute.as_os_str().encode_wide().collect::<Vec<_>>();
+        let prefixed = if path.starts_with(&wide(VERBATIM)) || path.starts_with(&wide(r"\\.\")) {
+            path
+        } else if let Some(unc) = path.strip_prefix(&wide(r"\\")[..]) {
+            [wide(VERBATIM_UNC), unc.to_vec()].concat()
+        } else {
+            [wide(VERBATIM), path].concat()
+        };
+        OsString::from_wide(&prefixed)
+    }
+
+    pub fn simplify(path: PathBuf) -> PathBuf {
+        let Some(text) = path.to_str() else {
+            return path;
+        };
+        let simplified = if let Some(unc) = text.strip_prefix(VERBATIM_UNC) {
+            format!(r"\\{unc}")
+        } else if let Some(local) = text.strip_prefix(VERBATIM) {
+            let drive = local.as_bytes();
+            if drive.len() < 3 || !drive[0].is_ascii_alphabetic() || &drive[1..3] != b":\\" {
+                // e.g. a volume guid, which has no drive letter form
+                return path;
+            }
+            local.to_string()
+        } else {
+            return path;
+        };
+        let simplified = PathBuf::from(simplified);
+        if len(&simplified) < MAX_PATH && !has_special_names(&simplified) {
+            simplified
+        } else {
+            path
+        }
+    }
+
+    /// Windows strips trailing dots and spaces from names outside of verbatim paths and
+    /// resolves device names, so such paths only mean the same in their verbatim form
+    fn has_special_names(path: &Path) -> bool {
+        path.iter().any(|name| {
+            let name = name.to_string_lossy();
+            let stem = name.split('.').next().unwrap_or_default().trim_end();
+            name.ends_with(['.', ' '])
+                || DEVICE_NAMES
+                    .iter()
+                    .any(|device| device.eq_ignore_ascii_case(stem))
+        })
+    }
+}
+
+#[cfg(all(test, windows))]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn long_paths_are_passed_on_verbatim() {
+        let short = Path::new(r"C:\videos\clip.mp4");
+        assert_eq!(for_ffmpeg(short), short.as_os_str());
+
+        let long = format!(r"C:\videos\{}\clip.mp4", "a".repeat(300));
+        assert_eq!(
+            for_ffmpeg(Path::new(&long)),
+            OsString::from(format!(r"\\?\{long}"))
+        );
+        let unc = format!(r"\\nas\media\{}\clip.mp4", "a".repeat(300));
+        assert_eq!(
+            for_ffmpeg(Path::new(&unc)),
+            OsString::from(format!(r"\\?\UNC\nas\media\{}\clip.mp4", "a".repeat(300)))
+        );
+        let forward = format!("C:/videos/{}/clip.mp4", "a".repeat(300));
+        assert_eq!(
+            for_ffmpeg(Path::new(&forward)),
+            OsString::from(format!(r"\\?\{long}"))
+        );
+    }
+
+    #[test]
+    fn canonical_paths_are_simplified_when_they_mean_the_same() {
+        let simplified = |path: &str| simplify(PathBuf::from(path));
+        assert_eq!(simplified(r"\\?\C:\videos"), Path::new(r"C:\videos"));
+        assert_eq!(simplified(r"\\?\D:\"), Path::new(r"D:\"));
+        assert_eq!(
+            simplified(r"\\?\UNC\nas\media\clip.mp4"),
+            Path::new(r"\\nas\media\clip.mp4")
+        );
+        assert_eq!(simplified(r"C:\videos"), Path::new(r"C:\videos"));
+        for verbatim in [
+            r"\\?\C:\videos\con.mp4",
+            r"\\?\C:\videos\trailing.",
+            r"\\?\Volume{00000000-0000-0000-0000-000000000000}\videos",
+        ] {
+            assert_eq!(simplified(verbatim), Path::new(verbatim));
+        }
+        let long = format!(r"\\?\C:\{}", "a".repeat(300));
+        assert_eq!(simplified(&long), Path::new(&long));
+    }
+
+    #[test]
+    fn long_paths_can_be_read() {
+        let root = TempDir::new("long");
+        let mut dir = root.to_path_buf();
+        while dir.as_os_str().len() < 300 {
+            dir.push("a".repeat(50));
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("clip.mp4");
+        std::fs::write(&file, "clip").unwrap();
+
+        assert_eq!(file.metadata().unwrap().len(), 4);
+        let canonical = simplify(file.canonicalize().unwrap());
+        assert_eq!(std::fs::read_to_string(&canonical).unwrap(), "clip");
+        assert_eq!(std::fs::read_to_string(for_ffmpeg(&file)).unwrap(), "clip");
+    }
+}
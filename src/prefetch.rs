@@ -0,0 +1,131 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::probe;
+
+/// Probes the candidates of the run with `--probe-jobs` ffprobe processes in the background,
+/// in the order they are compressed. The results land in the probe cache, where the checks
+/// of the encode loop find them, so encoding starts right away and the first scan of a large
+/// library doesn't probe one file after the other.
+pub struct Prefetch {
+    shared: Arc<Shared>,
+    total: usize,
+}
+
+struct Shared {
+    queue: Mutex<VecDeque<PathBuf>>,
+    done: AtomicUsize,
+    stopped: AtomicBool,
+}
+
+impl Prefetch {
+    /// `None` with `--probe-jobs 0` or nothing to probe
+    pub fn start(paths: Vec<PathBuf>, jobs: usize) -> Option<Self> {
+        // failures are reported by the check that needs the probe
+        Prefetch::start_with(paths, jobs, |path| {
+            let _ = probe::probe(path);
+        })
+    }
+
+    /// `start` with `probe` run for each path in place of ffprobe
+    fn start_with(
+        paths: Vec<PathBuf>,
+        jobs: usize,
+        probe: impl Fn(&Path) + Clone + Send + 'static,
+    ) -> Option<Self> {
+        if jobs == 0 || paths.is_empty() {
+            return None;
+        }
+
+        let total = paths.len();
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(paths.into()),
+            done: AtomicUsize::new(0),
+            stopped: AtomicBool::new(false),
+        });
+        for _ in 0..jobs.min(total) {
+            let shared = shared.clone();
+            let probe = probe.clone();
+            std::thread::spawn(move || {
+                while !shared.stopped.load(Ordering::Relaxed) {
+                    let Some(path) = shared.queue.lock().unwrap().pop_front() else {
+                        break;
+                    };
+                    probe(&path);
+                    shared.done.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+        Some(Prefetch { shared, total })
+    }
+
+    /// `Scanned 57/412 videos` while the workers are still probing
+    pub fn status(&self) -> Option<String> {
+        let done = self.shared.done.load(Ordering::Relaxed);
+        (done < self.total).then(|| format!("Scanned {done}/{} videos", self.total))
+    }
+}
+
+impl Drop for Prefetch {
+    /// Workers finish the file they are probing and exit, e.g. when the run is cancelled
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn candidates_are_probed_in_the_order_they_are_compressed() {
+        let paths = (0..20)
+            .map(|index| PathBuf::from(format!("{index}.mp4")))
+            .collect::<Vec<_>>();
+        assert!(Prefetch::start(paths.clone(), 0).is_none());
+        assert!(Prefetch::start(Vec::new(), 4).is_none());
+
+        let (sender, receiver) = mpsc::channel();
+        let prefetch = Prefetch::start_with(paths.clone(), 1, move |path| {
+            sender.send(path.to_path_buf()).unwrap();
+        })
+        .unwrap();
+        let probed = receiver.iter().take(paths.len()).collect::<Vec<_>>();
+        assert_eq!(probed, paths);
+        while prefetch.status().is_some() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn workers_stop_once_the_run_drops_the_prefetch() {
+        let paths = (0..1000)
+            .map(|index| PathBuf::from(format!("{index}.mp4")))
+            .collect::<Vec<_>>();
+        let (sender, receiver) = mpsc::channel();
+        let prefetch = Prefetch::start_with(paths, 2, move |path| {
+            std::thread::sleep(Duration::from_millis(5));
+            let _ = sender.send(path.to_path_buf());
+        })
+        .unwrap();
+        assert_eq!(
+            prefetch
+                .status()
+                .as_deref()
+                .map(|status| status.ends_with("/1000 videos")),
+            Some(true)
+        );
+        receiver.recv().unwrap();
+        drop(prefetch);
+        // the senders are dropped with the workers, which finish the file they are probing
+        assert!(receiver.iter().count() < 100);
+    }
+}
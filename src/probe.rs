@@ -0,0 +1,783 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Condvar, Mutex},
+    time::SystemTime,
+};
+
+use serde::{
+    de::{MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{atime, paths, unix_time};
+
+/// Probe results of earlier runs, next to the log
+pub const CACHE_FILE: &str = "probe_cache.json";
+/// Entries kept in the cache, the ones used longest ago are dropped beyond that
+const MAX_CACHED: usize = 100_000;
+
+/// Opened by the log of the run, there is no cache without one
+static CACHE: Mutex<Option<ProbeCache>> = Mutex::new(None);
+/// Paths ffprobe is running for, `PROBED` is notified whenever one finishes
+static IN_FLIGHT: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+static PROBED: Condvar = Condvar::new();
+
+/// What `ffprobe -show_format -show_streams` reports about a file, limited to the fields the
+/// compressor looks at. ffprobe prints most numbers other than sizes in pixels as strings.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct ProbeInfo {
+    #[serde(default)]
+    pub streams: Vec<Stream>,
+    #[serde(default)]
+    pub format: Format,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct Stream {
+    /// `video`, `audio`, `subtitle`, `data` or `attachment`
+    pub codec_type: Option<String>,
+    /// Missing if ffprobe has no decoder for the stream
+    pub codec_name: Option<String>,
+    /// e.g. `avc1`, or `[0][0][0][0]` in containers without codec tags
+    pub codec_tag_string: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Shape of the pixels, e.g. `16:11` for anamorphic PAL DVDs, `0:1` if unknown
+    pub sample_aspect_ratio: Option<String>,
+    /// In bit/s, `None` for containers that don't store per stream bitrates like matroska
+    pub bit_rate: Option<String>,
+    pub field_order: Option<String>,
+    /// Fraction like `30000/1001`, `0/0` if unknown
+    pub avg_frame_rate: Option<String>,
+    pub sample_rate: Option<String>,
+    #[serde(default)]
+    pub side_data_list: Vec<SideData>,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct SideData {
+    pub side_data_type: Option<String>,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct Format {
+    /// In seconds
+    pub duration: Option<String>,
+    /// Tags of the container in the order they are stored in
+    #[serde(
+        default,
+        serialize_with = "serialize_tags",
+        deserialize_with = "ordered_tags"
+    )]
+    pub tags: Vec<(String, String)>,
+}
+
+impl ProbeInfo {
+    /// The first video stream, like `-select_streams v:0` picks it
+    pub fn video(&self) -> Option<&Stream> {
+        self.first_of("video")
+    }
+
+    /// The first audio stream
+    pub fn audio(&self) -> Option<&Stream> {
+        self.first_of("audio")
+    }
+
+    fn first_of(&self, codec_type: &str) -> Option<&Stream> {
+        self.streams
+            .iter()
+            .find(|stream| stream.codec_type.as_deref() == Some(codec_type))
+    }
+
+    pub fn duration(&self) -> Option<f64> {
+        self.format.duration.as_deref()?.parse::<f64>().ok()
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.format
+            .tags
+            .iter()
+            .find(|(tag, _)| tag == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Keeps the tags in the order of the output, a map would sort them
+fn ordered_tags<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<(String, String)>, D::Error> {
+    struct TagsVisitor;
+
+    impl<'de> Visitor<'de> for TagsVisitor {
+        type Value = Vec<(String, String)>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("an object of tags")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut tags = Vec::new();
+            while let Some(tag) = map.next_entry::<String, String>()? {
+                tags.push(tag);
+            }
+            Ok(tags)
+        }
+    }
+
+    deserializer.deserialize_map(TagsVisitor)
+}
+
+/// Writes the tags as the object ffprobe prints, so `ordered_tags` reads the cache back
+fn serialize_tags<S: Serializer>(
+    tags: &[(String, String)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(tags.iter().map(|(key, value)| (key, value)))
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedProbe {
+    /// Of the file when it was probed, the entry is stale once either changes
+    modified: i64,
+    size: u64,
+    /// Unix time of the last run that used the entry
+    used: i64,
+    info: ProbeInfo,
+}
+
+/// ffprobe results by path, so unchanged files aren't probed again by every run. Slow disks
+/// like a NAS take longer to probe a large library than to compress the few new videos.
+#[derive(Default)]
+struct ProbeCache {
+    file: PathBuf,
+    entries: HashMap<String, CachedProbe>,
+    changed: bool,
+}
+
+impl ProbeCache {
+    fn get(&mut self, path: &str, modified: i64, size: u64, now: i64) -> Option<ProbeInfo> {
+        let entry = self
+            .entries
+            .get_mut(path)
+            .filter(|entry| entry.modified == modified && entry.size == size)?;
+        if entry.used != now {
+            entry.used = now;
+            self.changed = true;
+        }
+        Some(entry.info.clone())
+    }
+
+    fn insert(&mut self, path: String, modified: i64, size: u64, now: i64, info: ProbeInfo) {
+        let entry = CachedProbe {
+            modified,
+            size,
+            used: now,
+            info,
+        };
+        self.entries.insert(path, entry);
+        self.changed = true;
+    }
+
+    /// Drops the entries used longest ago, e.g. of files that were deleted or renamed
+    fn prune(&mut self, max: usize) {
+        if self.entries.len() <= max {
+            return;
+        }
+        let mut by_use = self
+            .entries
+            .iter()
+            .map(|(path, entry)| (entry.used, path.clone()))
+            .collect::<Vec<_>>();
+        by_use.sort_unstable_by(|a, b| b.cmp(a));
+        for (_, path) in &by_use[max..] {
+            self.entries.remove(path);
+        }
+    }
+}
+
+/// Loads the cache of the log in `dir`, an unreadable cache is started over
+pub fn open_cache(dir: &Path) {
+    let file = dir.join(CACHE_FILE);
+    let entries = std::fs::read(&file)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .unwrap_or_default();
+    *CACHE.lock().unwrap() = Some(ProbeCache {
+        file,
+        entries,
+        changed: false,
+    });
+}
+
+/// Writes the cache if a probe changed it, alongside the log
+pub fn save_cache() {
+    let mut cache = CACHE.lock().unwrap();
+    let Some(cache) = cache.as_mut().filter(|cache| cache.changed) else {
+        return;
+    };
+    cache.prune(MAX_CACHED);
+    let Ok(json) = serde_json::to_string(&cache.entries) else {
+        return;
+    };
+    match std::fs::write(&cache.file, json) {
+        Ok(()) => cache.changed = false,
+        Err(e) => info!(
+            "Failed to save the probe cache to `{}`: {e}",
+            cache.file.to_string_lossy()
+        ),
+    }
+}
+
+/// Drops the cached probe of `path`, e.g. of an original replaced by a compressed file that
+/// got its modification time, which only the size would tell apart otherwise
+pub fn forget(path: &Path) {
+    if let Some(cache) = CACHE.lock().unwrap().as_mut() {
+        if cache.entries.remove(&*path.to_string_lossy()).is_some() {
+            cache.changed = true;
+        }
+    }
+}
+
+/// Runs `ffprobe -show_format -show_streams` on the file, `Err` with the error ffprobe
+/// printed if it can't read it. Results of files that didn't change since they were probed
+/// come from the cache, errors aren't cached as the file might still be written.
+pub fn probe(path: &Path) -> Result<ProbeInfo, String> {
+    probe_with(path, run_ffprobe)
+}
+
+/// `probe` with `run` in place of ffprobe
+fn probe_with(
+    path: &Path,
+    run: impl Fn(&Path) -> Result<ProbeInfo, String>,
+) -> Result<ProbeInfo, String> {
+    let stamp = std::fs::metadata(path)
+        .ok()
+        .and_then(|metadata| Some((unix_time(metadata.modified().ok()?), metadata.len())));
+    let Some((modified, size)) = stamp else {
+        return run(path);
+    };
+    let key = path.to_string_lossy().to_string();
+    let now = unix_time(SystemTime::now());
+    let cached = || {
+        CACHE
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|cache| cache.get(&key, modified, size, now))
+    };
+
+    // a file the prefetch is probing right now is waited for instead of probed again
+    let mut in_flight = IN_FLIGHT.lock().unwrap();
+    loop {
+        if let Some(info) = cached() {
+            return Ok(info);
+        }
+        if in_flight.insert(key.clone()) {
+            break;
+        }
+        in_flight = PROBED.wait(in_flight).unwrap();
+    }
+    drop(in_flight);
+
+    let result = run(path);
+    if let (Ok(info), Some(cache)) = (&result, CACHE.lock().unwrap().as_mut()) {
+        cache.insert(key.clone(), modified, size, now, info.clone());
+    }
+    IN_FLIGHT.lock().unwrap().remove(&key);
+    PROBED.notify_all();
+    result
+}
+
+fn run_ffprobe(path: &Path) -> Result<ProbeInfo, String> {
+    let _accessed = atime::Preserved::new(path);
+    let output = Command::new("ffprobe")
+        .arg("-loglevel")
+        .arg("error")
+        .arg("-print_format")
+        .arg("json")
+        .arg("-show_format")
+        .arg("-show_streams")
+        .arg(paths::for_ffmpeg(path))
+        .output()
+        .map_err(|e| format!("Failed to run ffprobe: {e}"))?;
+    if !output.status.success() {
+        return Err(error_snippet(&output.stderr)
+            .unwrap_or_else(|| format!("ffprobe exited with {}", output.status)));
+    }
+
+    parse(&output.stdout)
+}
+
+fn parse(json: &[u8]) -> Result<ProbeInfo, String> {
+    serde_json::from_slice::<ProbeInfo>(json)
+        .map_err(|e| format!("Failed to parse the ffprobe output: {e}"))
+}
+
+/// `codec_type` of every stream in the file, e.g. `["video", "audio"]`.
+pub fn stream_types(path: &Path) -> Option<Vec<String>> {
+    let info = probe(path).ok()?;
+    Some(
+        info.streams
+            .into_iter()
+            .filter_map(|stream| stream.codec_type)
+            .collect(),
+    )
+}
+
+/// Duration of the file in seconds.
+pub fn duration(path: &Path) -> Option<f64> {
+    probe(path).ok()?.duration()
+}
+
+/// `codec_name` of the first video stream, e.g. `h264` or `hevc`.
+pub fn video_codec(path: &Path) -> Option<String> {
+    probe(path).ok()?.video()?.codec_name.clone()
+}
+
+/// `field_order` of the first video stream, e.g. `progressive` or `tt` for interlaced videos.
+pub fn field_order(path: &Path) -> Option<String> {
+    probe(path).ok()?.video()?.field_order.clone()
+}
+
+/// Bitrate of the first video stream in kbit/s, `None` if ffprobe can't report it
+/// (e.g. for containers that don't store per stream bitrates).
+pub fn video_bitrate(path: &Path) -> Option<u64> {
+    probe(path)
+        .ok()?
+        .video()?
+        .bit_rate
+        .as_deref()?
+        .parse::<u64>()
+        .ok()
+        .map(|bitrate| bitrate / 1000)
+}
+
+/// `creation_time` tag of the container, e.g. `2019-07-14T09:31:05.000000Z`.
+pub fn creation_time(path: &Path) -> Option<String> {
+    probe(path).ok()?.tag("creation_time").map(str::to_string)
+}
+
+/// All `(key, value)` tags of the container.
+pub fn format_tags(path: &Path) -> Option<Vec<(String, String)>> {
+    Some(probe(path).ok()?.format.tags)
+}
+
+/// Average frame rate of the first video stream, e.g. 29.97 for `30000/1001`.
+pub fn frame_rate(path: &Path) -> Option<f64> {
+    parse_frame_rate(probe(path).ok()?.video()?.avg_frame_rate.as_deref()?)
+}
+
+/// Parses ffprobe's fractional frame rates, `None` for the `0/0` of unknown rates
+fn parse_frame_rate(rate: &str) -> Option<f64> {
+    let (numerator, denominator) = rate.split_once('/')?;
+    let rate = numerator.parse::<f64>().ok()? / denominator.parse::<f64>().ok()?;
+    (rate.is_finite() && rate > 0.0).then_some(rate)
+}
+
+/// `sample_rate` of the first audio stream in Hz.
+pub fn audio_sample_rate(path: &Path) -> Option<u32> {
+    probe(path)
+        .ok()?
+        .audio()?
+        .sample_rate
+        .as_deref()?
+        .parse::<u32>()
+        .ok()
+}
+
+/// `codec_name` of every audio stream, `unknown` for those ffprobe can't decode
+pub fn audio_codecs(path: &Path) -> Vec<String> {
+    probe(path).map_or(Vec::new(), |info| {
+        info.streams
+            .iter()
+            .filter(|stream| stream.codec_type.as_deref() == Some("audio"))
+            .map(|stream| stream.codec_name.clone().unwrap_or("unknown".to_string()))
+            .collect()
+    })
+}
+
+/// `(width, height)` of the first video stream.
+pub fn video_resolution(path: &Path) -> Option<(u32, u32)> {
+    let info = probe(path).ok()?;
+    let video = info.video()?;
+    Some((video.width?, video.height?))
+}
+
+/// Resolution class of the first video stream as it's displayed, see `lines`. The width of
+/// anamorphic videos is scaled by their sample aspect ratio, e.g. a 720x576 PAL DVD with a
+/// `64:45` ratio is shown at 1024x576.
+pub fn display_lines(path: &Path) -> Option<u32> {
+    let info = probe(path).ok()?;
+    let video = info.video()?;
+    let aspect_ratio = video
+        .sample_aspect_ratio
+        .as_deref()
+        .and_then(parse_aspect_ratio)
+        .unwrap_or(1.0);
+    let width = (f64::from(video.width?) * aspect_ratio).round() as u32;
+    Some(lines(width, video.height?))
+}
+
+/// Lines of the 16:9 resolution a video of `width`x`height` is counted as, e.g. 1080 for
+/// 1920x1080, for a 1080x1920 portrait video, for a 1920x800 letterboxed film and for a 1440x1080
+/// 4:3 video. Either side reaching that of the 16:9 resolution is enough.
+pub fn lines(width: u32, height: u32) -> u32 {
+    let (long, short) = (width.max(height), width.min(height));
+    // in u64 as the long side of a broken probe may be anything
+    short.max((u64::from(long) * 9 / 16) as u32)
+}
+
+/// Parses `num:den` sample aspect ratios, `None` for the `0:1` of unknown ones
+fn parse_aspect_ratio(ratio: &str) -> Option<f64> {
+    let (numerator, denominator) = ratio.split_once(':')?;
+    let ratio = numerator.parse::<f64>().ok()? / denominator.parse::<f64>().ok()?;
+    (ratio.is_finite() && ratio > 0.0).then_some(ratio)
+}
+
+/// Last line ffprobe or ffmpeg printed to stderr, shortened for the overview
+fn error_snippet(stderr: &[u8]) -> Option<String> {
+    let stderr = String::from_utf8_lossy(stderr);
+    let line = stderr
+        .lines()
+        .map(str::trim)
+        .rfind(|line| !line.is_empty())?;
+    Some(match line.char_indices().nth(200) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line.to_string(),
+    })
+}
+
+/// Why the input check rejected a file
+pub enum InputError {
+    Corrupt(String),
+    /// Encrypted or DRM protected, e.g. bought from iTunes or downloaded from a DASH stream
+    Encrypted(String),
+}
+
+impl InputError {
+    /// Tells encryption errors of ffmpeg and ffprobe apart from other read errors. The path
+    /// they echo is left out, a file in `DRM free/` isn't encrypted.
+    fn classify(error: String, path: &Path) -> Self {
+        let mut message = error.clone();
+        for echoed in [paths::for_ffmpeg(path), path.as_os_str().to_owned()] {
+            message = message.replace(&*echoed.to_string_lossy(), "");
+        }
+        let lowercase = message.to_lowercase();
+        if ["encrypt", "decrypt", "drm"]
+            .iter()
+            .any(|pattern| lowercase.contains(pattern))
+        {
+            InputError::Encrypted(error)
+        } else {
+            InputError::Corrupt(error)
+        }
+    }
+}
+
+/// Codec tags of streams protected with FairPlay (`drm*`) or common encryption (`enc*`)
+const ENCRYPTED_CODEC_TAGS: [&str; 5] = ["drms", "drmi", "drmc", "encv", "enca"];
+
+/// Fails with ffprobe's error if it can't read the file, a stream is encrypted or a video
+/// stream has a codec it can't decode. Files without a video stream pass,
+/// `--audio-only-mode` decides about them.
+pub fn check_readable(path: &Path) -> Result<(), InputError> {
+    let info = probe(path).map_err(|error| InputError::classify(error, path))?;
+    if let Some(error) = info.streams.iter().find_map(encryption) {
+        return Err(InputError::Encrypted(error));
+    }
+    let undecodable = info
+        .streams
+        .iter()
+        .any(|stream| stream.codec_type.as_deref() == Some("video") && stream.codec_name.is_none());
+    if undecodable {
+        return Err(InputError::Corrupt(
+            "ffprobe found no decoder for the video stream".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Describes how an ffprobe stream is encrypted, `None` if it isn't
+fn encryption(stream: &Stream) -> Option<String> {
+    let codec_type = stream.codec_type.as_deref().unwrap_or("unknown");
+    let tag = stream.codec_tag_string.as_deref().unwrap_or_default();
+    if ENCRYPTED_CODEC_TAGS.contains(&tag) {
+        return Some(format!(
+            "{codec_type} stream has the encrypted codec tag `{tag}`"
+        ));
+    }
+    // the mov demuxer reports the original codec of common encryption streams, but attaches
+    // the encryption info as side data
+    stream
+        .side_data_list
+        .iter()
+        .filter_map(|side_data| side_data.side_data_type.as_deref())
+        .any(|kind| kind.starts_with("Encryption"))
+        .then(|| format!("{codec_type} stream carries encryption info"))
+}
+
+/// Decodes the first `seconds` of the file and fails with the first decode error ffmpeg reports
+pub fn check_decodable(path: &Path, seconds: f64) -> Result<(), InputError> {
+    let _accessed = atime::Preserved::new(path);
+    let output = Command::new("ffmpeg")
+        .arg("-loglevel")
+        .arg("error")
+        .arg("-t")
+        .arg(format!("{seconds:.3}"))
+        .arg("-i")
+        .arg(paths::for_ffmpeg(path))
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .output()
+        .map_err(|e| InputError::Corrupt(format!("Failed to run ffmpeg: {e}")))?;
+    // with `-loglevel error` anything on stderr is a problem, even if ffmpeg recovers from it
+    match error_snippet(&output.stderr) {
+        Some(error) => Err(InputError::classify(error, path)),
+        None if !output.status.success() => Err(InputError::Corrupt(format!(
+            "ffmpeg exited with {}",
+            output.status
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    fn fixture(name: &str) -> ProbeInfo {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name);
+        parse(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn a_file_being_probed_is_waited_for_instead_of_probed_again() {
+        let dir = TempDir::new("in_flight");
+        let video = dir.join("clip.mp4");
+        std::fs::write(&video, "video").unwrap();
+        open_cache(&dir);
+
+        let runs = std::sync::atomic::AtomicUsize::new(0);
+        let run = |_: &Path| {
+            runs.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            // long enough that the other thread finds the probe in flight
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            Ok(ProbeInfo {
+                format: Format {
+                    duration: Some("60.0".to_string()),
+                    ..Format::default()
+                },
+                ..ProbeInfo::default()
+            })
+        };
+        let durations = std::thread::scope(|scope| {
+            let probes = [(); 2].map(|_| scope.spawn(|| probe_with(&video, run)));
+            probes.map(|probe| probe.join().unwrap().unwrap().format.duration)
+        });
+        assert_eq!(
+            durations,
+            [Some("60.0".to_string()), Some("60.0".to_string())]
+        );
+        assert_eq!(runs.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+        let key = video.to_string_lossy().to_string();
+        let cache = CACHE.lock().unwrap();
+        let entries = &cache.as_ref().unwrap().entries;
+        assert!(entries.contains_key(&key));
+        assert_eq!(
+            entries
+                .keys()
+                .filter(|path| path.starts_with(&*dir.to_string_lossy()))
+                .count(),
+            1
+        );
+        drop(cache);
+    }
+
+    #[test]
+    fn error_snippet_is_the_last_line() {
+        assert_eq!(error_snippet(b"\n  \n"), None);
+        assert_eq!(
+            error_snippet(b"[mov,mp4 @ 0x1] error reading header\nclip.mp4: Invalid data found when processing input\n")
+                .as_deref(),
+            Some("clip.mp4: Invalid data found when processing input")
+        );
+        let long = "x".repeat(300);
+        assert_eq!(error_snippet(long.as_bytes()).unwrap().len(), 203);
+    }
+
+    #[test]
+    fn tags_of_phone_recording_keep_their_order() {
+        let info = fixture("probe_mp4.json");
+        let keys = info
+            .format
+            .tags
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                "major_brand",
+                "minor_version",
+                "compatible_brands",
+                "creation_time",
+                "com.android.version",
+                "location",
+                "comment"
+            ]
+        );
+        assert_eq!(
+            info.tag("creation_time"),
+            Some("2019-07-14T09:31:05.000000Z")
+        );
+        assert_eq!(info.tag("comment"), Some("key=value in a comment"));
+    }
+
+    #[test]
+    fn probe_fixtures() {
+        let mp4 = fixture("probe_mp4.json");
+        let video = mp4.video().unwrap();
+        assert_eq!(video.codec_name.as_deref(), Some("h264"));
+        assert_eq!((video.width, video.height), (Some(1920), Some(1080)));
+        assert_eq!(video.bit_rate.as_deref(), Some("17004601"));
+        assert_eq!(mp4.audio().unwrap().sample_rate.as_deref(), Some("48000"));
+        assert_eq!(mp4.duration(), Some(60.043));
+        assert!(mp4
+            .streams
+            .iter()
+            .all(|stream| encryption(stream).is_none()));
+
+        // hdr from an iphone with a timed metadata track
+        let mov = fixture("probe_mov.json");
+        let types = mov
+            .streams
+            .iter()
+            .filter_map(|stream| stream.codec_type.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(types, ["video", "audio", "data"]);
+        assert_eq!(mov.video().unwrap().codec_name.as_deref(), Some("hevc"));
+        assert_eq!(mov.streams[2].codec_name, None);
+
+        // interlaced dvd rip, matroska has no per stream bitrate of the video
+        let mkv = fixture("probe_mkv.json");
+        let video = mkv.video().unwrap();
+        assert_eq!(video.field_order.as_deref(), Some("tt"));
+        assert_eq!(video.bit_rate, None);
+        assert_eq!(
+            parse_frame_rate(video.avg_frame_rate.as_deref().unwrap()),
+            Some(25.0)
+        );
+        assert_eq!(mkv.tag("creation_time"), None);
+        assert_eq!(mkv.duration(), Some(5530.68));
+
+        let audio = fixture("probe_audio.json");
+        assert!(audio.video().is_none());
+        assert_eq!(audio.audio().unwrap().codec_name.as_deref(), Some("aac"));
+        assert_eq!(audio.duration(), Some(1795.552653));
+    }
+
+    #[test]
+    fn cache_entries_expire_with_the_file() {
+        let mut cache = ProbeCache::default();
+        cache.insert("a.mp4".to_string(), 100, 5000, 1, fixture("probe_mp4.json"));
+        let cached = cache.get("a.mp4", 100, 5000, 2).unwrap();
+        assert_eq!(cached.tag("comment"), Some("key=value in a comment"));
+        // replaced by the compressed file
+        assert!(cache.get("a.mp4", 100, 2500, 2).is_none());
+        assert!(cache.get("a.mp4", 200, 5000, 2).is_none());
+
+        // the tags survive the round trip through the cache file in their order
+        let json = serde_json::to_string(&cache.entries).unwrap();
+        let entries = serde_json::from_str::<HashMap<String, CachedProbe>>(&json).unwrap();
+        assert_eq!(entries["a.mp4"].info.format.tags, cached.format.tags);
+        assert_eq!(entries["a.mp4"].used, 2);
+
+        cache.insert("b.mp4".to_string(), 100, 5000, 1, ProbeInfo::default());
+        cache.insert("c.mp4".to_string(), 100, 5000, 3, ProbeInfo::default());
+        cache.prune(2);
+        let mut kept = cache.entries.keys().cloned().collect::<Vec<_>>();
+        kept.sort();
+        assert_eq!(kept, ["a.mp4", "c.mp4"]);
+        cache.prune(1);
+        assert_eq!(cache.entries.keys().collect::<Vec<_>>(), ["c.mp4"]);
+    }
+
+    #[test]
+    fn empty_output_has_no_streams() {
+        let info = parse(b"{}").unwrap();
+        assert!(info.streams.is_empty() && info.duration().is_none());
+        assert!(parse(b"not json").is_err());
+    }
+
+    #[test]
+    fn encrypted_streams_are_recognized() {
+        let stream = |json: serde_json::Value| serde_json::from_value::<Stream>(json).unwrap();
+        let fairplay =
+            stream(serde_json::json!({"codec_type": "video", "codec_tag_string": "drmi"}));
+        assert_eq!(
+            encryption(&fairplay).as_deref(),
+            Some("video stream has the encrypted codec tag `drmi`")
+        );
+        let cenc = stream(serde_json::json!({
+            "codec_type": "audio",
+            "codec_name": "aac",
+            "codec_tag_string": "mp4a",
+            "side_data_list": [{"side_data_type": "Encryption initialization data"}]
+        }));
+        assert!(encryption(&cenc).is_some());
+        let plain = stream(serde_json::json!({"codec_type": "video", "codec_tag_string": "avc1"}));
+        assert!(encryption(&plain).is_none());
+
+        let path = Path::new("/videos/DRM free/encrypted.mp4");
+        assert!(matches!(
+            InputError::classify(
+                "Incorrect encryption: no decryption key set".to_string(),
+                path
+            ),
+            InputError::Encrypted(_)
+        ));
+        assert!(matches!(
+            InputError::classify("moov atom not found".to_string(), path),
+            InputError::Corrupt(_)
+        ));
+        // only the error itself counts, not the path ffmpeg echoes
+        assert!(matches!(
+            InputError::classify(
+                "/videos/DRM free/encrypted.mp4: Invalid data found when processing input"
+                    .to_string(),
+                path
+            ),
+            InputError::Corrupt(_)
+        ));
+    }
+
+    #[test]
+    fn parse_fractional_frame_rates() {
+        assert_eq!(parse_frame_rate("25/1"), Some(25.0));
+        assert!((parse_frame_rate("30000/1001").unwrap() - 29.97).abs() < 0.001);
+        assert_eq!(parse_frame_rate("0/0"), None);
+        assert_eq!(parse_frame_rate("25"), None);
+    }
+
+    #[test]
+    fn resolutions_count_by_their_16_9_equivalent() {
+        assert_eq!(lines(1920, 1080), 1080);
+        assert_eq!(lines(1080, 1920), 1080);
+        assert_eq!(lines(1920, 800), 1080);
+        assert_eq!(lines(1440, 1080), 1080);
+        assert_eq!(lines(3840, 1600), 2160);
+        assert_eq!(lines(720, 576), 576);
+        assert_eq!(lines(u32::MAX, 1), (u64::from(u32::MAX) * 9 / 16) as u32);
+        assert_eq!(parse_aspect_ratio("64:45"), Some(64.0 / 45.0));
+        assert_eq!(parse_aspect_ratio("0:1"), None);
+    }
+}
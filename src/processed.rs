@@ -0,0 +1,123 @@
+use std::path::Path;
+
+use crate::{filesize, Log};
+
+/// Prints the compressed files of the log of `path` with their sizes and the ID of the run that
+/// added them, only those of runs whose ID starts with `run_id` if given, e.g. to find what a
+/// scheduled run did in the logs of other programs.
+pub fn list_processed(path: &Path, run_id: Option<&str>) {
+    if !path.is_dir() {
+        info!(
+            "`--list-processed` expects a directory, got `{}`",
+            path.to_string_lossy()
+        );
+        std::process::exit(1);
+    }
+
+    let log = Log::new(path.to_string_lossy().to_string());
+    let lines = report(&log, run_id);
+    if lines.is_empty() {
+        match run_id {
+            Some(run_id) => info!(
+                "No compressed files of a run `{run_id}` recorded in `{}`",
+                log.save_file
+            ),
+            None => info!("No compressed files recorded in `{}`", log.save_file),
+        }
+    }
+    for line in lines {
+        info!("{line}");
+    }
+}
+
+/// A line per file, sorted by run and path, and their total. Run IDs start with the time of
+/// the run, so the runs are listed in the order they happened. Files recorded before runs got
+/// an ID come first and are left out once a run is asked for.
+fn report(log: &Log, run_id: Option<&str>) -> Vec<String> {
+    let mut files = log
+        .shrunk_files
+        .iter()
+        .filter(|(_, file_log)| {
+            run_id.is_none_or(|run_id| {
+                file_log
+                    .run_id
+                    .as_ref()
+                    .is_some_and(|id| id.starts_with(run_id))
+            })
+        })
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        return Vec::new();
+    }
+    files.sort_by_key(|(path, file_log)| (&file_log.run_id, *path));
+
+    let id_width = files
+        .iter()
+        .map(|(_, file_log)| file_log.run_id.as_ref().map_or(1, |id| id.len()))
+        .max()
+        .unwrap_or_default();
+    let mut lines = Vec::new();
+    let (mut total_prev, mut total_post) = (0, 0);
+    for (path, file_log) in &files {
+        total_prev += file_log.size_prev;
+        total_post += file_log.size_post;
+        lines.push(format!(
+            "{:<id_width$}  {:>10} -> {:>10}  {path}",
+            file_log.run_id.as_deref().unwrap_or("-"),
+            filesize::display(file_log.size_prev),
+            filesize::display(file_log.size_post),
+        ));
+    }
+    let noun = if files.len() == 1 { "file" } else { "files" };
+    lines.push(format!(
+        "{} {noun}, {} -> {} ({})",
+        files.len(),
+        filesize::display(total_prev),
+        filesize::display(total_post),
+        filesize::display_delta(total_post as i64 - total_prev as i64)
+    ));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileLog;
+
+    #[test]
+    fn files_are_listed_by_run_and_filtered_by_its_id() {
+        let mut log = Log::in_memory();
+        for (path, run_id) in [
+            ("b.mp4", Some("20240312T031500-4711")),
+            ("a.mp4", Some("20240312T031500-4711")),
+            ("c.mp4", Some("20240305T031500-815")),
+            ("old.mp4", None),
+        ] {
+            let file_log = FileLog {
+                size_prev: 2048,
+                size_post: 1024,
+                run_id: run_id.map(str::to_string),
+                ..FileLog::default()
+            };
+            log.shrunk_files.insert(path.to_string(), file_log);
+        }
+
+        let lines = report(&log, None);
+        let paths = lines
+            .iter()
+            .filter_map(|line| line.rsplit_once("  ").map(|(_, path)| path))
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["old.mp4", "c.mp4", "a.mp4", "b.mp4"]);
+        assert_eq!(lines[4], "4 files, 8.00KiB -> 4.00KiB (-4.00KiB)");
+
+        assert_eq!(
+            report(&log, Some("20240312")),
+            [
+                "20240312T031500-4711     2.00KiB ->    1.00KiB  a.mp4",
+                "20240312T031500-4711     2.00KiB ->    1.00KiB  b.mp4",
+                "2 files, 4.00KiB -> 2.00KiB (-2.00KiB)",
+            ]
+        );
+        assert!(report(&log, Some("2023")).is_empty());
+    }
+}
@@ -0,0 +1,220 @@
+/// Position and speed of the encode as reported by one block of `-progress`, e.g.
+/// `frame=100`, `out_time_us=4000000`, `speed=2.01x` up to the `progress=continue` that ends it
+#[derive(Debug, PartialEq)]
+pub struct Progress {
+    /// Seconds of the output that have been encoded
+    pub time: f64,
+    /// Realtime factor, missing while ffmpeg reports `speed=N/A`
+    pub speed: Option<f64>,
+}
+
+/// Collects the `key=value` lines ffmpeg writes with `-progress` into a `Progress` per block.
+/// Unlike the `-stats` line the format is meant for programs and the same in every build,
+/// other records like warnings are ignored.
+#[derive(Default)]
+pub struct ProgressReader {
+    time: Option<f64>,
+    speed: Option<f64>,
+}
+
+impl ProgressReader {
+    /// Returns the progress once `record` ends a block
+    pub fn push(&mut self, record: &str) -> Option<Progress> {
+        let (key, value) = record.trim().split_once('=')?;
+        match key {
+            // `out_time_ms` is in microseconds as well, older builds only write that one
+            "out_time_us" | "out_time_ms" => {
+                if let Ok(microseconds) = value.parse::<i64>() {
+                    // negative until the first frame is written
+                    self.time = Some(microseconds.max(0) as f64 / 1_000_000.0);
+                }
+            }
+            "speed" => {
+                self.speed = value
+                    .trim()
+                    .strip_suffix('x')
+                    .and_then(|speed| speed.trim().parse::<f64>().ok())
+            }
+            "progress" => {
+                let progress = Progress {
+                    time: self.time.take()?,
+                    speed: self.speed.take(),
+                };
+                return Some(progress);
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Splits ffmpeg's stderr into records at `\n` and at the `\r` of lines that are redrawn in
+/// the terminal. Records are decoded separately, so invalid UTF-8 (e.g. from a file name in a
+/// warning) only affects its own record.
+#[derive(Default)]
+pub struct RecordSplitter {
+    pending: Vec<u8>,
+}
+
+impl RecordSplitter {
+    /// Calls `on_record` for every record completed by `chunk`
+    pub fn push(&mut self, chunk: &[u8], mut on_record: impl FnMut(&str)) {
+        let mut start = 0;
+        for (i, byte) in chunk.iter().enumerate() {
+            if *byte != b'\r' && *byte != b'\n' {
+                continue;
+            }
+
+            let record = if self.pending.is_empty() {
+                &chunk[start..i]
+            } else {
+                self.pending.extend_from_slice(&chunk[start..i]);
+                &self.pending[..]
+            };
+            if !record.is_empty() {
+                on_record(&String::from_utf8_lossy(record));
+            }
+            self.pending.clear();
+            start = i + 1;
+        }
+        self.pending.extend_from_slice(&chunk[start..]);
+    }
+
+    /// Calls `on_record` for the unterminated rest of the stream
+    pub fn finish(self, mut on_record: impl FnMut(&str)) {
+        if !self.pending.is_empty() {
+            on_record(&String::from_utf8_lossy(&self.pending));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    fn records(chunks: &[&[u8]]) -> Vec<String> {
+        let mut splitter = RecordSplitter::default();
+        let mut records = Vec::new();
+        for chunk in chunks {
+            splitter.push(chunk, |record| records.push(record.to_string()));
+        }
+        splitter.finish(|record| records.push(record.to_string()));
+        records
+    }
+
+    fn progress(records: &[&str]) -> Vec<Progress> {
+        let mut reader = ProgressReader::default();
+        records
+            .iter()
+            .filter_map(|record| reader.push(record))
+            .collect()
+    }
+
+    #[test]
+    fn parse_progress_block() {
+        let block = [
+            "frame=100",
+            "fps=50.00",
+            "stream_0_0_q=28.0",
+            "bitrate= 524.3kbits/s",
+            "total_size=262192",
+            "out_time_us=3724500000",
+            "out_time_ms=3724500000",
+            "out_time=01:02:04.500000",
+            "dup_frames=0",
+            "drop_frames=0",
+            "speed=2.01x",
+            "progress=continue",
+        ];
+        assert_eq!(
+            progress(&block),
+            [Progress {
+                time: 3724.5,
+                speed: Some(2.01)
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_progress_block_without_speed() {
+        // ffmpeg 4.0 has no `out_time_us` and the time is negative before the first frame
+        let blocks = [
+            "out_time_ms=-9223372036854775807",
+            "out_time=-2562047788:00:54.775807",
+            "speed=N/A",
+            "progress=continue",
+            "bitrate=N/A",
+            "speed=N/A",
+            "progress=end",
+        ];
+        assert_eq!(
+            progress(&blocks),
+            [Progress {
+                time: 0.0,
+                speed: None
+            }]
+        );
+        assert!(progress(&["[libx265 @ 0x1] warning: x=1", "speed=   1x"]).is_empty());
+    }
+
+    #[test]
+    fn records_are_split_across_chunks() {
+        assert_eq!(
+            records(&[
+                b"out_time_us=1000000\nspe",
+                b"ed=1.0x\rtime=00:00:02",
+                b".00\r\nwarning"
+            ]),
+            [
+                "out_time_us=1000000",
+                "speed=1.0x",
+                "time=00:00:02.00",
+                "warning"
+            ]
+        );
+    }
+
+    #[test]
+    fn records_with_invalid_utf8_are_decoded_lossily() {
+        let chunk =
+            b"[mov @ 0x1] Failed to open \xc3\x28lecture \xe4.mp4\nout_time_us=3000000\nprogress=continue\n";
+        let records = records(&[chunk]);
+        assert_eq!(records.len(), 3);
+        assert!(records[0].contains('\u{fffd}'));
+        let records = records.iter().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(progress(&records)[0].time, 3.0);
+    }
+
+    #[test]
+    fn multi_megabyte_stream_is_parsed_quickly() {
+        let mut stream = Vec::new();
+        for i in 0..100_000u64 {
+            stream.extend_from_slice(
+                format!("frame={i}\nfps=50.00\nstream_0_0_q=28.0\ntotal_size={i}000\nout_time_us={}\nspeed=1.5x\nprogress=continue\n", i * 1_000_000)
+                    .as_bytes(),
+            );
+        }
+        assert!(stream.len() > 8_000_000);
+
+        let started = Instant::now();
+        let mut splitter = RecordSplitter::default();
+        let mut reader = ProgressReader::default();
+        let mut parsed = 0;
+        let mut last_time = 0.0;
+        for chunk in stream.chunks(4096) {
+            splitter.push(chunk, |record| {
+                if let Some(progress) = reader.push(record) {
+                    parsed += 1;
+                    last_time = progress.time;
+                }
+            });
+        }
+
+        assert_eq!(parsed, 100_000);
+        assert_eq!(last_time, 99_999.0);
+        // generous enough for unoptimized builds, a quadratic reader takes minutes
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,50 @@
+use std::path::Path;
+
+use crate::{filter, options::Options, probe, scan, Log};
+
+/// Walks `path` and records the videos that are already compressed in its log, e.g. after
+/// the log was lost, so the next run skips them. Videos carrying the tag of a previous run are
+/// imported with its settings, untagged ones if their video stream is already hevc.
+pub fn rebuild_log(path: &Path, options: &Options) -> Log {
+    if !path.is_dir() {
+        info!(
+            "`--rebuild-log` expects a directory, got `{}`",
+            path.to_string_lossy()
+        );
+        std::process::exit(1);
+    }
+
+    let mut log = Log::new(path.to_string_lossy().to_string());
+    let candidates = scan(&path.to_path_buf(), &mut log, options);
+    let candidate_count = candidates.len();
+    let mut imported = 0;
+    for candidate in candidates {
+        if options.cancel.is_cancelled() {
+            break;
+        }
+        let tag = filter::output_tag(&candidate.path, options);
+        let compressed = tag.is_some()
+            || probe::video_codec(&candidate.path).is_some_and(|codec| codec == "hevc");
+        if !compressed {
+            continue;
+        }
+
+        let path = candidate.path.to_string_lossy().to_string();
+        info!(
+            "Imported `{path}`{}",
+            match &tag {
+                Some(tag) => format!(" ({tag})"),
+                None => " (hevc)".to_string(),
+            }
+        );
+        log.mark_imported(path, candidate.size, candidate.modified, tag.as_deref());
+        imported += 1;
+    }
+
+    info!(
+        "Imported {imported} compressed videos into `{}`, {} are left to compress",
+        log.save_file,
+        candidate_count - imported
+    );
+    log
+}
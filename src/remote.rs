@@ -0,0 +1,367 @@
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::{
+    compress_to, compressed_path, email, filter, options::Options, Candidate, Log, RunPosition,
+    SkipReason,
+};
+
+/// `user@host:/path` argument, a directory or a single video on a server reachable with ssh
+#[derive(Clone, PartialEq)]
+pub struct Remote {
+    pub host: String,
+    pub path: String,
+}
+
+impl Remote {
+    pub fn parse(arg: &str) -> Option<Self> {
+        let (host, path) = arg.split_once(':')?;
+        // `C:\videos` is a windows path and `./a:b` a local file with a colon in its name
+        if host.len() < 2 || host.contains(['/', '\\']) || path.is_empty() {
+            return None;
+        }
+
+        Some(Remote {
+            host: host.to_string(),
+            path: path.to_string(),
+        })
+    }
+
+    /// Runs `command` on the server and returns its stdout
+    fn ssh(&self, command: &str) -> Result<Vec<u8>, String> {
+        let output = Command::new("ssh")
+            .arg(&self.host)
+            .arg(command)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("Failed to run ssh: {e}"))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let last_line = stderr.lines().last().unwrap_or_default();
+            return Err(format!("`ssh {}` failed: {last_line}", self.host));
+        }
+
+        Ok(output.stdout)
+    }
+
+    /// `%T@ %s` of the remote file, which changes when it is modified or replaced
+    fn stamp(&self, path: &str) -> Result<String, String> {
+        let stdout = self.ssh(&format!(
+            "find {} -maxdepth 0 -printf '%T@ %s'",
+            shell_words::quote(path)
+        ))?;
+        Ok(String::from_utf8_lossy(&stdout).to_string())
+    }
+
+    /// Copies the remote `paths` into the local `dir`, keeping their modification times
+    fn download(&self, paths: &[&str], dir: &Path) -> Result<(), String> {
+        let sources = paths
+            .iter()
+            .map(|path| format!("{}:{path}", self.host).into());
+        rsync(sources.chain([dir.as_os_str().to_os_string()]))
+    }
+
+    fn upload(&self, local: &Path, path: &str) -> Result<(), String> {
+        rsync([
+            local.as_os_str(),
+            OsStr::new(&format!("{}:{path}", self.host)),
+        ])
+    }
+}
+
+fn rsync(args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> Result<(), String> {
+    // `--protect-args` keeps remote paths with spaces intact on older rsync versions
+    let output = Command::new("rsync")
+        .arg("--times")
+        .arg("--protect-args")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("Failed to run rsync: {e}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let last_line = stderr.lines().last().unwrap_or_default();
+        return Err(format!("rsync failed: {last_line}"));
+    }
+
+    Ok(())
+}
+
+/// File of the remote listing
+struct RemoteFile {
+    path: String,
+    size: u64,
+    modified: i64,
+    /// `%T@ %s` as listed, compared before replacing the file
+    stamp: String,
+}
+
+/// Parses the output of `find -printf '%T@ %s %p\0'`
+fn parse_listing(stdout: &[u8]) -> Vec<RemoteFile> {
+    String::from_utf8_lossy(stdout)
+        .split('\0')
+        .filter_map(|record| {
+            let (modified, rest) = record.split_once(' ')?;
+            let (size, path) = rest.split_once(' ')?;
+            Some(RemoteFile {
+                path: path.to_string(),
+                size: size.parse().ok()?,
+                modified: modified.parse::<f64>().ok()? as i64,
+                stamp: format!("{modified} {size}"),
+            })
+        })
+        .collect()
+}
+
+/// Compresses the videos at `remote` by copying each one into a local staging directory,
+/// compressing it there and moving the result back over the original. The log is kept on the
+/// server like for a local run and refers to the remote paths. Returns whether any file failed.
+pub fn run(remote: &Remote, options: &Options) -> (bool, email::Summary) {
+    let stage =
+        std::env::temp_dir().join(format!("video_compressor_remote_{}", std::process::id()));
+    if let Err(e) = std::fs::create_dir_all(&stage) {
+        info!("Failed to create `{}`: {e}", stage.to_string_lossy());
+        std::process::exit(1);
+    }
+    let failed = run_staged(remote, &stage, options);
+    let _ = std::fs::remove_dir_all(&stage);
+    failed
+}
+
+/// Saves the log into the staging directory and uploads it
+/// Only `compression_log.json` is downloaded and uploaded, the entries of a log a local run
+/// compacted into `compression_log.d` would be missing and new ones would be lost
+fn check_unsharded(log: &Log, remote_log: &str) -> Result<(), String> {
+    match log.shards {
+        Some(_) => Err(format!(
+            "`{remote_log}` was compacted into `{}` next to it, which runs over ssh don't sync; run the compressor on the server instead",
+            crate::shards::SHARD_DIR
+        )),
+        None => Ok(()),
+    }
+}
+
+fn save_log(log: &Log, remote: &Remote, remote_log: &str) {
+    log.save();
+    if let Err(e) = remote.upload(Path::new(&log.save_file), remote_log) {
+        info!("Failed to upload the log to `{remote_log}`: {e}");
+    }
+}
+
+fn run_staged(remote: &Remote, stage: &Path, options: &Options) -> (bool, email::Summary) {
+    let root = match remote.path.trim_end_matches('/') {
+        "" => "/",
+        root => root,
+    };
+    let files = match remote.ssh(&format!(
+        "find {} -type f -printf '%T@ %s %p\\0'",
+        shell_words::quote(root)
+    )) {
+        Ok(stdout) => parse_listing(&stdout),
+        Err(e) => {
+            info!("Failed to list `{}:{root}`: {e}", remote.host);
+            std::process::exit(1);
+        }
+    };
+    // find lists a single file as itself, its log is kept in its directory
+    let log_dir = if files.iter().any(|file| file.path == root) {
+        root.rsplit_once('/')
+            .map_or(".", |(dir, _)| dir)
+            .to_string()
+    } else {
+        root.to_string()
+    };
+    let remote_log = format!("{log_dir}/{}", crate::LOG_FILE);
+
+    if files.iter().any(|file| file.path == remote_log) {
+        if let Err(e) = remote.download(&[&remote_log], stage) {
+            info!("Failed to download `{remote_log}`: {e}");
+            std::process::exit(1);
+        }
+    }
+    let mut log = Log::load(stage.to_string_lossy().to_string());
+    if let Err(e) = check_unsharded(&log, &remote_log) {
+        info!("{e}");
+        std::process::exit(1);
+    }
+
+    let context = filter::Context::new(&log, options, &[]);
+    let (candidates, stamps): (Vec<_>, Vec<_>) = files
+        .iter()
+        .filter(|file| {
+            let relative = file.path.strip_prefix(&log_dir).unwrap_or(&file.path);
+            options.hidden || !relative.split('/').any(|name| name.starts_with('.'))
+        })
+        .filter(|file| {
+            let entry = filter::Entry {
+                path: Path::new(&file.path),
+                is_dir: false,
+                modified: file.modified,
+            };
+            filter::first_rejection(&filter::SCAN_CHECKS, &entry, &context).is_none()
+        })
+        .map(|file| {
+            let candidate = Candidate {
+                path: PathBuf::from(&file.path),
+                size: file.size,
+                modified: file.modified,
+            };
+            (candidate, &file.stamp)
+        })
+        .unzip();
+    info!(
+        "Found {} videos to compress on `{}`",
+        candidates.len(),
+        remote.host
+    );
+    options.systemd.ready();
+
+    let positions = RunPosition::all(&candidates);
+    for (index, ((candidate, stamp), position)) in
+        candidates.iter().zip(stamps).zip(positions).enumerate()
+    {
+        if options.cancel.is_cancelled() || log.stopped {
+            break;
+        }
+        let path = candidate.path.to_string_lossy().to_string();
+        // don't download a file that is known to be damaged
+        if let Some(reason) = filter::cached_input_rejection(&log, &path, candidate.modified) {
+            log.mark_skipped(path, reason);
+            continue;
+        }
+        let sidecar = format!("{path}.vc.toml");
+        let staged_dir = stage.join(index.to_string());
+        let staged = staged_dir.join(candidate.path.file_name().unwrap_or_default());
+
+        let mut downloads = vec![path.as_str()];
+        if files.iter().any(|file| file.path == sidecar) {
+            downloads.push(&sidecar);
+        }
+        let result = std::fs::create_dir_all(&staged_dir)
+            .map_err(|e| format!("Failed to create `{}`: {e}", staged_dir.to_string_lossy()))
+            .and_then(|_| remote.download(&downloads, &staged_dir));
+        if let Err(e) = result {
+            log.mark_skipped(path, SkipReason::Remote(e));
+            let _ = std::fs::remove_dir_all(&staged_dir);
+            continue;
+        }
+
+        let staged_candidate = Candidate {
+            path: staged.clone(),
+            ..*candidate
+        };
+        let rejection = staged_candidate.rejection(&log, options);
+        // the input check was cached for the staged path
+        log.rename_entry(&staged.to_string_lossy(), path.clone());
+        if let Some(reason) = rejection {
+            log.mark_skipped(path, reason);
+        } else if let Ok(encoded) = compress_to(
+            &staged,
+            &compressed_path(&staged),
+            Some(position),
+            &mut log,
+            options,
+        ) {
+            match replace_remote(remote, &encoded.dest, &path, stamp) {
+                Ok(()) => {
+                    log.mark_processed(path, candidate.size, &encoded);
+                    save_log(&log, remote, &remote_log);
+                }
+                Err(reason) => log.mark_skipped(path, reason),
+            }
+        } else {
+            // compress_to recorded the staged path
+            log.rename_entry(&staged.to_string_lossy(), path);
+        }
+        let _ = std::fs::remove_dir_all(&staged_dir);
+    }
+
+    let failed = log.has_failures();
+    let summary = email::Summary::of(&log);
+    log.print_status(None, options.verbose);
+    save_log(&log, remote, &remote_log);
+    (failed, summary)
+}
+
+/// Uploads the compressed file next to the remote original and moves it into place, unless
+/// the original changed since it was listed
+fn replace_remote(
+    remote: &Remote,
+    compressed: &Path,
+    path: &str,
+    stamp: &str,
+) -> Result<(), SkipReason> {
+    // remote paths always use `/`, so the name isn't built with `compressed_path`
+    let uploaded = format!("{path}_x265.mp4");
+    remote
+        .upload(compressed, &uploaded)
+        .map_err(SkipReason::Remote)?;
+
+    let remove_upload = || {
+        let _ = remote.ssh(&format!("rm -f {}", shell_words::quote(&uploaded)));
+    };
+    match remote.stamp(path) {
+        Ok(current) if current == stamp => {}
+        Ok(_) => {
+            remove_upload();
+            return Err(SkipReason::ChangedDuringCompression);
+        }
+        Err(e) => {
+            remove_upload();
+            return Err(SkipReason::Remote(e));
+        }
+    }
+
+    remote
+        .ssh(&format!(
+            "mv -f {} {}",
+            shell_words::quote(&uploaded),
+            shell_words::quote(path)
+        ))
+        .map(|_| ())
+        .map_err(|e| {
+            remove_upload();
+            SkipReason::Remote(e)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compacted_logs_are_refused() {
+        let mut log = Log::in_memory();
+        assert!(check_unsharded(&log, "/videos/compression_log.json").is_ok());
+        log.shards = Some(crate::shards::SHARD_COUNT);
+        let e = check_unsharded(&log, "/videos/compression_log.json").unwrap_err();
+        assert!(e.contains("compression_log.d"), "{e}");
+    }
+
+    #[test]
+    fn remote_paths_are_told_apart_from_local_ones() {
+        let remote = Remote::parse("me@nas:/srv/media").unwrap();
+        assert_eq!(
+            (remote.host.as_str(), remote.path.as_str()),
+            ("me@nas", "/srv/media")
+        );
+        assert!(Remote::parse("nas:videos/2019").is_some());
+        assert!(Remote::parse("C:\\videos").is_none());
+        assert!(Remote::parse("./a:b.mp4").is_none());
+        assert!(Remote::parse("/videos").is_none());
+    }
+
+    #[test]
+    fn parse_find_listing() {
+        let files =
+            parse_listing(b"1563096665.1234567890 1048576 /srv/media/a b.mp4\x00123 0 /srv/x\0");
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, "/srv/media/a b.mp4");
+        assert_eq!(files[0].size, 1048576);
+        assert_eq!(files[0].modified, 1563096665);
+        assert_eq!(files[0].stamp, "1563096665.1234567890 1048576");
+    }
+}
@@ -0,0 +1,106 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    compress_all, options::Options, scan_dir, unix_time, Candidate, Log, Scan, SkipReason,
+};
+
+/// Compresses the files that failed or lacked the space in earlier runs over `path`, as
+/// recorded in its log, without walking the rest of the tree. Directories that couldn't be
+/// walked are walked now. Files that are compressed or skipped on purpose this time leave the
+/// list, the others stay on it for the next retry.
+pub fn retry_failed(path: &Path, options: &Options) -> Log {
+    if !path.is_dir() {
+        info!(
+            "`--retry-failed` expects a directory, got `{}`",
+            path.to_string_lossy()
+        );
+        std::process::exit(1);
+    }
+
+    let mut log = Log::new(path.to_string_lossy().to_string());
+    let mut retry = log.retry_files.keys().cloned().collect::<Vec<_>>();
+    retry.sort();
+    if retry.is_empty() {
+        info!("No failed files recorded in `{}`", log.save_file);
+        return log;
+    }
+    info!("Retrying {} files that failed in earlier runs", retry.len());
+
+    let candidates = candidates(retry, &mut log, options);
+    options.systemd.ready();
+
+    compress_all(candidates, &mut log, options);
+    log
+}
+
+/// The files of `retry` that still exist and those found in its directories
+fn candidates(retry: Vec<String>, log: &mut Log, options: &Options) -> Vec<Candidate> {
+    let mut candidates = Vec::new();
+    for entry in retry {
+        let entry_path = PathBuf::from(&entry);
+        match std::fs::metadata(&entry_path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                info!("`{entry}` doesn't exist anymore");
+                log.retry_files.remove(&entry);
+            }
+            Err(e) => log.mark_skipped(entry, SkipReason::Metadata(e)),
+            Ok(metadata) if metadata.is_dir() => {
+                let mut scan = Scan::default();
+                scan_dir(&entry_path, log, options, &mut scan);
+                candidates.extend(scan.candidates);
+            }
+            Ok(metadata) => {
+                // a cached rejection would fail it again without looking, although e.g. a
+                // newer ffmpeg might read it now
+                log.forget_input_check(&entry);
+                candidates.push(Candidate {
+                    path: entry_path,
+                    size: metadata.len(),
+                    modified: metadata.modified().map_or(0, unix_time),
+                });
+            }
+        }
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filter, temp_dir::TempDir, RetryEntry};
+
+    #[test]
+    fn cached_rejections_of_retried_files_are_checked_again() {
+        let dir = TempDir::new("retry");
+        let path = dir.join("a.mp4");
+        std::fs::write(&path, "not a video").unwrap();
+        let key = path.to_string_lossy().to_string();
+        let modified = unix_time(path.metadata().unwrap().modified().unwrap());
+
+        let mut log = Log::in_memory();
+        let reason = "Input is corrupt or unreadable: moov atom not found".to_string();
+        log.retry_files.insert(
+            key.clone(),
+            RetryEntry {
+                reason,
+                recorded: None,
+            },
+        );
+        let check = serde_json::json!({
+            "modified": modified,
+            "deep_check": 0.0,
+            "error": "moov atom not found"
+        });
+        log.record_input_check(key.clone(), serde_json::from_value(check).unwrap());
+        assert!(filter::cached_input_rejection(&log, &key, modified).is_some());
+
+        let options = Options::default();
+        let candidates = candidates(vec![key.clone()], &mut log, &options);
+        assert_eq!(candidates.len(), 1);
+        assert!(filter::cached_input_rejection(&log, &key, modified).is_none());
+        // the file is probed again, whatever ffprobe makes of it this time
+        let rejection = candidates[0].rejection(&log, &options);
+        assert!(rejection.is_some_and(|reason| !reason.to_string().contains("moov atom")));
+        assert!(log.input_check(&key).is_some());
+    }
+}
@@ -0,0 +1,120 @@
+use std::time::Instant;
+
+use crate::{display_time_span, filesize, RunPosition};
+
+/// Progress of the whole run, printed before each video so the position within a long run
+/// isn't lost between the progress lines of the single encodes
+pub struct RunProgress {
+    started: Instant,
+    /// Durations of the candidates probed for `--estimate-time`. The time left is projected
+    /// from them if they are known and from the sizes of the candidates otherwise.
+    durations: Option<Vec<Option<f64>>>,
+    /// Of the videos compressed so far, whose encodes the time spent is projected from
+    compressed_size: u64,
+    compressed_duration: f64,
+    saved: i64,
+}
+
+impl RunProgress {
+    pub fn new(durations: Option<Vec<Option<f64>>>) -> Self {
+        RunProgress {
+            started: Instant::now(),
+            durations,
+            compressed_size: 0,
+            compressed_duration: 0.0,
+            saved: 0,
+        }
+    }
+
+    /// The video at `position` was compressed from `size` to `size_post` bytes
+    pub fn compressed(&mut self, position: &RunPosition, size: u64, size_post: u64) {
+        self.compressed_size += size;
+        self.compressed_duration += self.duration(position.index).unwrap_or(0.0);
+        self.saved += size as i64 - size_post as i64;
+    }
+
+    /// `Run: 12/412 videos done, 3.20GiB saved in 41 minutes, about 5.3 hours left`, `None`
+    /// before the first video
+    pub fn line(&self, position: &RunPosition) -> Option<String> {
+        self.line_after(position, self.started.elapsed().as_secs_f64())
+    }
+
+    fn line_after(&self, position: &RunPosition, elapsed: f64) -> Option<String> {
+        let done = position.index - 1;
+        if done == 0 {
+            return None;
+        }
+        let saved = if self.saved < 0 {
+            format!("{} added", filesize::display(self.saved.unsigned_abs()))
+        } else {
+            format!("{} saved", filesize::display(self.saved as u64))
+        };
+        let left = match self.eta(position, elapsed) {
+            Some(left) => format!(", about {} left", display_time_span(left)),
+            None => String::new(),
+        };
+        Some(format!(
+            "Run: {done}/{} videos done, {saved} in {}{left}",
+            position.count,
+            display_time_span(elapsed)
+        ))
+    }
+
+    /// Seconds left for the videos from `position` on, at the pace of the videos compressed
+    /// so far. Skipped videos take next to no time, so only compressed ones set the pace.
+    fn eta(&self, position: &RunPosition, elapsed: f64) -> Option<f64> {
+        let (left, done) = match &self.durations {
+            Some(durations) => (
+                durations[position.index - 1..]
+                    .iter()
+                    .flatten()
+                    .sum::<f64>(),
+                self.compressed_duration,
+            ),
+            None => (
+                (position.total_size - position.done_size) as f64,
+                self.compressed_size as f64,
+            ),
+        };
+        (done > 0.0).then(|| elapsed * left / done)
+    }
+
+    /// Probed duration of the video at the 1-based `index`
+    fn duration(&self, index: usize) -> Option<f64> {
+        *self.durations.as_ref()?.get(index - 1)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(index: usize, done_size: u64) -> RunPosition {
+        RunPosition {
+            index,
+            count: 4,
+            done_size,
+            total_size: 4000,
+        }
+    }
+
+    #[test]
+    fn time_left_follows_the_pace_of_the_compressed_videos() {
+        let mut by_size = RunProgress::new(None);
+        assert_eq!(by_size.line_after(&position(1, 0), 0.0), None);
+        by_size.compressed(&position(1, 0), 1000, 400);
+        assert_eq!(
+            by_size.line_after(&position(2, 1000), 600.0).unwrap(),
+            "Run: 1/4 videos done, 600.00B saved in 10 minutes, about 30 minutes left"
+        );
+
+        // the second video is as large as the first but three times as long
+        let mut by_duration =
+            RunProgress::new(Some(vec![Some(60.0), Some(180.0), None, Some(60.0)]));
+        by_duration.compressed(&position(1, 0), 1000, 1200);
+        assert_eq!(
+            by_duration.line_after(&position(2, 1000), 600.0).unwrap(),
+            "Run: 1/4 videos done, 200.00B added in 10 minutes, about 40 minutes left"
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::paths;
+
+/// Metric used to compare an encoded sample against its source.
+#[derive(Clone, Copy)]
+pub enum Metric {
+    Vmaf,
+    Ssim,
+}
+
+impl Metric {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "vmaf" => Some(Metric::Vmaf),
+            "ssim" => Some(Metric::Ssim),
+            _ => None,
+        }
+    }
+
+    fn filter(&self) -> &'static str {
+        match self {
+            Metric::Vmaf => "[0:v][1:v]libvmaf",
+            Metric::Ssim => "[0:v][1:v]ssim",
+        }
+    }
+
+    /// Extracts the score from the filter's summary line, e.g.
+    /// `[Parsed_libvmaf_0 @ 0x..] VMAF score: 94.123` or
+    /// `[Parsed_ssim_0 @ 0x..] SSIM Y:0.99 (20.1) U:0.99 (22.3) V:0.99 (22.1) All:0.99 (20.8)`
+    fn parse_score(&self, line: &str) -> Option<f64> {
+        let marker = match self {
+            Metric::Vmaf => "VMAF score: ",
+            Metric::Ssim => "All:",
+        };
+        let (_, score) = line.split_once(marker)?;
+        score.split_whitespace().next()?.parse::<f64>().ok()
+    }
+}
+
+impl Display for Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Metric::Vmaf => write!(f, "vmaf"),
+            Metric::Ssim => write!(f, "ssim"),
+        }
+    }
+}
+
+/// Scratch directory for the samples of this process, remove it with `remove_scratch_dir`.
+pub fn scratch_dir() -> Result<PathBuf, String> {
+    let dir = std::env::temp_dir().join(format!("video_compressor_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create `{}`: {e}", dir.to_string_lossy()))?;
+    Ok(dir)
+}
+
+/// `video_compressor_<pid>` in the temporary directory, which might be inside of the scanned
+/// tree, also the ones left behind by killed runs
+pub fn is_scratch_dir(path: &Path) -> bool {
+    let is_scratch_name = path
+        .file_name()
+        .and_then(|name| name.to_str()?.strip_prefix("video_compressor_"))
+        .is_some_and(|pid| !pid.is_empty() && pid.chars().all(|c| c.is_ascii_digit()));
+    let in_temp_dir = || {
+        let parent = path.parent().and_then(|parent| parent.canonicalize().ok());
+        parent.is_some() && parent == std::env::temp_dir().canonicalize().ok()
+    };
+    is_scratch_name && in_temp_dir()
+}
+
+pub fn remove_scratch_dir(dir: &Path) {
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+/// Evenly spaced `(start, duration)` windows of at most `length` seconds over a video of
+/// `duration` seconds. Short videos yield a single window covering the whole video.
+pub fn windows(duration: f64, count: usize, length: f64) -> Vec<(f64, f64)> {
+    if duration <= length * count as f64 {
+        return vec![(0.0, duration)];
+    }
+
+    (1..=count)
+        .map(|i| {
+            let center = duration * i as f64 / (count + 1) as f64;
+            (center - length / 2.0, length)
+        })
+        .collect()
+}
+
+fn run_ffmpeg(command: &mut Command) -> Result<String, String> {
+    let output = command
+        .output()
+        .map_err(|e| format!("Failed to run ffmpeg: {e}"))?;
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    if !output.status.success() {
+        let last_line = stderr.lines().last().unwrap_or_default();
+        return Err(format!("ffmpeg failed: {last_line}"));
+    }
+
+    Ok(stderr)
+}
+
+/// Copies `duration` seconds of the video stream starting at `start` into `dest` without
+/// re-encoding, so the sample can serve as the reference for quality measurements.
+pub fn extract(path: &Path, start: f64, duration: f64, dest: &Path) -> Result<(), String> {
+    run_ffmpeg(
+        Command::new("ffmpeg")
+            .arg("-loglevel")
+            .arg("error")
+            .arg("-ss")
+            .arg(format!("{start:.3}"))
+            .arg("-i")
+            .arg(paths::for_ffmpeg(path))
+            .arg("-t")
+            .arg(format!("{duration:.3}"))
+            .arg("-map")
+            .arg("0:v:0")
+            .arg("-c")
+            .arg("copy")
+            .arg(dest)
+            .arg("-y"),
+    )
+    .map(|_| ())
+}
+
+/// Encodes the video stream of `sample` into `dest` using the given encoder arguments.
+pub fn encode(sample: &Path, video_args: &[String], dest: &Path) -> Result<(), String> {
+    run_ffmpeg(
+        Command::new("ffmpeg")
+            .arg("-loglevel")
+            .arg("error")
+            .arg("-i")
+            .arg(sample)
+            .args(video_args)
+            .arg("-an")
+            .arg(dest)
+            .arg("-y"),
+    )
+    .map(|_| ())
+}
+
+/// Score of `distorted` compared to `reference`.
+pub fn measure(metric: Metric, distorted: &Path, reference: &Path) -> Result<f64, String> {
+    let stderr = run_ffmpeg(
+        Command::new("ffmpeg")
+            .arg("-hide_banner")
+            .arg("-nostats")
+            .arg("-i")
+            .arg(distorted)
+            .arg("-i")
+            .arg(reference)
+            .arg("-lavfi")
+            .arg(metric.filter())
+            .arg("-f")
+            .arg("null")
+            .arg("-"),
+    )?;
+
+    stderr
+        .lines()
+        .rev()
+        .find_map(|line| metric.parse_score(line))
+        .ok_or(format!("ffmpeg didn't report a {metric} score"))
+}
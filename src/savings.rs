@@ -0,0 +1,121 @@
+use crate::{filesize, FileLog};
+
+/// Files listed with the largest savings of the run
+const LARGEST: usize = 10;
+/// Files listed with the smallest share saved, e.g. to exclude their kind in the config
+const SMALLEST: usize = 5;
+/// Upper bounds in percent of the buckets of saved shares
+const BUCKETS: [(f64, &str); 4] = [
+    (25.0, "  <25%"),
+    (50.0, "25-50%"),
+    (75.0, "50-75%"),
+    (f64::INFINITY, "  >75%"),
+];
+/// Width of the bar of the fullest bucket
+const BAR_WIDTH: usize = 40;
+
+/// Overview of the files re-encoded in this run for the summary: the largest savings, the
+/// smallest shares saved and how the shares saved are distributed. Remuxes and copies of
+/// duplicates are left out, their sizes say nothing about the compression.
+pub fn report(files: &[(&String, &FileLog)]) -> Vec<String> {
+    let mut compressed = files
+        .iter()
+        .filter(|(_, file_log)| !file_log.remuxed && file_log.deduplicated_from.is_none())
+        .map(|(path, file_log)| (path.as_str(), file_log.size_prev, file_log.size_post))
+        .collect::<Vec<_>>();
+    if compressed.is_empty() {
+        return Vec::new();
+    }
+
+    let mut lines = vec!["Largest savings:".to_string()];
+    compressed.sort_by_key(|(path, prev, post)| (*post as i64 - *prev as i64, *path));
+    lines.extend(listing(&compressed[..compressed.len().min(LARGEST)]));
+
+    // with few files the smallest shares are the same files again
+    if compressed.len() > LARGEST {
+        lines.push("Smallest savings:".to_string());
+        compressed.sort_by(|a, b| saved(a.1, a.2).total_cmp(&saved(b.1, b.2)));
+        lines.extend(listing(&compressed[..SMALLEST]));
+    }
+
+    if compressed.len() > 1 {
+        lines.push("Saved per file:".to_string());
+        let mut counts = [0; BUCKETS.len()];
+        for (_, prev, post) in &compressed {
+            let saved = saved(*prev, *post);
+            let bucket = BUCKETS.iter().position(|(bound, _)| saved < *bound);
+            counts[bucket.unwrap_or(BUCKETS.len() - 1)] += 1;
+        }
+        let most = counts.iter().max().copied().unwrap_or_default().max(1);
+        for ((_, label), count) in BUCKETS.iter().zip(counts) {
+            // a bucket with files gets at least one mark
+            let bar = "#".repeat((count * BAR_WIDTH).div_ceil(most));
+            lines.push(format!("  {label} {bar:<BAR_WIDTH$} {count}"));
+        }
+    }
+    lines
+}
+
+/// Percent of the original size saved, negative for files that grew
+fn saved(prev: u64, post: u64) -> f64 {
+    if prev == 0 {
+        return 0.0;
+    }
+    (1.0 - post as f64 / prev as f64) * 100.0
+}
+
+fn listing(files: &[(&str, u64, u64)]) -> Vec<String> {
+    let path_width = files
+        .iter()
+        .map(|(path, _, _)| path.chars().count())
+        .max()
+        .unwrap_or_default();
+    files
+        .iter()
+        .map(|(path, prev, post)| {
+            format!(
+                "  {path:<path_width$}  {:>10} -> {:>10}  {:>10}  {:>3.0}% saved",
+                filesize::display(*prev),
+                filesize::display(*post),
+                filesize::display_delta(*post as i64 - *prev as i64),
+                saved(*prev, *post),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_the_largest_and_smallest_savings_and_buckets_the_shares() {
+        let files = (1..=12u64)
+            .map(|index| {
+                let file_log = FileLog {
+                    size_prev: 1000 * index,
+                    size_post: 80 * index * index,
+                    ..FileLog::default()
+                };
+                (format!("{index:02}.mp4"), file_log)
+            })
+            .collect::<Vec<_>>();
+        let files = files
+            .iter()
+            .map(|(path, file_log)| (path, file_log))
+            .collect::<Vec<_>>();
+
+        let lines = report(&files);
+        assert_eq!(lines[0], "Largest savings:");
+        assert!(lines[1].starts_with("  06.mp4"));
+        assert!(lines[1].ends_with(" 52% saved"));
+        assert_eq!(lines[11], "Smallest savings:");
+        assert!(lines[12].starts_with("  12.mp4"));
+        assert!(lines[12].ends_with("   4% saved"));
+        assert_eq!(lines[17], "Saved per file:");
+        assert_eq!(lines[18], format!("    <25% {} 3", "#".repeat(BAR_WIDTH)));
+        assert_eq!(lines.len(), 22);
+
+        assert_eq!(report(&files[..1]).len(), 2);
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    cell::{Cell, OnceCell},
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    ops::Index,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Directory next to the log holding its shards once it is compacted
+pub const SHARD_DIR: &str = "compression_log.d";
+/// Shards a log is split into, each with the entries of the directories hashed into it
+pub const SHARD_COUNT: usize = 256;
+/// Size of the JSON of the log from which it is compacted into shards. Smaller logs stay a
+/// single file that is easy to read and edit.
+pub const COMPACT_SIZE: usize = 8 * 1024 * 1024;
+
+/// Map of the log by path, e.g. the compressed files. Small logs keep the entries inline in
+/// `compression_log.json`. Compacted logs keep them in shard files of `compression_log.d`,
+/// each read once one of its paths is looked up and only rewritten if it changed. The files of
+/// a directory share a shard, so a run over a part of the tree only reads the shards of its
+/// directories.
+pub struct Sharded<V> {
+    /// Shard files are `<dir>/<name>_<index>.json`, `None` while the entries are inline
+    store: Option<(PathBuf, &'static str)>,
+    shards: Vec<OnceCell<HashMap<String, V>>>,
+    /// Changed since the shard was read or written
+    dirty: Vec<Cell<bool>>,
+}
+
+impl<V> Default for Sharded<V> {
+    fn default() -> Self {
+        Sharded::inline(HashMap::new())
+    }
+}
+
+impl<V> Sharded<V> {
+    pub fn inline(entries: HashMap<String, V>) -> Self {
+        Sharded {
+            store: None,
+            shards: vec![OnceCell::from(entries)],
+            dirty: vec![Cell::new(false)],
+        }
+    }
+
+    /// Map whose `count` shards are read from `dir` when needed
+    pub fn open(dir: PathBuf, name: &'static str, count: usize) -> Self {
+        Sharded {
+            store: Some((dir, name)),
+            shards: (0..count.max(1)).map(|_| OnceCell::new()).collect(),
+            dirty: (0..count.max(1)).map(|_| Cell::new(false)).collect(),
+        }
+    }
+
+    pub fn is_sharded(&self) -> bool {
+        self.store.is_some()
+    }
+
+    /// The entries if they are inline, for changes to all of them that shouldn't read every
+    /// shard
+    pub fn inline_mut(&mut self) -> Option<&mut HashMap<String, V>> {
+        if self.is_sharded() {
+            return None;
+        }
+        self.shards[0].get_mut()
+    }
+
+    fn index_of(&self, path: &str) -> usize {
+        if self.shards.len() == 1 {
+            return 0;
+        }
+        let dir = Path::new(path)
+            .parent()
+            .map_or(path.to_string(), |dir| dir.to_string_lossy().to_string());
+        (fnv1a(dir.as_bytes()) % self.shards.len() as u64) as usize
+    }
+
+    fn path_of(&self, index: usize) -> Option<PathBuf> {
+        let (dir, name) = self.store.as_ref()?;
+        Some(dir.join(format!("{name}_{index:02x}.json")))
+    }
+}
+
+impl<V: DeserializeOwned> Sharded<V> {
+    /// Moves the inline entries into `count` shards in `dir`, which are all written by the next
+    /// save
+    pub fn compact(&mut self, dir: PathBuf, name: &'static str, count: usize) {
+        let entries = self.shards[0].take().unwrap_or_default();
+        *self = Sharded::open(dir, name, count);
+        for shard in &self.shards {
+            let _ = shard.set(HashMap::new());
+        }
+        for dirty in &self.dirty {
+            dirty.set(true);
+        }
+        for (path, value) in entries {
+            let index = self.index_of(&path);
+            self.shards[index].get_mut().unwrap().insert(path, value);
+        }
+    }
+
+    fn shard(&self, index: usize) -> &HashMap<String, V> {
+        self.shards[index].get_or_init(|| self.read(index))
+    }
+
+    fn shard_mut(&mut self, index: usize) -> &mut HashMap<String, V> {
+        self.shard(index);
+        self.dirty[index].set(true);
+        self.shards[index].get_mut().unwrap()
+    }
+
+    /// A missing shard has no entries yet. One that can't be read loses its entries like a log
+    /// that can't be read, the tags of the compressed files still keep them from being
+    /// compressed twice.
+    fn read(&self, index: usize) -> HashMap<String, V> {
+        let Some(path) = self.path_of(index) else {
+            return HashMap::new();
+        };
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return HashMap::new(),
+            Err(e) => {
+                info!(
+                    "Warning: failed to read `{}`, its entries are lost: {e}",
+                    path.to_string_lossy()
+                );
+                return HashMap::new();
+            }
+        };
+        serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|e| {
+            info!(
+                "Warning: failed to parse `{}`, its entries are lost: {e}",
+                path.to_string_lossy()
+            );
+            HashMap::new()
+        })
+    }
+
+    pub fn get(&self, path: &str) -> Option<&V> {
+        self.shard(self.index_of(path)).get(path)
+    }
+
+    pub fn get_mut(&mut self, path: &str) -> Option<&mut V> {
+        let index = self.index_of(path);
+        self.shard(index).get(path)?;
+        self.shard_mut(index).get_mut(path)
+    }
+
+    pub fn insert(&mut self, path: String, value: V) -> Option<V> {
+        let index = self.index_of(&path);
+        self.shard_mut(index).insert(path, value)
+    }
+
+    pub fn remove(&mut self, path: &str) -> Option<V> {
+        let index = self.index_of(path);
+        self.shard(index).get(path)?;
+        self.shard_mut(index).remove(path)
+    }
+
+    /// Reads every shard
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &V)> {
+        (0..self.shards.len()).flat_map(|index| self.shard(index).iter())
+    }
+
+    /// Reads every shard
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    /// Reads every shard
+    pub fn len(&self) -> usize {
+        (0..self.shards.len())
+            .map(|index| self.shard(index).len())
+            .sum()
+    }
+
+    /// Reads every shard and rewrites those that lost entries
+    pub fn retain(&mut self, mut keep: impl FnMut(&String, &mut V) -> bool) {
+        for index in 0..self.shards.len() {
+            let shard = self.shard(index);
+            let len = shard.len();
+            let shard = self.shards[index].get_mut().unwrap();
+            shard.retain(&mut keep);
+            if shard.len() != len {
+                self.dirty[index].set(true);
+            }
+        }
+    }
+}
+
+impl<V: Serialize> Sharded<V> {
+    /// Writes the shards that changed since they were read or last written
+    pub fn save(&self, pretty: bool) -> Result<(), std::io::Error> {
+        let Some((dir, _)) = &self.store else {
+            return Ok(());
+        };
+        std::fs::create_dir_all(dir)?;
+        for (index, shard) in self.shards.iter().enumerate() {
+            let Some(entries) = shard.get().filter(|_| self.dirty[index].get()) else {
+                continue;
+            };
+            let path = self.path_of(index).unwrap();
+            // shards nothing was hashed into yet aren't worth a file
+            if entries.is_empty() && !path.exists() {
+                self.dirty[index].set(false);
+                continue;
+            }
+            let json = if pretty {
+                serde_json::to_string_pretty(entries)
+            } else {
+                serde_json::to_string(entries)
+            }?;
+            // a shard cut off by a crash wouldn't parse and lose all its entries
+            let mut temporary = path.as_os_str().to_os_string();
+            temporary.push(".tmp");
+            let written =
+                std::fs::write(&temporary, json).and_then(|_| std::fs::rename(&temporary, &path));
+            if written.is_err() {
+                let _ = std::fs::remove_file(&temporary);
+            }
+            written?;
+            self.dirty[index].set(false);
+        }
+        Ok(())
+    }
+}
+
+impl<V: DeserializeOwned, K: AsRef<str> + ?Sized> Index<&K> for Sharded<V> {
+    type Output = V;
+
+    fn index(&self, path: &K) -> &V {
+        self.get(path.as_ref()).expect("no entry for the path")
+    }
+}
+
+/// The inline entries, or none for a compacted log whose entries are in the shards
+impl<V: Serialize> Serialize for Sharded<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match (&self.store, self.shards[0].get()) {
+            (None, Some(entries)) => entries.serialize(serializer),
+            _ => HashMap::<String, V>::new().serialize(serializer),
+        }
+    }
+}
+
+impl<'de, V: Deserialize<'de>> Deserialize<'de> for Sharded<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        HashMap::deserialize(deserializer).map(Sharded::inline)
+    }
+}
+
+/// FNV-1a, which unlike the hasher of std is the same for every build
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn compacted_entries_are_read_back_per_shard() {
+        let dir = TempDir::new("shards");
+        let mut entries = Sharded::<u32>::default();
+        for index in 0..100 {
+            entries.insert(format!("/videos/{}/clip{index}.mp4", index % 10), index);
+        }
+        entries.compact(dir.clone(), "files", 16);
+        entries.save(false).unwrap();
+        let written = std::fs::read_dir(&dir).unwrap().count();
+        assert!(written > 1 && written <= 10, "{written}");
+
+        let mut entries = Sharded::<u32>::open(dir.clone(), "files", 16);
+        assert_eq!(entries.get("/videos/3/clip13.mp4"), Some(&13));
+        let read = entries
+            .shards
+            .iter()
+            .filter(|shard| shard.get().is_some())
+            .count();
+        assert_eq!(read, 1);
+        assert_eq!(entries.get("/videos/3/clip14.mp4"), None);
+
+        // only the shard of the changed directory is rewritten
+        entries.insert("/videos/3/new.mp4".to_string(), 1000);
+        assert_eq!(entries.dirty.iter().filter(|dirty| dirty.get()).count(), 1);
+        entries.save(false).unwrap();
+        assert!(entries.dirty.iter().all(|dirty| !dirty.get()));
+
+        let entries = Sharded::<u32>::open(dir.clone(), "files", 16);
+        assert_eq!(entries.len(), 101);
+        assert_eq!(entries["/videos/3/new.mp4"], 1000);
+        assert_eq!(serde_json::to_string(&entries).unwrap(), "{}");
+    }
+}
@@ -0,0 +1,162 @@
+use std::{ffi::OsString, fmt::Display, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::options::PRESETS;
+
+/// How the video stream of a file with a sidecar is encoded
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SidecarCodec {
+    /// Re-encode with x265, even if `--remux-efficient` would copy it
+    X265,
+    /// Copy the video stream into a new container
+    Copy,
+}
+
+/// Per file overrides read from `<video>.vc.toml` next to the video.
+///
+/// ```toml
+/// crf = 20
+/// codec = "x265"
+/// preset = "slow"
+/// skip = false
+/// ```
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sidecar {
+    pub crf: Option<u8>,
+    pub codec: Option<SidecarCodec>,
+    pub preset: Option<String>,
+    pub skip: bool,
+}
+
+impl Sidecar {
+    /// Reads the sidecar of the video at `path`, `Ok(None)` if there is none.
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        let mut file_name = OsString::from(path.file_name().unwrap_or_default());
+        file_name.push(".vc.toml");
+        let sidecar_path = path.with_file_name(file_name);
+
+        let content = match std::fs::read_to_string(&sidecar_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Failed to read sidecar: {e}")),
+        };
+        let sidecar = toml::from_str::<Sidecar>(&content)
+            .map_err(|e| format!("Failed to parse sidecar: {e}"))?;
+        sidecar.validate("sidecar")?;
+
+        Ok(Some(sidecar))
+    }
+
+    /// Checks the values the format doesn't restrict, `source` names the file they came from
+    pub fn validate(&self, source: &str) -> Result<(), String> {
+        if let Some(crf) = self.crf.filter(|crf| *crf > 51) {
+            return Err(format!(
+                "CRF must be between 0 and 51, got {crf} in {source}"
+            ));
+        }
+        if let Some(preset) = &self.preset {
+            if !PRESETS.contains(&preset.as_str()) {
+                return Err(format!(
+                    "Invalid preset `{preset}` in {source}, expected one of {}",
+                    PRESETS.join(", ")
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The overrides of `self` where it has them and those of `rule` otherwise, e.g. of the
+    /// extension rule in the config
+    pub fn over(&self, rule: &Sidecar) -> Sidecar {
+        Sidecar {
+            crf: self.crf.or(rule.crf),
+            codec: self.codec.or(rule.codec),
+            preset: self.preset.clone().or(rule.preset.clone()),
+            skip: self.skip,
+        }
+    }
+}
+
+/// The overrides as they are written, e.g. `crf = 20, preset = "slow"`
+impl Display for Sidecar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut overrides = Vec::new();
+        if let Some(crf) = self.crf {
+            overrides.push(format!("crf = {crf}"));
+        }
+        if let Some(codec) = self.codec {
+            overrides.push(match codec {
+                SidecarCodec::X265 => "codec = \"x265\"".to_string(),
+                SidecarCodec::Copy => "codec = \"copy\"".to_string(),
+            });
+        }
+        if let Some(preset) = &self.preset {
+            overrides.push(format!("preset = \"{preset}\""));
+        }
+        if self.skip {
+            overrides.push("skip = true".to_string());
+        }
+        if overrides.is_empty() {
+            write!(f, "no overrides")
+        } else {
+            write!(f, "{}", overrides.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn sidecars_are_validated() {
+        let dir = TempDir::new("sidecar");
+        let video = dir.join("clip.mp4");
+        assert!(Sidecar::load(&video).unwrap().is_none());
+
+        let valid = [
+            ("crf = 20", "crf = 20"),
+            ("crf = 0", "crf = 0"),
+            ("codec = \"copy\"", "codec = \"copy\""),
+            (
+                "preset = \"slow\"\nskip = true",
+                "preset = \"slow\", skip = true",
+            ),
+            ("", "no overrides"),
+        ];
+        for (content, overrides) in valid {
+            std::fs::write(dir.join("clip.mp4.vc.toml"), content).unwrap();
+            let sidecar = Sidecar::load(&video).unwrap().unwrap();
+            assert_eq!(sidecar.to_string(), overrides, "{content}");
+        }
+
+        let invalid = [
+            (
+                "crf = 52",
+                "CRF must be between 0 and 51, got 52 in sidecar",
+            ),
+            ("crf = 300", "Failed to parse sidecar"),
+            (
+                "preset = \"fastest\"",
+                "Invalid preset `fastest` in sidecar",
+            ),
+            ("codec = \"h264\"", "Failed to parse sidecar"),
+            ("skip = \"yes\"", "Failed to parse sidecar"),
+            // e.g. a misspelled field, which would otherwise be ignored silently
+            ("crf = 20\npreset_ = \"slow\"", "Failed to parse sidecar"),
+        ];
+        for (content, error) in invalid {
+            std::fs::write(dir.join("clip.mp4.vc.toml"), content).unwrap();
+            let e = Sidecar::load(&video).err().unwrap();
+            assert!(e.starts_with(error), "{content}: {e}");
+            if content.contains("preset_") {
+                assert!(e.contains("unknown field `preset_`"), "{e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    cell::{Cell, RefCell},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::Serialize;
+
+use crate::RunPosition;
+
+/// How often the progress of an encode is written at most
+const WRITE_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Running,
+    Completed,
+    Cancelled,
+}
+
+/// Document written to `--status-file`
+#[derive(Serialize)]
+struct Status {
+    state: State,
+    current_file: Option<String>,
+    /// Of the current file
+    percent: Option<f64>,
+    speed: Option<f64>,
+    files_done: usize,
+    files_total: usize,
+    /// Saved by the files compressed in this run
+    bytes_saved: i64,
+    /// Estimated seconds left in the run
+    eta_seconds: Option<u64>,
+    /// Unix timestamp of the write, lets readers notice a run that died
+    updated: u64,
+}
+
+/// State of the run, rewritten to `--status-file` as it changes
+pub struct StatusFile {
+    path: PathBuf,
+    status: RefCell<Status>,
+    started: Instant,
+    last_write: Cell<Option<Instant>>,
+    sizes: Cell<RunSizes>,
+}
+
+/// Bytes of the candidates of the run, for the ETA
+#[derive(Clone, Copy, Default)]
+struct RunSizes {
+    /// Before the current file
+    done: u64,
+    current: u64,
+    total: u64,
+}
+
+impl StatusFile {
+    pub fn new(path: PathBuf) -> Self {
+        StatusFile {
+            path,
+            status: RefCell::new(Status {
+                state: State::Running,
+                current_file: None,
+                percent: None,
+                speed: None,
+                files_done: 0,
+                files_total: 0,
+                bytes_saved: 0,
+                eta_seconds: None,
+                updated: 0,
+            }),
+            started: Instant::now(),
+            last_write: Cell::new(None),
+            sizes: Cell::default(),
+        }
+    }
+
+    /// A file of the run starts to be compressed
+    pub fn start_file(&self, path: &Path, size: u64, position: Option<RunPosition>) {
+        {
+            let mut status = self.status.borrow_mut();
+            status.current_file = Some(path.to_string_lossy().to_string());
+            status.percent = Some(0.0);
+            status.speed = None;
+            (status.files_done, status.files_total) = match position {
+                Some(position) => (position.index - 1, position.count),
+                None => (0, 1),
+            };
+            self.sizes.set(RunSizes {
+                done: position.map_or(0, |position| position.done_size),
+                current: size,
+                total: position.map_or(size, |position| position.total_size),
+            });
+        }
+        self.write();
+    }
+
+    /// Progress of the current encode, written every few seconds
+    pub fn progress(&self, percent: Option<f64>, speed: Option<f64>) {
+        {
+            let mut status = self.status.borrow_mut();
+            status.percent = percent;
+            status.speed = speed;
+        }
+        let due = self
+            .last_write
+            .get()
+            .is_none_or(|last_write| last_write.elapsed() >= WRITE_INTERVAL);
+        if due {
+            self.write();
+        }
+    }
+
+    /// The current file was compressed, saving `saved` bytes
+    pub fn finish_file(&self, saved: i64) {
+        {
+            let mut status = self.status.borrow_mut();
+            status.bytes_saved += saved;
+            status.files_done += 1;
+            status.percent = Some(100.0);
+        }
+        self.write();
+    }
+
+    /// The run is over, readers see the final numbers with the state
+    pub fn finish_run(&self, state: State) {
+        {
+            let mut status = self.status.borrow_mut();
+            status.state = state;
+            status.current_file = None;
+            status.percent = None;
+            status.speed = None;
+            status.eta_seconds = None;
+        }
+        self.write();
+    }
+
+    /// Extrapolates the time spent so far over the bytes of the run that are left
+    fn eta(&self, percent: Option<f64>) -> Option<u64> {
+        let sizes = self.sizes.get();
+        let done = sizes.done as f64 + sizes.current as f64 * percent? / 100.0;
+        if done <= 0.0 {
+            return None;
+        }
+        let elapsed = self.started.elapsed().as_secs_f64();
+        Some((elapsed * (sizes.total as f64 - done).max(0.0) / done) as u64)
+    }
+
+    /// Writes to a temporary file next to it first, the rename replaces the file atomically so
+    /// readers never see a partial document
+    fn write(&self) {
+        self.last_write.set(Some(Instant::now()));
+        let json = {
+            let mut status = self.status.borrow_mut();
+            if status.state == State::Running {
+                status.eta_seconds = self.eta(status.percent);
+            }
+            status.updated = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs());
+            serde_json::to_string_pretty(&*status)
+        };
+        let Ok(json) = json else {
+            return;
+        };
+
+        let mut temporary = self.path.as_os_str().to_os_string();
+        temporary.push(".tmp");
+        let result =
+            std::fs::write(&temporary, json).and_then(|_| std::fs::rename(&temporary, &self.path));
+        if let Err(e) = result {
+            let _ = std::fs::remove_file(&temporary);
+            info!(
+                "Failed to write the status file `{}`: {e}",
+                self.path.to_string_lossy()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    fn read(path: &Path) -> serde_json::Value {
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn every_write_replaces_the_whole_document() {
+        let dir = TempDir::new("status");
+        let path = dir.join("status.json");
+        let status_file = StatusFile::new(path.clone());
+        let position = RunPosition {
+            index: 2,
+            count: 3,
+            done_size: 100,
+            total_size: 600,
+        };
+
+        status_file.start_file(Path::new("/videos/b.mp4"), 200, Some(position));
+        let status = read(&path);
+        assert_eq!(status["state"], "running");
+        assert_eq!(status["current_file"], "/videos/b.mp4");
+        assert_eq!(
+            (
+                status["files_done"].as_u64(),
+                status["files_total"].as_u64()
+            ),
+            (Some(1), Some(3))
+        );
+        assert!(!dir.join("status.json.tmp").exists());
+
+        status_file.finish_file(150);
+        status_file.finish_run(State::Completed);
+        let status = read(&path);
+        assert_eq!(status["state"], "completed");
+        assert_eq!(status["current_file"], serde_json::Value::Null);
+        assert_eq!(status["eta_seconds"], serde_json::Value::Null);
+        assert_eq!(status["files_done"], 2);
+        assert_eq!(status["bytes_saved"], 150);
+        assert!(status["updated"].as_u64().unwrap() > 0);
+        assert_eq!(
+            std::fs::read_dir(&dir).unwrap().count(),
+            1,
+            "the temporary file was renamed"
+        );
+
+        status_file.finish_run(State::Cancelled);
+        assert_eq!(read(&path)["state"], "cancelled");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // a status file that can't be written only warns
+        status_file.finish_run(State::Completed);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn eta_extrapolates_the_bytes_done() {
+        let mut status_file = StatusFile::new(PathBuf::new());
+        status_file.sizes.set(RunSizes {
+            done: 100,
+            current: 100,
+            total: 400,
+        });
+        assert_eq!(status_file.eta(None), None);
+
+        status_file.started = Instant::now() - Duration::from_secs(10);
+        // 150 of 400 bytes in 10 seconds leave 250 bytes for another 16.7 seconds
+        assert_eq!(status_file.eta(Some(50.0)), Some(16));
+        assert_eq!(status_file.eta(Some(100.0)), Some(10));
+
+        status_file.sizes.set(RunSizes::default());
+        assert_eq!(status_file.eta(Some(0.0)), None);
+    }
+}
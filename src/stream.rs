@@ -0,0 +1,32 @@
+use std::path::Path;
+
+use crate::{compress, options::Options, prepare, Log};
+
+/// Compresses the video at `path` to stdout as a fragmented mp4, which can be written without
+/// seeking. Nothing is logged and the original is left untouched.
+pub fn run(path: &Path, options: &Options) -> Result<(), ()> {
+    if !path.is_file() {
+        eprintln!(
+            "`--stdout` expects a file, got `{}`",
+            path.to_string_lossy()
+        );
+        return Err(());
+    }
+
+    let mut log = Log::in_memory();
+    let Ok(mut settings) = prepare(path, None, &mut log, options) else {
+        for reason in log
+            .skipped_files
+            .values()
+            .chain(log.encrypted_files.values())
+            .chain(log.failed_files.values())
+        {
+            eprintln!("Failed to compress `{}`: {reason}", path.to_string_lossy());
+        }
+        return Err(());
+    };
+    settings.stream = true;
+
+    compress(path.to_path_buf(), "pipe:1".into(), &settings, options)
+        .map_err(|reason| eprintln!("Failed to compress `{}`: {reason}", path.to_string_lossy()))
+}
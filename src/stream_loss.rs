@@ -0,0 +1,122 @@
+use std::path::Path;
+
+use crate::{probe, EncodeSettings};
+
+/// Streams of `original` missing from `compressed`, e.g. `audio #2 (ac3)`. Streams the settings
+/// drop on purpose, like the audio of `--no-audio`, don't count, and subtitles embedded from
+/// files don't stand in for lost ones. Empty if either can't be probed, the encode itself
+/// succeeded then.
+pub fn lost(original: &Path, compressed: &Path, settings: &EncodeSettings) -> Vec<String> {
+    let probed = (probe::probe(original), probe::probe(compressed));
+    // the compressed file is renamed over the original, its entry would only linger in the cache
+    probe::forget(compressed);
+    let (Ok(original), Ok(compressed)) = probed else {
+        return Vec::new();
+    };
+    let streams = |info: &probe::ProbeInfo| {
+        info.streams
+            .iter()
+            .map(|stream| {
+                (
+                    stream.codec_type.clone().unwrap_or_default(),
+                    stream.codec_name.clone(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut compressed = streams(&compressed);
+    // embedded files are mapped after the streams of the original
+    for _ in &settings.subtitles {
+        if let Some(index) = compressed
+            .iter()
+            .rposition(|(codec_type, _)| codec_type == "subtitle")
+        {
+            compressed.remove(index);
+        }
+    }
+    let mut dropped = Vec::new();
+    if settings.audio_only {
+        dropped.push("video");
+    }
+    if settings.no_audio {
+        dropped.push("audio");
+    }
+    missing(&streams(&original), &compressed, &dropped)
+}
+
+/// Streams of `original` beyond the number of streams of the same type in `compressed`.
+/// ffmpeg maps the first streams of a type, so the later ones are the missing ones.
+fn missing(
+    original: &[(String, Option<String>)],
+    compressed: &[(String, Option<String>)],
+    dropped: &[&str],
+) -> Vec<String> {
+    let mut seen = Vec::<(&str, usize)>::new();
+    original
+        .iter()
+        .enumerate()
+        .filter(|(_, (codec_type, _))| !dropped.contains(&codec_type.as_str()))
+        .filter_map(|(index, (codec_type, codec))| {
+            let position = match seen.iter_mut().find(|(seen, _)| seen == codec_type) {
+                Some((_, count)) => {
+                    *count += 1;
+                    *count
+                }
+                None => {
+                    seen.push((codec_type, 1));
+                    1
+                }
+            };
+            let kept = compressed
+                .iter()
+                .filter(|(kept, _)| kept == codec_type)
+                .count();
+            (position > kept).then(|| {
+                let codec = codec.as_deref().unwrap_or("unknown");
+                format!("{codec_type} #{index} ({codec})")
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_streams_of_a_type_are_the_lost_ones() {
+        let streams = |streams: &[(&str, &str)]| {
+            streams
+                .iter()
+                .map(|(codec_type, codec)| (codec_type.to_string(), Some(codec.to_string())))
+                .collect::<Vec<_>>()
+        };
+        let original = streams(&[
+            ("video", "h264"),
+            ("audio", "aac"),
+            ("audio", "ac3"),
+            ("subtitle", "hdmv_pgs_subtitle"),
+            ("data", "bin_data"),
+        ]);
+        let compressed = streams(&[("video", "hevc"), ("audio", "aac")]);
+        assert_eq!(
+            missing(&original, &compressed, &[]),
+            [
+                "audio #2 (ac3)",
+                "subtitle #3 (hdmv_pgs_subtitle)",
+                "data #4 (bin_data)"
+            ]
+        );
+        assert_eq!(
+            missing(&original, &streams(&[("video", "hevc")]), &["audio"]).len(),
+            2
+        );
+        let compressed = streams(&[
+            ("video", "hevc"),
+            ("audio", "aac"),
+            ("audio", "ac3"),
+            ("subtitle", "mov_text"),
+        ]);
+        assert_eq!(missing(&original, &compressed, &[]), ["data #4 (bin_data)"]);
+    }
+}
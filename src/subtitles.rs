@@ -0,0 +1,107 @@
+use std::path::{Path, PathBuf};
+
+use crate::probe;
+
+/// Extensions of the subtitle files embedded with `--embed-subtitles`
+const EXTENSIONS: [&str; 2] = ["srt", "vtt"];
+/// Codecs of text subtitles in the original, which the mp4 muxer takes as `mov_text`. Bitmap
+/// subtitles like those of DVDs and Blu-rays can't be converted and are dropped.
+const TEXT_CODECS: [&str; 6] = ["mov_text", "subrip", "webvtt", "ass", "ssa", "text"];
+
+/// Subtitle file next to a video, e.g. `clip.srt` or `clip.en.srt` for `clip.mp4`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subtitle {
+    pub path: PathBuf,
+    /// Language code between the name of the video and the extension
+    pub language: Option<String>,
+}
+
+/// Subtitle files named after `video` in its directory, sorted by name
+pub fn find(video: &Path) -> Vec<Subtitle> {
+    let (Some(dir), Some(stem)) = (video.parent(), video.file_stem()) else {
+        return Vec::new();
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let stem = stem.to_string_lossy();
+    let mut subtitles = entries
+        .flatten()
+        .filter_map(|entry| {
+            let language = matching(&stem, &entry.file_name().to_string_lossy())?;
+            Some(Subtitle {
+                path: video.with_file_name(entry.file_name()),
+                language,
+            })
+        })
+        .collect::<Vec<_>>();
+    subtitles.sort_by(|a, b| a.path.cmp(&b.path));
+    subtitles
+}
+
+/// `Some` with the language code of the file name if it is a subtitle of the video `stem`
+fn matching(stem: &str, file_name: &str) -> Option<Option<String>> {
+    let (name, extension) = file_name.rsplit_once('.')?;
+    if !EXTENSIONS
+        .iter()
+        .any(|known| known.eq_ignore_ascii_case(extension))
+    {
+        return None;
+    }
+    if name == stem {
+        return Some(None);
+    }
+    // two or three letters like ISO 639, longer ones could be part of another video's name
+    let language = name.strip_prefix(stem)?.strip_prefix('.')?;
+    let is_code =
+        (2..=3).contains(&language.len()) && language.chars().all(|c| c.is_ascii_alphabetic());
+    is_code.then(|| Some(language.to_ascii_lowercase()))
+}
+
+/// Indices of the text subtitle streams of the original, which are kept next to the embedded
+/// files
+pub fn text_streams(path: &Path) -> Vec<usize> {
+    let Ok(info) = probe::probe(path) else {
+        return Vec::new();
+    };
+    info.streams
+        .iter()
+        .enumerate()
+        .filter(|(_, stream)| {
+            stream.codec_type.as_deref() == Some("subtitle")
+                && stream
+                    .codec_name
+                    .as_deref()
+                    .is_some_and(|codec| TEXT_CODECS.contains(&codec))
+        })
+        .map(|(index, _)| index)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subtitles_match_the_name_of_the_video() {
+        assert_eq!(matching("clip", "clip.srt"), Some(None));
+        assert_eq!(matching("clip", "clip.VTT"), Some(None));
+        assert_eq!(
+            matching("clip", "clip.en.srt"),
+            Some(Some("en".to_string()))
+        );
+        assert_eq!(
+            matching("clip", "clip.DEU.srt"),
+            Some(Some("deu".to_string()))
+        );
+        assert_eq!(matching("clip", "clip.mp4"), None);
+        assert_eq!(matching("clip", "clip2.srt"), None);
+        assert_eq!(matching("clip", "clip.part2.srt"), None);
+        assert_eq!(matching("clip.part1", "clip.part1.srt"), Some(None));
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+/// Shortest time between two `STATUS=` messages, progress arrives several times per second
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Notifications to the service manager with `--systemd`, see sd_notify(3). Sends nothing
+/// when the program wasn't started by systemd.
+#[derive(Default)]
+pub struct Systemd {
+    socket: Option<imp::Socket>,
+    /// Half of `WATCHDOG_USEC`, as systemd recommends
+    watchdog: Option<Duration>,
+    last_ping: Cell<Option<Instant>>,
+    last_status: Cell<Option<Instant>>,
+}
+
+impl Systemd {
+    pub fn from_env() -> Self {
+        let socket = imp::Socket::from_env();
+        // a watchdog meant for the parent process is passed on with its pid
+        let for_us = std::env::var("WATCHDOG_PID")
+            .map_or(true, |pid| pid.trim() == std::process::id().to_string());
+        let watchdog = std::env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.trim().parse::<u64>().ok())
+            .filter(|&usec| usec > 0 && for_us && socket.is_some())
+            .map(|usec| Duration::from_micros(usec / 2));
+        Systemd {
+            socket,
+            watchdog,
+            last_ping: Cell::new(None),
+            last_status: Cell::new(None),
+        }
+    }
+
+    /// The scan is done and the run starts compressing
+    pub fn ready(&self) {
+        self.send("READY=1");
+    }
+
+    /// Shown by `systemctl status`, pings the watchdog as well
+    pub fn status(&self, status: &str) {
+        self.last_status.set(Some(Instant::now()));
+        self.send(&format!("STATUS={status}"));
+        self.last_ping.set(Some(Instant::now()));
+        if self.watchdog.is_some() {
+            self.send("WATCHDOG=1");
+        }
+    }
+
+    /// Progress of an encode, sent at most once per `STATUS_INTERVAL`. As ffmpeg reports it,
+    /// a hung ffmpeg stops the pings and the watchdog restarts the service.
+    pub fn progress(&self, status: &str) {
+        let due = self
+            .last_status
+            .get()
+            .is_none_or(|last_status| last_status.elapsed() >= STATUS_INTERVAL);
+        if due {
+            self.status(status);
+        } else {
+            self.ping();
+        }
+    }
+
+    /// Tells the watchdog the run is alive, at most every half interval
+    pub fn ping(&self) {
+        let Some(interval) = self.watchdog else {
+            return;
+        };
+        let due = self
+            .last_ping
+            .get()
+            .is_none_or(|last_ping| last_ping.elapsed() >= interval);
+        if due {
+            self.last_ping.set(Some(Instant::now()));
+            self.send("WATCHDOG=1");
+        }
+    }
+
+    /// The run is over and the process exits
+    pub fn stopping(&self, status: &str) {
+        self.send(&format!("STOPPING=1\nSTATUS={status}"));
+    }
+
+    fn send(&self, message: &str) {
+        if let Some(socket) = &self.socket {
+            // the service manager going away isn't a reason to stop compressing
+            let _ = socket.send(message);
+        }
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::os::unix::net::UnixDatagram;
+
+    /// Datagram socket connected to `NOTIFY_SOCKET`
+    pub struct Socket(pub(super) UnixDatagram);
+
+    impl Socket {
+        pub fn from_env() -> Option<Self> {
+            let path = std::env::var_os("NOTIFY_SOCKET")?;
+            let socket = UnixDatagram::unbound().ok()?;
+            connect(&socket, &path.to_string_lossy()).ok()?;
+            Some(Socket(socket))
+        }
+
+        pub fn send(&self, message: &str) -> Result<(), std::io::Error> {
+            self.0.send(message.as_bytes()).map(|_| ())
+        }
+    }
+
+    /// `@name` is a socket in the abstract namespace of linux
+    #[cfg(target_os = "linux")]
+    fn connect(socket: &UnixDatagram, path: &str) -> Result<(), std::io::Error> {
+        use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+        match path.strip_prefix('@') {
+            Some(name) => socket.connect_addr(&SocketAddr::from_abstract_name(name)?),
+            None => socket.connect(path),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn connect(socket: &UnixDatagram, path: &str) -> Result<(), std::io::Error> {
+        socket.connect(path)
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    pub struct Socket;
+
+    impl Socket {
+        pub fn from_env() -> Option<Self> {
+            None
+        }
+
+        pub fn send(&self, _: &str) -> Result<(), std::io::Error> {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::os::unix::net::UnixDatagram;
+
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn notifications_reach_the_socket() {
+        let dir = TempDir::new("notify");
+        let path = dir.join("notify.sock");
+        let listener = UnixDatagram::bind(&path).unwrap();
+        let socket = UnixDatagram::unbound().unwrap();
+        socket.connect(&path).unwrap();
+        let systemd = Systemd {
+            socket: Some(imp::Socket(socket)),
+            watchdog: Some(Duration::from_secs(60)),
+            ..Systemd::default()
+        };
+
+        let mut buffer = [0; 256];
+        let mut receive = || {
+            let read = listener.recv(&mut buffer).unwrap();
+            String::from_utf8_lossy(&buffer[..read]).to_string()
+        };
+        systemd.ready();
+        assert_eq!(receive(), "READY=1");
+        systemd.progress("Compressing clip.mp4: 42%");
+        assert_eq!(receive(), "STATUS=Compressing clip.mp4: 42%");
+        assert_eq!(receive(), "WATCHDOG=1");
+        // too soon for both, the next datagram is the stop
+        systemd.progress("Compressing clip.mp4: 43%");
+        systemd.stopping("Completed");
+        assert_eq!(receive(), "STOPPING=1\nSTATUS=Completed");
+    }
+}
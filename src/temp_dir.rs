@@ -0,0 +1,38 @@
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// Temporary directory removed when the test ends, even if it fails
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Empty directory unique to the test `name` and the test process
+    pub fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("video_compressor_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = PathBuf;
+
+    fn deref(&self) -> &PathBuf {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
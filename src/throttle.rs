@@ -0,0 +1,302 @@
+use std::{
+    process::Child,
+    time::{Duration, Instant},
+};
+
+/// Duty cycle of `--cpu-limit` on unix: ffmpeg runs for its share of it and is paused for the
+/// rest. Short enough that the load stays even, long enough that switching costs nothing.
+const PERIOD: Duration = Duration::from_millis(500);
+
+/// Keeps the ffmpeg of an encode to `--cpu-limit` percent of the CPU, e.g. on a machine that
+/// throttles once it runs hot, where a lower priority doesn't help. On unix ffmpeg is stopped
+/// and continued in a duty cycle (SIGSTOP/SIGCONT), on windows a job object caps its CPU rate.
+pub struct Throttle {
+    percent: u8,
+    limiter: imp::Limiter,
+    /// Start of the current run or pause of the duty cycle
+    switched: Instant,
+    paused: bool,
+}
+
+impl Throttle {
+    /// `None` without a limit or if ffmpeg can't be limited, which is warned about
+    pub fn start(child: &Child, percent: Option<u8>) -> Option<Self> {
+        let percent = percent.filter(|&percent| percent < 100)?;
+        match imp::limit(child, percent) {
+            Ok(limiter) => Some(Throttle {
+                percent,
+                limiter,
+                switched: Instant::now(),
+                paused: false,
+            }),
+            Err(e) => {
+                info!("Warning: failed to limit the CPU usage of ffmpeg, it runs unlimited: {e}");
+                None
+            }
+        }
+    }
+
+    pub fn percent(&self) -> u8 {
+        self.percent
+    }
+
+    /// Pauses or continues ffmpeg once its share of the cycle is over. How long it was paused
+    /// if it was just continued, that time must not count as ffmpeg making no progress.
+    pub fn tick(&mut self) -> Option<Duration> {
+        if !imp::DUTY_CYCLE || self.switched.elapsed() < self.share() {
+            return None;
+        }
+        let elapsed = self.switched.elapsed();
+        self.paused = !self.paused;
+        self.limiter.pause(self.paused);
+        self.switched = Instant::now();
+        (!self.paused).then_some(elapsed)
+    }
+
+    /// Time until the next `tick` switches, to wake up for it while waiting for ffmpeg
+    pub fn next_switch(&self) -> Option<Duration> {
+        imp::DUTY_CYCLE.then(|| self.share().saturating_sub(self.switched.elapsed()))
+    }
+
+    /// How long ffmpeg has been paused, zero while it runs
+    pub fn paused_for(&self) -> Duration {
+        if self.paused {
+            self.switched.elapsed()
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// Length of the current run or pause
+    fn share(&self) -> Duration {
+        let run = PERIOD * u32::from(self.percent) / 100;
+        if self.paused {
+            PERIOD - run
+        } else {
+            run
+        }
+    }
+}
+
+/// Continues a paused ffmpeg, which must happen before it's waited for: it can't exit while
+/// stopped, and once it's reaped its PID may belong to another process.
+impl Drop for Throttle {
+    fn drop(&mut self) {
+        if self.paused {
+            self.limiter.pause(false);
+        }
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::{ffi::c_int, process::Child};
+
+    /// SIGSTOP and SIGCONT of the targets they were checked for. Linux on MIPS, SPARC and
+    /// Alpha, Solaris and illumos number them differently, sending these there would hit other
+    /// signals.
+    #[cfg(all(
+        any(target_os = "linux", target_os = "android"),
+        any(
+            target_arch = "x86",
+            target_arch = "x86_64",
+            target_arch = "arm",
+            target_arch = "aarch64",
+            target_arch = "riscv64",
+            target_arch = "powerpc",
+            target_arch = "powerpc64",
+            target_arch = "s390x",
+            target_arch = "loongarch64"
+        )
+    ))]
+    const SIGNALS: Option<(c_int, c_int)> = Some((19, 18));
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    ))]
+    const SIGNALS: Option<(c_int, c_int)> = Some((17, 19));
+    #[cfg(not(any(
+        all(
+            any(target_os = "linux", target_os = "android"),
+            any(
+                target_arch = "x86",
+                target_arch = "x86_64",
+                target_arch = "arm",
+                target_arch = "aarch64",
+                target_arch = "riscv64",
+                target_arch = "powerpc",
+                target_arch = "powerpc64",
+                target_arch = "s390x",
+                target_arch = "loongarch64"
+            )
+        ),
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    )))]
+    const SIGNALS: Option<(c_int, c_int)> = None;
+
+    pub const DUTY_CYCLE: bool = SIGNALS.is_some();
+
+    extern "C" {
+        fn kill(pid: c_int, signal: c_int) -> c_int;
+    }
+
+    /// PID of the ffmpeg, which is stopped with all its encoder threads, and the numbers of
+    /// SIGSTOP and SIGCONT
+    pub struct Limiter(c_int, (c_int, c_int));
+
+    pub fn limit(child: &Child, _percent: u8) -> Result<Limiter, String> {
+        let signals = SIGNALS.ok_or("the signals to pause it aren't known on this platform")?;
+        Ok(Limiter(child.id() as c_int, signals))
+    }
+
+    impl Limiter {
+        pub fn pause(&self, paused: bool) {
+            let (stop, cont) = self.1;
+            unsafe {
+                kill(self.0, if paused { stop } else { cont });
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::{ffi::c_void, os::windows::io::AsRawHandle, process::Child};
+
+    const JOB_OBJECT_CPU_RATE_CONTROL_INFORMATION: i32 = 15;
+    const JOB_OBJECT_CPU_RATE_CONTROL_ENABLE: u32 = 0x1;
+    const JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP: u32 = 0x4;
+
+    /// The scheduler caps the rate itself, nothing is paused
+    pub const DUTY_CYCLE: bool = false;
+
+    /// `JOBOBJECT_CPU_RATE_CONTROL_INFORMATION` with the `CpuRate` of its union
+    #[repr(C)]
+    struct CpuRateControl {
+        control_flags: u32,
+        /// Hundredths of a percent of all processors
+        cpu_rate: u32,
+    }
+
+    extern "system" {
+        fn CreateJobObjectW(attributes: *const c_void, name: *const u16) -> *mut c_void;
+        fn SetInformationJobObject(
+            job: *mut c_void,
+            class: i32,
+            information: *const c_void,
+            length: u32,
+        ) -> i32;
+        fn AssignProcessToJobObject(job: *mut c_void, process: *mut c_void) -> i32;
+        fn CloseHandle(handle: *mut c_void) -> i32;
+    }
+
+    /// Job ffmpeg was assigned to, closing it leaves the limit in place until ffmpeg exits
+    pub struct Limiter(*mut c_void);
+
+    /// The rate is a share of all processors rather than of what ffmpeg would use, which is
+    /// about the same for x265 keeping every core busy
+    pub fn limit(child: &Child, percent: u8) -> Result<Limiter, String> {
+        let job = unsafe { CreateJobObjectW(std::ptr::null(), std::ptr::null()) };
+        if job.is_null() {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+        let limiter = Limiter(job);
+        let information = CpuRateControl {
+            control_flags: JOB_OBJECT_CPU_RATE_CONTROL_ENABLE
+                | JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP,
+            cpu_rate: u32::from(percent) * 100,
+        };
+        let limited = unsafe {
+            SetInformationJobObject(
+                job,
+                JOB_OBJECT_CPU_RATE_CONTROL_INFORMATION,
+                &information as *const CpuRateControl as *const c_void,
+                std::mem::size_of::<CpuRateControl>() as u32,
+            ) != 0
+                && AssignProcessToJobObject(job, child.as_raw_handle()) != 0
+        };
+        if !limited {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+        Ok(limiter)
+    }
+
+    impl Limiter {
+        pub fn pause(&self, _paused: bool) {}
+    }
+
+    impl Drop for Limiter {
+        fn drop(&mut self) {
+            unsafe {
+                CloseHandle(self.0);
+            }
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    use std::process::Child;
+
+    pub const DUTY_CYCLE: bool = false;
+
+    pub struct Limiter;
+
+    pub fn limit(_child: &Child, _percent: u8) -> Result<Limiter, String> {
+        Err("not supported on this platform".to_string())
+    }
+
+    impl Limiter {
+        pub fn pause(&self, _paused: bool) {}
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+
+    /// State of the process in `/proc/<pid>/stat`, `T` while stopped
+    fn state(child: &Child) -> char {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", child.id())).unwrap();
+        let (_, after_name) = stat.rsplit_once(')').unwrap();
+        after_name.trim_start().chars().next().unwrap()
+    }
+
+    #[test]
+    fn the_child_is_paused_for_the_rest_of_its_share() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let mut throttle = Throttle::start(&child, Some(20)).unwrap();
+        assert!(Throttle::start(&child, Some(100)).is_none());
+        assert_eq!(throttle.tick(), None);
+        assert_ne!(state(&child), 'T');
+
+        std::thread::sleep(throttle.next_switch().unwrap());
+        assert_eq!(throttle.tick(), None);
+        assert!(throttle.paused);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(state(&child), 'T');
+        assert!(throttle.paused_for() > Duration::ZERO);
+
+        std::thread::sleep(throttle.next_switch().unwrap());
+        let paused = throttle.tick().unwrap();
+        assert!(paused >= PERIOD * 80 / 100, "{paused:?}");
+        assert_eq!(throttle.paused_for(), Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_ne!(state(&child), 'T');
+
+        drop(throttle);
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    fs::FileTimes,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::SystemTime,
+};
+
+use crate::atime;
+
+/// Set from `--preserve-times` at startup
+pub static PRESERVE_TIMES: AtomicBool = AtomicBool::new(false);
+
+/// Modification and creation time of an original, set on the compressed file that replaces
+/// it with `--preserve-times`. Only macOS and windows let the creation time be set, on other
+/// systems the compressed file keeps its own.
+pub struct Times {
+    modified: SystemTime,
+    created: Option<SystemTime>,
+}
+
+impl Times {
+    /// `None` without `--preserve-times` or if the times can't be read
+    pub fn of(path: &Path) -> Option<Self> {
+        if !PRESERVE_TIMES.load(Ordering::Relaxed) {
+            return None;
+        }
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(Times {
+            modified: metadata.modified().ok()?,
+            created: metadata.created().ok(),
+        })
+    }
+
+    pub fn restore(&self, path: &Path) -> Result<(), std::io::Error> {
+        let times = imp::with_created(FileTimes::new().set_modified(self.modified), self.created);
+        atime::open(path)?.set_times(times)
+    }
+}
+
+/// `YYYY-MM-DDThh:mm:ss` in UTC
+pub fn utc_date_time(time: SystemTime) -> String {
+    let date = UtcDate::of(time);
+    format!(
+        "{:04}-{:02}-{:02}T{}",
+        date.year,
+        date.month,
+        date.day,
+        date.time_of_day()
+    )
+}
+
+/// Date of the `Date:` header of a mail (RFC 2822) in UTC, e.g. `Wed, 14 Oct 2026 16:11:09 +0000`
+pub fn rfc2822_date(time: SystemTime) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let date = UtcDate::of(time);
+    format!(
+        "{}, {:02} {} {:04} {} +0000",
+        // 1970-01-01 was a thursday
+        WEEKDAYS[date.days.rem_euclid(7) as usize],
+        date.day,
+        MONTHS[date.month as usize - 1],
+        date.year,
+        date.time_of_day()
+    )
+}
+
+/// Calendar date of a time in UTC, times before 1970 count as 1970-01-01
+struct UtcDate {
+    /// Since 1970-01-01
+    days: i64,
+    year: i64,
+    month: i64,
+    day: i64,
+    seconds: i64,
+}
+
+impl UtcDate {
+    fn of(time: SystemTime) -> Self {
+        let seconds = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs()) as i64;
+        let (days, time_of_day) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+
+        // inverse of the day count in `options::parse_since`
+        let shifted = days + 719468;
+        let era = shifted.div_euclid(146097);
+        let day_of_era = shifted - era * 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+        UtcDate {
+            days,
+            year,
+            month,
+            day,
+            seconds: time_of_day,
+        }
+    }
+
+    /// `hh:mm:ss`
+    fn time_of_day(&self) -> String {
+        format!(
+            "{:02}:{:02}:{:02}",
+            self.seconds / 3600,
+            self.seconds / 60 % 60,
+            self.seconds % 60
+        )
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use std::{fs::FileTimes, os::macos::fs::FileTimesExt, time::SystemTime};
+
+    pub fn with_created(times: FileTimes, created: Option<SystemTime>) -> FileTimes {
+        match created {
+            Some(created) => times.set_created(created),
+            None => times,
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::{fs::FileTimes, os::windows::fs::FileTimesExt, time::SystemTime};
+
+    pub fn with_created(times: FileTimes, created: Option<SystemTime>) -> FileTimes {
+        match created {
+            Some(created) => times.set_created(created),
+            None => times,
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+mod imp {
+    use std::{fs::FileTimes, time::SystemTime};
+
+    /// e.g. linux has no call to set the birth time
+    pub fn with_created(times: FileTimes, _created: Option<SystemTime>) -> FileTimes {
+        times
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    fn times_of(dir: &Path, age: Duration) -> (std::path::PathBuf, Times) {
+        let path = dir.join("clip.mp4");
+        std::fs::write(&path, "original").unwrap();
+        let long_ago = SystemTime::now() - age;
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_times(imp::with_created(
+            FileTimes::new().set_modified(long_ago),
+            Some(long_ago),
+        ))
+        .unwrap();
+        let metadata = path.metadata().unwrap();
+        let times = Times {
+            modified: metadata.modified().unwrap(),
+            created: metadata.created().ok(),
+        };
+        (path, times)
+    }
+
+    #[test]
+    fn mail_dates_name_the_weekday() {
+        let date = |seconds| rfc2822_date(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(date(0), "Thu, 01 Jan 1970 00:00:00 +0000");
+        assert_eq!(date(1563096665), "Sun, 14 Jul 2019 09:31:05 +0000");
+        assert_eq!(date(951782400), "Tue, 29 Feb 2000 00:00:00 +0000");
+        assert_eq!(
+            utc_date_time(SystemTime::UNIX_EPOCH + Duration::from_secs(1563096665)),
+            "2019-07-14T09:31:05"
+        );
+    }
+
+    #[test]
+    fn modification_time_is_restored() {
+        let dir = TempDir::new("times_modified");
+        let (path, times) = times_of(&dir, Duration::from_secs(86400 * 400));
+        std::fs::write(&path, "compressed").unwrap();
+        assert_ne!(path.metadata().unwrap().modified().unwrap(), times.modified);
+
+        times.restore(&path).unwrap();
+        assert_eq!(path.metadata().unwrap().modified().unwrap(), times.modified);
+    }
+
+    #[cfg(any(target_os = "macos", windows))]
+    #[test]
+    fn creation_time_is_restored() {
+        let dir = TempDir::new("times_created");
+        let (path, times) = times_of(&dir, Duration::from_secs(86400 * 400));
+        std::fs::remove_file(&path).unwrap();
+        std::fs::write(&path, "compressed").unwrap();
+        assert_ne!(path.metadata().unwrap().created().ok(), times.created);
+
+        times.restore(&path).unwrap();
+        assert_eq!(path.metadata().unwrap().created().ok(), times.created);
+    }
+}
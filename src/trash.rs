@@ -0,0 +1,390 @@
+use std::path::{Path, PathBuf};
+
+/// Original moved into the trash by `--trash`
+pub struct Trashed {
+    /// Where the original is now
+    pub file: PathBuf,
+    /// `.trashinfo` or `$I` file the desktop uses to put it back
+    info: Option<PathBuf>,
+}
+
+impl Trashed {
+    /// Moves the original back, e.g. because the compressed file couldn't take its place
+    pub fn restore(self, original: &Path) -> Result<(), std::io::Error> {
+        std::fs::rename(&self.file, original)?;
+        if let Some(info) = &self.info {
+            let _ = std::fs::remove_file(info);
+        }
+        Ok(())
+    }
+}
+
+/// Moves `path` into the trash of the user, `Err` with the reason if there is none the file
+/// can be moved into
+pub fn trash(path: &Path) -> Result<Trashed, String> {
+    let path = std::fs::canonicalize(path).map_err(|e| e.to_string())?;
+    let name = path
+        .file_name()
+        .ok_or("the path has no file name")?
+        .to_string_lossy()
+        .to_string();
+    imp::trash(&path, &name)
+}
+
+/// Names tried for a file in the trash: `clip.mp4`, `clip.2.mp4`, `clip.3.mp4`...
+#[cfg(unix)]
+fn candidate_names(name: &str) -> impl Iterator<Item = String> + '_ {
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+        _ => (name, String::new()),
+    };
+    std::iter::once(name.to_string())
+        .chain((2..1000).map(move |index| format!("{stem}.{index}{extension}")))
+}
+
+#[cfg(unix)]
+fn move_error(e: std::io::Error) -> String {
+    if e.kind() == std::io::ErrorKind::CrossesDevices {
+        "the file is on another filesystem than the trash".to_string()
+    } else {
+        e.to_string()
+    }
+}
+
+/// Trash of the freedesktop.org specification, used by the linux and bsd desktops
+#[cfg(all(unix, not(target_os = "macos")))]
+mod imp {
+    use std::{
+        fs::OpenOptions,
+        io::Write,
+        path::{Path, PathBuf},
+        time::SystemTime,
+    };
+
+    use super::{candidate_names, move_error, Trashed};
+
+    fn trash_dir() -> Option<PathBuf> {
+        let data_home = match std::env::var_os("XDG_DATA_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/share"),
+        };
+        Some(data_home.join("Trash"))
+    }
+
+    pub fn trash(path: &Path, name: &str) -> Result<Trashed, String> {
+        let trash = trash_dir().ok_or("HOME isn't set")?;
+        let (files, infos) = (trash.join("files"), trash.join("info"));
+        std::fs::create_dir_all(&files).map_err(|e| e.to_string())?;
+        std::fs::create_dir_all(&infos).map_err(|e| e.to_string())?;
+
+        // creating the info file reserves the name, like the desktops do
+        let (name, info_path, mut info) = candidate_names(name)
+            .filter(|name| !files.join(name).exists())
+            .find_map(|name| {
+                let info_path = infos.join(format!("{name}.trashinfo"));
+                let file = OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&info_path)
+                    .ok()?;
+                Some((name, info_path, file))
+            })
+            .ok_or("no free name in the trash")?;
+        let written = write!(
+            info,
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            percent_encode(&path.to_string_lossy()),
+            deletion_date(SystemTime::now())
+        );
+
+        let file = files.join(name);
+        match written
+            .map_err(|e| e.to_string())
+            .and_then(|_| std::fs::rename(path, &file).map_err(move_error))
+        {
+            Ok(()) => Ok(Trashed {
+                file,
+                info: Some(info_path),
+            }),
+            Err(e) => {
+                let _ = std::fs::remove_file(&info_path);
+                Err(e)
+            }
+        }
+    }
+
+    /// Percent-encodes everything but unreserved characters and `/`, as the spec requires
+    pub(super) fn percent_encode(path: &str) -> String {
+        path.bytes()
+            .map(|byte| match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                    (byte as char).to_string()
+                }
+                _ => format!("%{byte:02X}"),
+            })
+            .collect()
+    }
+
+    /// `YYYY-MM-DDThh:mm:ss` in UTC, the spec asks for local time but std doesn't know the
+    /// time zone. The desktops only show it.
+    pub(super) fn deletion_date(time: SystemTime) -> String {
+        crate::times::utc_date_time(time)
+    }
+}
+
+/// `~/.Trash`, without the metadata finder uses to put files back
+#[cfg(target_os = "macos")]
+mod imp {
+    use std::path::{Path, PathBuf};
+
+    use super::{candidate_names, move_error, Trashed};
+
+    pub fn trash(path: &Path, name: &str) -> Result<Trashed, String> {
+        let home = std::env::var_os("HOME").ok_or("HOME isn't set")?;
+        let trash = PathBuf::from(home).join(".Trash");
+        let file = candidate_names(name)
+            .map(|name| trash.join(name))
+            .find(|file| !file.exists())
+            .ok_or("no free name in the trash")?;
+        std::fs::rename(path, &file).map_err(move_error)?;
+        Ok(Trashed { file, info: None })
+    }
+}
+
+/// Recycle bin of the drive, which the shell moves the original into under a generated `$R`
+/// name, next to a `$I` file recording where it came from
+#[cfg(windows)]
+mod imp {
+    use std::{
+        ffi::c_void,
+        os::windows::ffi::OsStrExt,
+        path::{Path, PathBuf},
+    };
+
+    use super::Trashed;
+    use crate::paths;
+
+    const FO_DELETE: u32 = 3;
+    const FOF_SILENT: u16 = 0x4;
+    const FOF_NOCONFIRMATION: u16 = 0x10;
+    const FOF_ALLOWUNDO: u16 = 0x40;
+    const FOF_NOERRORUI: u16 = 0x400;
+    /// Asks instead of deleting the file for good if it is too large for the recycle bin
+    const FOF_WANTNUKEWARNING: u16 = 0x4000;
+
+    /// `SHFILEOPSTRUCTW`, packed on 32-bit windows
+    #[cfg_attr(target_pointer_width = "64", repr(C))]
+    #[cfg_attr(target_pointer_width = "32", repr(C, packed))]
+    struct FileOperation {
+        window: *mut c_void,
+        function: u32,
+        from: *const u16,
+        to: *const u16,
+        flags: u16,
+        aborted: i32,
+        name_mappings: *mut c_void,
+        progress_title: *const u16,
+    }
+
+    /// `SHQUERYRBINFO`
+    #[cfg_attr(target_pointer_width = "64", repr(C))]
+    #[cfg_attr(target_pointer_width = "32", repr(C, packed))]
+    struct RecycleBinInfo {
+        size: u32,
+        bytes: i64,
+        items: i64,
+    }
+
+    #[link(name = "shell32")]
+    extern "system" {
+        fn SHFileOperationW(operation: *mut FileOperation) -> i32;
+        fn SHQueryRecycleBinW(root: *const u16, info: *mut RecycleBinInfo) -> i32;
+    }
+
+    fn wide(path: &Path) -> Vec<u16> {
+        path.as_os_str().encode_wide().chain(Some(0)).collect()
+    }
+
+    pub fn trash(path: &Path, _: &str) -> Result<Trashed, String> {
+        // the shell doesn't take `\\?\` paths
+        let path = paths::simplify(path.to_path_buf());
+        if path.to_string_lossy().starts_with(r"\\?\") {
+            return Err("the path is too long for the recycle bin".to_string());
+        }
+        let root = path.components().take(2).collect::<PathBuf>();
+        // the shell deletes files on drives without a recycle bin for good, e.g. network drives
+        let mut info = RecycleBinInfo {
+            size: std::mem::size_of::<RecycleBinInfo>() as u32,
+            bytes: 0,
+            items: 0,
+        };
+        if unsafe { SHQueryRecycleBinW(wide(&root).as_ptr(), &mut info) } != 0 {
+            return Err(format!(
+                "the drive {} has no recycle bin",
+                root.to_string_lossy()
+            ));
+        }
+
+        // a list of paths, ended by an empty one
+        let mut from = wide(&path);
+        from.push(0);
+        let mut operation = FileOperation {
+            window: std::ptr::null_mut(),
+            function: FO_DELETE,
+            from: from.as_ptr(),
+            to: std::ptr::null(),
+            flags: FOF_ALLOWUNDO
+                | FOF_NOCONFIRMATION
+                | FOF_SILENT
+                | FOF_NOERRORUI
+                | FOF_WANTNUKEWARNING,
+            aborted: 0,
+            name_mappings: std::ptr::null_mut(),
+            progress_title: std::ptr::null(),
+        };
+        // the codes aren't windows errors, some date back to windows 3.1
+        let result = unsafe { SHFileOperationW(&mut operation) };
+        if result != 0 {
+            return Err(format!("the shell failed with error {result:#x}"));
+        }
+        let aborted = operation.aborted;
+        if aborted != 0 || path.exists() {
+            return Err("moving the file to the recycle bin was cancelled".to_string());
+        }
+
+        Ok(match find(&root, &path) {
+            Some((file, info)) => Trashed {
+                file,
+                info: Some(info),
+            },
+            // it is in the recycle bin, the run only can't move it back on its own
+            None => Trashed {
+                file: root.join("$Recycle.Bin"),
+                info: None,
+            },
+        })
+    }
+
+    /// `$R` file the original became and the `$I` file recording its path, the most recent
+    /// ones if the same path was trashed before. The bins of other users can't be read.
+    fn find(root: &Path, original: &Path) -> Option<(PathBuf, PathBuf)> {
+        let original = original.to_str()?.to_lowercase();
+        std::fs::read_dir(root.join("$Recycle.Bin"))
+            .ok()?
+            .flatten()
+            .filter_map(|bin| std::fs::read_dir(bin.path()).ok())
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with("$I"))
+            .filter_map(|entry| {
+                let (deleted, path) = parse_info(&std::fs::read(entry.path()).ok()?)?;
+                if path.to_lowercase() != original {
+                    return None;
+                }
+                let name = entry.file_name().to_string_lossy().replacen("$I", "$R", 1);
+                Some((deleted, entry.path().with_file_name(name), entry.path()))
+            })
+            .max_by_key(|(deleted, ..)| *deleted)
+            .map(|(_, file, info)| (file, info))
+    }
+
+    /// Deletion time and original path of a `$I` file, in the layout of version 1 (windows
+    /// vista to 8) or version 2 (windows 10 and later)
+    pub(super) fn parse_info(info: &[u8]) -> Option<(u64, String)> {
+        let u64_at = |at: usize| Some(u64::from_le_bytes(info.get(at..at + 8)?.try_into().ok()?));
+        let deleted = u64_at(16)?;
+        let path = match u64_at(0)? {
+            1 => info.get(24..24 + 520)?,
+            2 => {
+                let len = u32::from_le_bytes(info.get(24..28)?.try_into().ok()?) as usize;
+                info.get(28..28 + len * 2)?
+            }
+            _ => return None,
+        };
+        let path = path
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .take_while(|&unit| unit != 0)
+            .collect::<Vec<_>>();
+        Some((deleted, String::from_utf16(&path).ok()?))
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    use std::path::Path;
+
+    use super::Trashed;
+
+    pub fn trash(_: &Path, _: &str) -> Result<Trashed, String> {
+        Err("the recycle bin isn't supported on this platform".to_string())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trash_names_keep_the_extension() {
+        let names = candidate_names("clip.mp4").take(3).collect::<Vec<_>>();
+        assert_eq!(names, ["clip.mp4", "clip.2.mp4", "clip.3.mp4"]);
+        assert_eq!(candidate_names(".hidden").nth(1).unwrap(), ".hidden.2");
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn trash_info_encoding() {
+        use std::time::{Duration, SystemTime};
+
+        assert_eq!(
+            imp::percent_encode("/videos/Lecture 01 (ä).mp4"),
+            "/videos/Lecture%2001%20%28%C3%A4%29.mp4"
+        );
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1563096665);
+        assert_eq!(imp::deletion_date(time), "2019-07-14T09:31:05");
+        let leap_day = SystemTime::UNIX_EPOCH + Duration::from_secs(951782400);
+        assert_eq!(imp::deletion_date(leap_day), "2000-02-29T00:00:00");
+    }
+}
+
+#[cfg(all(test, windows))]
+mod windows_tests {
+    use super::*;
+
+    #[test]
+    fn recycle_bin_entries_record_the_original_path() {
+        let wide = |path: &str| {
+            path.encode_utf16()
+                .chain(Some(0))
+                .flat_map(u16::to_le_bytes)
+                .collect::<Vec<_>>()
+        };
+        let header = |version: u64| {
+            [version, 1000, 133_000_000_000_000_000]
+                .into_iter()
+                .flat_map(u64::to_le_bytes)
+                .collect::<Vec<_>>()
+        };
+
+        let path = wide(r"C:\videos\lecture ä.mp4");
+        let version_2 = [
+            header(2),
+            (path.len() as u32 / 2).to_le_bytes().to_vec(),
+            path.clone(),
+        ]
+        .concat();
+        let expected = Some((
+            133_000_000_000_000_000,
+            r"C:\videos\lecture ä.mp4".to_string(),
+        ));
+        assert_eq!(imp::parse_info(&version_2), expected);
+
+        let mut version_1 = [header(1), path].concat();
+        version_1.resize(24 + 520, 0);
+        assert_eq!(imp::parse_info(&version_1), expected);
+
+        assert_eq!(imp::parse_info(&header(3)), None);
+        assert_eq!(imp::parse_info(&version_2[..40]), None);
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Release a version starts with, e.g. `[6, 1, 1]` for `6.1.1-3ubuntu5` or `[7, 0, 0]` for
+/// `n7.0`. `None` for builds from git master like `N-112345-g...` or `2023-12-04-git-...`,
+/// which don't name a release.
+pub fn release(version: &str) -> Option<[u32; 3]> {
+    let version = version.strip_prefix('n').unwrap_or(version);
+    let numbers = version
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .next()?;
+    let mut parts = numbers.split('.').map(|part| part.parse::<u32>().ok());
+    let major = parts.next()??;
+    // the year of a build named by its date
+    if major >= 1000 {
+        return None;
+    }
+    let mut next = || parts.next().flatten().unwrap_or(0);
+    Some([major, next(), next()])
+}
+
+/// Fails with an actionable message if the ffmpeg release is older than `minimum`. Builds
+/// that don't name a release pass.
+pub fn check_minimum(version: &str, minimum: [u32; 3]) -> Result<(), String> {
+    match release(version) {
+        Some(release) if release < minimum => {
+            let path = program_path("ffmpeg").map_or("ffmpeg".to_string(), |path| {
+                path.to_string_lossy().to_string()
+            });
+            Err(format!(
+                "ffmpeg {version} at `{path}` is older than the required {}, install a newer build (static builds are linked on https://ffmpeg.org/download.html) or pass `--allow-old-ffmpeg`",
+                display_release(minimum)
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// `4.0` or `4.4.1`
+pub fn display_release(release: [u32; 3]) -> String {
+    match release {
+        [major, minor, 0] => format!("{major}.{minor}"),
+        [major, minor, patch] => format!("{major}.{minor}.{patch}"),
+    }
+}
+
+/// Where `name` is found in PATH, the one that runs
+pub fn program_path(name: &str) -> Option<PathBuf> {
+    let file_name = if cfg!(windows) {
+        format!("{name}.exe")
+    } else {
+        name.to_string()
+    };
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(&file_name))
+        .find(|path| Path::is_file(path))
+}
+
+/// Version of the ffmpeg in PATH, e.g. `6.1.1-3ubuntu5`
+pub fn ffmpeg() -> Option<String> {
+    program("ffmpeg")
+}
+
+/// Version of the ffprobe in PATH, usually the same as the one of ffmpeg
+pub fn ffprobe() -> Option<String> {
+    program("ffprobe")
+}
+
+fn program(name: &str) -> Option<String> {
+    let output = Command::new(name).arg("-version").output().ok()?;
+    // ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023 the FFmpeg developers
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout
+        .lines()
+        .next()?
+        .strip_prefix(name)?
+        .strip_prefix(" version ")?;
+    Some(version.split(" Copyright").next()?.trim().to_string())
+}
+
+/// Version and build of the x265 library linked into ffmpeg, e.g.
+/// `3.5+1-f0c1022b6 [Linux][GCC 11.2.0][64 bit] 8bit+10bit+12bit`.
+/// `ffmpeg -version` doesn't report it, so a single blank frame is encoded to get x265's banner.
+pub fn x265() -> Option<String> {
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-f", "lavfi", "-i", "nullsrc=s=64x64"])
+        .args(["-frames:v", "1", "-c:v", "libx265", "-f", "null", "-"])
+        .output()
+        .ok()?;
+
+    // x265 [info]: HEVC encoder version 3.5+1-f0c1022b6
+    // x265 [info]: build info [Linux][GCC 11.2.0][64 bit] 8bit+10bit+12bit
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let info = |marker: &str| {
+        stderr
+            .lines()
+            .find_map(|line| Some(line.split_once(marker)?.1.trim().to_string()))
+    };
+    let version = info("HEVC encoder version ")?;
+    match info("build info ") {
+        Some(build) => Some(format!("{version} {build}")),
+        None => Some(version),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn releases_of_version_strings() {
+        assert_eq!(release("6.1.1-3ubuntu5"), Some([6, 1, 1]));
+        assert_eq!(release("4.4.2-0ubuntu0.22.04.1"), Some([4, 4, 2]));
+        assert_eq!(release("n7.0"), Some([7, 0, 0]));
+        assert_eq!(release("7.0-full_build-www.gyan.dev"), Some([7, 0, 0]));
+        assert_eq!(release("3.4.11"), Some([3, 4, 11]));
+        assert_eq!(release("N-112345-g1d1b8c6fa5"), None);
+        assert_eq!(release("2023-12-04-git-8c117b0c5e-full_build"), None);
+
+        assert!(check_minimum("3.4.11", [4, 0, 0]).is_err());
+        assert!(check_minimum("4.0", [4, 0, 0]).is_ok());
+        assert!(check_minimum("N-112345-g1d1b8c6fa5", [4, 0, 0]).is_ok());
+    }
+}
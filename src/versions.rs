@@ -0,0 +1,109 @@
+use std::{collections::BTreeMap, path::Path};
+
+use crate::Log;
+
+/// Prints the compressed files of the log of `path` grouped by the ffmpeg and x265 builds that
+/// compressed them, e.g. to find the files of a build with a known bug.
+pub fn log_versions(path: &Path) {
+    if !path.is_dir() {
+        info!(
+            "`--log-versions` expects a directory, got `{}`",
+            path.to_string_lossy()
+        );
+        std::process::exit(1);
+    }
+
+    let log = Log::new(path.to_string_lossy().to_string());
+    let lines = report(&log);
+    if lines.is_empty() {
+        info!("No compressed files recorded in `{}`", log.save_file);
+    }
+    for line in lines {
+        info!("{line}");
+    }
+}
+
+/// A header per ffmpeg and x265 build with the files compressed by it, oldest builds by their
+/// first run first. Files without a recorded run, like imported ones and copies of duplicates,
+/// come last.
+fn report(log: &Log) -> Vec<String> {
+    // keyed by the first run of the build, so the builds are listed in the order they were used
+    let mut builds = BTreeMap::<usize, (String, Vec<&str>)>::new();
+    let mut unknown = Vec::new();
+    for (path, file_log) in log.shrunk_files.iter() {
+        let Some((index, run)) = file_log
+            .run
+            .and_then(|index| Some((index, log.runs.get(index)?)))
+        else {
+            unknown.push(path.as_str());
+            continue;
+        };
+        let name = |build: &Option<String>| build.as_deref().unwrap_or("unknown").to_string();
+        let header = format!("ffmpeg {}, x265 {}", name(&run.ffmpeg), name(&run.x265));
+        let first = log
+            .runs
+            .iter()
+            .position(|other| other.ffmpeg == run.ffmpeg && other.x265 == run.x265)
+            .unwrap_or(index);
+        builds
+            .entry(first)
+            .or_insert_with(|| (header, Vec::new()))
+            .1
+            .push(path);
+    }
+
+    let mut groups = builds.into_values().collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        groups.push(("Unknown build (imported or copied)".to_string(), unknown));
+    }
+    let mut lines = Vec::new();
+    for (header, mut files) in groups {
+        files.sort();
+        let noun = if files.len() == 1 { "file" } else { "files" };
+        lines.push(format!("{header}: {} {noun}", files.len()));
+        lines.extend(files.into_iter().map(|path| format!("  {path}")));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileLog, Run};
+
+    #[test]
+    fn files_are_grouped_by_the_build_of_their_run() {
+        let mut log = Log::in_memory();
+        for (ffmpeg, x265) in [("6.0", "3.5"), ("6.1", "3.5"), ("6.0", "3.5")] {
+            log.runs.push(Run {
+                recorded: 0,
+                ffmpeg: Some(ffmpeg.to_string()),
+                ffmpeg_path: None,
+                x265: Some(x265.to_string()),
+                ffprobe: None,
+            });
+        }
+        for (path, run) in [("c.mp4", Some(2)), ("a.mp4", Some(1)), ("b.mp4", Some(0))] {
+            let file_log = FileLog {
+                run,
+                ..FileLog::default()
+            };
+            log.shrunk_files.insert(path.to_string(), file_log);
+        }
+        log.shrunk_files
+            .insert("old.mp4".to_string(), FileLog::default());
+
+        assert_eq!(
+            report(&log),
+            [
+                "ffmpeg 6.0, x265 3.5: 2 files",
+                "  b.mp4",
+                "  c.mp4",
+                "ffmpeg 6.1, x265 3.5: 1 file",
+                "  a.mp4",
+                "Unknown build (imported or copied): 1 file",
+                "  old.mp4",
+            ]
+        );
+    }
+}
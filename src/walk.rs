@@ -0,0 +1,74 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// Directories entered by the scan, so one that is reached a second time, e.g. through a bind
+/// mount or a symlink loop, isn't walked again
+#[derive(Default)]
+pub struct Visited(HashMap<imp::DirId, PathBuf>);
+
+impl Visited {
+    /// Records `path` as walked, returning the path it was first walked as if it already was.
+    /// Directories that can't be identified are walked, reading them reports the error.
+    pub fn enter(&mut self, path: &Path) -> Option<PathBuf> {
+        let id = imp::DirId::of(path).ok()?;
+        match self.0.get(&id) {
+            Some(first) => Some(first.clone()),
+            None => {
+                self.0.insert(id, path.to_path_buf());
+                None
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::{os::unix::fs::MetadataExt, path::Path};
+
+    /// Device and inode, which links and mounts of the directory share
+    #[derive(PartialEq, Eq, Hash)]
+    pub struct DirId(u64, u64);
+
+    impl DirId {
+        pub fn of(path: &Path) -> Result<Self, std::io::Error> {
+            let metadata = std::fs::metadata(path)?;
+            Ok(DirId(metadata.dev(), metadata.ino()))
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::path::{Path, PathBuf};
+
+    /// Canonical path, the file index isn't exposed by the standard library elsewhere
+    #[derive(PartialEq, Eq, Hash)]
+    pub struct DirId(PathBuf);
+
+    impl DirId {
+        pub fn of(path: &Path) -> Result<Self, std::io::Error> {
+            path.canonicalize().map(DirId)
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn symlinked_directories_are_entered_once() {
+        let dir = TempDir::new("visited");
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("a/loop")).unwrap();
+
+        let mut visited = Visited::default();
+        assert_eq!(visited.enter(&dir), None);
+        assert_eq!(visited.enter(&dir.join("a")), None);
+        assert_eq!(visited.enter(&dir.join("a/loop")), Some(dir.clone()));
+        assert_eq!(visited.enter(&dir.join("a/loop/a")), Some(dir.join("a")));
+    }
+}